@@ -1,77 +1,1088 @@
 use anyhow::Result;
+use parking_lot::Mutex;
 use rmcp::{ServiceExt, service::QuitReason, transport::stdio};
 use std::{
-  path::PathBuf,
-  sync::{Arc, Mutex},
+  collections::{BTreeMap, HashMap},
+  path::{Path, PathBuf},
+  sync::{Arc, atomic::AtomicU64},
+  time::Duration,
 };
 
+#[cfg(feature = "sqlite")]
+use crate::search::sqlite_index::SqliteIndex;
 use crate::{
   file::{
-    file_filter::ExtensionFileFilter, file_watcher::NotifyFileWatcher,
+    extractor::ExtractorRegistry,
+    file_filter::{
+      AnyFileFilter, ContentTypeFileFilter, EventFilter, ExcludedPathFileFilter,
+      ExtensionFileFilter, PathExclusion,
+    },
+    file_watcher::{NotifyFileWatcher, WATCH_LIMIT_GUIDANCE, is_watch_limit_error},
+    file_writer::FileWriter,
+    http_file_loader::{HttpFileLoader, UrlFileFilter},
     lazy_file_loader::LazyFileLoader,
+    periodic_rescan::{PeriodicRescan, RescanTarget},
+    poll_file_watcher::PollFileWatcher,
+    read_file::RetryPolicy,
+    url_refresh::UrlRefresh,
+    version_history::VersionHistory,
   },
   search::{
-    file::{FileLoader, FileWatcher},
-    index_operation::IndexOperation,
-    text_index::TextIndex,
+    bench::{BenchReport, run_bench},
+    bookmarks::Bookmarks,
+    disk_space_guard::DiskSpaceGuardBackend,
+    event_log::EventLog,
+    export::{EXPORT_LIMIT, ExportFormat, export_hits, write_hits},
+    extra_fields::{ExtraField, ExtraFieldBackend},
+    file::{FileFilter, FileLoader, FileOperationHandler, FileWatcher},
+    git_metadata::{GitMetadataBackend, GitRepository},
+    index_backend::IndexBackend,
+    index_operation::{ErrorPolicy, IndexOperation, IndexOperationOptions},
+    language_index::LanguageIndexBackend,
+    maintenance::{CronSchedule, MaintenanceScheduler, MaintenanceTask},
+    memory_budget::MemoryBudget,
+    operation_recording::{
+      OperationRecorder, ReplayReport, ReplayResults, read_recorded_operations,
+    },
+    project_index::{ProjectIndexBackend, default_project_name},
+    query_stats::QueryStats,
+    query_syntax::QuerySyntax,
+    query_template::QueryTemplate,
+    result_sets::ResultSets,
+    saved_filters::{SavedFilter, SavedFilters},
+    search_in_dir::DirectorySearch,
+    semantic_index::{Embedder, SemanticIndex, SemanticIndexBackend, SemanticSearch},
+    shadow_copies::{ShadowCopies, ShadowCopyBackend},
+    sharded_index::{SearchScope, ShardHealth, ShardedIndex, shard_directory_name},
+    skipped_files::SkippedFiles,
+    text_index::{DEFAULT_WRITER_HEAP, TextIndex},
   },
-  servers::search::SearchServer,
+  servers::{audit_log::AuditLog, request_limiter::RequestLimiter, search::SearchServer},
+  systemd,
 };
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum WatchBackend {
+  /// Native file system events, falling back to polling when they are unavailable
+  Auto,
+  /// Native file system events only
+  Notify,
+  /// Periodic rescans comparing modification times, for NFS/SMB mounts and containers
+  Poll,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backend {
+  /// Tantivy, one index directory per watch root
+  Tantivy,
+  /// SQLite FTS5, one database file per watch root
+  #[cfg(feature = "sqlite")]
+  Sqlite,
+}
+
+pub struct ApplicationConfig {
+  pub watch_dirs: Vec<PathBuf>,
+  /// Project names of watch dirs; unnamed ones are named after their last path component
+  pub project_names: HashMap<PathBuf, String>,
+  pub index_dir: Option<PathBuf>,
+  /// Indexing into `index_dir` pauses while less space is free on its disk, 0 to never pause
+  pub min_free_disk_space: u64,
+  pub backend: Backend,
+  pub extensions: Vec<String>,
+  pub excluded_directories: Vec<String>,
+  pub include_hidden: bool,
+  pub detect_text_content: bool,
+  pub follow_symlinks: bool,
+  pub respect_ignore_files: bool,
+  pub max_file_size: Option<u64>,
+  pub index_archives: bool,
+  pub chunk_size: Option<usize>,
+  pub index_sections: bool,
+  pub retry_policy: RetryPolicy,
+  pub created_grace_delay: Duration,
+  pub error_policy: ErrorPolicy,
+  /// Crawl and apply changes on threads of lowered priority
+  pub index_nice: bool,
+  /// Most files loaded per second while crawling and applying changes
+  pub max_files_per_second: Option<u32>,
+  pub watch_backend: WatchBackend,
+  pub poll_interval: Duration,
+  pub rescan_interval: Option<Duration>,
+  pub url_sources: Vec<String>,
+  pub url_refresh_interval: Option<Duration>,
+  /// When to run the maintenance tasks, e.g. every night
+  pub maintenance_schedule: Option<CronSchedule>,
+  pub maintenance_tasks: Vec<MaintenanceTask>,
+  /// Directories the search_directory tool may index on demand
+  pub allowed_search_dirs: Vec<PathBuf>,
+  /// Enables semantic search, embedding every document with it
+  pub embedder: Option<Arc<dyn Embedder + Send + Sync>>,
+  pub extractors: ExtractorRegistry,
+  /// Queries the run_query_template tool runs by name
+  pub query_templates: Vec<QueryTemplate>,
+  /// Filters searches can combine by name
+  pub saved_filters: Vec<SavedFilter>,
+  /// Score factors by lowercase file extension
+  pub extension_boosts: BTreeMap<String, f32>,
+  /// Searches taking longer are logged as slow
+  pub slow_query_threshold: Duration,
+  /// File every tool call is appended to
+  pub audit_log: Option<PathBuf>,
+  /// Search results carry no content and files are only loaded once confirmed
+  pub privacy_mode: bool,
+  /// Tool calls of a session allowed to run at once
+  pub max_concurrent_requests: Option<usize>,
+  /// Tool calls of a session allowed per second
+  pub max_requests_per_second: Option<u32>,
+  /// Result sets kept for follow-up tools, none when 0
+  pub max_result_sets: usize,
+  /// Directory the export_results tool writes into
+  pub export_dir: Option<PathBuf>,
+  /// Markers the todo_report tool looks for by default
+  pub todo_markers: Vec<String>,
+  /// Enables the tools writing, deleting and moving files inside the watched directories
+  pub allow_write: bool,
+  /// Directory deleted and replaced files are moved to, `trash` in the index directory by default
+  pub trash_dir: Option<PathBuf>,
+  /// Keeps the previously indexed content of documents in memory for the diff_file tool
+  pub keep_shadow_copies: bool,
+  /// Snapshots watched files whenever they change into `history` in the index directory
+  pub version_history: bool,
+  /// Stamps documents in git work trees with the last commit of their file and their checkout,
+  /// filterable by author and branch
+  pub git_metadata: bool,
+  /// Metadata fields extracted from documents by pattern or front matter key
+  pub extra_fields: Vec<ExtraField>,
+  /// Reads new index segments after every commit, before the next search needs them
+  pub warm_up: bool,
+  /// Memory shared out between index writers, queued operations, caches and in-memory indexes
+  pub max_memory_bytes: Option<u64>,
+  /// Appends every queued file operation to this file as a JSON line, for `replay`
+  pub record_events: Option<PathBuf>,
+}
+
 pub struct Application {
-  index: Arc<Mutex<TextIndex>>,
+  index: Arc<ShardedIndex>,
   file_loader: Arc<dyn FileLoader + Send + Sync>,
-  _index_operation: Arc<IndexOperation>,
-  _file_watcher: NotifyFileWatcher,
+  skipped_files: Arc<SkippedFiles>,
+  event_log: Arc<EventLog>,
+  semantic_search: Option<Arc<SemanticSearch>>,
+  directory_search: Option<Arc<DirectorySearch>>,
+  file_writer: Option<Arc<FileWriter>>,
+  shadow_copies: Option<Arc<ShadowCopies>>,
+  version_history: Option<Arc<VersionHistory>>,
+  bookmarks: Arc<Bookmarks>,
+  result_sets: Arc<ResultSets>,
+  export_dir: Option<PathBuf>,
+  todo_markers: Arc<Vec<String>>,
+  query_stats: Arc<QueryStats>,
+  audit_log: Option<Arc<AuditLog>>,
+  request_limiter: Arc<RequestLimiter>,
+  privacy_mode: bool,
+  query_templates: Arc<Vec<QueryTemplate>>,
+  query_syntax: Arc<QuerySyntax>,
+  watched_roots: Vec<WatchedRoot>,
+  _maintenance: Option<MaintenanceScheduler>,
+}
+
+struct WatchedRoot {
+  root: String,
+  index_operation: Arc<IndexOperation>,
+  _file_watchers: Vec<Box<dyn FileWatcher + Send>>,
+  _periodic_rescan: Option<PeriodicRescan>,
+  _url_refresh: Option<UrlRefresh>,
+}
+
+/// How the index of each watch root or URL source is opened.
+#[derive(Clone, Copy)]
+struct ShardOptions<'a> {
+  backend: Backend,
+  index_dir: Option<&'a Path>,
+  min_free_disk_space: u64,
+  semantic_search: Option<&'a SemanticSearch>,
+  shadow_copies: Option<&'a Arc<ShadowCopies>>,
+  git_metadata: bool,
+  extra_fields: &'a [ExtraField],
+  warm_up: bool,
+  writer_heap: usize,
+}
+
+struct WatchOptions {
+  created_grace_delay: Duration,
+  error_policy: ErrorPolicy,
+  index_nice: bool,
+  max_batch_operations: Option<usize>,
+  watch_backend: WatchBackend,
+  poll_interval: Duration,
+  rescan_interval: Option<Duration>,
+  exclusion: PathExclusion,
+  event_log: Arc<EventLog>,
+  version_history: Option<Arc<VersionHistory>>,
 }
 
 impl Application {
-  pub fn new(watch_dir: PathBuf, index_dir: Option<PathBuf>, extensions: String) -> Result<Self> {
-    let index = if let Some(index_dir) = &index_dir {
-      Arc::new(Mutex::new(TextIndex::new_with_directory(index_dir)?))
+  pub fn new(config: ApplicationConfig) -> Result<Self> {
+    /* Pins are kept next to the index, and only for the session when the index is in memory */
+    let bookmarks = Arc::new(match &config.index_dir {
+      Some(index_dir) => Bookmarks::new_with_file(index_dir.join("bookmarks.json")),
+      None => Bookmarks::new(),
+    });
+    let result_sets = Arc::new(match &config.index_dir {
+      Some(index_dir) => {
+        ResultSets::new_with_file(config.max_result_sets, index_dir.join("result_sets.json"))
+      }
+      None => ResultSets::new(config.max_result_sets),
+    });
+    let memory_budget = config.max_memory_bytes.map(|max_memory_bytes| {
+      MemoryBudget::new(
+        max_memory_bytes,
+        config.watch_dirs.len() + config.url_sources.len(),
+        config.max_file_size,
+      )
+    });
+    let index = ShardedIndex::new()
+      .with_bookmarks(bookmarks.clone())
+      .with_extension_boosts(config.extension_boosts.clone())
+      .with_saved_filters(Arc::new(SavedFilters::new(config.saved_filters.clone())));
+    let index = Arc::new(match memory_budget {
+      Some(memory_budget) => index.with_memory_budget(memory_budget),
+      None => index,
+    });
+    let extension_filter: Arc<dyn FileFilter + Send + Sync> =
+      Arc::new(ExtensionFileFilter::new(config.extensions));
+    let file_filter: Arc<dyn FileFilter + Send + Sync> = if config.detect_text_content {
+      Arc::new(AnyFileFilter::new(vec![
+        extension_filter,
+        Arc::new(ContentTypeFileFilter),
+      ]))
     } else {
-      Arc::new(Mutex::new(TextIndex::new()?))
+      extension_filter
     };
-    let file_filter = Arc::new(ExtensionFileFilter::new(
-      extensions
-        .split(",")
-        .map(|e| e.to_string())
-        .collect::<Vec<_>>(),
-    ));
-    let file_loader = Arc::new(LazyFileLoader::new());
-    let mut file_watcher = NotifyFileWatcher::new();
-
-    let index_operation = Arc::new(IndexOperation::new(
-      index.clone(),
-      file_filter.clone(),
-      file_loader.clone(),
-    )?);
-    index_operation.initialize_index(
-      watch_dir.to_string_lossy().as_ref(),
-      file_filter.clone(),
-      file_loader.clone(),
-    )?;
-
-    file_watcher.watch_directory(watch_dir.to_string_lossy().as_ref(), {
-      let index_operation = index_operation.clone();
-      Box::new(move |op| index_operation.enqueue(op))
-    })?;
+    let exclusion = PathExclusion {
+      excluded_names: config.excluded_directories,
+      include_hidden: config.include_hidden,
+      follow_symlinks: config.follow_symlinks,
+      respect_ignore_files: config.respect_ignore_files,
+    };
+    let skipped_files = Arc::new(SkippedFiles::new());
+    let file_loader = Arc::new(
+      LazyFileLoader::new_with_options(
+        exclusion.clone(),
+        config.max_file_size,
+        skipped_files.clone(),
+        Arc::new(config.extractors),
+      )
+      .with_archive_indexing(config.index_archives)
+      .with_chunk_size(config.chunk_size)
+      .with_section_indexing(config.index_sections)
+      .with_retry_policy(config.retry_policy)
+      .with_rate_limit(config.max_files_per_second),
+    );
+
+    let event_log = Arc::new(match &config.record_events {
+      Some(path) => EventLog::default().with_recorder(OperationRecorder::open(path)?),
+      None => EventLog::default(),
+    });
+    let audit_log = config
+      .audit_log
+      .as_deref()
+      .map(|path| AuditLog::open(path).map(Arc::new))
+      .transpose()?;
+    let semantic_search = config
+      .embedder
+      .map(|embedder| Arc::new(SemanticSearch::new(embedder)));
+    let shadow_copies = config
+      .keep_shadow_copies
+      .then(|| Arc::new(ShadowCopies::new()));
+    let project_name = |watch_dir: &Path| {
+      config
+        .project_names
+        .get(watch_dir)
+        .cloned()
+        .unwrap_or_else(|| default_project_name(&watch_dir.to_string_lossy()))
+    };
+    let version_history = if config.version_history {
+      let Some(index_dir) = &config.index_dir else {
+        anyhow::bail!("--version-history needs --index-dir to keep the history next to the index");
+      };
+      Some(Arc::new(VersionHistory::open(&index_dir.join("history"))?))
+    } else {
+      None
+    };
+    let shard_options = ShardOptions {
+      backend: config.backend,
+      index_dir: config.index_dir.as_deref(),
+      min_free_disk_space: config.min_free_disk_space,
+      semantic_search: semantic_search.as_deref(),
+      shadow_copies: shadow_copies.as_ref(),
+      git_metadata: config.git_metadata,
+      extra_fields: &config.extra_fields,
+      warm_up: config.warm_up,
+      writer_heap: memory_budget.map_or(DEFAULT_WRITER_HEAP, |budget| {
+        budget.writer_heap_bytes as usize
+      }),
+    };
+    let query_syntax = match config.backend {
+      Backend::Tantivy => QuerySyntax::tantivy(),
+      #[cfg(feature = "sqlite")]
+      Backend::Sqlite => QuerySyntax::sqlite(),
+    };
+    let query_syntax = if config.git_metadata {
+      query_syntax.with_git_metadata()
+    } else {
+      query_syntax
+    }
+    .with_extra_fields(&config.extra_fields);
+    let watch_options = WatchOptions {
+      created_grace_delay: config.created_grace_delay,
+      error_policy: config.error_policy,
+      index_nice: config.index_nice,
+      max_batch_operations: memory_budget.map(|budget| budget.batch_operations),
+      watch_backend: config.watch_backend,
+      poll_interval: config.poll_interval,
+      rescan_interval: config.rescan_interval,
+      exclusion: exclusion.clone(),
+      event_log: event_log.clone(),
+      version_history: version_history.clone(),
+    };
+    let mut watched_roots = config
+      .watch_dirs
+      .iter()
+      .map(|watch_dir| {
+        let text_index = open_text_index(watch_dir, &project_name(watch_dir), shard_options)?;
+        let root = watch_dir.to_string_lossy();
+        index.add_project_shard(&project_name(watch_dir), &root, text_index.clone())?;
+        let health = index.health(&root)?.unwrap_or_default();
+        let root_file_filter = Arc::new(ExcludedPathFileFilter::new(
+          file_filter.clone(),
+          watch_dir,
+          exclusion.clone(),
+        ));
+        let event_filter = Arc::new(EventFilter::new(
+          root_file_filter.clone(),
+          file_loader.clone(),
+        ));
+        let watched_root = watch_root(
+          watch_dir,
+          text_index,
+          root_file_filter,
+          file_loader.clone(),
+          event_filter,
+          &watch_options,
+          health,
+        )?;
+        index.set_reindex_handler(
+          &root,
+          prioritized_queue_handler(&watched_root.index_operation, &event_log),
+        )?;
+        Ok(watched_root)
+      })
+      .collect::<Result<Vec<_>>>()?;
+
+    let directory_search = (!config.allowed_search_dirs.is_empty()).then(|| {
+      Arc::new(DirectorySearch::new(
+        &config.allowed_search_dirs,
+        &config.watch_dirs,
+        file_filter.clone(),
+        file_loader.clone(),
+      ))
+    });
+
+    let file_writer = config.allow_write.then(|| {
+      let trash_dir = config
+        .trash_dir
+        .clone()
+        .or_else(|| {
+          config
+            .index_dir
+            .as_ref()
+            .map(|index_dir| index_dir.join("trash"))
+        })
+        .unwrap_or_else(|| std::env::temp_dir().join("fs-text-search-mcp-trash"));
+      let file_writer = FileWriter::new(&config.watch_dirs, trash_dir);
+      let file_writer = config
+        .watch_dirs
+        .iter()
+        .fold(file_writer, |file_writer, watch_dir| {
+          file_writer.with_root_filter(
+            watch_dir,
+            Arc::new(ExcludedPathFileFilter::new(
+              file_filter.clone(),
+              watch_dir,
+              exclusion.clone(),
+            )),
+          )
+        });
+      Arc::new(file_writer)
+    });
+
+    let http_file_loader = Arc::new(HttpFileLoader::new().with_max_file_size(config.max_file_size));
+    for source in &config.url_sources {
+      let text_index = open_text_index(
+        Path::new(source),
+        &default_project_name(source),
+        ShardOptions {
+          git_metadata: false,
+          ..shard_options
+        },
+      )?;
+      index.add_shard(source, text_index.clone())?;
+      let health = index.health(source)?.unwrap_or_default();
+      let index_options = IndexOperationOptions {
+        error_policy: config.error_policy,
+        commit_status: health.commit_status,
+        queued_operations: health.queued_operations,
+        nice: config.index_nice,
+        max_batch_operations: memory_budget.map(|budget| budget.batch_operations),
+        ..Default::default()
+      };
+      watched_roots.push(watch_url_source(
+        source,
+        text_index,
+        http_file_loader.clone(),
+        config.url_refresh_interval,
+        index_options,
+        &event_log,
+      )?);
+    }
+    /* Indexed URLs are loaded over HTTP too, but only when URL sources were configured */
+    let file_loader: Arc<dyn FileLoader + Send + Sync> = if config.url_sources.is_empty() {
+      file_loader
+    } else {
+      Arc::new(
+        HttpFileLoader::new()
+          .with_max_file_size(config.max_file_size)
+          .with_local_loader(file_loader),
+      )
+    };
+
+    let maintenance = config
+      .maintenance_schedule
+      .map(|schedule| {
+        MaintenanceScheduler::start(schedule, config.maintenance_tasks, index.clone())
+      })
+      .transpose()?;
 
     Ok(Application {
       index,
-      file_loader: file_loader.clone(),
-      _index_operation: index_operation,
-      _file_watcher: file_watcher,
+      file_loader,
+      skipped_files,
+      event_log,
+      semantic_search,
+      directory_search,
+      file_writer,
+      shadow_copies,
+      version_history,
+      bookmarks,
+      result_sets,
+      export_dir: config.export_dir,
+      todo_markers: Arc::new(config.todo_markers),
+      query_stats: Arc::new(QueryStats::new(config.slow_query_threshold)),
+      audit_log,
+      request_limiter: Arc::new(RequestLimiter::new(
+        config.max_concurrent_requests,
+        config.max_requests_per_second,
+      )),
+      privacy_mode: config.privacy_mode,
+      query_templates: Arc::new(config.query_templates),
+      query_syntax: Arc::new(query_syntax),
+      watched_roots,
+      _maintenance: maintenance,
+    })
+  }
+
+  /// Writes the results of `query` to `output`, or to stdout, instead of serving. The format
+  /// defaults to the one matching the extension of `output`.
+  pub fn export(
+    &self,
+    query: &str,
+    format: Option<ExportFormat>,
+    output: Option<&Path>,
+  ) -> Result<usize> {
+    let hits = self
+      .index
+      .search_hits(query, EXPORT_LIMIT, &SearchScope::default())?;
+    let format = format
+      .or_else(|| output.and_then(ExportFormat::from_path))
+      .unwrap_or_default();
+    match output {
+      Some(output) => export_hits(output, query, &hits, format)?,
+      None => write_hits(&mut std::io::stdout().lock(), query, &hits, format)?,
+    }
+    Ok(hits.len())
+  }
+
+  /// Runs `queries` `iterations` times against the index built in `index_duration`, instead of
+  /// serving. Without queries, words of the indexed file names are searched.
+  pub fn bench(
+    &self,
+    index_duration: Duration,
+    queries: Vec<String>,
+    iterations: usize,
+  ) -> Result<BenchReport> {
+    run_bench(&self.index, index_duration, queries, iterations)
+  }
+
+  /// Applies the operations recorded in `recording` one at a time and in order, bypassing the
+  /// batching of the queue so that the outcome is the same on every run, then runs `queries`.
+  pub fn replay(&self, recording: &Path, queries: &[String]) -> Result<ReplayReport> {
+    let mut applied = 0;
+    let mut skipped = 0;
+    for operation in read_recorded_operations(recording)? {
+      let watched_root = self.watched_roots.iter().find(|watched_root| {
+        operation
+          .paths()
+          .iter()
+          .any(|path| Path::new(path).starts_with(&watched_root.root))
+      });
+      match watched_root {
+        Some(watched_root) => {
+          watched_root
+            .index_operation
+            .apply(std::slice::from_ref(&operation))?;
+          applied += 1;
+        }
+        None => {
+          tracing::warn!("Skipping {:?} outside the watched directories", operation);
+          skipped += 1;
+        }
+      }
+    }
+    let results = queries
+      .iter()
+      .map(|query| {
+        let hits = self
+          .index
+          .search_hits(query, EXPORT_LIMIT, &SearchScope::default())?;
+        Ok(ReplayResults {
+          query: query.clone(),
+          file_paths: hits.into_iter().map(|hit| hit.file_path).collect(),
+        })
+      })
+      .collect::<Result<Vec<_>>>()?;
+    Ok(ReplayReport {
+      applied,
+      skipped,
+      results,
     })
   }
 
   pub async fn run(&self) -> Result<QuitReason> {
-    let service = SearchServer::new(self.index.clone(), self.file_loader.clone())
-      .serve(stdio())
+    let service = SearchServer::new(
+      self.index.clone(),
+      self.file_loader.clone(),
+      self.skipped_files.clone(),
+      self.event_log.clone(),
+    )
+    .with_semantic_search(self.semantic_search.clone())
+    .with_directory_search(self.directory_search.clone())
+    .with_file_writer(self.file_writer.clone())
+    .with_shadow_copies(self.shadow_copies.clone())
+    .with_version_history(self.version_history.clone())
+    .with_bookmarks(self.bookmarks.clone())
+    .with_result_sets(self.result_sets.clone())
+    .with_export_dir(self.export_dir.clone())
+    .with_todo_markers(self.todo_markers.clone())
+    .with_query_stats(self.query_stats.clone())
+    .with_audit_log(self.audit_log.clone())
+    .with_request_limiter(self.request_limiter.clone())
+    .with_privacy_mode(self.privacy_mode)
+    .with_query_templates(self.query_templates.clone())
+    .with_query_syntax(self.query_syntax.clone())
+    .serve(stdio())
+    .await
+    .inspect_err(|e| {
+      tracing::error!("serving error: {:?}", e);
+    })?;
+    /* The initial indexing is done by now */
+    if let Err(e) = systemd::notify("READY=1") {
+      tracing::warn!("Failed to notify systemd of readiness: {}", e);
+    }
+    let watchdog = systemd::watchdog_interval().map(|interval| {
+      let index_operations = self
+        .watched_roots
+        .iter()
+        .map(|root| root.index_operation.clone())
+        .collect();
+      tokio::spawn(watchdog(index_operations, interval))
+    });
+    /* Dropping the service on a signal stops it, and dropping the application flushes the index */
+    let quit_reason = tokio::select! {
+      quit_reason = service.waiting() => quit_reason.map_err(|e| e.into()),
+      signal = shutdown_signal() => {
+        tracing::info!("Received {}, shutting down", signal?);
+        Ok(QuitReason::Cancelled)
+      }
+    };
+    if let Some(watchdog) = watchdog {
+      watchdog.abort();
+    }
+    if let Err(e) = systemd::notify("STOPPING=1") {
+      tracing::warn!("Failed to notify systemd of the shutdown: {}", e);
+    }
+    quit_reason
+  }
+}
+
+/// Pings the systemd watchdog from the event loop as long as no index worker is stuck on a batch
+/// for longer than the watchdog timeout, so that systemd restarts a hung server.
+async fn watchdog(index_operations: Vec<Arc<IndexOperation>>, interval: Duration) {
+  let mut ticks = tokio::time::interval(interval);
+  loop {
+    ticks.tick().await;
+    match index_operations
+      .iter()
+      .map(|index_operation| index_operation.busy_for())
+      .find(|busy_for| *busy_for > interval * 2)
+    {
+      Some(busy_for) => tracing::error!(
+        "Index worker stuck for {:?}, withholding the watchdog ping",
+        busy_for
+      ),
+      None => {
+        if let Err(e) = systemd::notify("WATCHDOG=1") {
+          tracing::warn!("Failed to ping the systemd watchdog: {}", e);
+        }
+      }
+    }
+  }
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() -> Result<&'static str> {
+  use tokio::signal::unix::{SignalKind, signal};
+  let mut terminate = signal(SignalKind::terminate())?;
+  let mut hangup = signal(SignalKind::hangup())?;
+  Ok(tokio::select! {
+    result = tokio::signal::ctrl_c() => result.map(|()| "SIGINT")?,
+    _ = terminate.recv() => "SIGTERM",
+    _ = hangup.recv() => "SIGHUP",
+  })
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() -> Result<&'static str> {
+  tokio::signal::ctrl_c().await?;
+  Ok("Ctrl-C")
+}
+
+fn open_text_index(
+  root: &Path,
+  project: &str,
+  options: ShardOptions,
+) -> Result<Arc<Mutex<dyn IndexBackend>>> {
+  let ShardOptions {
+    backend,
+    index_dir,
+    min_free_disk_space,
+    semantic_search,
+    shadow_copies,
+    git_metadata,
+    extra_fields,
+    warm_up,
+    writer_heap,
+  } = options;
+  let shard_name = shard_directory_name(root);
+  let git_repository = git_metadata
+    .then(|| GitRepository::discover(root))
+    .flatten();
+  let semantic_index = semantic_search.map(|semantic_search| {
+    let embedder = semantic_search.embedder();
+    let semantic_index = Arc::new(Mutex::new(match index_dir {
+      Some(index_dir) => SemanticIndex::new_with_file(
+        embedder,
+        index_dir.join(format!("{}.vectors.json", shard_name)),
+      ),
+      None => SemanticIndex::new(embedder),
+    }));
+    semantic_search.add_shard(&root.to_string_lossy(), semantic_index.clone());
+    semantic_index
+  });
+  let guarded_dir = index_dir.map(Path::to_path_buf);
+  Ok(match backend {
+    Backend::Tantivy => shared_backend(
+      DiskSpaceGuardBackend::new(
+        TextIndex::new_with_writer_heap(
+          index_dir
+            .map(|index_dir| index_dir.join(&shard_name))
+            .as_deref(),
+          writer_heap,
+        )?
+        .with_warm_up(warm_up),
+        guarded_dir,
+        min_free_disk_space,
+      ),
+      root,
+      project,
+      semantic_index,
+      shadow_copies,
+      git_repository,
+      extra_fields,
+    ),
+    #[cfg(feature = "sqlite")]
+    Backend::Sqlite => shared_backend(
+      DiskSpaceGuardBackend::new(
+        match index_dir {
+          Some(index_dir) => {
+            SqliteIndex::new_with_file(index_dir.join(format!("{}.sqlite", shard_name)))?
+          }
+          None => SqliteIndex::new()?,
+        },
+        guarded_dir,
+        min_free_disk_space,
+      ),
+      root,
+      project,
+      semantic_index,
+      shadow_copies,
+      git_repository,
+      extra_fields,
+    ),
+  })
+}
+
+fn shared_backend<B: IndexBackend + 'static>(
+  backend: B,
+  root: &Path,
+  project: &str,
+  semantic_index: Option<Arc<Mutex<SemanticIndex>>>,
+  shadow_copies: Option<&Arc<ShadowCopies>>,
+  git_repository: Option<GitRepository>,
+  extra_fields: &[ExtraField],
+) -> Arc<Mutex<dyn IndexBackend>> {
+  let backend = ExtraFieldBackend::new(
+    GitMetadataBackend::new(backend, git_repository),
+    extra_fields,
+  );
+  match shadow_copies {
+    Some(shadow_copies) => layered_backend(
+      ShadowCopyBackend::new(backend, &root.to_string_lossy(), shadow_copies.clone()),
+      project,
+      semantic_index,
+    ),
+    None => layered_backend(backend, project, semantic_index),
+  }
+}
+
+fn layered_backend<B: IndexBackend + 'static>(
+  backend: B,
+  project: &str,
+  semantic_index: Option<Arc<Mutex<SemanticIndex>>>,
+) -> Arc<Mutex<dyn IndexBackend>> {
+  match semantic_index {
+    Some(semantic_index) => Arc::new(Mutex::new(ProjectIndexBackend::new(
+      LanguageIndexBackend::new(SemanticIndexBackend::new(backend, semantic_index)),
+      project,
+    ))),
+    None => Arc::new(Mutex::new(ProjectIndexBackend::new(
+      LanguageIndexBackend::new(backend),
+      project,
+    ))),
+  }
+}
+
+fn watch_root(
+  watch_dir: &Path,
+  text_index: Arc<Mutex<dyn IndexBackend>>,
+  file_filter: Arc<dyn FileFilter + Send + Sync>,
+  file_loader: Arc<dyn FileLoader + Send + Sync>,
+  event_filter: Arc<EventFilter>,
+  options: &WatchOptions,
+  health: ShardHealth,
+) -> Result<WatchedRoot> {
+  let index_operation = Arc::new(IndexOperation::new_with_options(
+    text_index.clone(),
+    file_filter.clone(),
+    file_loader.clone(),
+    IndexOperationOptions {
+      created_grace_delay: options.created_grace_delay,
+      error_policy: options.error_policy,
+      commit_status: health.commit_status,
+      nice: options.index_nice,
+      max_batch_operations: options.max_batch_operations,
+      queued_operations: health.queued_operations,
+    },
+  )?);
+  index_operation.initialize_index(
+    watch_dir.to_string_lossy().as_ref(),
+    file_filter.clone(),
+    file_loader.clone(),
+  )?;
+
+  let file_watchers = start_file_watchers(
+    watch_dir,
+    options,
+    &index_operation,
+    &event_filter,
+    &health.watcher_restarts,
+  )?;
+  let periodic_rescan = options
+    .rescan_interval
+    .map(|interval| {
+      PeriodicRescan::start(
+        watch_dir.to_string_lossy().as_ref(),
+        interval,
+        RescanTarget {
+          text_index,
+          file_filter,
+          file_loader,
+          exclusion: options.exclusion.clone(),
+        },
+        watched_handler(&index_operation, options),
+      )
+    })
+    .transpose()?;
+
+  Ok(WatchedRoot {
+    root: watch_dir.to_string_lossy().to_string(),
+    index_operation,
+    _file_watchers: file_watchers,
+    _periodic_rescan: periodic_rescan,
+    _url_refresh: None,
+  })
+}
+
+fn watch_url_source(
+  source: &str,
+  text_index: Arc<Mutex<dyn IndexBackend>>,
+  file_loader: Arc<HttpFileLoader>,
+  refresh_interval: Option<Duration>,
+  index_options: IndexOperationOptions,
+  event_log: &Arc<EventLog>,
+) -> Result<WatchedRoot> {
+  let file_filter: Arc<dyn FileFilter + Send + Sync> = Arc::new(UrlFileFilter);
+  let index_operation = Arc::new(IndexOperation::new_with_options(
+    text_index.clone(),
+    file_filter.clone(),
+    file_loader.clone(),
+    index_options,
+  )?);
+  index_operation.initialize_index(source, file_filter, file_loader.clone())?;
+
+  let url_refresh = refresh_interval
+    .map(|interval| {
+      UrlRefresh::start(
+        source,
+        interval,
+        text_index,
+        file_loader,
+        queue_handler(&index_operation, event_log),
+      )
+    })
+    .transpose()?;
+
+  Ok(WatchedRoot {
+    root: source.to_string(),
+    index_operation,
+    _file_watchers: Vec::new(),
+    _periodic_rescan: None,
+    _url_refresh: url_refresh,
+  })
+}
+
+fn queue_handler(
+  index_operation: &Arc<IndexOperation>,
+  event_log: &Arc<EventLog>,
+) -> Box<FileOperationHandler> {
+  let index_operation = index_operation.clone();
+  let event_log = event_log.clone();
+  Box::new(move |op| {
+    event_log.record_queued(op, false);
+    index_operation.enqueue(op)
+  })
+}
+
+/// Queues changes found in a watched directory, snapshotting the changed files first when version
+/// history is enabled.
+fn watched_handler(
+  index_operation: &Arc<IndexOperation>,
+  options: &WatchOptions,
+) -> Box<FileOperationHandler> {
+  let handler = queue_handler(index_operation, &options.event_log);
+  match options.version_history.clone() {
+    Some(version_history) => Box::new(move |op| {
+      version_history.snapshot_operation(op);
+      handler(op)
+    }),
+    None => handler,
+  }
+}
+
+/// Queues operations ahead of the file changes waiting to be indexed, for searches to refresh the
+/// paths they look at.
+fn prioritized_queue_handler(
+  index_operation: &Arc<IndexOperation>,
+  event_log: &Arc<EventLog>,
+) -> Box<FileOperationHandler> {
+  let index_operation = index_operation.clone();
+  let event_log = event_log.clone();
+  Box::new(move |op| {
+    event_log.record_queued(op, true);
+    index_operation.enqueue_prioritized(op)
+  })
+}
+
+fn start_file_watchers(
+  watch_dir: &Path,
+  options: &WatchOptions,
+  index_operation: &Arc<IndexOperation>,
+  event_filter: &Arc<EventFilter>,
+  watcher_restarts: &Arc<AtomicU64>,
+) -> Result<Vec<Box<dyn FileWatcher + Send>>> {
+  let path = watch_dir.to_string_lossy();
+  let handler = || watched_handler(index_operation, options);
+  let start_polling = |path: &str| -> Result<Box<dyn FileWatcher + Send>> {
+    let mut file_watcher = PollFileWatcher::new(options.poll_interval, options.exclusion.clone())
+      .with_event_filter(event_filter.clone())
+      .with_event_log(options.event_log.clone());
+    file_watcher.watch_directory(path, handler())?;
+    Ok(Box::new(file_watcher))
+  };
+
+  let notify_watcher = || {
+    NotifyFileWatcher::new()
+      .with_restart_counter(watcher_restarts.clone())
+      .with_event_filter(event_filter.clone())
+      .with_event_log(options.event_log.clone())
+  };
+
+  if options.watch_backend == WatchBackend::Poll {
+    return Ok(vec![start_polling(&path)?]);
+  }
+  let mut file_watcher = notify_watcher();
+  let error = match file_watcher.watch_directory(&path, handler()) {
+    Ok(()) => return Ok(vec![Box::new(file_watcher)]),
+    Err(e) => e,
+  };
+  if is_watch_limit_error(&error) {
+    tracing::error!("Cannot watch all of {}: {}", path, WATCH_LIMIT_GUIDANCE);
+  }
+  if options.watch_backend == WatchBackend::Notify {
+    return Err(error);
+  }
+
+  if is_watch_limit_error(&error) {
+    /* Keep native events where watches are still available and poll only the rest */
+    let mut file_watcher = notify_watcher();
+    if let Ok(unwatched) = file_watcher.watch_directory_partially(&path, handler()) {
+      let mut file_watchers: Vec<Box<dyn FileWatcher + Send>> = vec![Box::new(file_watcher)];
+      for subdirectory in unwatched {
+        tracing::warn!("Polling {:?} for changes", subdirectory);
+        file_watchers.push(start_polling(subdirectory.to_string_lossy().as_ref())?);
+      }
+      return Ok(file_watchers);
+    }
+  }
+  tracing::warn!(
+    "Native file watching is unavailable for {}, polling instead: {}",
+    path,
+    error
+  );
+  Ok(vec![start_polling(&path)?])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{MockFileFilter, MockFileLoader, TestClient, in_memory_index};
+
+  #[tokio::test]
+  async fn file_watchers_should_log_events_for_recent_events() {
+    for watch_backend in [WatchBackend::Notify, WatchBackend::Poll] {
+      let dir = std::env::temp_dir().canonicalize().unwrap().join(format!(
+        "file-watchers-event-log-test-{:?}-{}",
+        watch_backend,
+        std::process::id()
+      ));
+      std::fs::create_dir_all(&dir).unwrap();
+      let event_log = Arc::new(EventLog::default());
+      let options = WatchOptions {
+        created_grace_delay: Duration::ZERO,
+        error_policy: ErrorPolicy::default(),
+        index_nice: false,
+        max_batch_operations: None,
+        watch_backend,
+        poll_interval: Duration::from_millis(100),
+        rescan_interval: None,
+        exclusion: PathExclusion::none(),
+        event_log: event_log.clone(),
+        version_history: None,
+      };
+      let file_filter = Arc::new(MockFileFilter::default());
+      let file_loader = Arc::new(MockFileLoader::new(Vec::new(), "content".to_string()));
+      let index_operation = Arc::new(
+        IndexOperation::new(
+          Arc::new(Mutex::new(TextIndex::new().unwrap())),
+          file_filter.clone(),
+          file_loader.clone(),
+        )
+        .unwrap(),
+      );
+      let event_filter = Arc::new(EventFilter::new(
+        Arc::new(ExcludedPathFileFilter::new(
+          file_filter,
+          &dir,
+          PathExclusion::none(),
+        )),
+        file_loader.clone(),
+      ));
+      let file_watchers = start_file_watchers(
+        &dir,
+        &options,
+        &index_operation,
+        &event_filter,
+        &Arc::new(AtomicU64::new(0)),
+      )
+      .unwrap();
+      let client = TestClient::connect(SearchServer::new(
+        in_memory_index(&dir.to_string_lossy(), Vec::new()).unwrap(),
+        file_loader,
+        Arc::new(SkippedFiles::new()),
+        event_log,
+      ))
       .await
-      .inspect_err(|e| {
-        tracing::error!("serving error: {:?}", e);
-      })?;
-    service.waiting().await.map_err(|e| e.into())
+      .unwrap();
+
+      std::fs::write(dir.join("indexed.txt"), "indexed").unwrap();
+      std::fs::write(dir.join("ignored.md"), "ignored").unwrap();
+      let mut stages = Vec::new();
+      for _ in 0..50 {
+        let events: serde_json::Value = serde_json::from_str(
+          &client
+            .call_tool(
+              "recent_events",
+              serde_json::json!({ "path_contains": dir.to_string_lossy() }),
+            )
+            .await
+            .unwrap(),
+        )
+        .unwrap();
+        stages = events
+          .as_array()
+          .unwrap()
+          .iter()
+          .map(|event| event["stage"].as_str().unwrap().to_string())
+          .collect::<Vec<_>>();
+        if ["raw", "filtered", "queued"]
+          .iter()
+          .all(|stage| stages.contains(&stage.to_string()))
+        {
+          break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+      }
+      for stage in ["raw", "filtered", "queued"] {
+        assert!(
+          stages.contains(&stage.to_string()),
+          "{:?} logged no {} events: {:?}",
+          watch_backend,
+          stage,
+          stages
+        );
+      }
+
+      drop(file_watchers);
+      std::fs::remove_dir_all(&dir).unwrap();
+    }
   }
 }
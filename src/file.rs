@@ -1,4 +1,18 @@
+pub mod archive;
+pub mod chunk;
+pub mod extractor;
 pub mod file_filter;
+pub mod file_info;
 pub mod file_watcher;
+pub mod file_writer;
+pub mod http_file_loader;
+pub mod ignore_file;
 pub mod lazy_file_loader;
+pub mod outline;
+pub mod path_normalizer;
+pub mod periodic_rescan;
+pub mod poll_file_watcher;
 pub mod read_file;
+pub mod throttle;
+pub mod url_refresh;
+pub mod version_history;
@@ -0,0 +1,103 @@
+use anyhow::Result;
+use flate2::read::GzDecoder;
+use std::{fs, io::Read, path::Path};
+use zip::ZipArchive;
+
+enum ArchiveKind {
+  Zip,
+  Tar,
+  TarGz,
+}
+
+fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+  let name = path.file_name()?.to_string_lossy().to_lowercase();
+  if name.ends_with(".zip") {
+    Some(ArchiveKind::Zip)
+  } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+    Some(ArchiveKind::TarGz)
+  } else if name.ends_with(".tar") {
+    Some(ArchiveKind::Tar)
+  } else {
+    None
+  }
+}
+
+pub fn is_archive_path(path: &Path) -> bool {
+  archive_kind(path).is_some()
+}
+
+/// Reads every UTF-8 member of the archive as `(member name, content)`, skipping binary members
+/// and members larger than `max_member_size`.
+pub fn read_archive_members(
+  path: &Path,
+  max_member_size: Option<u64>,
+) -> Result<Vec<(String, String)>> {
+  let is_small_enough = |size: u64| max_member_size.is_none_or(|max| size <= max);
+  let mut members = Vec::new();
+  match archive_kind(path) {
+    Some(ArchiveKind::Zip) => {
+      let mut archive = ZipArchive::new(fs::File::open(path)?)?;
+      for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if !entry.is_file() || !is_small_enough(entry.size()) {
+          continue;
+        }
+        let name = entry.name().to_string();
+        if let Some(content) = read_text(&mut entry) {
+          members.push((name, content));
+        }
+      }
+    }
+    Some(ArchiveKind::Tar) => {
+      read_tar_members(fs::File::open(path)?, &is_small_enough, &mut members)?;
+    }
+    Some(ArchiveKind::TarGz) => {
+      read_tar_members(
+        GzDecoder::new(fs::File::open(path)?),
+        &is_small_enough,
+        &mut members,
+      )?;
+    }
+    None => return Err(anyhow::anyhow!("Not a supported archive: {:?}", path)),
+  }
+  Ok(members)
+}
+
+pub fn read_archive_member(path: &Path, member: &str) -> Result<String> {
+  if let Some(ArchiveKind::Zip) = archive_kind(path) {
+    let mut archive = ZipArchive::new(fs::File::open(path)?)?;
+    let mut entry = archive.by_name(member)?;
+    let mut content = String::new();
+    entry.read_to_string(&mut content)?;
+    return Ok(content);
+  }
+  read_archive_members(path, None)?
+    .into_iter()
+    .find(|(name, _)| name == member)
+    .map(|(_, content)| content)
+    .ok_or_else(|| anyhow::anyhow!("Member {} not found in archive {:?}", member, path))
+}
+
+fn read_tar_members(
+  reader: impl Read,
+  is_small_enough: &impl Fn(u64) -> bool,
+  members: &mut Vec<(String, String)>,
+) -> Result<()> {
+  let mut archive = tar::Archive::new(reader);
+  for entry in archive.entries()? {
+    let mut entry = entry?;
+    if !entry.header().entry_type().is_file() || !is_small_enough(entry.size()) {
+      continue;
+    }
+    let name = entry.path()?.to_string_lossy().to_string();
+    if let Some(content) = read_text(&mut entry) {
+      members.push((name, content));
+    }
+  }
+  Ok(())
+}
+
+fn read_text(reader: &mut impl Read) -> Option<String> {
+  let mut content = String::new();
+  reader.read_to_string(&mut content).ok().map(|_| content)
+}
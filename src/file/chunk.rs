@@ -0,0 +1,225 @@
+use anyhow::Result;
+use std::io::{BufRead, Read};
+
+use crate::search::file::{CHUNK_START_LINE_KEY, File, FileSection, chunk_path};
+
+/// Bytes repeated at the start of each chunk so matches spanning a boundary are still found.
+pub const CHUNK_OVERLAP: usize = 4 * 1024;
+
+fn floor_char_boundary(content: &str, mut index: usize) -> usize {
+  while !content.is_char_boundary(index) {
+    index -= 1;
+  }
+  index
+}
+
+fn line_break_end(chunk: &[u8], chunk_size: usize) -> Option<usize> {
+  /* Prefer to end a chunk after a line break so snippets are not cut in half */
+  chunk
+    .iter()
+    .rposition(|b| *b == b'\n')
+    .map(|newline| newline + 1)
+    .filter(|end| *end > CHUNK_OVERLAP.min(chunk_size / 2))
+}
+
+fn chunk_end(content: &str, start: usize, chunk_size: usize) -> usize {
+  if start + chunk_size >= content.len() {
+    return content.len();
+  }
+  let end = floor_char_boundary(content, start + chunk_size);
+  line_break_end(&content.as_bytes()[start..end], chunk_size)
+    .map(|end| start + end)
+    .unwrap_or(end)
+}
+
+/// Splits a file whose content exceeds `chunk_size` bytes into overlapping `path#chunk=<offset>`
+/// documents; smaller files are returned as they are.
+pub fn split_into_chunks(file: File, chunk_size: usize) -> Vec<File> {
+  if chunk_size == 0 || file.content.len() <= chunk_size {
+    return vec![file];
+  }
+  let overlap = CHUNK_OVERLAP.min(chunk_size / 4);
+  let content = file.content.as_str();
+
+  let mut chunks = Vec::new();
+  let mut start = 0;
+  let mut start_line = 1;
+  loop {
+    let end = chunk_end(content, start, chunk_size);
+    let sections = file
+      .sections
+      .iter()
+      .filter(|section| section.offset < end)
+      .fold(Vec::<FileSection>::new(), |mut sections, section| {
+        /* The section the chunk starts in is kept at offset 0 */
+        let offset = section.offset.saturating_sub(start);
+        if offset == 0 {
+          sections.clear();
+        }
+        sections.push(FileSection {
+          offset,
+          title: section.title.clone(),
+        });
+        sections
+      });
+    let mut chunk = File::new(
+      chunk_path(&file.path, start),
+      content[start..end].to_string(),
+    )
+    .with_sections(sections)
+    .with_metadata(CHUNK_START_LINE_KEY, &start_line.to_string());
+    for (key, value) in &file.metadata {
+      chunk = chunk.with_metadata(key, value);
+    }
+    chunks.push(chunk);
+
+    if end == content.len() {
+      break;
+    }
+    let next_start = (end - overlap..=end)
+      .find(|index| content.is_char_boundary(*index))
+      .unwrap_or(end);
+    start_line += content[start..next_start].matches('\n').count();
+    start = next_start;
+  }
+  chunks
+}
+
+/// Reads text chunk by chunk with the same boundaries as `split_into_chunks`, so only about
+/// `chunk_size` bytes are held in memory at once.
+pub struct ChunkReader<R> {
+  reader: R,
+  path: String,
+  chunk_size: usize,
+  offset: usize,
+  start_line: usize,
+  buffer: Vec<u8>,
+  finished: bool,
+}
+
+impl<R: BufRead> ChunkReader<R> {
+  pub fn new(reader: R, path: String, chunk_size: usize) -> Self {
+    Self {
+      reader,
+      path,
+      /* A chunk must be able to hold at least one UTF-8 character */
+      chunk_size: chunk_size.max(4),
+      offset: 0,
+      start_line: 1,
+      buffer: Vec::new(),
+      finished: false,
+    }
+  }
+
+  fn fill_buffer(&mut self) -> Result<bool> {
+    while self.buffer.len() < self.chunk_size {
+      let limit = (self.chunk_size - self.buffer.len()) as u64;
+      if (&mut self.reader)
+        .take(limit)
+        .read_until(b'\n', &mut self.buffer)?
+        == 0
+      {
+        return Ok(true);
+      }
+    }
+    Ok(self.reader.fill_buf()?.is_empty())
+  }
+
+  fn next_chunk(&mut self) -> Result<Option<File>> {
+    if self.finished {
+      return Ok(None);
+    }
+    let at_eof = self.fill_buffer()?;
+    let end = if at_eof {
+      self.buffer.len()
+    } else {
+      line_break_end(&self.buffer, self.chunk_size).unwrap_or(self.buffer.len())
+    };
+    let end = match std::str::from_utf8(&self.buffer[..end]) {
+      Ok(_) => end,
+      Err(e) if e.error_len().is_none() && !at_eof => e.valid_up_to(),
+      Err(e) => return Err(anyhow::anyhow!("{:?} is not valid UTF-8: {}", self.path, e)),
+    };
+    let content = String::from_utf8(self.buffer[..end].to_vec())?;
+    self.finished = at_eof && end == self.buffer.len();
+
+    if self.offset == 0 && self.finished {
+      return Ok(Some(File::new(self.path.clone(), content)));
+    }
+    let chunk = File::new(chunk_path(&self.path, self.offset), content.clone())
+      .with_metadata(CHUNK_START_LINE_KEY, &self.start_line.to_string());
+    if !self.finished {
+      let overlap = CHUNK_OVERLAP.min(self.chunk_size / 4);
+      let next_start = (end - overlap..=end)
+        .find(|index| content.is_char_boundary(*index))
+        .unwrap_or(end);
+      self.start_line += content[..next_start].matches('\n').count();
+      self.offset += next_start;
+      self.buffer.drain(..next_start);
+    }
+    Ok(Some(chunk))
+  }
+}
+
+impl<R: BufRead> Iterator for ChunkReader<R> {
+  type Item = Result<File>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.next_chunk().transpose()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::search::file::parse_chunk_path;
+
+  #[test]
+  fn split_into_chunks_should_cover_whole_content_with_overlap() {
+    let content = (0..2000)
+      .map(|i| format!("line {}\n", i))
+      .collect::<String>();
+    let file = File::new("/logs/app.log".to_string(), content.clone());
+    let chunks = split_into_chunks(file, 4096);
+
+    assert!(chunks.len() > 1);
+    assert_eq!(chunks[0].path, "/logs/app.log#chunk=0");
+    assert_eq!(chunks[0].metadata[CHUNK_START_LINE_KEY], "1");
+    for chunk in &chunks {
+      let (_, offset) = parse_chunk_path(&chunk.path).unwrap();
+      assert!(content[offset..].starts_with(&chunk.content));
+      let start_line = chunk.metadata[CHUNK_START_LINE_KEY]
+        .parse::<usize>()
+        .unwrap();
+      assert_eq!(content[..offset].matches('\n').count() + 1, start_line);
+    }
+    assert!(chunks.last().unwrap().content.ends_with("line 1999\n"));
+  }
+
+  #[test]
+  fn chunk_reader_should_produce_same_chunks_as_split_into_chunks() {
+    let content = (0..2000)
+      .map(|i| format!("línea {}\n", i))
+      .collect::<String>();
+    let file = File::new("/logs/app.log".to_string(), content.clone());
+    let expected = split_into_chunks(file, 4096);
+    let chunks = ChunkReader::new(content.as_bytes(), "/logs/app.log".to_string(), 4096)
+      .collect::<Result<Vec<_>>>()
+      .unwrap();
+
+    assert_eq!(chunks.len(), expected.len());
+    for (chunk, expected) in chunks.iter().zip(&expected) {
+      assert_eq!(chunk.path, expected.path);
+      assert_eq!(chunk.content, expected.content);
+      assert_eq!(chunk.metadata, expected.metadata);
+    }
+  }
+
+  #[test]
+  fn split_into_chunks_should_keep_small_files_whole() {
+    let file = File::new("/notes/a.txt".to_string(), "short".to_string());
+    let chunks = split_into_chunks(file, 4096);
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].path, "/notes/a.txt");
+  }
+}
@@ -0,0 +1,159 @@
+pub mod email;
+pub mod epub;
+pub mod markdown;
+#[cfg(feature = "ocr")]
+pub mod ocr;
+pub mod office;
+pub mod org;
+pub mod plain_text;
+pub mod rst;
+pub mod structured;
+
+use anyhow::Result;
+use std::{collections::HashMap, fs, io::Read, path::Path, sync::Arc};
+use zip::ZipArchive;
+
+use super::outline::outline;
+use crate::search::file::{ContentExtractor, FileSection};
+
+/// Extractors that turn files into indexable text, looked up by lowercase extension first, then by
+/// the MIME type guessed from the path, and finally falling back to reading the file as plain text.
+///
+/// Library users can register their own extractors to support additional formats.
+pub struct ExtractorRegistry {
+  by_extension: HashMap<String, Arc<dyn ContentExtractor + Send + Sync>>,
+  by_mime: HashMap<String, Arc<dyn ContentExtractor + Send + Sync>>,
+  fallback: Arc<dyn ContentExtractor + Send + Sync>,
+}
+
+impl ExtractorRegistry {
+  pub fn new() -> Self {
+    Self {
+      by_extension: HashMap::new(),
+      by_mime: HashMap::new(),
+      fallback: Arc::new(plain_text::PlainTextExtractor),
+    }
+  }
+
+  pub fn new_with_default_extractors() -> Self {
+    let mut registry = Self::new();
+    registry.register("docx", Arc::new(office::DocxExtractor));
+    registry.register("pptx", Arc::new(office::PptxExtractor));
+    registry.register("odt", Arc::new(office::OpenDocumentExtractor));
+    registry.register("odp", Arc::new(office::OpenDocumentExtractor));
+    registry.register("ods", Arc::new(office::OpenDocumentExtractor));
+    registry.register("eml", Arc::new(email::EmlExtractor));
+    registry.register("epub", Arc::new(epub::EpubExtractor));
+    registry.register("md", Arc::new(markdown::MarkdownExtractor));
+    registry.register("markdown", Arc::new(markdown::MarkdownExtractor));
+    registry.register("org", Arc::new(org::OrgExtractor));
+    registry.register("rst", Arc::new(rst::RstExtractor));
+    registry.register("json", Arc::new(structured::JsonExtractor));
+    registry.register("yaml", Arc::new(structured::YamlExtractor));
+    registry.register("yml", Arc::new(structured::YamlExtractor));
+    registry.register_mime("message/rfc822", Arc::new(email::EmlExtractor));
+    registry.register_mime("application/epub+zip", Arc::new(epub::EpubExtractor));
+    registry.register_mime("application/json", Arc::new(structured::JsonExtractor));
+    registry
+  }
+
+  #[cfg(feature = "ocr")]
+  pub fn register_ocr(&mut self, language: &str) {
+    let extractor = Arc::new(ocr::OcrExtractor::new(language));
+    for extension in ocr::OCR_EXTENSIONS {
+      self.register(extension, extractor.clone());
+    }
+  }
+
+  pub fn register(&mut self, extension: &str, extractor: Arc<dyn ContentExtractor + Send + Sync>) {
+    self
+      .by_extension
+      .insert(extension.to_lowercase(), extractor);
+  }
+
+  pub fn register_mime(&mut self, mime: &str, extractor: Arc<dyn ContentExtractor + Send + Sync>) {
+    self.by_mime.insert(mime.to_lowercase(), extractor);
+  }
+
+  pub fn set_fallback(&mut self, extractor: Arc<dyn ContentExtractor + Send + Sync>) {
+    self.fallback = extractor;
+  }
+
+  pub fn get(&self, path: &Path) -> &Arc<dyn ContentExtractor + Send + Sync> {
+    let by_extension = path
+      .extension()
+      .and_then(|ext| ext.to_str())
+      .and_then(|ext| self.by_extension.get(&ext.to_lowercase()));
+    let by_mime = || {
+      mime_guess::from_path(path)
+        .iter_raw()
+        .find_map(|mime| self.by_mime.get(mime))
+    };
+    by_extension.or_else(by_mime).unwrap_or(&self.fallback)
+  }
+}
+
+impl Default for ExtractorRegistry {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+pub(crate) fn read_zip_entry(archive: &mut ZipArchive<fs::File>, name: &str) -> Result<String> {
+  let mut entry = archive.by_name(name)?;
+  let mut content = String::new();
+  entry.read_to_string(&mut content)?;
+  Ok(content)
+}
+
+/// Sections starting at each heading `outline` finds in the extracted text.
+pub(crate) fn heading_sections(path: &str, content: &str) -> Vec<FileSection> {
+  let line_starts = std::iter::once(0)
+    .chain(content.match_indices('\n').map(|(offset, _)| offset + 1))
+    .collect::<Vec<_>>();
+  outline(path, content)
+    .unwrap_or_default()
+    .into_iter()
+    .map(|heading| FileSection {
+      offset: line_starts[heading.line_number - 1],
+      title: heading.title,
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::search::file::File;
+
+  struct NamedExtractor(&'static str);
+
+  impl ContentExtractor for NamedExtractor {
+    fn extract(&self, path: &Path) -> Result<File> {
+      Ok(File::new(
+        path.to_string_lossy().to_string(),
+        self.0.to_string(),
+      ))
+    }
+  }
+
+  fn extracted_by(registry: &ExtractorRegistry, path: &str) -> String {
+    registry
+      .get(Path::new(path))
+      .extract(Path::new(path))
+      .unwrap()
+      .content
+  }
+
+  #[test]
+  fn extractor_registry_should_prefer_extension_then_mime_then_fallback() {
+    let mut registry = ExtractorRegistry::new();
+    registry.register("custom", Arc::new(NamedExtractor("extension")));
+    registry.register_mime("text/html", Arc::new(NamedExtractor("mime")));
+    registry.set_fallback(Arc::new(NamedExtractor("fallback")));
+
+    assert_eq!(extracted_by(&registry, "a.CUSTOM"), "extension");
+    assert_eq!(extracted_by(&registry, "a.html"), "mime");
+    assert_eq!(extracted_by(&registry, "a.txt"), "fallback");
+  }
+}
@@ -0,0 +1,141 @@
+use anyhow::Result;
+use mail_parser::MessageParser;
+use std::{fs, path::Path};
+
+use crate::search::file::{CONTAINER_MEMBER_SEPARATOR, ContentExtractor, File};
+
+pub struct EmlExtractor;
+
+impl ContentExtractor for EmlExtractor {
+  fn extract(&self, path: &Path) -> Result<File> {
+    let raw = fs::read(path)?;
+    parse_message(path.to_string_lossy().to_string(), &raw)
+      .ok_or_else(|| anyhow::anyhow!("Failed to parse email message: {:?}", path))
+  }
+}
+
+pub fn is_mailbox_path(path: &Path) -> bool {
+  path
+    .extension()
+    .and_then(|ext| ext.to_str())
+    .is_some_and(|ext| ext.eq_ignore_ascii_case("mbox"))
+}
+
+/// Parses every message of an mbox file into a `mailbox.mbox!/<n>` document.
+pub fn read_mailbox_messages(path: &Path, max_message_size: Option<u64>) -> Result<Vec<File>> {
+  let raw = fs::read(path)?;
+  Ok(
+    split_mailbox(&raw)
+      .into_iter()
+      .enumerate()
+      .filter(|(_, message)| max_message_size.is_none_or(|max| message.len() as u64 <= max))
+      .filter_map(|(i, message)| {
+        parse_message(
+          format!(
+            "{}{}{}",
+            path.to_string_lossy(),
+            CONTAINER_MEMBER_SEPARATOR,
+            i
+          ),
+          message,
+        )
+      })
+      .collect(),
+  )
+}
+
+pub fn read_mailbox_message(path: &Path, member: &str) -> Result<File> {
+  let index = member
+    .parse::<usize>()
+    .map_err(|_| anyhow::anyhow!("Invalid mailbox message number: {}", member))?;
+  let raw = fs::read(path)?;
+  split_mailbox(&raw)
+    .get(index)
+    .and_then(|message| {
+      parse_message(
+        format!(
+          "{}{}{}",
+          path.to_string_lossy(),
+          CONTAINER_MEMBER_SEPARATOR,
+          member
+        ),
+        message,
+      )
+    })
+    .ok_or_else(|| anyhow::anyhow!("Message {} not found in mailbox {:?}", member, path))
+}
+
+fn split_mailbox(raw: &[u8]) -> Vec<&[u8]> {
+  /* Every message of an mbox file starts with a "From " separator line */
+  let mut messages = Vec::new();
+  let mut start = None;
+  let mut offset = 0;
+  for line in raw.split_inclusive(|b| *b == b'\n') {
+    if line.starts_with(b"From ") {
+      if let Some(start) = start {
+        messages.push(&raw[start..offset]);
+      }
+      start = Some(offset + line.len());
+    }
+    offset += line.len();
+  }
+  if let Some(start) = start {
+    messages.push(&raw[start..]);
+  }
+  messages
+}
+
+fn parse_message(path: String, raw: &[u8]) -> Option<File> {
+  let message = MessageParser::default().parse(raw)?;
+  let body = (0..message.text_body_count())
+    .filter_map(|i| message.body_text(i))
+    .collect::<Vec<_>>()
+    .join("\n");
+  let subject = message.subject().unwrap_or_default().to_string();
+  let from = message
+    .from()
+    .and_then(|from| from.first())
+    .map(|address| match (address.name(), address.address()) {
+      (Some(name), Some(email)) => format!("{} <{}>", name, email),
+      (None, Some(email)) => email.to_string(),
+      (Some(name), None) => name.to_string(),
+      (None, None) => String::new(),
+    })
+    .unwrap_or_default();
+  let date = message
+    .date()
+    .map(|date| date.to_rfc3339())
+    .unwrap_or_default();
+
+  let content = format!("{}\n{}", subject, body);
+  Some(
+    File::new(path, content)
+      .with_metadata("subject", &subject)
+      .with_metadata("from", &from)
+      .with_metadata("date", &date),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn split_mailbox_should_split_on_from_lines() {
+    let raw = b"From alice@example.com Mon Jan 1 00:00:00 2024\nSubject: one\n\nbody one\nFrom bob@example.com Tue Jan 2 00:00:00 2024\nSubject: two\n\nbody two\n";
+    let messages = split_mailbox(raw);
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0], b"Subject: one\n\nbody one\n");
+    assert_eq!(messages[1], b"Subject: two\n\nbody two\n");
+  }
+
+  #[test]
+  fn parse_message_should_extract_subject_from_and_date() {
+    let raw = b"From: Alice <alice@example.com>\r\nSubject: Quarterly report\r\nDate: Mon, 1 Jan 2024 10:00:00 +0000\r\n\r\nThe numbers are in.\r\n";
+    let file = parse_message("mail.eml".to_string(), raw).unwrap();
+    assert_eq!(file.metadata["subject"], "Quarterly report");
+    assert_eq!(file.metadata["from"], "Alice <alice@example.com>");
+    assert!(file.metadata["date"].starts_with("2024-01-01T10:00:00"));
+    assert!(file.content.contains("The numbers are in."));
+  }
+}
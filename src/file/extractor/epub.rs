@@ -0,0 +1,170 @@
+use anyhow::Result;
+use quick_xml::{
+  Reader,
+  events::{BytesStart, BytesText, Event},
+};
+use std::{collections::HashMap, fs, path::Path};
+use zip::ZipArchive;
+
+use super::read_zip_entry;
+use crate::search::file::{ContentExtractor, File, FileSection};
+
+pub struct EpubExtractor;
+
+impl ContentExtractor for EpubExtractor {
+  fn extract(&self, path: &Path) -> Result<File> {
+    let mut archive = ZipArchive::new(fs::File::open(path)?)?;
+    let container = read_zip_entry(&mut archive, "META-INF/container.xml")?;
+    let package_path = package_path(&container)?
+      .ok_or_else(|| anyhow::anyhow!("EPUB container has no rootfile: {:?}", path))?;
+    let package = read_zip_entry(&mut archive, &package_path)?;
+    let base_dir = package_path
+      .rsplit_once('/')
+      .map(|(dir, _)| format!("{}/", dir))
+      .unwrap_or_default();
+
+    let mut content = String::new();
+    let mut sections = Vec::new();
+    for href in spine_hrefs(&package)? {
+      let chapter = match read_zip_entry(&mut archive, &format!("{}{}", base_dir, href)) {
+        Ok(chapter) => chapter,
+        Err(e) => {
+          tracing::debug!("Skipping EPUB chapter {} in {:?}: {}", href, path, e);
+          continue;
+        }
+      };
+      let (title, text) = xhtml_to_text(&chapter);
+      sections.push(FileSection {
+        offset: content.len(),
+        title: title.unwrap_or(href),
+      });
+      content.push_str(text.trim());
+      content.push('\n');
+    }
+
+    Ok(File::new(path.to_string_lossy().to_string(), content).with_sections(sections))
+  }
+}
+
+fn attribute(element: &BytesStart, name: &[u8]) -> Result<Option<String>> {
+  Ok(match element.try_get_attribute(name)? {
+    Some(attribute) => Some(attribute.unescape_value()?.into_owned()),
+    None => None,
+  })
+}
+
+fn package_path(container: &str) -> Result<Option<String>> {
+  let mut reader = Reader::from_str(container);
+  loop {
+    match reader.read_event()? {
+      Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"rootfile" => {
+        return attribute(&e, b"full-path");
+      }
+      Event::Eof => return Ok(None),
+      _ => {}
+    }
+  }
+}
+
+fn spine_hrefs(package: &str) -> Result<Vec<String>> {
+  let mut reader = Reader::from_str(package);
+  let mut manifest = HashMap::new();
+  let mut spine = Vec::new();
+  loop {
+    match reader.read_event()? {
+      Event::Start(e) | Event::Empty(e) => match e.local_name().as_ref() {
+        b"item" => {
+          if let (Some(id), Some(href)) = (attribute(&e, b"id")?, attribute(&e, b"href")?) {
+            manifest.insert(id, href);
+          }
+        }
+        b"itemref" => {
+          if let Some(idref) = attribute(&e, b"idref")? {
+            spine.push(idref);
+          }
+        }
+        _ => {}
+      },
+      Event::Eof => break,
+      _ => {}
+    }
+  }
+  Ok(
+    spine
+      .iter()
+      .filter_map(|idref| manifest.get(idref).cloned())
+      .collect(),
+  )
+}
+
+fn decode_text(text: &BytesText) -> String {
+  /* XHTML chapters often use HTML entities such as &nbsp; that XML unescaping rejects */
+  text
+    .unescape()
+    .map(|text| text.into_owned())
+    .unwrap_or_else(|_| String::from_utf8_lossy(text).into_owned())
+}
+
+/// Returns the first heading (as the chapter title) and the plain text of an XHTML document.
+pub fn xhtml_to_text(xhtml: &str) -> (Option<String>, String) {
+  let mut reader = Reader::from_str(xhtml);
+  reader.config_mut().check_end_names = false;
+
+  let mut text = String::new();
+  let mut title = None;
+  let mut heading: Option<String> = None;
+  let mut skip_depth = 0usize;
+  loop {
+    match reader.read_event() {
+      Ok(Event::Start(e)) => match e.local_name().as_ref() {
+        b"head" | b"script" | b"style" => skip_depth += 1,
+        b"h1" | b"h2" | b"h3" | b"h4" | b"h5" | b"h6" if title.is_none() => {
+          heading = Some(String::new())
+        }
+        _ => {}
+      },
+      Ok(Event::End(e)) => match e.local_name().as_ref() {
+        b"head" | b"script" | b"style" => skip_depth = skip_depth.saturating_sub(1),
+        name @ (b"h1" | b"h2" | b"h3" | b"h4" | b"h5" | b"h6" | b"p" | b"div" | b"li" | b"tr") => {
+          if name.starts_with(b"h")
+            && let Some(heading) = heading.take().filter(|h| !h.trim().is_empty())
+          {
+            title = Some(heading.trim().to_string());
+          }
+          text.push('\n');
+        }
+        _ => {}
+      },
+      Ok(Event::Empty(e)) if e.local_name().as_ref() == b"br" => text.push('\n'),
+      Ok(Event::Text(e)) if skip_depth == 0 => {
+        let decoded = decode_text(&e);
+        if let Some(heading) = heading.as_mut() {
+          heading.push_str(&decoded);
+        }
+        text.push_str(&decoded);
+      }
+      Ok(Event::Eof) => break,
+      Err(e) => {
+        tracing::debug!("Stopped reading malformed XHTML: {}", e);
+        break;
+      }
+      _ => {}
+    }
+  }
+  (title, text)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn xhtml_to_text_should_use_first_heading_as_title() {
+    let xhtml = r#"<html><head><title>ignored</title><style>p {}</style></head><body><h1>Chapter 1</h1><p>It was a dark&nbsp;night.</p><p>Second<br/>line</p></body></html>"#;
+    let (title, text) = xhtml_to_text(xhtml);
+    assert_eq!(title.as_deref(), Some("Chapter 1"));
+    assert!(!text.contains("ignored"));
+    assert!(text.contains("Chapter 1\n"));
+    assert!(text.contains("Second\nline\n"));
+  }
+}
@@ -0,0 +1,291 @@
+use anyhow::Result;
+use serde_yaml::Value;
+use std::{
+  fs,
+  io::BufReader,
+  path::{Component, Path, PathBuf},
+};
+
+use crate::{
+  file::{chunk::ChunkReader, read_file::path_to_file},
+  search::file::{
+    ContentExtractor, File, FileChunks, LINKS_METADATA_KEY, TAGS_METADATA_KEY, wikilink_key,
+  },
+};
+
+/// Reads Markdown notes as plain text and collects their tags, both from the `tags` key of the
+/// YAML front matter and from inline `#tag` tokens, and the notes they link to.
+pub struct MarkdownExtractor;
+
+impl ContentExtractor for MarkdownExtractor {
+  fn extract(&self, path: &Path) -> Result<File> {
+    let mut file = path_to_file(path)?;
+    let tags = extract_tags(&file.content);
+    if !tags.is_empty() {
+      file = file.with_metadata(TAGS_METADATA_KEY, &tags.join(" "));
+    }
+    let links = extract_links(path, &file.content);
+    if !links.is_empty() {
+      file = file.with_metadata(LINKS_METADATA_KEY, &links.join("\n"));
+    }
+    Ok(file)
+  }
+
+  fn extract_chunks(&self, path: &Path, chunk_size: usize) -> Result<Option<FileChunks>> {
+    let reader = BufReader::new(fs::File::open(path)?);
+    Ok(Some(Box::new(ChunkReader::new(
+      reader,
+      path.to_string_lossy().to_string(),
+      chunk_size,
+    ))))
+  }
+}
+
+/// Splits off the YAML front matter delimited by `---` lines, returning it and the body.
+fn split_front_matter(content: &str) -> (Option<&str>, &str) {
+  let Some(rest) = content
+    .strip_prefix("---\n")
+    .or_else(|| content.strip_prefix("---\r\n"))
+  else {
+    return (None, content);
+  };
+  let mut offset = 0;
+  for line in rest.split_inclusive('\n') {
+    if matches!(line.trim_end(), "---" | "...") {
+      return (Some(&rest[..offset]), &rest[offset + line.len()..]);
+    }
+    offset += line.len();
+  }
+  (None, content)
+}
+
+fn front_matter_tags(front_matter: &str) -> Vec<String> {
+  let value = match serde_yaml::from_str::<Value>(front_matter) {
+    Ok(value) => value,
+    Err(e) => {
+      tracing::debug!("Ignoring invalid front matter: {}", e);
+      return Vec::new();
+    }
+  };
+  let tags = value.get("tags").or_else(|| value.get("tag"));
+  let values = match tags {
+    Some(Value::Sequence(items)) => items
+      .iter()
+      .filter_map(|item| match item {
+        Value::String(tag) => Some(tag.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+      })
+      .collect(),
+    /* A single string may list several tags, e.g. `tags: draft, ideas` */
+    Some(Value::String(tags)) => tags
+      .split([',', ' '])
+      .map(str::to_string)
+      .collect::<Vec<_>>(),
+    _ => Vec::new(),
+  };
+  values
+    .iter()
+    .map(|tag| {
+      tag
+        .trim()
+        .trim_start_matches('#')
+        .replace(char::is_whitespace, "-")
+    })
+    .filter(|tag| !tag.is_empty())
+    .collect()
+}
+
+/// Values of a key of the YAML front matter: the value itself, or each item of a list.
+pub fn front_matter_values(content: &str, key: &str) -> Vec<String> {
+  let (Some(front_matter), _) = split_front_matter(content) else {
+    return Vec::new();
+  };
+  let Ok(value) = serde_yaml::from_str::<Value>(front_matter) else {
+    return Vec::new();
+  };
+  let scalar = |value: &Value| match value {
+    Value::String(text) => Some(text.clone()),
+    Value::Number(number) => Some(number.to_string()),
+    Value::Bool(flag) => Some(flag.to_string()),
+    _ => None,
+  };
+  match value.get(key) {
+    Some(Value::Sequence(items)) => items.iter().filter_map(scalar).collect(),
+    Some(value) => scalar(value).into_iter().collect(),
+    None => Vec::new(),
+  }
+}
+
+fn is_tag_char(c: char) -> bool {
+  c.is_alphanumeric() || matches!(c, '_' | '-' | '/')
+}
+
+/// Collects `#tag` tokens outside code blocks. Headings (`# Title`), numbers (`#1`) and anchors
+/// inside words (`page#section`) are not tags.
+fn inline_tags(body: &str) -> Vec<String> {
+  let mut tags = Vec::new();
+  for line in prose_lines(body) {
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+      if c == '#' && (previous.is_whitespace() || matches!(previous, '(' | '[' | ',')) {
+        let tag = line[i + 1..]
+          .split(|c: char| !is_tag_char(c))
+          .next()
+          .unwrap_or_default()
+          .trim_end_matches('/');
+        if tag.chars().any(|c| !c.is_ascii_digit() && c != '/') {
+          tags.push(tag.to_string());
+        }
+      }
+      previous = c;
+    }
+  }
+  tags
+}
+
+pub fn extract_tags(content: &str) -> Vec<String> {
+  let (front_matter, body) = split_front_matter(content);
+  let mut tags = Vec::new();
+  let declared = front_matter.map(front_matter_tags).unwrap_or_default();
+  for tag in declared.into_iter().chain(inline_tags(body)) {
+    if !tags.contains(&tag) {
+      tags.push(tag);
+    }
+  }
+  tags
+}
+
+/// Lines outside code blocks.
+fn prose_lines(body: &str) -> impl Iterator<Item = &str> {
+  let mut in_code_block = false;
+  body.lines().filter(move |line| {
+    if line.trim_start().starts_with("```") {
+      in_code_block = !in_code_block;
+      return false;
+    }
+    !in_code_block
+  })
+}
+
+/// Decodes `%20` style escapes, which Markdown editors write for spaces in link targets.
+fn percent_decode(target: &str) -> String {
+  let bytes = target.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let escaped = (bytes[i] == b'%')
+      .then(|| target.get(i + 1..i + 3))
+      .flatten()
+      .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+    match escaped {
+      Some(byte) => {
+        decoded.push(byte);
+        i += 3;
+      }
+      None => {
+        decoded.push(bytes[i]);
+        i += 1;
+      }
+    }
+  }
+  String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Resolves a link target against the directory of the note, without touching the file system.
+fn resolve_link(path: &Path, target: &str) -> String {
+  let joined = path.parent().unwrap_or(Path::new("")).join(target);
+  let mut resolved = PathBuf::new();
+  for component in joined.components() {
+    match component {
+      Component::CurDir => {}
+      Component::ParentDir => {
+        resolved.pop();
+      }
+      component => resolved.push(component),
+    }
+  }
+  resolved.to_string_lossy().to_string()
+}
+
+/// Target of a `[text](target "title")` link if it points at a local file.
+fn local_link_target(target: &str) -> Option<String> {
+  let target = target.trim();
+  let target = match target.strip_prefix('<') {
+    Some(rest) => rest.split('>').next()?,
+    None => target.split_whitespace().next()?,
+  };
+  let target = target.split(['#', '?']).next()?;
+  let is_external = target.contains("://") || target.starts_with("mailto:");
+  (!target.is_empty() && !is_external).then(|| percent_decode(target))
+}
+
+/// Collects `[[wikilinks]]` as `wikilink_key`s and relative `[text](file.md)` links as paths
+/// resolved against the note, skipping external links, anchors and code blocks.
+pub fn extract_links(path: &Path, content: &str) -> Vec<String> {
+  let (_, body) = split_front_matter(content);
+  let mut links = Vec::new();
+  for line in prose_lines(body) {
+    let mut rest = line;
+    while let Some(start) = rest.find("[[") {
+      let Some(end) = rest[start..].find("]]") else {
+        break;
+      };
+      /* `[[Note#Heading|alias]]` links to Note */
+      let name = rest[start + 2..start + end]
+        .split(['|', '#'])
+        .next()
+        .unwrap_or_default();
+      if !name.trim().is_empty() {
+        links.push(wikilink_key(name));
+      }
+      rest = &rest[start + end + 2..];
+    }
+    let mut rest = line;
+    while let Some(start) = rest.find("](") {
+      let Some(end) = rest[start..].find(')') else {
+        break;
+      };
+      if let Some(target) = local_link_target(&rest[start + 2..start + end]) {
+        links.push(resolve_link(path, &target));
+      }
+      rest = &rest[start + end + 1..];
+    }
+  }
+  let mut unique = Vec::new();
+  for link in links {
+    if !unique.contains(&link) {
+      unique.push(link);
+    }
+  }
+  unique
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn extract_tags_should_read_front_matter_and_inline_tags() {
+    let content = "---\ntitle: Trip\ntags: [travel, project/alpha]\n---\n# Heading\nBooked #flights and #travel.\nSee page#anchor and issue #42.\n```\n#include <stdio.h>\n```\n";
+    assert_eq!(
+      extract_tags(content),
+      vec!["travel", "project/alpha", "flights"]
+    );
+  }
+
+  #[test]
+  fn extract_links_should_collect_wikilinks_and_relative_links() {
+    let content = "See [[Alpha Plan|the plan]] and [[projects/Beta.md#Goals]].\nAlso [notes](../shared/My%20Notes.md#top), [site](https://example.com), [top](#intro) and [[alpha plan]].\n```\n[[Not A Link]]\n```\n";
+    assert_eq!(
+      extract_links(Path::new("/vault/daily/today.md"), content),
+      vec!["[[alpha plan]]", "[[beta]]", "/vault/shared/My Notes.md"]
+    );
+  }
+
+  #[test]
+  fn extract_tags_should_split_front_matter_tag_string() {
+    let content = "---\ntags: draft, ideas\n---\nbody";
+    assert_eq!(extract_tags(content), vec!["draft", "ideas"]);
+  }
+}
@@ -0,0 +1,30 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::search::file::{ContentExtractor, File};
+
+pub const OCR_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "tif", "tiff"];
+
+/// Recognizes the text of scanned documents and screenshots with tesseract.
+pub struct OcrExtractor {
+  language: String,
+}
+
+impl OcrExtractor {
+  pub fn new(language: &str) -> Self {
+    Self {
+      language: language.to_string(),
+    }
+  }
+}
+
+impl ContentExtractor for OcrExtractor {
+  fn extract(&self, path: &Path) -> Result<File> {
+    let image_path = path
+      .to_str()
+      .ok_or_else(|| anyhow::anyhow!("Image path is not valid UTF-8: {:?}", path))?;
+    let content = tesseract::ocr(image_path, &self.language)
+      .map_err(|e| anyhow::anyhow!("Failed to recognize text in {:?}: {}", path, e))?;
+    Ok(File::new(path.to_string_lossy().to_string(), content))
+  }
+}
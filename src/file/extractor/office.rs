@@ -0,0 +1,80 @@
+use anyhow::Result;
+use quick_xml::{Reader, events::Event};
+use std::{fs, path::Path};
+use zip::ZipArchive;
+
+use super::read_zip_entry;
+use crate::search::file::{ContentExtractor, File};
+
+pub struct DocxExtractor;
+
+pub struct PptxExtractor;
+
+pub struct OpenDocumentExtractor;
+
+impl ContentExtractor for DocxExtractor {
+  fn extract(&self, path: &Path) -> Result<File> {
+    let mut archive = ZipArchive::new(fs::File::open(path)?)?;
+    let content = xml_to_text(&read_zip_entry(&mut archive, "word/document.xml")?)?;
+    Ok(File::new(path.to_string_lossy().to_string(), content))
+  }
+}
+
+impl ContentExtractor for PptxExtractor {
+  fn extract(&self, path: &Path) -> Result<File> {
+    let mut archive = ZipArchive::new(fs::File::open(path)?)?;
+    let mut slides = archive
+      .file_names()
+      .filter_map(|name| {
+        name
+          .strip_prefix("ppt/slides/slide")
+          .and_then(|rest| rest.strip_suffix(".xml"))
+          .and_then(|number| number.parse::<usize>().ok())
+          .map(|number| (number, name.to_string()))
+      })
+      .collect::<Vec<_>>();
+    slides.sort();
+
+    let content = slides
+      .iter()
+      .map(|(_number, name)| xml_to_text(&read_zip_entry(&mut archive, name)?))
+      .collect::<Result<Vec<_>>>()?
+      .join("\n");
+    Ok(File::new(path.to_string_lossy().to_string(), content))
+  }
+}
+
+impl ContentExtractor for OpenDocumentExtractor {
+  fn extract(&self, path: &Path) -> Result<File> {
+    let mut archive = ZipArchive::new(fs::File::open(path)?)?;
+    let content = xml_to_text(&read_zip_entry(&mut archive, "content.xml")?)?;
+    Ok(File::new(path.to_string_lossy().to_string(), content))
+  }
+}
+
+pub fn xml_to_text(xml: &str) -> Result<String> {
+  /* Paragraph-like elements ("w:p", "a:p", "text:p", "text:h") end a line */
+  let mut reader = Reader::from_str(xml);
+  let mut text = String::new();
+  loop {
+    match reader.read_event()? {
+      Event::Text(e) => text.push_str(&e.unescape()?),
+      Event::End(e) if matches!(e.local_name().as_ref(), b"p" | b"h") => text.push('\n'),
+      Event::Empty(e) if matches!(e.local_name().as_ref(), b"br" | b"tab" | b"s") => text.push(' '),
+      Event::Eof => break,
+      _ => {}
+    }
+  }
+  Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn xml_to_text_should_split_paragraphs_into_lines() {
+    let xml = r#"<w:document xmlns:w="w"><w:body><w:p><w:r><w:t>Hello</w:t></w:r><w:r><w:t> world</w:t></w:r></w:p><w:p><w:r><w:t>Second &amp; last</w:t></w:r></w:p></w:body></w:document>"#;
+    assert_eq!(xml_to_text(xml).unwrap(), "Hello world\nSecond & last\n");
+  }
+}
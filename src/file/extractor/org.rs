@@ -0,0 +1,196 @@
+use anyhow::Result;
+use std::path::Path;
+
+use super::heading_sections;
+use crate::search::file::{ContentExtractor, File, TAGS_METADATA_KEY};
+
+/// Reads Org files without their markup: drawers, keyword lines, comments, block delimiters and
+/// planning lines are blanked, link targets are replaced by their descriptions and heading tags
+/// become document tags. Every line stays on its line so that line numbers match the file.
+pub struct OrgExtractor;
+
+impl ContentExtractor for OrgExtractor {
+  fn extract(&self, path: &Path) -> Result<File> {
+    let org = std::fs::read_to_string(path)?;
+    let document = clean_org(&org);
+    let path = path.to_string_lossy().to_string();
+    let sections = heading_sections(&path, &document.content);
+    let mut file = File::new(path, document.content).with_sections(sections);
+    if let Some(title) = document.title {
+      file = file.with_metadata("title", &title);
+    }
+    if !document.tags.is_empty() {
+      file = file.with_metadata(TAGS_METADATA_KEY, &document.tags.join(" "));
+    }
+    Ok(file)
+  }
+}
+
+pub struct OrgDocument {
+  pub content: String,
+  pub title: Option<String>,
+  pub tags: Vec<String>,
+}
+
+const PLANNING_KEYWORDS: &[&str] = &["SCHEDULED:", "DEADLINE:", "CLOSED:"];
+
+/// `:a:b:` as `["a", "b"]`, or `None` if the text is not a tag list.
+fn tag_list(text: &str) -> Option<Vec<&str>> {
+  let inner = text.strip_prefix(':')?.strip_suffix(':')?;
+  let tags = inner.split(':').collect::<Vec<_>>();
+  tags
+    .iter()
+    .all(|tag| {
+      !tag.is_empty()
+        && tag
+          .chars()
+          .all(|c| c.is_alphanumeric() || "_@#%".contains(c))
+    })
+    .then_some(tags)
+}
+
+/// `[[target][description]]` as `description` and `[[target]]` as `target`.
+fn strip_links(line: &str) -> String {
+  let mut text = String::new();
+  let mut rest = line;
+  while let Some(start) = rest.find("[[") {
+    let Some(end) = rest[start..].find("]]") else {
+      break;
+    };
+    text.push_str(&rest[..start]);
+    let link = &rest[start + 2..start + end];
+    text.push_str(
+      link
+        .split_once("][")
+        .map_or(link, |(_, description)| description),
+    );
+    rest = &rest[start + end + 2..];
+  }
+  text.push_str(rest);
+  text
+}
+
+/// Heading with its priority cookie and tags removed, keeping the stars for `outline`.
+fn clean_heading(stars: &str, title: &str, tags: &mut Vec<String>) -> String {
+  let mut title = title.trim();
+  if let Some((rest, last_word)) = title.rsplit_once(char::is_whitespace) {
+    if let Some(heading_tags) = tag_list(last_word) {
+      tags.extend(heading_tags.iter().map(|tag| tag.to_string()));
+      title = rest.trim_end();
+    }
+  } else if let Some(heading_tags) = tag_list(title) {
+    tags.extend(heading_tags.iter().map(|tag| tag.to_string()));
+    title = "";
+  }
+  let words = title
+    .split_whitespace()
+    .filter(|word| !(word.starts_with("[#") && word.ends_with(']') && word.len() == 4))
+    .collect::<Vec<_>>();
+  format!("{} {}", stars, strip_links(&words.join(" ")))
+}
+
+pub fn clean_org(org: &str) -> OrgDocument {
+  let mut lines = Vec::new();
+  let mut title = None;
+  let mut tags = Vec::new();
+  let mut in_drawer = false;
+  let mut in_literal_block = false;
+  for line in org.lines() {
+    let trimmed = line.trim();
+    /* Source and example blocks are kept verbatim, a `# comment` there is code */
+    if in_literal_block {
+      in_literal_block = !trimmed.to_uppercase().starts_with("#+END_");
+      lines.push(if in_literal_block {
+        line.to_string()
+      } else {
+        String::new()
+      });
+      continue;
+    }
+    let stars = line.chars().take_while(|c| *c == '*').count();
+    if stars > 0 && line[stars..].starts_with(' ') {
+      in_drawer = false;
+      lines.push(clean_heading(&line[..stars], &line[stars..], &mut tags));
+      continue;
+    }
+    if in_drawer {
+      in_drawer = !trimmed.eq_ignore_ascii_case(":END:");
+      lines.push(String::new());
+      continue;
+    }
+    let is_drawer_start = trimmed.len() > 2
+      && !trimmed.eq_ignore_ascii_case(":END:")
+      && trimmed.starts_with(':')
+      && trimmed.ends_with(':')
+      && trimmed[1..trimmed.len() - 1]
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+    if is_drawer_start {
+      in_drawer = true;
+      lines.push(String::new());
+      continue;
+    }
+    if let Some(keyword_line) = trimmed.strip_prefix("#+") {
+      let (keyword, value) = keyword_line.split_once(':').unwrap_or((keyword_line, ""));
+      let keyword = keyword.split_whitespace().next().unwrap_or_default();
+      let value = value.trim();
+      match keyword.to_uppercase().as_str() {
+        "TITLE" => {
+          title = Some(value.to_string());
+          lines.push(value.to_string());
+        }
+        "AUTHOR" | "SUBTITLE" | "DESCRIPTION" | "CAPTION" => lines.push(value.to_string()),
+        "FILETAGS" => {
+          let filetags = value.split([':', ' ']).filter(|tag| !tag.is_empty());
+          tags.extend(filetags.map(str::to_string));
+          lines.push(String::new());
+        }
+        "BEGIN_SRC" | "BEGIN_EXAMPLE" => {
+          in_literal_block = true;
+          lines.push(String::new());
+        }
+        /* #+BEGIN_QUOTE, #+OPTIONS and friends */
+        _ => lines.push(String::new()),
+      }
+      continue;
+    }
+    let is_comment = trimmed == "#" || trimmed.starts_with("# ");
+    let is_planning = PLANNING_KEYWORDS
+      .iter()
+      .any(|keyword| trimmed.starts_with(keyword));
+    if is_comment || is_planning {
+      lines.push(String::new());
+      continue;
+    }
+    lines.push(strip_links(line));
+  }
+
+  let mut unique_tags = Vec::new();
+  for tag in tags {
+    if !unique_tags.contains(&tag) {
+      unique_tags.push(tag);
+    }
+  }
+  OrgDocument {
+    content: lines.join("\n"),
+    title,
+    tags: unique_tags,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn clean_org_should_drop_markup_and_keep_lines() {
+    let org = "#+TITLE: Plans\n#+FILETAGS: :home:\n* TODO [#A] Move out :chores:urgent:\n  SCHEDULED: <2024-05-01 Wed>\n  :PROPERTIES:\n  :ID: 42\n  :END:\n  Call [[https://example.com][the landlord]].\n# private note\n#+BEGIN_SRC sh\n# list\nls\n#+END_SRC";
+    let document = clean_org(org);
+    assert_eq!(
+      document.content,
+      "Plans\n\n* TODO Move out\n\n\n\n\n  Call the landlord.\n\n\n# list\nls\n"
+    );
+    assert_eq!(document.title.as_deref(), Some("Plans"));
+    assert_eq!(document.tags, vec!["home", "chores", "urgent"]);
+  }
+}
@@ -0,0 +1,24 @@
+use anyhow::Result;
+use std::{fs, io::BufReader, path::Path};
+
+use crate::{
+  file::{chunk::ChunkReader, read_file::path_to_file},
+  search::file::{ContentExtractor, File, FileChunks},
+};
+
+pub struct PlainTextExtractor;
+
+impl ContentExtractor for PlainTextExtractor {
+  fn extract(&self, path: &Path) -> Result<File> {
+    path_to_file(path)
+  }
+
+  fn extract_chunks(&self, path: &Path, chunk_size: usize) -> Result<Option<FileChunks>> {
+    let reader = BufReader::new(fs::File::open(path)?);
+    Ok(Some(Box::new(ChunkReader::new(
+      reader,
+      path.to_string_lossy().to_string(),
+      chunk_size,
+    ))))
+  }
+}
@@ -0,0 +1,176 @@
+use anyhow::Result;
+use std::path::Path;
+
+use super::heading_sections;
+use crate::search::file::{ContentExtractor, File};
+
+/// Reads reStructuredText without its markup: directive markers and options, comments, link
+/// targets and substitution definitions are blanked and roles, literals and references are
+/// reduced to their text. Section adornments stay so that `outline` still finds the headings,
+/// and every line stays on its line so that line numbers match the file.
+pub struct RstExtractor;
+
+impl ContentExtractor for RstExtractor {
+  fn extract(&self, path: &Path) -> Result<File> {
+    let rst = std::fs::read_to_string(path)?;
+    let content = clean_rst(&rst);
+    let path = path.to_string_lossy().to_string();
+    let sections = heading_sections(&path, &content);
+    Ok(File::new(path, content).with_sections(sections))
+  }
+}
+
+/// Directives whose argument is a path, a language or an identifier rather than prose.
+const NON_PROSE_DIRECTIVES: &[&str] = &[
+  "code",
+  "code-block",
+  "sourcecode",
+  "highlight",
+  "literalinclude",
+  "include",
+  "image",
+  "figure",
+  "toctree",
+  "automodule",
+  "autoclass",
+  "autofunction",
+];
+
+fn indentation(line: &str) -> usize {
+  line.len() - line.trim_start().len()
+}
+
+/// `:role:`text <target>`` and `` `text <url>`_ `` as `text`, ```literal``` as `literal`.
+fn strip_inline_markup(line: &str) -> String {
+  let mut text = String::new();
+  let mut rest = line;
+  while let Some(start) = rest.find('`') {
+    let before = &rest[..start];
+    /* A role directly precedes the backquote, e.g. :ref: */
+    let role_start = before
+      .strip_suffix(':')
+      .and_then(|role| role.rfind(':'))
+      .filter(|role_start| {
+        before[role_start + 1..before.len() - 1]
+          .chars()
+          .all(|c| c.is_alphanumeric() || "-_.:".contains(c))
+      });
+    text.push_str(&before[..role_start.unwrap_or(start)]);
+    let double = rest[start..].starts_with("``");
+    let delimiter = if double { "``" } else { "`" };
+    let inner_start = start + delimiter.len();
+    let Some(length) = rest[inner_start..].find(delimiter) else {
+      text.push_str(&rest[start..]);
+      return text;
+    };
+    let inner = &rest[inner_start..inner_start + length];
+    /* `text <target>` keeps only the text */
+    let inner = match inner.rsplit_once(" <") {
+      Some((label, target)) if !double && target.ends_with('>') => label,
+      _ => inner,
+    };
+    text.push_str(inner);
+    rest = &rest[inner_start + length + delimiter.len()..];
+    rest = rest
+      .strip_prefix("__")
+      .or_else(|| rest.strip_prefix('_'))
+      .unwrap_or(rest);
+  }
+  text.push_str(rest);
+  text
+}
+
+pub fn clean_rst(rst: &str) -> String {
+  let mut lines = Vec::new();
+  /* Indentation of the block being dropped, of the directive whose options follow and of the
+  paragraph introducing a literal block */
+  let mut skipped_block: Option<usize> = None;
+  let mut directive_options: Option<usize> = None;
+  let mut literal_block: Option<usize> = None;
+  for line in rst.lines() {
+    let trimmed = line.trim();
+    let indent = indentation(line);
+    if let Some(block_indent) = literal_block {
+      if trimmed.is_empty() || indent > block_indent {
+        lines.push(line.to_string());
+        continue;
+      }
+      literal_block = None;
+    }
+    if let Some(block_indent) = skipped_block {
+      if trimmed.is_empty() || indent > block_indent {
+        lines.push(String::new());
+        continue;
+      }
+      skipped_block = None;
+    }
+    if let Some(block_indent) = directive_options {
+      if indent > block_indent && trimmed.starts_with(':') && trimmed[1..].contains(':') {
+        lines.push(String::new());
+        continue;
+      }
+      directive_options = None;
+    }
+
+    let Some(markup) = trimmed
+      .strip_prefix("..")
+      .filter(|markup| markup.is_empty() || markup.starts_with(' '))
+    else {
+      let text = strip_inline_markup(line);
+      /* A paragraph ending in `::` introduces an indented literal block */
+      if text.trim_end().ends_with("::") {
+        literal_block = Some(indent);
+        lines.push(
+          text
+            .trim_end()
+            .strip_suffix(':')
+            .unwrap_or_default()
+            .to_string(),
+        );
+      } else {
+        lines.push(text);
+      }
+      continue;
+    };
+    let markup = markup.trim();
+    match markup.split_once("::") {
+      Some((name, argument)) if !name.starts_with('|') && !name.contains(' ') => {
+        directive_options = Some(indent);
+        let name = name.to_lowercase();
+        if NON_PROSE_DIRECTIVES.contains(&name.as_str()) {
+          if name.starts_with("code") || name == "sourcecode" {
+            literal_block = Some(indent);
+          }
+          lines.push(String::new());
+        } else {
+          lines.push(format!("{}{}", " ".repeat(indent), argument.trim()));
+        }
+      }
+      /* Footnotes and citations are prose */
+      _ if markup.starts_with('[') => match markup.split_once(']') {
+        Some((_, text)) => lines.push(strip_inline_markup(text.trim())),
+        None => lines.push(String::new()),
+      },
+      /* Comments, `.. _target:` and `.. |name| image::` with everything indented below them */
+      _ => {
+        skipped_block = Some(indent);
+        lines.push(String::new());
+      }
+    }
+  }
+  lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn clean_rst_should_drop_markup_and_keep_lines() {
+    let rst = "Guide\n=====\n\n.. _install:\n\n.. note:: Read first\n   :class: tip\n\n   See :ref:`the setup <setup>` and ``pip``.\n\n.. code-block:: python\n\n   import os\n\n.. a comment\n   spanning lines\n\nVisit `the site <https://example.com>`_.";
+    assert_eq!(
+      clean_rst(rst),
+      "Guide\n=====\n\n\n\nRead first\n\n\n   See the setup and pip.\n\n\n\n   import os\n\n\n\n\nVisit the site."
+    );
+  }
+}
@@ -0,0 +1,106 @@
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::Path;
+
+use crate::search::file::{ContentExtractor, File};
+
+pub struct JsonExtractor;
+
+pub struct YamlExtractor;
+
+impl ContentExtractor for JsonExtractor {
+  fn extract(&self, path: &Path) -> Result<File> {
+    let content = std::fs::read_to_string(path)?;
+    let text = match serde_json::from_str::<Value>(&content) {
+      Ok(value) => flatten_to_text(&[value]),
+      Err(e) => {
+        tracing::debug!("Indexing {:?} as plain text: {}", path, e);
+        content
+      }
+    };
+    Ok(File::new(path.to_string_lossy().to_string(), text))
+  }
+}
+
+impl ContentExtractor for YamlExtractor {
+  fn extract(&self, path: &Path) -> Result<File> {
+    let content = std::fs::read_to_string(path)?;
+    let documents = serde_yaml::Deserializer::from_str(&content)
+      .map(Value::deserialize)
+      .collect::<Result<Vec<_>, _>>();
+    let text = match documents {
+      Ok(documents) => flatten_to_text(&documents),
+      Err(e) => {
+        tracing::debug!("Indexing {:?} as plain text: {}", path, e);
+        content
+      }
+    };
+    Ok(File::new(path.to_string_lossy().to_string(), text))
+  }
+}
+
+fn flatten_to_text(documents: &[Value]) -> String {
+  let mut lines = Vec::new();
+  for document in documents {
+    flatten_value("", document, &mut lines);
+  }
+  lines.join("\n")
+}
+
+/// Writes one `key.path[0]: value` line per scalar so both keys and values are searchable.
+pub fn flatten_value(key_path: &str, value: &Value, lines: &mut Vec<String>) {
+  match value {
+    Value::Object(map) => {
+      for (key, child) in map {
+        let child_path = if key_path.is_empty() {
+          key.clone()
+        } else {
+          format!("{}.{}", key_path, key)
+        };
+        flatten_value(&child_path, child, lines);
+      }
+    }
+    Value::Array(items) => {
+      for (i, child) in items.iter().enumerate() {
+        flatten_value(&format!("{}[{}]", key_path, i), child, lines);
+      }
+    }
+    scalar => {
+      let text = match scalar {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+      };
+      if key_path.is_empty() {
+        lines.push(text);
+      } else {
+        lines.push(format!("{}: {}", key_path, text));
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn flatten_value_should_write_key_paths_and_values() {
+    let value: Value = serde_json::from_str(
+      r#"{"server": {"port": 8080, "hosts": ["a.example", "b.example"]}, "debug": null}"#,
+    )
+    .unwrap();
+    let mut lines = Vec::new();
+    flatten_value("", &value, &mut lines);
+    lines.sort();
+    assert_eq!(
+      lines,
+      vec![
+        "debug: null",
+        "server.hosts[0]: a.example",
+        "server.hosts[1]: b.example",
+        "server.port: 8080",
+      ]
+    );
+  }
+}
@@ -1,6 +1,56 @@
-use std::path::Path;
+use std::{
+  ffi::OsStr,
+  fs,
+  io::Read,
+  path::{Component, Path, PathBuf},
+  sync::Arc,
+};
 
-use crate::search::file::FileFilter;
+use super::{ignore_file::IgnoreFiles, path_normalizer::PathNormalizer};
+use crate::search::file::{FileFilter, FileLoader, FileOperation};
+
+pub const DEFAULT_EXCLUDED_DIRECTORIES: &[&str] = &[
+  ".git",
+  "node_modules",
+  "target",
+  ".venv",
+  "__pycache__",
+  ".DS_Store",
+];
+
+#[derive(Debug, Clone)]
+pub struct PathExclusion {
+  pub excluded_names: Vec<String>,
+  pub include_hidden: bool,
+  pub follow_symlinks: bool,
+  pub respect_ignore_files: bool,
+}
+
+impl PathExclusion {
+  pub fn none() -> Self {
+    Self {
+      excluded_names: Vec::new(),
+      include_hidden: true,
+      follow_symlinks: true,
+      respect_ignore_files: false,
+    }
+  }
+
+  pub fn is_excluded_symlink(&self, path: &Path) -> bool {
+    !self.follow_symlinks
+      && path
+        .symlink_metadata()
+        .is_ok_and(|metadata| metadata.file_type().is_symlink())
+  }
+
+  pub fn is_excluded_name(&self, name: &OsStr) -> bool {
+    (!self.include_hidden && name.to_string_lossy().starts_with('.'))
+      || self
+        .excluded_names
+        .iter()
+        .any(|excluded| name == excluded.as_str())
+  }
+}
 
 pub struct ExtensionFileFilter {
   allowed_extensions: Vec<String>,
@@ -24,3 +74,152 @@ impl FileFilter for ExtensionFileFilter {
     false
   }
 }
+
+const CONTENT_SNIFF_BYTES: u64 = 8 * 1024;
+
+/// Accepts files whose content looks like text regardless of their extension, so extensionless
+/// files such as README, LICENSE or shell scripts can be indexed.
+pub struct ContentTypeFileFilter;
+
+impl ContentTypeFileFilter {
+  fn detect_text(path: &Path) -> Option<bool> {
+    let mut head = Vec::new();
+    fs::File::open(path)
+      .ok()?
+      .take(CONTENT_SNIFF_BYTES)
+      .read_to_end(&mut head)
+      .ok()?;
+    if let Some(kind) = infer::get(&head) {
+      return Some(kind.mime_type().starts_with("text/"));
+    }
+    if head.contains(&0) {
+      return Some(false);
+    }
+    /* The sniffed prefix may cut a multi-byte character in half */
+    Some(match std::str::from_utf8(&head) {
+      Ok(_) => true,
+      Err(e) => e.error_len().is_none(),
+    })
+  }
+}
+
+impl FileFilter for ContentTypeFileFilter {
+  fn is_target(&self, path: &str) -> bool {
+    let path = Path::new(path);
+    path.is_file() && Self::detect_text(path).unwrap_or(false)
+  }
+}
+
+pub struct AnyFileFilter {
+  filters: Vec<Arc<dyn FileFilter + Send + Sync>>,
+}
+
+impl AnyFileFilter {
+  pub fn new(filters: Vec<Arc<dyn FileFilter + Send + Sync>>) -> Self {
+    Self { filters }
+  }
+}
+
+impl FileFilter for AnyFileFilter {
+  fn is_target(&self, path: &str) -> bool {
+    self.filters.iter().any(|filter| filter.is_target(path))
+  }
+}
+
+pub struct ExcludedPathFileFilter {
+  inner: Arc<dyn FileFilter + Send + Sync>,
+  roots: Vec<PathBuf>,
+  exclusion: PathExclusion,
+  ignore_files: Option<IgnoreFiles>,
+}
+
+impl ExcludedPathFileFilter {
+  pub fn new(
+    inner: Arc<dyn FileFilter + Send + Sync>,
+    root: &Path,
+    exclusion: PathExclusion,
+  ) -> Self {
+    /* Paths are normalized to the canonical root, but filters may also be asked about raw paths */
+    let mut roots = vec![
+      PathNormalizer::new(root).root().to_path_buf(),
+      root.to_path_buf(),
+    ];
+    roots.dedup();
+    let ignore_files = exclusion
+      .respect_ignore_files
+      .then(|| IgnoreFiles::new(root));
+    Self {
+      inner,
+      roots,
+      exclusion,
+      ignore_files,
+    }
+  }
+
+  pub fn is_excluded(&self, path: &Path) -> bool {
+    let Some(relative_path) = self
+      .roots
+      .iter()
+      .find_map(|root| path.strip_prefix(root).ok())
+    else {
+      return self.has_excluded_component(path);
+    };
+    self.has_excluded_component(relative_path)
+      || self
+        .ignore_files
+        .as_ref()
+        .is_some_and(|ignore_files| ignore_files.is_ignored(relative_path))
+  }
+
+  fn has_excluded_component(&self, path: &Path) -> bool {
+    path.components().any(|component| match component {
+      Component::Normal(name) => self.exclusion.is_excluded_name(name),
+      _ => false,
+    })
+  }
+}
+
+impl FileFilter for ExcludedPathFileFilter {
+  fn is_target(&self, path: &str) -> bool {
+    !self.is_excluded(Path::new(path))
+      && !self.exclusion.is_excluded_symlink(Path::new(path))
+      && self.inner.is_target(path)
+  }
+}
+
+/// Drops events that cannot affect the index right at the watcher, so churn in excluded or
+/// non-indexed files (e.g. `.git` internals) never reaches the index queue.
+pub struct EventFilter {
+  file_filter: Arc<ExcludedPathFileFilter>,
+  file_loader: Arc<dyn FileLoader + Send + Sync>,
+}
+
+impl EventFilter {
+  pub fn new(
+    file_filter: Arc<ExcludedPathFileFilter>,
+    file_loader: Arc<dyn FileLoader + Send + Sync>,
+  ) -> Self {
+    Self {
+      file_filter,
+      file_loader,
+    }
+  }
+
+  pub fn is_relevant(&self, op: &FileOperation) -> bool {
+    let is_excluded = |path: &str| self.file_filter.is_excluded(Path::new(path));
+    match op {
+      FileOperation::FileCreated(path) | FileOperation::FileModified(path) => {
+        self.file_filter.is_target(path) || self.file_loader.is_container(path)
+      }
+      /* A deleted file can no longer be inspected, so only its path is checked */
+      FileOperation::FileDeleted(path)
+      | FileOperation::DirectoryDeleted(path)
+      | FileOperation::DirectoryRescan(path) => !is_excluded(path),
+      FileOperation::FileRenamed { old_path, new_path }
+      | FileOperation::DirectoryRenamed { old_path, new_path }
+      | FileOperation::PathRenamed { old_path, new_path } => {
+        !is_excluded(old_path) || !is_excluded(new_path)
+      }
+    }
+  }
+}
@@ -0,0 +1,136 @@
+use anyhow::Result;
+use std::{
+  fs::Metadata,
+  io::Read,
+  path::Path,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+/// What agents need to decide whether to read a file again.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct FileInfo {
+  pub file_path: String,
+  pub size: u64,
+  pub extension: Option<String>,
+  /// Milliseconds since the Unix epoch of the last content change
+  pub modified_ms: Option<u64>,
+  /// Milliseconds since the Unix epoch of the last content or permission change (ctime), or of
+  /// the creation where there is no ctime
+  pub changed_ms: Option<u64>,
+  pub indexed: bool,
+  pub indexed_at_ms: Option<u64>,
+  /// Whether the index has seen the current content, unknown when either time is
+  pub index_fresh: Option<bool>,
+  /// FNV-1a hash of the content, as `fnv1a64:<hex>`
+  pub content_hash: String,
+}
+
+impl FileInfo {
+  /// Reads the metadata and hashes the content of a file on disk. Index fields are left for the
+  /// caller to fill in with `with_indexed_at`.
+  pub fn read(file_path: &str) -> Result<Self> {
+    let path = Path::new(file_path);
+    let metadata =
+      std::fs::metadata(path).map_err(|e| anyhow::anyhow!("Cannot read {}: {}", file_path, e))?;
+    if !metadata.is_file() {
+      anyhow::bail!("{} is not a file", file_path);
+    }
+    Ok(FileInfo {
+      file_path: file_path.to_string(),
+      size: metadata.len(),
+      extension: path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase()),
+      modified_ms: metadata.modified().ok().and_then(unix_ms),
+      changed_ms: changed_ms(&metadata),
+      indexed: false,
+      indexed_at_ms: None,
+      index_fresh: None,
+      content_hash: content_hash(path)?,
+    })
+  }
+
+  pub fn with_indexed_at(mut self, indexed: bool, indexed_at_ms: Option<u64>) -> Self {
+    self.indexed = indexed;
+    self.indexed_at_ms = indexed_at_ms;
+    self.index_fresh = match (indexed_at_ms, self.modified_ms) {
+      (Some(indexed_at_ms), Some(modified_ms)) => Some(indexed_at_ms >= modified_ms),
+      _ => None,
+    };
+    self
+  }
+}
+
+fn unix_ms(time: SystemTime) -> Option<u64> {
+  time
+    .duration_since(UNIX_EPOCH)
+    .ok()
+    .map(|duration| duration.as_millis() as u64)
+}
+
+#[cfg(unix)]
+fn changed_ms(metadata: &Metadata) -> Option<u64> {
+  use std::os::unix::fs::MetadataExt;
+  let seconds = u64::try_from(metadata.ctime()).ok()?;
+  let nanoseconds = u64::try_from(metadata.ctime_nsec()).ok()?;
+  Some(seconds * 1000 + nanoseconds / 1_000_000)
+}
+
+#[cfg(not(unix))]
+fn changed_ms(metadata: &Metadata) -> Option<u64> {
+  metadata.created().ok().and_then(unix_ms)
+}
+
+/* FNV-1a is not cryptographic, but stable across releases and enough to notice a change */
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+fn fnv1a64(hash: u64, bytes: &[u8]) -> u64 {
+  bytes.iter().fold(hash, |hash, byte| {
+    (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+  })
+}
+
+/// Hash of some content as `fnv1a64:<hex>`, the same as the `content_hash` of a file holding it.
+pub fn hash_bytes(bytes: &[u8]) -> String {
+  format!("fnv1a64:{:016x}", fnv1a64(FNV_OFFSET_BASIS, bytes))
+}
+
+fn content_hash(path: &Path) -> Result<String> {
+  let mut file = std::fs::File::open(path)?;
+  let mut buffer = [0u8; 64 * 1024];
+  let mut hash = FNV_OFFSET_BASIS;
+  loop {
+    let read = file.read(&mut buffer)?;
+    if read == 0 {
+      break;
+    }
+    hash = fnv1a64(hash, &buffer[..read]);
+  }
+  Ok(format!("fnv1a64:{:016x}", hash))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn file_info_should_tell_whether_the_index_is_fresh() {
+    let path = std::env::temp_dir().join(format!("file-info-test-{}.md", std::process::id()));
+    std::fs::write(&path, "a").unwrap();
+    let info = FileInfo::read(path.to_str().unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(info.size, 1);
+    assert_eq!(info.extension.as_deref(), Some("md"));
+    /* The well-known FNV-1a 64 hash of "a" */
+    assert_eq!(info.content_hash, "fnv1a64:af63dc4c8601ec8c");
+    assert_eq!(hash_bytes(b"a"), info.content_hash);
+    let modified_ms = info.modified_ms.unwrap();
+    let fresh = info.clone().with_indexed_at(true, Some(modified_ms + 1));
+    assert_eq!(fresh.index_fresh, Some(true));
+    let stale = info.clone().with_indexed_at(true, Some(modified_ms - 1));
+    assert_eq!(stale.index_fresh, Some(false));
+    assert_eq!(info.with_indexed_at(false, None).index_fresh, None);
+  }
+}
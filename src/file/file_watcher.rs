@@ -2,37 +2,122 @@ use anyhow::Result;
 use notify_debouncer_full::{
   DebounceEventResult, DebouncedEvent, FileIdMap, new_debouncer,
   notify::{
-    EventKind, RecursiveMode, Watcher,
+    self, EventKind, RecursiveMode, Watcher,
     event::{ModifyKind, RemoveKind, RenameMode},
   },
 };
+use parking_lot::Mutex;
 use std::{
-  path::{Component, Path, PathBuf},
-  sync::mpsc::{Receiver, channel},
-  thread,
-  time::Duration,
+  collections::HashMap,
+  path::{Path, PathBuf},
+  sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+  },
+  time::{Duration, Instant, SystemTime},
+};
+use tokio::{
+  sync::mpsc::{UnboundedReceiver, unbounded_channel},
+  task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+use super::{file_filter::EventFilter, path_normalizer::PathNormalizer};
+use crate::search::{
+  event_log::{EventLog, EventStage},
+  file::{FileOperation, FileOperationHandler, FileWatcher},
 };
 
-use crate::search::file::{FileOperation, FileOperationHandler, FileWatcher};
+const INITIAL_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+
+type NotifyDebouncer =
+  notify_debouncer_full::Debouncer<notify_debouncer_full::notify::RecommendedWatcher, FileIdMap>;
+
+type NotifyWatch = (NotifyDebouncer, UnboundedReceiver<DebounceEventResult>);
+
+type WatchFactory = Box<dyn Fn() -> Result<NotifyWatch> + Send + Sync>;
 
 pub struct NotifyFileWatcher {
-  watcher: Option<
-    notify_debouncer_full::Debouncer<notify_debouncer_full::notify::RecommendedWatcher, FileIdMap>,
-  >,
-  stop_tx: Option<std::sync::mpsc::Sender<()>>,
-  watch_target: Option<String>,
-  thread_handle: Option<std::thread::JoinHandle<()>>,
+  cancellation: Option<CancellationToken>,
+  task: Option<JoinHandle<()>>,
+  restarts: Arc<AtomicU64>,
+  event_filter: Option<Arc<EventFilter>>,
+  event_log: Option<Arc<EventLog>>,
 }
 
 impl NotifyFileWatcher {
   pub fn new() -> Self {
     Self {
-      watcher: None,
-      stop_tx: None,
-      watch_target: None,
-      thread_handle: None,
+      cancellation: None,
+      task: None,
+      restarts: Arc::new(AtomicU64::new(0)),
+      event_filter: None,
+      event_log: None,
     }
   }
+
+  pub fn with_event_log(mut self, event_log: Arc<EventLog>) -> Self {
+    self.event_log = Some(event_log);
+    self
+  }
+
+  pub fn with_event_filter(mut self, event_filter: Arc<EventFilter>) -> Self {
+    self.event_filter = Some(event_filter);
+    self
+  }
+
+  /// Counts how often the watcher had to be restarted after the notify backend failed.
+  pub fn with_restart_counter(mut self, restarts: Arc<AtomicU64>) -> Self {
+    self.restarts = restarts;
+    self
+  }
+
+  /// Watches `path` itself and each of its subdirectories separately, returning the
+  /// subdirectories that could not be watched because the inotify watch limit was reached, so
+  /// that they can be polled instead.
+  pub fn watch_directory_partially(
+    &mut self,
+    path: &str,
+    handler: Box<FileOperationHandler>,
+  ) -> Result<Vec<PathBuf>> {
+    let (debouncer, rx, unwatched) = watch_partially(path)?;
+    let factory_path = path.to_string();
+    self.start(
+      (debouncer, rx),
+      Box::new(move || watch_partially(&factory_path).map(|(debouncer, rx, _)| (debouncer, rx))),
+      path,
+      handler,
+    )?;
+    Ok(unwatched)
+  }
+
+  fn start(
+    &mut self,
+    watch: NotifyWatch,
+    factory: WatchFactory,
+    path: &str,
+    handler: Box<FileOperationHandler>,
+  ) -> Result<()> {
+    let cancellation = CancellationToken::new();
+    let supervisor = Supervisor {
+      path: path.to_string(),
+      factory,
+      cancellation: cancellation.clone(),
+      normalizer: PathNormalizer::new(Path::new(path)),
+      event_filter: self.event_filter.clone(),
+      event_log: self.event_log.clone(),
+      file_states: FileStates::default(),
+      handler,
+      restarts: self.restarts.clone(),
+    };
+    let task = tokio::runtime::Handle::try_current()?.spawn(supervisor.run(watch));
+
+    self.cancellation = Some(cancellation);
+    self.task = Some(task);
+
+    Ok(())
+  }
 }
 
 impl Default for NotifyFileWatcher {
@@ -41,82 +126,285 @@ impl Default for NotifyFileWatcher {
   }
 }
 
-impl FileWatcher for NotifyFileWatcher {
-  fn watch_directory(&mut self, path: &str, handler: Box<FileOperationHandler>) -> Result<()> {
-    let (tx, rx) = channel::<DebounceEventResult>();
-    let (stop_tx, stop_rx) = channel::<()>();
+/// Shown when the inotify watch limit is exhausted, which otherwise silently drops events.
+pub const WATCH_LIMIT_GUIDANCE: &str = "The inotify watch limit (fs.inotify.max_user_watches) is \
+  exhausted. Raise it with `sudo sysctl fs.inotify.max_user_watches=524288` (persist it in \
+  /etc/sysctl.d/), or exclude large directories from the watch directory";
+
+fn is_watch_limit_notify_error(error: &notify::Error) -> bool {
+  match &error.kind {
+    notify::ErrorKind::MaxFilesWatch => true,
+    /* inotify_add_watch fails with ENOSPC when the limit is reached */
+    notify::ErrorKind::Io(e) => e.raw_os_error() == Some(28),
+    _ => false,
+  }
+}
 
-    let mut debouncer = new_debouncer(Duration::from_secs(1), None, tx)?;
+pub fn is_watch_limit_error(error: &anyhow::Error) -> bool {
+  error
+    .downcast_ref::<notify::Error>()
+    .is_some_and(is_watch_limit_notify_error)
+}
+
+fn new_notify_debouncer() -> Result<NotifyWatch> {
+  let (tx, rx) = unbounded_channel::<DebounceEventResult>();
+  let debouncer = new_debouncer(Duration::from_secs(1), None, move |result| {
+    let _ = tx.send(result);
+  })?;
+  Ok((debouncer, rx))
+}
 
-    debouncer
+fn watch_recursively(path: &str) -> Result<NotifyWatch> {
+  let (mut debouncer, rx) = new_notify_debouncer()?;
+  let path = Path::new(path);
+  match path.parent().filter(|_| path.is_file()) {
+    /* Saves that replace the file would orphan a watch on the file itself */
+    Some(parent) => debouncer
       .watcher()
-      .watch(Path::new(path), RecursiveMode::Recursive)?;
+      .watch(parent, RecursiveMode::NonRecursive)?,
+    None => debouncer.watcher().watch(path, RecursiveMode::Recursive)?,
+  }
+  Ok((debouncer, rx))
+}
 
-    let thread_handle = thread::Builder::new()
-      .name("debounced-file-watcher".to_string())
-      .spawn(move || {
-        event_loop(rx, stop_rx, handler);
-      })?;
+/// Passes on only the events of `path` when a single file is watched through its directory.
+fn only_events_of_file(
+  path: &str,
+  handler: Box<FileOperationHandler>,
+) -> Box<FileOperationHandler> {
+  let file = PathNormalizer::new(Path::new(path)).root().to_path_buf();
+  Box::new(move |op| {
+    if op.paths().iter().any(|op_path| Path::new(op_path) == file) {
+      handler(op)
+    } else {
+      Ok(())
+    }
+  })
+}
 
-    self.watcher = Some(debouncer);
-    self.stop_tx = Some(stop_tx);
-    self.watch_target = Some(path.to_string());
-    self.thread_handle = Some(thread_handle);
+fn watch_partially(
+  path: &str,
+) -> Result<(
+  NotifyDebouncer,
+  UnboundedReceiver<DebounceEventResult>,
+  Vec<PathBuf>,
+)> {
+  let (mut debouncer, rx) = new_notify_debouncer()?;
+  debouncer
+    .watcher()
+    .watch(Path::new(path), RecursiveMode::NonRecursive)?;
 
-    Ok(())
+  let mut unwatched = Vec::new();
+  for entry in std::fs::read_dir(path)?.flatten() {
+    if !entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+      continue;
+    }
+    let subdirectory = entry.path();
+    match debouncer
+      .watcher()
+      .watch(&subdirectory, RecursiveMode::Recursive)
+    {
+      Ok(()) => {}
+      Err(e) if is_watch_limit_notify_error(&e) => {
+        let _ = debouncer.watcher().unwatch(&subdirectory);
+        unwatched.push(subdirectory);
+      }
+      Err(e) => return Err(e.into()),
+    }
+  }
+  Ok((debouncer, rx, unwatched))
+}
+
+impl FileWatcher for NotifyFileWatcher {
+  fn watch_directory(&mut self, path: &str, handler: Box<FileOperationHandler>) -> Result<()> {
+    let handler = if Path::new(path).is_file() {
+      only_events_of_file(path, handler)
+    } else {
+      handler
+    };
+    let watch = watch_recursively(path)?;
+    let factory_path = path.to_string();
+    self.start(
+      watch,
+      Box::new(move || watch_recursively(&factory_path)),
+      path,
+      handler,
+    )
   }
 
   fn stop_watching(&mut self) -> Result<()> {
-    if let Some(stop_tx) = self.stop_tx.take() {
-      let _ = stop_tx.send(());
+    /* The debouncer is owned by the task and stops watching when the task ends */
+    if let Some(cancellation) = self.cancellation.take() {
+      cancellation.cancel();
     }
+    self.task = None;
+    Ok(())
+  }
+}
 
-    if let Some(handle) = self.thread_handle.take() {
-      handle
-        .join()
-        .map_err(|_| anyhow::anyhow!("Failed to join watcher thread"))?;
-    }
+#[derive(Debug, PartialEq, Eq)]
+enum LoopExit {
+  Stopped,
+  WatchLost,
+  RootRemoved,
+}
 
-    if let Some(mut watcher) = self.watcher.take() {
-      if let Some(path) = &self.watch_target {
-        let _ = watcher.watcher().unwatch(Path::new(path));
+const ROOT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Last seen size and modification time of files, so that metadata-only or unspecific modify
+/// events (touch, chmod, truncation on some platforms) re-index a file only when it may have
+/// changed.
+#[derive(Default)]
+struct FileStates {
+  states: Mutex<HashMap<PathBuf, (u64, Option<SystemTime>)>>,
+}
+
+impl FileStates {
+  /// Records the current state of `path`, returning whether it differs from the last one seen.
+  fn update(&self, path: &Path) -> bool {
+    let mut states = self.states.lock();
+    match path.metadata() {
+      Ok(metadata) if metadata.is_file() => {
+        let state = (metadata.len(), metadata.modified().ok());
+        states.insert(path.to_path_buf(), state) != Some(state)
+      }
+      _ => {
+        states.remove(path);
+        false
       }
     }
+  }
 
-    Ok(())
+  fn forget(&self, path: &Path) {
+    let mut states = self.states.lock();
+    states.retain(|known_path, _| !known_path.starts_with(path));
   }
 }
 
-fn event_loop(
-  rx: Receiver<DebounceEventResult>,
-  stop_rx: Receiver<()>,
+/// Runs the event loop and recreates the notify watcher with exponential backoff whenever it
+/// fails, so that the index does not silently go stale.
+struct Supervisor {
+  path: String,
+  factory: WatchFactory,
+  cancellation: CancellationToken,
+  normalizer: PathNormalizer,
+  event_filter: Option<Arc<EventFilter>>,
+  event_log: Option<Arc<EventLog>>,
+  file_states: FileStates,
   handler: Box<FileOperationHandler>,
-) {
-  loop {
-    if stop_rx.try_recv().is_ok() {
-      break;
+  restarts: Arc<AtomicU64>,
+}
+
+impl Supervisor {
+  /// The canonical root, if absolute: an empty root would prefix-match every indexed document.
+  fn root(&self) -> Option<String> {
+    let root = self.normalizer.root();
+    root
+      .is_absolute()
+      .then(|| root.to_string_lossy().to_string())
+  }
+
+  fn handle(&self, op: FileOperation) {
+    if let Err(e) = (self.handler)(&op) {
+      tracing::error!("Error processing {:?}: {}", op, e);
     }
+  }
+
+  async fn run(self, watch: NotifyWatch) {
+    let mut watch = Some(watch);
+    let mut backoff = INITIAL_RESTART_BACKOFF;
+    loop {
+      if let Some((debouncer, mut rx)) = watch.take() {
+        let started_at = Instant::now();
+        let exit = event_loop(&mut rx, &self).await;
+        drop(debouncer);
+        if started_at.elapsed() >= MAX_RESTART_BACKOFF {
+          backoff = INITIAL_RESTART_BACKOFF;
+        }
+        match exit {
+          LoopExit::Stopped => break,
+          LoopExit::WatchLost => tracing::warn!(
+            "File watcher for {} stopped unexpectedly, restarting in {:?}",
+            self.path,
+            backoff
+          ),
+          LoopExit::RootRemoved => {
+            tracing::warn!(
+              "Watch root {} was removed, waiting for it to reappear",
+              self.path
+            );
+            if let Some(root) = self.root() {
+              self.handle(FileOperation::DirectoryDeleted(root));
+            }
+          }
+        }
+      }
 
-    match rx.recv_timeout(Duration::from_millis(100)) {
-      Result::Ok(result) => match result {
-        Result::Ok(events) => {
-          let _ = process_events(events, &handler).map_err(|e| {
+      tokio::select! {
+        _ = self.cancellation.cancelled() => break,
+        _ = tokio::time::sleep(backoff) => {}
+      }
+      backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+      match (self.factory)() {
+        Ok(new_watch) => {
+          self.restarts.fetch_add(1, Ordering::Relaxed);
+          tracing::info!("Restarted file watcher for {}", self.path);
+          watch = Some(new_watch);
+          /* Events may have been missed while the watch was down */
+          if let Some(root) = self.root() {
+            self.handle(FileOperation::DirectoryRescan(root));
+          }
+        }
+        Err(e) => tracing::error!("Failed to restart file watcher for {}: {}", self.path, e),
+      }
+    }
+  }
+}
+
+async fn event_loop(
+  rx: &mut UnboundedReceiver<DebounceEventResult>,
+  supervisor: &Supervisor,
+) -> LoopExit {
+  let root = supervisor.normalizer.root();
+  let mut root_check = tokio::time::interval_at(
+    tokio::time::Instant::now() + ROOT_CHECK_INTERVAL,
+    ROOT_CHECK_INTERVAL,
+  );
+  loop {
+    tokio::select! {
+      _ = supervisor.cancellation.cancelled() => return LoopExit::Stopped,
+      /* A removed root does not always surface as an event, and its watch is gone for good */
+      _ = root_check.tick() => {
+        if root.is_absolute() && !root.exists() {
+          return LoopExit::RootRemoved;
+        }
+      }
+      result = rx.recv() => match result {
+        Some(Ok(events)) => {
+          let _ = process_events(events, supervisor).map_err(|e| {
             tracing::error!("Error processing file events: {}", e);
             e
           });
         }
-        Result::Err(errors) => {
+        Some(Err(errors)) => {
+          let mut watch_lost = false;
           for error in errors {
-            tracing::error!("File watcher error: {}", error);
+            if is_watch_limit_notify_error(&error) {
+              tracing::error!("New directories are not watched: {}", WATCH_LIMIT_GUIDANCE);
+            } else {
+              tracing::error!("File watcher error: {}", error);
+              watch_lost |= matches!(
+                error.kind,
+                notify::ErrorKind::WatchNotFound | notify::ErrorKind::PathNotFound
+              );
+            }
+          }
+          if watch_lost {
+            return LoopExit::WatchLost;
           }
         }
+        None => return LoopExit::WatchLost,
       },
-      Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
-        // タイムアウトは正常
-      }
-      Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
-        break;
-      }
     }
   }
 }
@@ -128,47 +416,50 @@ fn process_paths(
   paths.iter().map(|path| f(path)).collect()
 }
 
-pub fn normalize_notify_path(path: &Path) -> PathBuf {
-  /* notify sends path includes unnecessary "./" */
-  path
-    .components()
-    .filter(|component| !matches!(component, Component::CurDir))
-    .collect::<PathBuf>()
-}
-
-fn process_events(events: Vec<DebouncedEvent>, handler: &FileOperationHandler) -> Result<()> {
+fn process_events(events: Vec<DebouncedEvent>, supervisor: &Supervisor) -> Result<()> {
+  let normalizer = &supervisor.normalizer;
+  let file_states = &supervisor.file_states;
   events
     .into_iter()
     .flat_map(|event| {
       tracing::debug!("Processing event: {:?} for path {:?}", event, event.paths);
+      if let Some(event_log) = &supervisor.event_log {
+        event_log.record(
+          EventStage::Raw,
+          format!("{:?} {:?}", event.kind, event.paths),
+        );
+      }
       match event.kind {
         EventKind::Create(_) => process_paths(&event.paths, |path: &Path| {
-          Ok(FileOperation::FileCreated(
-            normalize_notify_path(path).to_string_lossy().to_string(),
-          ))
+          file_states.update(path);
+          Ok(FileOperation::FileCreated(normalizer.normalize_str(path)))
         }),
         EventKind::Modify(modify_kind) => match modify_kind {
           ModifyKind::Data(_) => process_paths(&event.paths, |path: &Path| {
-            Ok(FileOperation::FileModified(
-              normalize_notify_path(path).to_string_lossy().to_string(),
-            ))
+            file_states.update(path);
+            Ok(FileOperation::FileModified(normalizer.normalize_str(path)))
           }),
+          ModifyKind::Metadata(_) | ModifyKind::Any | ModifyKind::Other => event
+            .paths
+            .iter()
+            .filter(|path| file_states.update(path))
+            .map(|path| Ok(FileOperation::FileModified(normalizer.normalize_str(path))))
+            .collect(),
           ModifyKind::Name(RenameMode::Both) => {
             const OLD_PATH_INDEX: usize = 0;
             const NEW_PATH_INDEX: usize = 1;
             let old_path = event.paths.get(OLD_PATH_INDEX);
             let new_path = event.paths.get(NEW_PATH_INDEX);
             if let (Some(old), Some(new)) = (old_path, new_path) {
-              if new.is_file() {
-                vec![Ok(FileOperation::FileRenamed {
-                  old_path: normalize_notify_path(old).to_string_lossy().to_string(),
-                  new_path: normalize_notify_path(new).to_string_lossy().to_string(),
-                })]
-              } else {
-                vec![Ok(FileOperation::DirectoryRenamed {
-                  old_path: normalize_notify_path(old).to_string_lossy().to_string(),
-                  new_path: normalize_notify_path(new).to_string_lossy().to_string(),
-                })]
+              let old_path = normalizer.normalize_str(old);
+              let new_path = normalizer.normalize_str(new);
+              /* symlink_metadata does not follow a symlink to its target's type */
+              match new.symlink_metadata() {
+                Ok(metadata) if metadata.is_dir() => {
+                  vec![Ok(FileOperation::DirectoryRenamed { old_path, new_path })]
+                }
+                Ok(_) => vec![Ok(FileOperation::FileRenamed { old_path, new_path })],
+                Err(_) => vec![Ok(FileOperation::PathRenamed { old_path, new_path })],
               }
             } else {
               vec![Err(anyhow::anyhow!("Rename event missing paths"))]
@@ -177,13 +468,13 @@ fn process_events(events: Vec<DebouncedEvent>, handler: &FileOperationHandler) -
           _ => vec![], // 他の ModifyKind は無視
         },
         EventKind::Remove(RemoveKind::File) => process_paths(&event.paths, |path: &Path| {
-          Ok(FileOperation::FileDeleted(
-            normalize_notify_path(path).to_string_lossy().to_string(),
-          ))
+          file_states.forget(path);
+          Ok(FileOperation::FileDeleted(normalizer.normalize_str(path)))
         }),
         EventKind::Remove(RemoveKind::Folder) => process_paths(&event.paths, |path: &Path| {
+          file_states.forget(path);
           Ok(FileOperation::DirectoryDeleted(
-            normalize_notify_path(path).to_string_lossy().to_string(),
+            normalizer.normalize_str(path),
           ))
         }),
         _ => vec![], // その他のイベントは無視
@@ -192,7 +483,15 @@ fn process_events(events: Vec<DebouncedEvent>, handler: &FileOperationHandler) -
     .collect::<Result<Vec<_>>>()
     .and_then(|ops| {
       for op in ops {
-        handler(&op)?;
+        if supervisor
+          .event_filter
+          .as_ref()
+          .is_none_or(|event_filter| event_filter.is_relevant(&op))
+        {
+          (supervisor.handler)(&op)?;
+        } else if let Some(event_log) = &supervisor.event_log {
+          event_log.record(EventStage::Filtered, format!("{:?}", op));
+        }
       }
       Ok(())
     })
@@ -206,3 +505,78 @@ impl Drop for NotifyFileWatcher {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+
+  #[test]
+  fn file_states_should_report_change_only_when_size_or_mtime_differs() {
+    let dir = std::env::temp_dir().join(format!("file-states-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("a.txt");
+    fs::write(&path, "content").unwrap();
+    let file_states = FileStates::default();
+
+    assert!(file_states.update(&path));
+    assert!(!file_states.update(&path));
+    fs::write(&path, "").unwrap();
+    assert!(file_states.update(&path));
+    assert!(!file_states.update(&dir));
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn file_watcher_should_delete_and_rescan_removed_and_recreated_root() {
+    let dir = std::env::temp_dir().join(format!("file-watcher-root-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let root = dir.canonicalize().unwrap().to_string_lossy().to_string();
+    let operations = Arc::new(Mutex::new(Vec::new()));
+    let recorded = operations.clone();
+    let mut watcher = NotifyFileWatcher::new();
+    watcher
+      .watch_directory(
+        &dir.to_string_lossy(),
+        Box::new(move |op| {
+          recorded.lock().push(op.clone());
+          Ok(())
+        }),
+      )
+      .unwrap();
+    let has_operation = |expected: &FileOperation| operations.lock().contains(expected);
+
+    tokio::time::sleep(ROOT_CHECK_INTERVAL * 2).await;
+    assert!(operations.lock().is_empty());
+
+    fs::remove_dir_all(&dir).unwrap();
+    let deleted = FileOperation::DirectoryDeleted(root.clone());
+    for _ in 0..50 {
+      if has_operation(&deleted) {
+        break;
+      }
+      tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(has_operation(&deleted));
+
+    fs::create_dir_all(&dir).unwrap();
+    let rescan = FileOperation::DirectoryRescan(root.clone());
+    for _ in 0..100 {
+      if has_operation(&rescan) {
+        break;
+      }
+      tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(has_operation(&rescan));
+    assert!(
+      operations
+        .lock()
+        .iter()
+        .all(|op| op.paths().iter().all(|path| path.starts_with(&root)))
+    );
+
+    watcher.stop_watching().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,466 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::{
+  fs::OpenOptions,
+  io::Write,
+  path::{Component, Path, PathBuf},
+  sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+  },
+};
+
+use crate::search::{
+  file::{FileFilter, FileOperation},
+  index_backend::unix_time_ms,
+};
+
+/// Changes kept for `undo_last`; the oldest are forgotten first, their trashed files kept.
+const MAX_UNDO_CHANGES: usize = 100;
+/// Directories of version control systems, never written even when indexed: a file such as
+/// `.git/hooks/pre-commit` would run on the user's next commit.
+const VCS_DIRECTORIES: &[&str] = &[".git", ".hg", ".svn", ".jj", ".bzr"];
+
+/// A change made by the writer, with where the content it replaced or removed was trashed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+  Created {
+    path: PathBuf,
+  },
+  Modified {
+    path: PathBuf,
+    backup: PathBuf,
+  },
+  Deleted {
+    path: PathBuf,
+    backup: PathBuf,
+  },
+  Moved {
+    from: PathBuf,
+    to: PathBuf,
+    /// The file that was at `to` before, if it was replaced
+    replaced: Option<PathBuf>,
+  },
+}
+
+impl Change {
+  /// What undoing the change did, for the client.
+  pub fn describe_undo(&self) -> String {
+    match self {
+      Change::Created { path } => format!("Moved the created file {} to the trash", path.display()),
+      Change::Modified { path, .. } => {
+        format!("Restored the previous content of {}", path.display())
+      }
+      Change::Deleted { path, .. } => format!("Restored the deleted file {}", path.display()),
+      Change::Moved { from, to, .. } => {
+        format!("Moved {} back to {}", to.display(), from.display())
+      }
+    }
+  }
+
+  /// Index updates undoing the change calls for.
+  pub fn undo_operations(&self) -> Vec<FileOperation> {
+    let path = |path: &Path| path.to_string_lossy().to_string();
+    match self {
+      Change::Created { path: created } => vec![FileOperation::FileDeleted(path(created))],
+      Change::Modified { path: modified, .. } => vec![FileOperation::FileModified(path(modified))],
+      Change::Deleted { path: deleted, .. } => vec![FileOperation::FileCreated(path(deleted))],
+      Change::Moved { from, to, replaced } => vec![
+        match replaced {
+          Some(_) => FileOperation::FileModified(path(to)),
+          None => FileOperation::FileDeleted(path(to)),
+        },
+        FileOperation::FileCreated(path(from)),
+      ],
+    }
+  }
+}
+
+/// Creates, updates, moves and deletes files for clients, only inside the watched roots. The
+/// watchers pick the changes up and re-index them like any other edit. Content that is replaced
+/// or deleted is moved to a trash directory first, so that the last changes can be undone.
+pub struct FileWriter {
+  roots: Vec<PathBuf>,
+  /// Decides which files below a root are indexed, and so may be written
+  root_filters: Vec<(PathBuf, Arc<dyn FileFilter + Send + Sync>)>,
+  trash_dir: PathBuf,
+  next_trash_id: AtomicU64,
+  changes: Mutex<Vec<Change>>,
+}
+
+impl FileWriter {
+  pub fn new(watched_roots: &[PathBuf], trash_dir: PathBuf) -> Self {
+    let roots = watched_roots
+      .iter()
+      .filter_map(|root| {
+        root
+          .canonicalize()
+          .inspect_err(|e| tracing::warn!("Cannot write below {:?}: {}", root, e))
+          .ok()
+      })
+      .filter(|root| root.is_dir())
+      .collect::<Vec<_>>();
+    if roots.iter().any(|root| trash_dir.starts_with(root)) {
+      tracing::warn!(
+        "Trash directory {:?} is inside a watched directory, so trashed files stay searchable",
+        trash_dir
+      );
+    }
+    FileWriter {
+      roots,
+      root_filters: Vec::new(),
+      trash_dir,
+      next_trash_id: AtomicU64::new(0),
+      changes: Mutex::new(Vec::new()),
+    }
+  }
+
+  /// Refuses paths below `root` that `file_filter` does not index, such as files in excluded or
+  /// ignored directories or with other extensions.
+  pub fn with_root_filter(
+    mut self,
+    root: &Path,
+    file_filter: Arc<dyn FileFilter + Send + Sync>,
+  ) -> Self {
+    match root.canonicalize() {
+      Ok(root) => self.root_filters.push((root, file_filter)),
+      Err(e) => tracing::warn!("Cannot write below {:?}: {}", root, e),
+    }
+    self
+  }
+
+  /// Resolves an absolute path, refusing paths outside the watched roots, including through `..`
+  /// or symbolic links, in version control directories, or that would not be indexed.
+  pub fn resolve(&self, file_path: &str) -> Result<PathBuf> {
+    let path = Path::new(file_path);
+    if !path.is_absolute()
+      || path
+        .components()
+        .any(|component| matches!(component, Component::ParentDir))
+    {
+      anyhow::bail!("{} must be an absolute path without ..", file_path);
+    }
+    /* The deepest existing ancestor is resolved so that symlinks cannot lead outside the roots */
+    let existing = path
+      .ancestors()
+      .find(|ancestor| ancestor.exists())
+      .ok_or_else(|| anyhow::anyhow!("Cannot access {}", file_path))?;
+    let mut resolved = existing
+      .canonicalize()
+      .map_err(|e| anyhow::anyhow!("Cannot access {}: {}", file_path, e))?;
+    /* Joining the empty remainder of an existing file would add a trailing slash */
+    let remainder = path.strip_prefix(existing)?;
+    if !remainder.as_os_str().is_empty() {
+      resolved.push(remainder);
+    }
+    let Some(root) = self.roots.iter().find(|root| resolved.starts_with(root)) else {
+      anyhow::bail!("{} is not inside a watched directory", file_path);
+    };
+    let in_vcs_directory = resolved
+      .strip_prefix(root)?
+      .components()
+      .any(|component| {
+        matches!(component, Component::Normal(name) if VCS_DIRECTORIES.iter().any(|vcs| name == *vcs))
+      });
+    if in_vcs_directory {
+      anyhow::bail!("{} is inside a version control directory", file_path);
+    }
+    let excluded = self
+      .root_filters
+      .iter()
+      .filter(|(filter_root, _)| resolved.starts_with(filter_root))
+      .any(|(_, file_filter)| !file_filter.is_target(&resolved.to_string_lossy()));
+    if excluded {
+      anyhow::bail!(
+        "{} would not be indexed: it is excluded, ignored or has an extension that is not indexed",
+        file_path
+      );
+    }
+    /* Only a dangling link is left unresolved, and writing through it could land anywhere */
+    if resolved.is_symlink() {
+      anyhow::bail!("{} is a broken symbolic link", file_path);
+    }
+    if resolved.is_dir() {
+      anyhow::bail!("{} is a directory", file_path);
+    }
+    Ok(resolved)
+  }
+
+  /// Writes a file, creating its directory if needed. Existing files are only replaced when
+  /// `overwrite` is set.
+  pub fn write(&self, file_path: &str, content: &str, overwrite: bool) -> Result<PathBuf> {
+    let path = self.resolve(file_path)?;
+    if path.exists() && !overwrite {
+      anyhow::bail!(
+        "{} already exists; set overwrite to replace it or append to it",
+        file_path
+      );
+    }
+    let (Some(parent), Some(file_name)) = (path.parent(), path.file_name()) else {
+      anyhow::bail!("{} is not a file path", file_path);
+    };
+    let backup = path
+      .exists()
+      .then(|| self.copy_to_trash(&path))
+      .transpose()?;
+    std::fs::create_dir_all(parent)?;
+    /* Written next to the target and renamed over it so the watcher never indexes a partial file */
+    let temporary_file = parent.join(format!(".{}.tmp", file_name.to_string_lossy()));
+    std::fs::write(&temporary_file, content)?;
+    std::fs::rename(&temporary_file, &path)?;
+    self.record(match backup {
+      Some(backup) => Change::Modified {
+        path: path.clone(),
+        backup,
+      },
+      None => Change::Created { path: path.clone() },
+    });
+    Ok(path)
+  }
+
+  /// Moves a file to the trash.
+  pub fn delete(&self, file_path: &str) -> Result<PathBuf> {
+    let path = self.resolve(file_path)?;
+    if !path.is_file() {
+      anyhow::bail!("{} does not exist", file_path);
+    }
+    let backup = self.move_to_trash(&path)?;
+    self.record(Change::Deleted {
+      path: path.clone(),
+      backup,
+    });
+    Ok(path)
+  }
+
+  /// Moves a file, creating the directory of its new path if needed. An existing file at the new
+  /// path is only replaced when `overwrite` is set.
+  pub fn rename(
+    &self,
+    from_path: &str,
+    to_path: &str,
+    overwrite: bool,
+  ) -> Result<(PathBuf, PathBuf)> {
+    let from = self.resolve(from_path)?;
+    let to = self.resolve(to_path)?;
+    if !from.is_file() {
+      anyhow::bail!("{} does not exist", from_path);
+    }
+    if to.exists() && !overwrite {
+      anyhow::bail!("{} already exists; set overwrite to replace it", to_path);
+    }
+    let replaced = to.exists().then(|| self.move_to_trash(&to)).transpose()?;
+    if let Some(parent) = to.parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+    std::fs::rename(&from, &to)?;
+    self.record(Change::Moved {
+      from: from.clone(),
+      to: to.clone(),
+      replaced,
+    });
+    Ok((from, to))
+  }
+
+  /// Appends to a file, creating it and its directory if needed.
+  pub fn append(&self, file_path: &str, content: &str) -> Result<PathBuf> {
+    let path = self.resolve(file_path)?;
+    let backup = path
+      .exists()
+      .then(|| self.copy_to_trash(&path))
+      .transpose()?;
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+    OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&path)?
+      .write_all(content.as_bytes())?;
+    self.record(match backup {
+      Some(backup) => Change::Modified {
+        path: path.clone(),
+        backup,
+      },
+      None => Change::Created { path: path.clone() },
+    });
+    Ok(path)
+  }
+
+  /// Reverts the most recent change still kept, returning it. A change that cannot be reverted,
+  /// e.g. because a file was created again at a deleted path, stays the last one.
+  pub fn undo_last(&self) -> Result<Change> {
+    let mut changes = self.changes.lock();
+    let change = changes
+      .last()
+      .ok_or_else(|| anyhow::anyhow!("There is no change to undo"))?;
+    match change {
+      Change::Created { path } => {
+        if path.exists() {
+          self.move_to_trash(path)?;
+        }
+      }
+      Change::Modified { path, backup } => restore(backup, path)?,
+      Change::Deleted { path, backup } => {
+        if path.exists() {
+          anyhow::bail!(
+            "{} exists again; move it away before undoing its deletion",
+            path.display()
+          );
+        }
+        restore(backup, path)?;
+      }
+      Change::Moved { from, to, replaced } => {
+        if from.exists() {
+          anyhow::bail!(
+            "{} exists again; move it away before undoing the move",
+            from.display()
+          );
+        }
+        if let Some(parent) = from.parent() {
+          std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(to, from)?;
+        if let Some(replaced) = replaced {
+          restore(replaced, to)?;
+        }
+      }
+    }
+    Ok(changes.pop().expect("the change undone is the last one"))
+  }
+
+  fn record(&self, change: Change) {
+    let mut changes = self.changes.lock();
+    if changes.len() >= MAX_UNDO_CHANGES {
+      changes.remove(0);
+    }
+    changes.push(change);
+  }
+
+  /// A new path in the trash for a file, unique across restarts.
+  fn trash_path(&self, path: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(&self.trash_dir)?;
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    Ok(self.trash_dir.join(format!(
+      "{}-{}-{}",
+      unix_time_ms(),
+      self.next_trash_id.fetch_add(1, Ordering::Relaxed),
+      file_name
+    )))
+  }
+
+  fn copy_to_trash(&self, path: &Path) -> Result<PathBuf> {
+    let trashed = self.trash_path(path)?;
+    std::fs::copy(path, &trashed)?;
+    Ok(trashed)
+  }
+
+  fn move_to_trash(&self, path: &Path) -> Result<PathBuf> {
+    let trashed = self.trash_path(path)?;
+    move_file(path, &trashed)?;
+    Ok(trashed)
+  }
+}
+
+/// Puts a trashed file back in place, replacing what is there now.
+fn restore(backup: &Path, path: &Path) -> Result<()> {
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+  move_file(backup, path)
+}
+
+/// Renames a file, copying it when the trash is on another file system.
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+  if std::fs::rename(from, to).is_err() {
+    std::fs::copy(from, to)?;
+    std::fs::remove_file(from)?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::file::file_filter::ExtensionFileFilter;
+
+  #[test]
+  fn file_writer_should_only_write_inside_watched_roots() {
+    let root = std::env::temp_dir().join(format!("file-writer-test-{}", std::process::id()));
+    let trash_dir = root.with_extension("trash");
+    std::fs::create_dir_all(&root).unwrap();
+    let writer = FileWriter::new(std::slice::from_ref(&root), trash_dir.clone());
+    let note = root.join("notes/todo.md");
+    let note_path = note.to_str().unwrap();
+
+    writer.write(note_path, "- water", false).unwrap();
+    assert!(writer.write(note_path, "- plant", false).is_err());
+    writer.append(note_path, "\n- plant").unwrap();
+    assert_eq!(std::fs::read_to_string(&note).unwrap(), "- water\n- plant");
+    writer.write(note_path, "- harvest", true).unwrap();
+    assert_eq!(std::fs::read_to_string(&note).unwrap(), "- harvest");
+
+    let moved = root.join("archive/todo.md");
+    let moved_path = moved.to_str().unwrap();
+    writer.rename(note_path, moved_path, false).unwrap();
+    assert!(!note.exists());
+    assert!(writer.delete(note_path).is_err());
+    writer.delete(moved_path).unwrap();
+    assert!(!moved.exists());
+
+    let escape = format!("{}/../escape.md", root.to_str().unwrap());
+    assert!(writer.write(&escape, "x", false).is_err());
+    assert!(writer.write("/etc/escape.md", "x", false).is_err());
+    assert!(writer.write("notes/relative.md", "x", false).is_err());
+
+    let writer = writer.with_root_filter(
+      &root,
+      Arc::new(ExtensionFileFilter::new(vec!["md".to_string()])),
+    );
+    let hook = root.join(".git/hooks/pre-commit.md");
+    assert!(writer.write(hook.to_str().unwrap(), "x", false).is_err());
+    let script = root.join("notes/run.sh");
+    assert!(writer.write(script.to_str().unwrap(), "x", false).is_err());
+    writer.write(note_path, "- sow", false).unwrap();
+    std::fs::remove_dir_all(&root).unwrap();
+    std::fs::remove_dir_all(&trash_dir).unwrap();
+  }
+
+  #[test]
+  fn file_writer_should_undo_changes_from_the_trash() {
+    let root = std::env::temp_dir().join(format!("file-writer-undo-test-{}", std::process::id()));
+    let trash_dir = root.with_extension("trash");
+    std::fs::create_dir_all(&root).unwrap();
+    let writer = FileWriter::new(std::slice::from_ref(&root), trash_dir.clone());
+    let note = root.join("todo.md");
+    let note_path = note.to_str().unwrap();
+    let moved = root.join("done.md");
+
+    writer.write(note_path, "- water", false).unwrap();
+    writer.write(note_path, "- harvest", true).unwrap();
+    writer
+      .rename(note_path, moved.to_str().unwrap(), false)
+      .unwrap();
+    writer.delete(moved.to_str().unwrap()).unwrap();
+
+    assert!(matches!(
+      writer.undo_last().unwrap(),
+      Change::Deleted { .. }
+    ));
+    assert_eq!(std::fs::read_to_string(&moved).unwrap(), "- harvest");
+    assert!(matches!(writer.undo_last().unwrap(), Change::Moved { .. }));
+    assert!(!moved.exists());
+    assert!(matches!(
+      writer.undo_last().unwrap(),
+      Change::Modified { .. }
+    ));
+    assert_eq!(std::fs::read_to_string(&note).unwrap(), "- water");
+    assert!(matches!(
+      writer.undo_last().unwrap(),
+      Change::Created { .. }
+    ));
+    assert!(!note.exists());
+    assert!(writer.undo_last().is_err());
+    std::fs::remove_dir_all(&root).unwrap();
+    std::fs::remove_dir_all(&trash_dir).unwrap();
+  }
+}
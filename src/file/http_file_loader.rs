@@ -0,0 +1,286 @@
+use anyhow::Result;
+use quick_xml::{Reader, events::Event};
+use std::{fs, io::Read, sync::Arc, time::Duration};
+
+use super::extractor::epub::xhtml_to_text;
+use crate::search::file::{File, FileFilter, FileLoader};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/* Sitemap indexes may point to further sitemaps; stop following them past this depth */
+const MAX_SITEMAP_DEPTH: usize = 3;
+
+pub fn is_url(path: &str) -> bool {
+  path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Accepts every HTTP(S) URL; which URLs are indexed is decided by the URL source.
+pub struct UrlFileFilter;
+
+impl FileFilter for UrlFileFilter {
+  fn is_target(&self, path: &str) -> bool {
+    is_url(path)
+  }
+}
+
+/// Loads documents over HTTP(S). A "directory" is a URL source: a sitemap or a file listing one
+/// URL per line, either local or itself served over HTTP(S).
+#[derive(Clone)]
+pub struct HttpFileLoader {
+  agent: ureq::Agent,
+  max_file_size: Option<u64>,
+  local_loader: Option<Arc<dyn FileLoader + Send + Sync>>,
+}
+
+impl HttpFileLoader {
+  pub fn new() -> Self {
+    HttpFileLoader {
+      agent: ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build(),
+      max_file_size: None,
+      local_loader: None,
+    }
+  }
+
+  pub fn with_max_file_size(mut self, max_file_size: Option<u64>) -> Self {
+    self.max_file_size = max_file_size;
+    self
+  }
+
+  /// Loads paths that are not URLs with `local_loader`, so one loader can serve both.
+  pub fn with_local_loader(mut self, local_loader: Arc<dyn FileLoader + Send + Sync>) -> Self {
+    self.local_loader = Some(local_loader);
+    self
+  }
+
+  fn local_loader(&self, path: &str) -> Result<&Arc<dyn FileLoader + Send + Sync>> {
+    self
+      .local_loader
+      .as_ref()
+      .ok_or_else(|| anyhow::anyhow!("Not an HTTP(S) URL: {}", path))
+  }
+
+  fn fetch(&self, url: &str) -> Result<(String, String)> {
+    let response = self.agent.get(url).call()?;
+    let content_type = response.content_type().to_lowercase();
+    let mut reader = response.into_reader();
+    let mut body = Vec::new();
+    match self.max_file_size {
+      Some(limit) => {
+        reader.by_ref().take(limit + 1).read_to_end(&mut body)?;
+        if body.len() as u64 > limit {
+          anyhow::bail!("{} is larger than {} bytes", url, limit);
+        }
+      }
+      None => {
+        reader.read_to_end(&mut body)?;
+      }
+    }
+    Ok((content_type, String::from_utf8_lossy(&body).into_owned()))
+  }
+
+  fn read_source(&self, source: &str) -> Result<String> {
+    if is_url(source) {
+      self.fetch(source).map(|(_, body)| body)
+    } else {
+      Ok(fs::read_to_string(source)?)
+    }
+  }
+
+  /// Lists the URLs of a sitemap (following sitemap indexes) or of a URL list file.
+  pub fn load_url_list(&self, source: &str) -> Result<Vec<String>> {
+    self.load_url_list_with_depth(source, 0)
+  }
+
+  fn load_url_list_with_depth(&self, source: &str, depth: usize) -> Result<Vec<String>> {
+    let content = self.read_source(source)?;
+    let Some(sitemap) = parse_sitemap(&content) else {
+      return Ok(parse_url_lines(&content));
+    };
+    let mut urls = sitemap.urls;
+    for nested in sitemap.sitemaps {
+      if depth >= MAX_SITEMAP_DEPTH {
+        tracing::warn!("Not following nested sitemap {}: too deep", nested);
+        continue;
+      }
+      match self.load_url_list_with_depth(&nested, depth + 1) {
+        Ok(nested_urls) => urls.extend(nested_urls),
+        Err(e) => tracing::error!("Failed to load sitemap {}: {}", nested, e),
+      }
+    }
+    urls.sort();
+    urls.dedup();
+    Ok(urls)
+  }
+}
+
+impl Default for HttpFileLoader {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[async_trait::async_trait]
+impl FileLoader for HttpFileLoader {
+  fn load_directory<'a>(
+    &'a self,
+    path: &str,
+    file_filter: &'a dyn FileFilter,
+  ) -> Box<dyn Iterator<Item = Result<File>> + 'a> {
+    match self.load_url_list(path) {
+      Ok(urls) => Box::new(
+        urls
+          .into_iter()
+          .filter(move |url| file_filter.is_target(url))
+          .map(move |url| {
+            self
+              .load_file_blocking(&url)
+              .inspect_err(|e| tracing::error!("Failed to load {}: {}", url, e))
+          }),
+      ),
+      Err(e) => Box::new(std::iter::once(Err(e))),
+    }
+  }
+
+  fn load_file_blocking(&self, path: &str) -> Result<File> {
+    if !is_url(path) {
+      return self.local_loader(path)?.load_file_blocking(path);
+    }
+    let (content_type, body) = self.fetch(path)?;
+    if !content_type.contains("html") {
+      return Ok(File::new(path.to_string(), body));
+    }
+    let (title, text) = xhtml_to_text(&body);
+    let file = File::new(path.to_string(), text);
+    Ok(match title {
+      Some(title) => file.with_metadata("title", &title),
+      None => file,
+    })
+  }
+
+  async fn load_file(&self, path: &str) -> Result<File> {
+    if !is_url(path) {
+      return self.local_loader(path)?.load_file(path).await;
+    }
+    let loader = self.clone();
+    let path = path.to_string();
+    tokio::task::spawn_blocking(move || loader.load_file_blocking(&path)).await?
+  }
+
+  fn is_container(&self, path: &str) -> bool {
+    !is_url(path)
+      && self
+        .local_loader
+        .as_ref()
+        .is_some_and(|local_loader| local_loader.is_container(path))
+  }
+
+  fn load_documents(&self, path: &str, file_filter: &dyn FileFilter) -> Result<Vec<File>> {
+    if !is_url(path) {
+      return self.local_loader(path)?.load_documents(path, file_filter);
+    }
+    self.load_file_blocking(path).map(|file| vec![file])
+  }
+}
+
+struct Sitemap {
+  urls: Vec<String>,
+  sitemaps: Vec<String>,
+}
+
+/// Parses `<urlset>` and `<sitemapindex>` documents, or returns `None` for anything else.
+fn parse_sitemap(content: &str) -> Option<Sitemap> {
+  let mut reader = Reader::from_str(content);
+  reader.config_mut().trim_text(true);
+
+  let mut sitemap: Option<Sitemap> = None;
+  let mut is_index = false;
+  let mut in_loc = false;
+  loop {
+    match reader.read_event() {
+      Ok(Event::Start(e)) => match e.local_name().as_ref() {
+        name @ (b"urlset" | b"sitemapindex") => {
+          is_index = name == b"sitemapindex";
+          sitemap.get_or_insert(Sitemap {
+            urls: Vec::new(),
+            sitemaps: Vec::new(),
+          });
+        }
+        b"loc" => in_loc = true,
+        _ => {}
+      },
+      Ok(Event::End(e)) if e.local_name().as_ref() == b"loc" => in_loc = false,
+      Ok(Event::Text(e)) if in_loc => {
+        if let (Some(sitemap), Ok(loc)) = (sitemap.as_mut(), e.unescape()) {
+          let loc = loc.trim().to_string();
+          if is_index {
+            sitemap.sitemaps.push(loc);
+          } else {
+            sitemap.urls.push(loc);
+          }
+        }
+      }
+      Ok(Event::Eof) | Err(_) => break,
+      _ => {}
+    }
+  }
+  sitemap
+}
+
+/// One URL per line; blank lines and lines starting with `#` are ignored.
+fn parse_url_lines(content: &str) -> Vec<String> {
+  content
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    .filter(|line| {
+      let is_url = is_url(line);
+      if !is_url {
+        tracing::warn!("Ignoring line that is not an HTTP(S) URL: {}", line);
+      }
+      is_url
+    })
+    .map(str::to_string)
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_sitemap_should_list_urls_and_nested_sitemaps() {
+    let urlset = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url><loc>https://docs.example.com/a?x=1&amp;y=2</loc><lastmod>2024-01-01</lastmod></url>
+  <url><loc> https://docs.example.com/b </loc></url>
+</urlset>"#;
+    let sitemap = parse_sitemap(urlset).expect("Failed to parse urlset");
+    assert_eq!(
+      sitemap.urls,
+      vec![
+        "https://docs.example.com/a?x=1&y=2",
+        "https://docs.example.com/b"
+      ]
+    );
+    assert!(sitemap.sitemaps.is_empty());
+
+    let index = r#"<sitemapindex><sitemap><loc>https://docs.example.com/sitemap-1.xml</loc></sitemap></sitemapindex>"#;
+    let sitemap = parse_sitemap(index).expect("Failed to parse sitemap index");
+    assert!(sitemap.urls.is_empty());
+    assert_eq!(
+      sitemap.sitemaps,
+      vec!["https://docs.example.com/sitemap-1.xml"]
+    );
+
+    assert!(parse_sitemap("https://docs.example.com/a\n").is_none());
+  }
+
+  #[test]
+  fn parse_url_lines_should_skip_comments_blank_lines_and_non_urls() {
+    let content =
+      "# docs\nhttps://docs.example.com/a\n\n  http://docs.example.com/b  \n/local/file.txt\n";
+    assert_eq!(
+      parse_url_lines(content),
+      vec!["https://docs.example.com/a", "http://docs.example.com/b"]
+    );
+  }
+}
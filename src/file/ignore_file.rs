@@ -0,0 +1,116 @@
+use ignore::{
+  Match,
+  gitignore::{Gitignore, GitignoreBuilder},
+};
+use parking_lot::Mutex;
+use std::{
+  collections::HashMap,
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::SystemTime,
+};
+
+/// Ignore files in order of precedence within one directory, as in ripgrep.
+pub const IGNORE_FILE_NAMES: &[&str] = &[".rgignore", ".ignore", ".gitignore"];
+
+struct CachedIgnoreFile {
+  modified: Option<SystemTime>,
+  matcher: Option<Arc<Gitignore>>,
+}
+
+/// Matches paths against the `.rgignore`, `.ignore` and `.gitignore` files found between the watch
+/// root and the path. Rules of deeper directories win over those of their parents.
+pub struct IgnoreFiles {
+  root: PathBuf,
+  cache: Mutex<HashMap<PathBuf, CachedIgnoreFile>>,
+}
+
+impl IgnoreFiles {
+  pub fn new(root: &Path) -> Self {
+    Self {
+      root: root.to_path_buf(),
+      cache: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// `relative_path` is relative to the watch root.
+  pub fn is_ignored(&self, relative_path: &Path) -> bool {
+    let path = self.root.join(relative_path);
+    let is_dir = path.is_dir();
+    for dir in relative_path.ancestors().skip(1) {
+      let dir = self.root.join(dir);
+      for name in IGNORE_FILE_NAMES {
+        let Some(matcher) = self.matcher(&dir.join(name)) else {
+          continue;
+        };
+        match matcher.matched_path_or_any_parents(&path, is_dir) {
+          Match::Ignore(_) => return true,
+          Match::Whitelist(_) => return false,
+          Match::None => {}
+        }
+      }
+    }
+    false
+  }
+
+  fn matcher(&self, ignore_file: &Path) -> Option<Arc<Gitignore>> {
+    /* Ignore files are re-read whenever their modification time changes */
+    let modified = ignore_file
+      .metadata()
+      .and_then(|metadata| metadata.modified())
+      .ok();
+    let mut cache = self.cache.lock();
+    if let Some(cached) = cache.get(ignore_file)
+      && cached.modified == modified
+    {
+      return cached.matcher.clone();
+    }
+    let matcher = modified
+      .and_then(|_| build_matcher(ignore_file))
+      .map(Arc::new);
+    cache.insert(
+      ignore_file.to_path_buf(),
+      CachedIgnoreFile {
+        modified,
+        matcher: matcher.clone(),
+      },
+    );
+    matcher
+  }
+}
+
+fn build_matcher(ignore_file: &Path) -> Option<Gitignore> {
+  let mut builder = GitignoreBuilder::new(ignore_file.parent()?);
+  if let Some(e) = builder.add(ignore_file) {
+    tracing::warn!("Failed to read ignore file {:?}: {}", ignore_file, e);
+  }
+  builder
+    .build()
+    .inspect_err(|e| tracing::warn!("Invalid ignore file {:?}: {}", ignore_file, e))
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+
+  #[test]
+  fn ignore_files_should_prefer_deeper_and_higher_precedence_rules() {
+    let root = std::env::temp_dir().join(format!("ignore-files-test-{}", std::process::id()));
+    fs::create_dir_all(root.join("logs/keep")).unwrap();
+    fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+    fs::write(root.join(".ignore"), "drafts/\n").unwrap();
+    fs::write(root.join("logs/keep/.rgignore"), "!*.log\n").unwrap();
+    fs::write(root.join("logs/.ignore"), "*.tmp\n").unwrap();
+
+    let ignore_files = IgnoreFiles::new(&root);
+    assert!(ignore_files.is_ignored(Path::new("app.log")));
+    assert!(ignore_files.is_ignored(Path::new("drafts/a.md")));
+    assert!(ignore_files.is_ignored(Path::new("logs/a.tmp")));
+    assert!(!ignore_files.is_ignored(Path::new("logs/keep/app.log")));
+    assert!(!ignore_files.is_ignored(Path::new("notes.md")));
+
+    fs::remove_dir_all(&root).unwrap();
+  }
+}
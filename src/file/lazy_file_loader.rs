@@ -1,14 +1,212 @@
 use anyhow::Result;
+use std::{path::Path, sync::Arc};
 use walkdir::WalkDir;
 
-use super::read_file::path_to_file;
-use crate::search::file::{File, FileLoader};
+use super::{
+  archive::{is_archive_path, read_archive_member, read_archive_members},
+  chunk::split_into_chunks,
+  extractor::{
+    ExtractorRegistry,
+    email::{is_mailbox_path, read_mailbox_message, read_mailbox_messages},
+  },
+  file_filter::PathExclusion,
+  outline::split_into_sections,
+  path_normalizer::PathNormalizer,
+  read_file::RetryPolicy,
+  throttle::RateLimiter,
+};
+use crate::search::{
+  file::{
+    CONTAINER_MEMBER_SEPARATOR, File, FileChunks, FileFilter, FileLoader, container_member_prefix,
+    is_section_file, parse_chunk_path, parse_section_path,
+  },
+  skipped_files::SkippedFiles,
+};
 
-pub struct LazyFileLoader {}
+#[derive(Clone)]
+pub struct LazyFileLoader {
+  exclusion: PathExclusion,
+  max_file_size: Option<u64>,
+  skipped_files: Arc<SkippedFiles>,
+  extractors: Arc<ExtractorRegistry>,
+  index_archives: bool,
+  chunk_size: Option<usize>,
+  index_sections: bool,
+  retry_policy: RetryPolicy,
+  rate_limiter: Option<Arc<RateLimiter>>,
+}
 
 impl LazyFileLoader {
   pub fn new() -> Self {
-    LazyFileLoader {}
+    LazyFileLoader {
+      exclusion: PathExclusion::none(),
+      max_file_size: None,
+      skipped_files: Arc::new(SkippedFiles::new()),
+      extractors: Arc::new(ExtractorRegistry::new_with_default_extractors()),
+      index_archives: false,
+      chunk_size: None,
+      index_sections: false,
+      retry_policy: RetryPolicy::default(),
+      rate_limiter: None,
+    }
+  }
+
+  pub fn new_with_options(
+    exclusion: PathExclusion,
+    max_file_size: Option<u64>,
+    skipped_files: Arc<SkippedFiles>,
+    extractors: Arc<ExtractorRegistry>,
+  ) -> Self {
+    LazyFileLoader {
+      exclusion,
+      max_file_size,
+      skipped_files,
+      extractors,
+      index_archives: false,
+      chunk_size: None,
+      index_sections: false,
+      retry_policy: RetryPolicy::default(),
+      rate_limiter: None,
+    }
+  }
+
+  pub fn with_archive_indexing(mut self, index_archives: bool) -> Self {
+    self.index_archives = index_archives;
+    self
+  }
+
+  /// Files larger than `chunk_size` bytes are indexed as overlapping `path#chunk=<offset>`
+  /// documents.
+  pub fn with_chunk_size(mut self, chunk_size: Option<usize>) -> Self {
+    self.chunk_size = chunk_size;
+    self
+  }
+
+  /// Markdown files are indexed as one `path#anchor` document per heading section instead of
+  /// being chunked.
+  pub fn with_section_indexing(mut self, index_sections: bool) -> Self {
+    self.index_sections = index_sections;
+    self
+  }
+
+  pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+    self.retry_policy = retry_policy;
+    self
+  }
+
+  /// Loads at most `max_files_per_second` files while crawling directories and applying changes,
+  /// `None` for no limit. Files loaded on request, e.g. by the load_file tool, are not limited.
+  pub fn with_rate_limit(mut self, max_files_per_second: Option<u32>) -> Self {
+    self.rate_limiter =
+      max_files_per_second.map(|per_second| Arc::new(RateLimiter::new(per_second)));
+    self
+  }
+
+  fn throttle(&self) {
+    if let Some(rate_limiter) = &self.rate_limiter {
+      rate_limiter.wait();
+    }
+  }
+
+  /// Keeps files over the size limit out of the index. Files are still loaded in full on request,
+  /// e.g. by load_file, which reads line ranges of them.
+  fn check_size(&self, path: &Path) -> Result<()> {
+    if let Some(max_file_size) = self.max_file_size {
+      let size = std::fs::metadata(path)?.len();
+      if size > max_file_size {
+        let reason = format!(
+          "File size {} bytes exceeds the limit of {} bytes",
+          size, max_file_size
+        );
+        self
+          .skipped_files
+          .record(path.to_string_lossy().as_ref(), &reason);
+        return Err(anyhow::anyhow!(reason));
+      }
+    }
+    self.skipped_files.remove(path.to_string_lossy().as_ref());
+    Ok(())
+  }
+
+  /// Records why a file failed to load so that it can be listed with the skipped files, or forgets
+  /// an earlier failure once it loads.
+  fn track_failure<T>(&self, path: &Path, result: Result<T>) -> Result<T> {
+    let path = path.to_string_lossy();
+    match &result {
+      Ok(_) => self.skipped_files.remove(path.as_ref()),
+      Err(e) => self.skipped_files.record_failure(path.as_ref(), e),
+    }
+    result
+  }
+
+  fn extract(&self, path: &Path) -> Result<File> {
+    let extractor = self.extractors.get(path);
+    self.retry_policy.retry(|| extractor.extract(path))
+  }
+
+  fn load(&self, path: &Path) -> Result<File> {
+    let file = self.extract(path);
+    self.track_failure(path, file)
+  }
+
+  fn load_chunks(&self, path: &Path, chunk_size: usize) -> Result<FileChunks> {
+    let chunks = self.extract_chunks(path, chunk_size);
+    self.track_failure(path, chunks)
+  }
+
+  fn extract_chunks(&self, path: &Path, chunk_size: usize) -> Result<FileChunks> {
+    let extractor = self.extractors.get(path);
+    match self
+      .retry_policy
+      .retry(|| extractor.extract_chunks(path, chunk_size))?
+    {
+      Some(chunks) => Ok(chunks),
+      None => Ok(Box::new(
+        split_into_chunks(
+          self.retry_policy.retry(|| extractor.extract(path))?,
+          chunk_size,
+        )
+        .into_iter()
+        .map(Ok),
+      )),
+    }
+  }
+
+  fn is_split_into_sections(&self, path: &str) -> bool {
+    self.index_sections && is_section_file(path)
+  }
+
+  fn load_sections(&self, path: &str) -> Result<Vec<File>> {
+    Ok(split_into_sections(self.load(Path::new(path))?))
+  }
+
+  fn documents(&self, path: &str, file_filter: &dyn FileFilter) -> Result<FileChunks> {
+    match self.chunk_size {
+      _ if self.is_split_into_sections(path) => {
+        self.check_size(Path::new(path))?;
+        Ok(Box::new(self.load_sections(path)?.into_iter().map(Ok)))
+      }
+      Some(chunk_size) if !self.is_container(path) => {
+        self.check_size(Path::new(path))?;
+        self.load_chunks(Path::new(path), chunk_size)
+      }
+      _ => Ok(Box::new(
+        self.load_documents(path, file_filter)?.into_iter().map(Ok),
+      )),
+    }
+  }
+
+  fn load_archive_members(&self, path: &str, file_filter: &dyn FileFilter) -> Result<Vec<File>> {
+    let prefix = container_member_prefix(path);
+    let members = read_archive_members(Path::new(path), self.max_file_size);
+    Ok(
+      self
+        .track_failure(Path::new(path), members)?
+        .into_iter()
+        .map(|(member, content)| File::new(format!("{}{}", prefix, member), content))
+        .filter(|file| file_filter.is_target(&file.path))
+        .collect(),
+    )
   }
 }
 
@@ -18,23 +216,188 @@ impl Default for LazyFileLoader {
   }
 }
 
+#[async_trait::async_trait]
 impl FileLoader for LazyFileLoader {
-  fn load_directory(
-    &self,
+  fn load_directory<'a>(
+    &'a self,
     dir_path: &str,
-  ) -> Box<dyn Iterator<Item = Result<crate::search::file::File>> + '_> {
+    file_filter: &'a dyn FileFilter,
+  ) -> Box<dyn Iterator<Item = Result<crate::search::file::File>> + 'a> {
+    let normalizer = PathNormalizer::new(Path::new(dir_path));
+    let follow_symlinks = self.exclusion.follow_symlinks;
     let paths = WalkDir::new(dir_path)
+      .follow_links(follow_symlinks)
       .into_iter()
-      .flatten()
-      .map(|e| e.path().to_owned())
-      .flat_map(|p| p.canonicalize())
-      .filter(|p| p.is_file());
+      .filter_entry(move |entry| {
+        entry.depth() == 0 || !self.exclusion.is_excluded_name(entry.file_name())
+      })
+      .filter_map(move |entry| {
+        entry
+          .inspect_err(|e| match (e.loop_ancestor(), e.path()) {
+            (Some(ancestor), _) => tracing::warn!("Skipping symlink loop back to {:?}", ancestor),
+            (None, Some(path)) => self
+              .skipped_files
+              .record_failure(path.to_string_lossy().as_ref(), &anyhow::anyhow!("{}", e)),
+            (None, None) => tracing::debug!("Skipping unreadable entry: {}", e),
+          })
+          .ok()
+      })
+      .filter(move |entry| follow_symlinks || !entry.path_is_symlink())
+      .filter(|entry| entry.file_type().is_file())
+      .map(move |entry| normalizer.normalize_str(entry.path()))
+      .filter(move |p| file_filter.is_target(p) || self.is_container(p));
 
-    Box::new(paths.map(|p| path_to_file(&p)))
+    Box::new(paths.inspect(move |_| self.throttle()).flat_map(move |p| {
+      match self.documents(&p, file_filter) {
+        Ok(files) => files,
+        Err(e) => Box::new(std::iter::once(Err(e))),
+      }
+    }))
   }
 
-  fn load_file(&self, path: &str) -> Result<File> {
-    let file_path = std::path::Path::new(path);
-    path_to_file(file_path)
+  async fn load_file(&self, path: &str) -> Result<File> {
+    /* Extractors and retries read synchronously, so they run on the blocking pool */
+    let loader = self.clone();
+    let path = path.to_string();
+    tokio::task::spawn_blocking(move || loader.load_file_blocking(&path)).await?
+  }
+
+  fn load_file_blocking(&self, path: &str) -> Result<File> {
+    if let Some((container_path, member)) = path.split_once(CONTAINER_MEMBER_SEPARATOR) {
+      if is_mailbox_path(Path::new(container_path)) {
+        return read_mailbox_message(Path::new(container_path), member);
+      }
+      if self.is_container(container_path) {
+        let content = read_archive_member(Path::new(container_path), member)?;
+        return Ok(File::new(path.to_string(), content));
+      }
+    }
+    if let Some((file_path, offset)) = parse_chunk_path(path) {
+      for chunk in self.load_chunks(Path::new(file_path), self.chunk_size.unwrap_or_default())? {
+        let chunk = chunk?;
+        if chunk.path == path {
+          return Ok(chunk);
+        }
+      }
+      return Err(anyhow::anyhow!(
+        "Chunk at offset {} not found in {:?}",
+        offset,
+        file_path
+      ));
+    }
+    if let Some((file_path, anchor)) = parse_section_path(path) {
+      return self
+        .load_sections(file_path)?
+        .into_iter()
+        .find(|section| section.path == path)
+        .ok_or_else(|| anyhow::anyhow!("Section #{} not found in {:?}", anchor, file_path));
+    }
+    /* Skipped files are about the index, so loading on request neither lists nor forgets them */
+    self.extract(Path::new(path))
+  }
+
+  fn is_container(&self, path: &str) -> bool {
+    let path = Path::new(path);
+    is_mailbox_path(path) || (self.index_archives && is_archive_path(path))
+  }
+
+  fn load_documents(&self, path: &str, file_filter: &dyn FileFilter) -> Result<Vec<File>> {
+    self.throttle();
+    if is_mailbox_path(Path::new(path)) {
+      /* Messages have no extension of their own, so the mailbox itself must be a target */
+      if file_filter.is_target(path) {
+        let messages = read_mailbox_messages(Path::new(path), self.max_file_size);
+        self.track_failure(Path::new(path), messages)
+      } else {
+        Ok(Vec::new())
+      }
+    } else if self.is_container(path) {
+      self.load_archive_members(path, file_filter)
+    } else {
+      self.check_size(Path::new(path))?;
+      if self.is_split_into_sections(path) {
+        return self.load_sections(path);
+      }
+      match self.chunk_size {
+        Some(chunk_size) => self.load_chunks(Path::new(path), chunk_size)?.collect(),
+        None => self.load(Path::new(path)).map(|file| vec![file]),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn lazy_file_loader_should_only_keep_large_files_out_of_index() {
+    let dir = std::env::temp_dir().join(format!("lazy-file-loader-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("large.txt");
+    std::fs::write(&path, "line one\nline two\n").unwrap();
+    let path = path.to_string_lossy().to_string();
+    let skipped_files = Arc::new(SkippedFiles::new());
+    let loader = LazyFileLoader::new_with_options(
+      PathExclusion::none(),
+      Some(8),
+      skipped_files.clone(),
+      Arc::new(ExtractorRegistry::new_with_default_extractors()),
+    );
+    let file_filter = crate::testing::MockFileFilter::default();
+
+    assert!(loader.load_documents(&path, &file_filter).is_err());
+    assert!(
+      loader
+        .load_directory(&dir.to_string_lossy(), &file_filter)
+        .all(|file| file.is_err())
+    );
+    assert_eq!(skipped_files.list().len(), 1);
+    assert_eq!(
+      loader.load_file_blocking(&path).unwrap().content,
+      "line one\nline two\n"
+    );
+    assert_eq!(skipped_files.list().len(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn lazy_file_loader_should_stop_at_symlink_loops_and_keep_symlink_paths() {
+    use std::os::unix::fs::symlink;
+
+    let dir = std::env::temp_dir().canonicalize().unwrap().join(format!(
+      "lazy-file-loader-symlink-test-{}",
+      std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("docs")).unwrap();
+    std::fs::write(dir.join("docs/a.txt"), "content").unwrap();
+    symlink(&dir, dir.join("docs/loop")).unwrap();
+    symlink(dir.join("docs"), dir.join("alias")).unwrap();
+    symlink(dir.join("docs/a.txt"), dir.join("link.txt")).unwrap();
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let root = dir.to_string_lossy().to_string();
+    std::thread::spawn(move || {
+      let loader = LazyFileLoader::new();
+      let file_filter = crate::testing::MockFileFilter::default();
+      let mut paths = loader
+        .load_directory(&root, &file_filter)
+        .map(|file| file.unwrap().path)
+        .collect::<Vec<_>>();
+      paths.sort();
+      let _ = sender.send(paths);
+    });
+    let paths = receiver
+      .recv_timeout(std::time::Duration::from_secs(10))
+      .expect("Crawling a symlink loop should terminate");
+    let expected = ["alias/a.txt", "docs/a.txt", "link.txt"]
+      .iter()
+      .map(|path| dir.join(path).to_string_lossy().to_string())
+      .collect::<Vec<_>>();
+    assert_eq!(paths, expected);
+    std::fs::remove_dir_all(&dir).unwrap();
   }
 }
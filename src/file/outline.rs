@@ -0,0 +1,333 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::search::file::{CHUNK_START_LINE_KEY, File, FileSection, section_path};
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct OutlineHeading {
+  /// 1 for top-level headings
+  pub level: usize,
+  pub title: String,
+  /// 1-based line of the heading
+  pub line_number: usize,
+  /// Last line of the section, up to the next heading of the same or a higher level
+  pub end_line: usize,
+}
+
+/// Formats whose heading structure `outline` understands, by lowercase extension.
+pub const OUTLINE_EXTENSIONS: &[&str] = &["md", "markdown", "org", "rst"];
+
+/// Heading structure of a Markdown, Org or reStructuredText document, or `None` for other
+/// formats.
+pub fn outline(path: &str, content: &str) -> Option<Vec<OutlineHeading>> {
+  let extension = Path::new(path)
+    .extension()
+    .map(|extension| extension.to_string_lossy().to_lowercase())?;
+  let lines = content.lines().collect::<Vec<_>>();
+  let headings = match extension.as_str() {
+    "md" | "markdown" => markdown_headings(&lines),
+    "org" => org_headings(&lines),
+    "rst" => rst_headings(&lines),
+    _ => return None,
+  };
+  Some(with_end_lines(headings, lines.len()))
+}
+
+/// Lines `start_line..=end_line` of the content, 1-based, to load one section of a document.
+pub fn select_lines(content: &str, start_line: Option<usize>, end_line: Option<usize>) -> String {
+  let start_line = start_line.unwrap_or(1).max(1);
+  content
+    .lines()
+    .enumerate()
+    .skip(start_line - 1)
+    .take_while(|(i, _)| end_line.is_none_or(|end_line| *i < end_line))
+    .map(|(_, line)| line)
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+/// Anchor of a heading as GitHub renders it: lowercase, punctuation dropped, spaces as `-`.
+pub fn heading_anchor(title: &str) -> String {
+  title
+    .trim()
+    .to_lowercase()
+    .chars()
+    .filter_map(|c| match c {
+      ' ' => Some('-'),
+      c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+      _ => None,
+    })
+    .collect()
+}
+
+/// Splits a document into one `path#anchor` document per heading section, each holding the text
+/// up to the next heading. Text before the first heading stays under the plain path.
+pub fn split_into_sections(file: File) -> Vec<File> {
+  let headings = match outline(&file.path, &file.content) {
+    Some(headings) if !headings.is_empty() => headings,
+    _ => return vec![file],
+  };
+  let line_starts = std::iter::once(0)
+    .chain(
+      file
+        .content
+        .match_indices('\n')
+        .map(|(offset, _)| offset + 1),
+    )
+    .collect::<Vec<_>>();
+  let line_start = |line_number: usize| line_starts[line_number - 1];
+
+  let mut documents = Vec::new();
+  let preamble = &file.content[..line_start(headings[0].line_number)];
+  if !preamble.trim().is_empty() {
+    documents.push(File::new(file.path.clone(), preamble.to_string()).with_metadata_from(&file));
+  }
+  let mut anchors: HashMap<String, usize> = HashMap::new();
+  let mut breadcrumb: Vec<(usize, &str)> = Vec::new();
+  for (i, heading) in headings.iter().enumerate() {
+    let start = line_start(heading.line_number);
+    let end = headings
+      .get(i + 1)
+      .map_or(file.content.len(), |next| line_start(next.line_number));
+    /* Repeated headings get numbered anchors, as on GitHub */
+    let anchor = heading_anchor(&heading.title);
+    let count = anchors.entry(anchor.clone()).or_default();
+    let anchor = match *count {
+      0 => anchor,
+      n => format!("{}-{}", anchor, n),
+    };
+    *count += 1;
+    breadcrumb.retain(|(level, _)| *level < heading.level);
+    breadcrumb.push((heading.level, &heading.title));
+    let title = breadcrumb
+      .iter()
+      .map(|(_, title)| *title)
+      .collect::<Vec<_>>()
+      .join(" > ");
+
+    let document = File::new(
+      section_path(&file.path, &anchor),
+      file.content[start..end].to_string(),
+    )
+    .with_metadata_from(&file)
+    .with_sections(vec![FileSection { offset: 0, title }])
+    .with_metadata(CHUNK_START_LINE_KEY, &heading.line_number.to_string());
+    documents.push(document);
+  }
+  documents
+}
+
+/// Headings as (level, title, line index) without their end lines.
+type Headings = Vec<(usize, String, usize)>;
+
+fn with_end_lines(headings: Headings, line_count: usize) -> Vec<OutlineHeading> {
+  headings
+    .iter()
+    .enumerate()
+    .map(|(i, (level, title, line_index))| {
+      let end_line = headings[i + 1..]
+        .iter()
+        .find(|(next_level, _, _)| next_level <= level)
+        .map_or(line_count, |(_, _, next_index)| *next_index);
+      OutlineHeading {
+        level: *level,
+        title: title.clone(),
+        line_number: line_index + 1,
+        end_line,
+      }
+    })
+    .collect()
+}
+
+fn markdown_headings(lines: &[&str]) -> Headings {
+  let mut headings = Vec::new();
+  let mut in_code_block = false;
+  /* Skip the YAML front matter, whose closing `---` would read as a setext underline */
+  let mut start = 0;
+  if lines.first().is_some_and(|line| line.trim_end() == "---")
+    && let Some(end) = lines[1..].iter().position(|line| line.trim_end() == "---")
+  {
+    start = end + 2;
+  }
+  for (i, line) in lines.iter().enumerate().skip(start) {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+      in_code_block = !in_code_block;
+      continue;
+    }
+    if in_code_block {
+      continue;
+    }
+    let level = trimmed.chars().take_while(|c| *c == '#').count();
+    let rest = &trimmed[level..];
+    if (1..=6).contains(&level) && (rest.is_empty() || rest.starts_with(' ')) {
+      let title = rest.trim().trim_end_matches('#').trim_end();
+      headings.push((level, title.to_string(), i));
+      continue;
+    }
+    /* Setext headings underline a paragraph line with `===` or `---` */
+    let previous = i.checked_sub(1).filter(|previous| *previous >= start);
+    let underline = line.trim();
+    let setext_level = if !underline.is_empty() && underline.chars().all(|c| c == '=') {
+      Some(1)
+    } else if underline.len() >= 2 && underline.chars().all(|c| c == '-') {
+      Some(2)
+    } else {
+      None
+    };
+    if let (Some(level), Some(previous)) = (setext_level, previous) {
+      let title = lines[previous].trim();
+      let is_heading = headings
+        .last()
+        .is_some_and(|(_, _, line_index)| *line_index == previous);
+      if !title.is_empty() && !title.starts_with('#') && !is_heading {
+        headings.push((level, title.to_string(), previous));
+      }
+    }
+  }
+  headings
+}
+
+fn org_headings(lines: &[&str]) -> Headings {
+  lines
+    .iter()
+    .enumerate()
+    .filter_map(|(i, line)| {
+      let level = line.chars().take_while(|c| *c == '*').count();
+      let title = line[level..].strip_prefix(' ')?;
+      (level > 0).then(|| (level, title.trim().to_string(), i))
+    })
+    .collect()
+}
+
+fn rst_adornment(line: &str) -> Option<char> {
+  let line = line.trim_end();
+  let c = line.chars().next()?;
+  (line.len() >= 2 && c.is_ascii_punctuation() && line.chars().all(|other| other == c)).then_some(c)
+}
+
+/// Section titles are underlined, optionally also overlined, and their levels follow the order in
+/// which each adornment style first appears.
+fn rst_headings(lines: &[&str]) -> Headings {
+  let mut headings = Vec::new();
+  let mut styles: HashMap<(char, bool), usize> = HashMap::new();
+  let mut i = 0;
+  while i + 1 < lines.len() {
+    let (title_index, underline_index, overline) = match rst_adornment(lines[i]) {
+      Some(_) if i + 2 < lines.len() => (i + 1, i + 2, true),
+      Some(_) => break,
+      None => (i, i + 1, false),
+    };
+    let title = lines[title_index].trim();
+    let heading = rst_adornment(lines[underline_index]).filter(|c| {
+      !title.is_empty()
+        && rst_adornment(lines[title_index]).is_none()
+        && lines[underline_index].trim_end().len() >= title.chars().count()
+        && (!overline || rst_adornment(lines[i]) == Some(*c))
+    });
+    match heading {
+      Some(c) => {
+        let next_level = styles.len() + 1;
+        let level = *styles.entry((c, overline)).or_insert(next_level);
+        headings.push((level, title.to_string(), title_index));
+        i = underline_index + 1;
+      }
+      None => i += 1,
+    }
+  }
+  headings
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn titles(headings: &[OutlineHeading]) -> Vec<(usize, &str, usize, usize)> {
+    headings
+      .iter()
+      .map(|heading| {
+        (
+          heading.level,
+          heading.title.as_str(),
+          heading.line_number,
+          heading.end_line,
+        )
+      })
+      .collect()
+  }
+
+  #[test]
+  fn outline_should_read_markdown_headings() {
+    let content = "---\ntitle: x\n---\n# Guide\nintro\n## Install ##\n```\n# not a heading\n```\nUsage\n-----\ntext\n";
+    let headings = outline("guide.md", content).unwrap();
+    assert_eq!(
+      titles(&headings),
+      vec![
+        (1, "Guide", 4, 12),
+        (2, "Install", 6, 9),
+        (2, "Usage", 10, 12)
+      ]
+    );
+  }
+
+  #[test]
+  fn outline_should_read_org_headings() {
+    let content = "* Tasks\n** TODO Write\n*bold* text\n* Notes\n";
+    let headings = outline("todo.org", content).unwrap();
+    assert_eq!(
+      titles(&headings),
+      vec![
+        (1, "Tasks", 1, 3),
+        (2, "TODO Write", 2, 3),
+        (1, "Notes", 4, 4)
+      ]
+    );
+  }
+
+  #[test]
+  fn outline_should_level_rst_headings_by_adornment_order() {
+    let content = "=====\nTitle\n=====\n\nIntro\n-----\n\ntext\n\nUsage\n-----\n";
+    let headings = outline("index.rst", content).unwrap();
+    assert_eq!(
+      titles(&headings),
+      vec![
+        (1, "Title", 2, 11),
+        (2, "Intro", 5, 9),
+        (2, "Usage", 10, 11)
+      ]
+    );
+    assert!(outline("notes.txt", content).is_none());
+  }
+
+  #[test]
+  fn split_into_sections_should_key_sections_by_anchor() {
+    let content = "intro\n# Guide\ntext\n## Setup\none\n## Setup\ntwo\n";
+    let file =
+      File::new("/notes/guide.md".to_string(), content.to_string()).with_metadata("tags", "docs");
+    let documents = split_into_sections(file);
+    let paths = documents
+      .iter()
+      .map(|document| document.path.as_str())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      paths,
+      vec![
+        "/notes/guide.md",
+        "/notes/guide.md#guide",
+        "/notes/guide.md#setup",
+        "/notes/guide.md#setup-1"
+      ]
+    );
+    assert_eq!(documents[3].content, "## Setup\ntwo\n");
+    assert_eq!(documents[3].sections[0].title, "Guide > Setup");
+    assert_eq!(documents[3].metadata[CHUNK_START_LINE_KEY], "6");
+    assert_eq!(documents[3].metadata["tags"], "docs");
+  }
+
+  #[test]
+  fn select_lines_should_return_inclusive_range() {
+    let content = "one\ntwo\nthree\nfour\n";
+    assert_eq!(select_lines(content, Some(2), Some(3)), "two\nthree");
+    assert_eq!(select_lines(content, Some(3), None), "three\nfour");
+    assert_eq!(select_lines(content, None, Some(1)), "one");
+  }
+}
@@ -0,0 +1,173 @@
+use std::path::{Component, Path, PathBuf};
+
+/// Maps every path below a watch root to one stable form, `<canonical root>/<relative path>`, so
+/// that crawled paths and watcher event paths of the same file match in the index. Files below
+/// the root are not canonicalized themselves, so symlinked files keep their path inside the root.
+#[derive(Debug, Clone)]
+pub struct PathNormalizer {
+  roots: Vec<PathBuf>,
+  canonical_root: PathBuf,
+}
+
+fn without_cur_dir(path: &Path) -> PathBuf {
+  /* notify sends paths including unnecessary "./" */
+  path
+    .components()
+    .filter(|component| !matches!(component, Component::CurDir))
+    .collect()
+}
+
+fn absolute_without_cur_dir(path: &Path) -> PathBuf {
+  /* A relative watch dir like the default "." would otherwise strip down to an empty path, which
+  neither canonicalizes nor exists */
+  let path = if path.as_os_str().is_empty() {
+    Path::new(".")
+  } else {
+    path
+  };
+  let absolute_path = without_cur_dir(&std::path::absolute(path).unwrap_or_else(|_| path.into()));
+  if absolute_path.as_os_str().is_empty() {
+    PathBuf::from(".")
+  } else {
+    absolute_path
+  }
+}
+
+impl PathNormalizer {
+  pub fn new(root: &Path) -> Self {
+    let root = absolute_without_cur_dir(root);
+    let canonical_root = root.canonicalize().unwrap_or_else(|_| root.clone());
+    /* The same root may be reported as absolute path or resolved (/var vs /private/var) */
+    let mut roots = vec![canonical_root.clone(), root];
+    roots.dedup();
+    Self {
+      roots,
+      canonical_root,
+    }
+  }
+
+  pub fn root(&self) -> &Path {
+    &self.canonical_root
+  }
+
+  pub fn normalize(&self, path: &Path) -> PathBuf {
+    let path = absolute_without_cur_dir(path);
+    self
+      .roots
+      .iter()
+      .find_map(|root| path.strip_prefix(root).ok())
+      .map(|relative_path| {
+        /* Joining an empty path would append a separator, which breaks a root that is a file */
+        if relative_path.as_os_str().is_empty() {
+          self.canonical_root.clone()
+        } else {
+          self.canonical_root.join(relative_path)
+        }
+      })
+      .unwrap_or(path)
+  }
+
+  pub fn normalize_str(&self, path: &Path) -> String {
+    self.normalize(path).to_string_lossy().to_string()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+
+  #[test]
+  fn path_normalizer_should_map_root_forms_to_canonical_root() {
+    let dir = std::env::temp_dir().join(format!("path-normalizer-test-{}", std::process::id()));
+    fs::create_dir_all(dir.join("real/docs")).unwrap();
+    let canonical_real = dir.join("real").canonicalize().unwrap();
+    let expected = canonical_real.join("docs/a.txt");
+
+    let normalizer = PathNormalizer::new(&dir.join("real"));
+    assert_eq!(
+      normalizer.normalize(&dir.join("real/./docs/a.txt")),
+      expected
+    );
+    assert_eq!(normalizer.normalize(&expected), expected);
+
+    #[cfg(unix)]
+    {
+      std::os::unix::fs::symlink(dir.join("real"), dir.join("link")).unwrap();
+      let normalizer = PathNormalizer::new(&dir.join("link"));
+      assert_eq!(normalizer.root(), canonical_real);
+      assert_eq!(normalizer.normalize(&dir.join("link/docs/a.txt")), expected);
+      assert_eq!(normalizer.normalize(&expected), expected);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn path_normalizer_should_resolve_relative_roots_against_current_dir() {
+    let current_dir = std::env::current_dir().unwrap().canonicalize().unwrap();
+    for root in [".", "./", ""] {
+      let normalizer = PathNormalizer::new(Path::new(root));
+      assert_eq!(normalizer.root(), current_dir);
+      assert_eq!(
+        normalizer.normalize(Path::new("./docs/a.txt")),
+        current_dir.join("docs/a.txt")
+      );
+      assert_eq!(
+        normalizer.normalize(&current_dir.join("b.txt")),
+        current_dir.join("b.txt")
+      );
+    }
+
+    let dir = std::env::temp_dir().canonicalize().unwrap().join(format!(
+      "path-normalizer-relative-test-{}",
+      std::process::id()
+    ));
+    fs::create_dir_all(dir.join("docs")).unwrap();
+    let relative_dir = current_dir
+      .components()
+      .skip(1)
+      .map(|_| Path::new(".."))
+      .collect::<PathBuf>()
+      .join(dir.strip_prefix("/").unwrap());
+    assert!(relative_dir.is_relative());
+    let normalizer = PathNormalizer::new(&relative_dir);
+    assert_eq!(normalizer.root(), dir);
+    assert_eq!(
+      normalizer.normalize(&relative_dir.join("docs/a.txt")),
+      dir.join("docs/a.txt")
+    );
+    fs::remove_dir_all(&dir).unwrap();
+
+    /* A root that does not exist cannot be canonicalized, but still becomes absolute */
+    let absolute_root = std::env::current_dir().unwrap().join("some/relative");
+    let normalizer = PathNormalizer::new(Path::new("some/relative"));
+    assert_eq!(normalizer.root(), absolute_root);
+    assert_eq!(
+      normalizer.normalize(Path::new("some/relative/a.txt")),
+      absolute_root.join("a.txt")
+    );
+  }
+
+  #[test]
+  fn path_normalizer_should_keep_paths_outside_root() {
+    let normalizer = PathNormalizer::new(Path::new("/not/existing/root"));
+    assert_eq!(
+      normalizer.normalize(Path::new("/elsewhere/./a.txt")),
+      PathBuf::from("/elsewhere/a.txt")
+    );
+    assert_eq!(
+      normalizer.normalize(Path::new("/not/existing/root/a.txt")),
+      PathBuf::from("/not/existing/root/a.txt")
+    );
+  }
+
+  #[test]
+  fn path_normalizer_should_not_append_separator_to_file_root() {
+    let normalizer = PathNormalizer::new(Path::new("/not/existing/notes.md"));
+    assert_eq!(
+      normalizer.normalize_str(Path::new("/not/existing/notes.md")),
+      "/not/existing/notes.md"
+    );
+  }
+}
@@ -0,0 +1,214 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  path::{Path, PathBuf},
+  sync::{
+    Arc,
+    mpsc::{RecvTimeoutError, Sender, channel},
+  },
+  thread,
+  time::{Duration, SystemTime},
+};
+use walkdir::WalkDir;
+
+use super::{file_filter::PathExclusion, path_normalizer::PathNormalizer};
+use crate::search::{
+  file::{
+    CONTAINER_MEMBER_SEPARATOR, FileFilter, FileLoader, FileOperation, FileOperationHandler,
+    document_file_path,
+  },
+  index_backend::IndexBackend,
+};
+
+/// Periodically compares the files under a root with what the index holds and reports the
+/// differences as file operations, catching changes whose events were dropped.
+pub struct PeriodicRescan {
+  stop_tx: Option<Sender<()>>,
+  thread_handle: Option<thread::JoinHandle<()>>,
+}
+
+pub struct RescanTarget {
+  pub text_index: Arc<Mutex<dyn IndexBackend>>,
+  pub file_filter: Arc<dyn FileFilter + Send + Sync>,
+  pub file_loader: Arc<dyn FileLoader + Send + Sync>,
+  pub exclusion: PathExclusion,
+}
+
+impl PeriodicRescan {
+  pub fn start(
+    path: &str,
+    interval: Duration,
+    target: RescanTarget,
+    handler: Box<FileOperationHandler>,
+  ) -> Result<Self> {
+    let (stop_tx, stop_rx) = channel::<()>();
+    let root = PathBuf::from(path);
+    let normalizer = PathNormalizer::new(&root);
+
+    let thread_handle = thread::Builder::new()
+      .name("periodic-rescan".to_string())
+      .spawn(move || {
+        let mut last_rescan = SystemTime::now();
+        while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+          let started_at = SystemTime::now();
+          let indexed = match indexed_source_paths(&target.text_index) {
+            Ok(indexed) => indexed,
+            Err(e) => {
+              tracing::error!("Failed to list indexed files for rescan: {}", e);
+              continue;
+            }
+          };
+          let files = scan(&root, &normalizer, &target);
+          let operations = reconcile(&indexed, &files, last_rescan);
+          if !operations.is_empty() {
+            tracing::info!(
+              "Rescan of {:?} found {} missed changes",
+              root,
+              operations.len()
+            );
+          }
+          for op in operations {
+            if let Err(e) = handler(&op) {
+              tracing::error!("Error processing rescanned change {:?}: {}", op, e);
+            }
+          }
+          last_rescan = started_at;
+        }
+      })?;
+
+    Ok(Self {
+      stop_tx: Some(stop_tx),
+      thread_handle: Some(thread_handle),
+    })
+  }
+
+  pub fn stop(&mut self) -> Result<()> {
+    if let Some(stop_tx) = self.stop_tx.take() {
+      let _ = stop_tx.send(());
+    }
+    if let Some(handle) = self.thread_handle.take() {
+      handle
+        .join()
+        .map_err(|_| anyhow::anyhow!("Failed to join periodic rescan thread"))?;
+    }
+    Ok(())
+  }
+}
+
+impl Drop for PeriodicRescan {
+  fn drop(&mut self) {
+    if let Err(e) = self.stop() {
+      tracing::error!("Error stopping periodic rescan in Drop: {}", e);
+    }
+  }
+}
+
+fn source_path(document_path: &str) -> &str {
+  let path = document_file_path(document_path);
+  path
+    .split_once(CONTAINER_MEMBER_SEPARATOR)
+    .map(|(container, _)| container)
+    .unwrap_or(path)
+}
+
+fn indexed_source_paths(text_index: &Mutex<dyn IndexBackend>) -> Result<BTreeSet<String>> {
+  let index = text_index.lock();
+  Ok(
+    index
+      .indexed_paths()?
+      .iter()
+      .map(|path| source_path(path).to_string())
+      .collect(),
+  )
+}
+
+fn scan(
+  root: &Path,
+  normalizer: &PathNormalizer,
+  target: &RescanTarget,
+) -> BTreeMap<String, Option<SystemTime>> {
+  let exclusion = &target.exclusion;
+  WalkDir::new(root)
+    .follow_links(exclusion.follow_symlinks)
+    .min_depth(if root.is_file() { 0 } else { 1 })
+    .into_iter()
+    .filter_entry(|entry| !exclusion.is_excluded_name(entry.file_name()))
+    .flatten()
+    .filter(|entry| entry.file_type().is_file())
+    .filter_map(|entry| {
+      let path = normalizer.normalize_str(entry.path());
+      let is_target = target.file_filter.is_target(&path) || target.file_loader.is_container(&path);
+      is_target.then(|| (path, entry.metadata().ok().and_then(|m| m.modified().ok())))
+    })
+    .collect()
+}
+
+fn reconcile(
+  indexed: &BTreeSet<String>,
+  files: &BTreeMap<String, Option<SystemTime>>,
+  modified_since: SystemTime,
+) -> Vec<FileOperation> {
+  let mut operations = indexed
+    .iter()
+    .filter(|path| !files.contains_key(*path))
+    .map(|path| FileOperation::FileDeleted(path.clone()))
+    .collect::<Vec<_>>();
+  operations.extend(files.iter().filter_map(|(path, modified)| {
+    if !indexed.contains(path) {
+      Some(FileOperation::FileCreated(path.clone()))
+    } else if modified.is_some_and(|modified| modified >= modified_since) {
+      Some(FileOperation::FileModified(path.clone()))
+    } else {
+      None
+    }
+  }));
+  operations
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reconcile_should_report_files_missing_from_index_or_disk_and_recent_changes() {
+    let since = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+    let indexed = BTreeSet::from([
+      "/root/stale.txt".to_string(),
+      "/root/old.txt".to_string(),
+      "/root/recent.txt".to_string(),
+    ]);
+    let files = BTreeMap::from([
+      (
+        "/root/old.txt".to_string(),
+        Some(since - Duration::from_secs(1)),
+      ),
+      (
+        "/root/recent.txt".to_string(),
+        Some(since + Duration::from_secs(1)),
+      ),
+      ("/root/new.txt".to_string(), None),
+    ]);
+
+    let operations = reconcile(&indexed, &files, since)
+      .into_iter()
+      .map(|op| format!("{:?}", op))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      operations,
+      vec![
+        r#"FileDeleted("/root/stale.txt")"#,
+        r#"FileCreated("/root/new.txt")"#,
+        r#"FileModified("/root/recent.txt")"#,
+      ]
+    );
+  }
+
+  #[test]
+  fn source_path_should_strip_chunk_and_container_member() {
+    assert_eq!(source_path("/root/app.log#chunk=1024"), "/root/app.log");
+    assert_eq!(source_path("/root/guide.md#install"), "/root/guide.md");
+    assert_eq!(source_path("/root/notes.zip!/a.txt"), "/root/notes.zip");
+    assert_eq!(source_path("/root/a.txt"), "/root/a.txt");
+  }
+}
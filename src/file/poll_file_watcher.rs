@@ -0,0 +1,247 @@
+use anyhow::Result;
+use std::{
+  collections::BTreeMap,
+  path::{Path, PathBuf},
+  sync::{
+    Arc,
+    mpsc::{RecvTimeoutError, Sender, channel},
+  },
+  thread,
+  time::{Duration, SystemTime},
+};
+use walkdir::WalkDir;
+
+use super::{
+  file_filter::{EventFilter, PathExclusion},
+  path_normalizer::PathNormalizer,
+};
+use crate::search::{
+  event_log::{EventLog, EventStage},
+  file::{FileOperation, FileOperationHandler, FileWatcher},
+};
+
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct EntryState {
+  is_dir: bool,
+  modified: Option<SystemTime>,
+  len: u64,
+}
+
+type Snapshot = BTreeMap<PathBuf, EntryState>;
+
+/// Watches a directory by rescanning it periodically and comparing modification times and sizes,
+/// for file systems such as NFS/SMB mounts where native change events do not arrive.
+pub struct PollFileWatcher {
+  interval: Duration,
+  exclusion: PathExclusion,
+  event_filter: Option<Arc<EventFilter>>,
+  event_log: Option<Arc<EventLog>>,
+  stop_tx: Option<Sender<()>>,
+  thread_handle: Option<thread::JoinHandle<()>>,
+}
+
+impl PollFileWatcher {
+  pub fn new(interval: Duration, exclusion: PathExclusion) -> Self {
+    Self {
+      interval,
+      exclusion,
+      event_filter: None,
+      event_log: None,
+      stop_tx: None,
+      thread_handle: None,
+    }
+  }
+
+  pub fn with_event_filter(mut self, event_filter: Arc<EventFilter>) -> Self {
+    self.event_filter = Some(event_filter);
+    self
+  }
+
+  pub fn with_event_log(mut self, event_log: Arc<EventLog>) -> Self {
+    self.event_log = Some(event_log);
+    self
+  }
+}
+
+impl Default for PollFileWatcher {
+  fn default() -> Self {
+    Self::new(DEFAULT_POLL_INTERVAL, PathExclusion::none())
+  }
+}
+
+fn scan(root: &Path, exclusion: &PathExclusion, normalizer: &PathNormalizer) -> Snapshot {
+  WalkDir::new(root)
+    .follow_links(exclusion.follow_symlinks)
+    .min_depth(if root.is_file() { 0 } else { 1 })
+    .into_iter()
+    .filter_entry(|entry| !exclusion.is_excluded_name(entry.file_name()))
+    .flatten()
+    .filter(|entry| exclusion.follow_symlinks || !entry.path_is_symlink())
+    .filter_map(|entry| {
+      let metadata = entry.metadata().ok()?;
+      Some((
+        normalizer.normalize(entry.path()),
+        EntryState {
+          is_dir: metadata.is_dir(),
+          modified: metadata.modified().ok(),
+          len: metadata.len(),
+        },
+      ))
+    })
+    .collect()
+}
+
+fn diff(previous: &Snapshot, current: &Snapshot) -> Vec<FileOperation> {
+  let to_string = |path: &Path| path.to_string_lossy().to_string();
+  let deleted_dirs = previous
+    .iter()
+    .filter(|(path, state)| state.is_dir && !current.get(*path).is_some_and(|c| c.is_dir))
+    .map(|(path, _)| path)
+    .collect::<Vec<_>>();
+  let is_under_deleted_dir = |path: &Path| {
+    deleted_dirs
+      .iter()
+      .any(|dir| path != dir.as_path() && path.starts_with(dir))
+  };
+
+  /* Deletions come first so that a path replaced by another kind of entry is re-created */
+  let mut operations = deleted_dirs
+    .iter()
+    .filter(|dir| !is_under_deleted_dir(dir))
+    .map(|dir| FileOperation::DirectoryDeleted(to_string(dir)))
+    .collect::<Vec<_>>();
+  operations.extend(
+    previous
+      .iter()
+      .filter(|(path, state)| {
+        !state.is_dir && current.get(*path).is_none_or(|c| c.is_dir) && !is_under_deleted_dir(path)
+      })
+      .map(|(path, _)| FileOperation::FileDeleted(to_string(path))),
+  );
+  operations.extend(
+    current
+      .iter()
+      .filter(|(_, state)| !state.is_dir)
+      .filter_map(|(path, state)| match previous.get(path) {
+        Some(previous_state) if !previous_state.is_dir => {
+          (previous_state != state).then(|| FileOperation::FileModified(to_string(path)))
+        }
+        _ => Some(FileOperation::FileCreated(to_string(path))),
+      }),
+  );
+  operations
+}
+
+impl FileWatcher for PollFileWatcher {
+  fn watch_directory(&mut self, path: &str, handler: Box<FileOperationHandler>) -> Result<()> {
+    let (stop_tx, stop_rx) = channel::<()>();
+    let root = PathBuf::from(path);
+    let normalizer = PathNormalizer::new(&root);
+    let exclusion = self.exclusion.clone();
+    let interval = self.interval;
+    let event_filter = self.event_filter.clone();
+    let event_log = self.event_log.clone();
+    let mut snapshot = scan(&root, &exclusion, &normalizer);
+
+    let thread_handle = thread::Builder::new()
+      .name("poll-file-watcher".to_string())
+      .spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+          let current = scan(&root, &exclusion, &normalizer);
+          for op in diff(&snapshot, &current) {
+            if let Some(event_log) = &event_log {
+              event_log.record(EventStage::Raw, format!("{:?}", op));
+            }
+            if !event_filter
+              .as_ref()
+              .is_none_or(|filter| filter.is_relevant(&op))
+            {
+              if let Some(event_log) = &event_log {
+                event_log.record(EventStage::Filtered, format!("{:?}", op));
+              }
+              continue;
+            }
+            if let Err(e) = handler(&op) {
+              tracing::error!("Error processing polled change {:?}: {}", op, e);
+            }
+          }
+          snapshot = current;
+        }
+      })?;
+
+    self.stop_tx = Some(stop_tx);
+    self.thread_handle = Some(thread_handle);
+    Ok(())
+  }
+
+  fn stop_watching(&mut self) -> Result<()> {
+    if let Some(stop_tx) = self.stop_tx.take() {
+      let _ = stop_tx.send(());
+    }
+    if let Some(handle) = self.thread_handle.take() {
+      handle
+        .join()
+        .map_err(|_| anyhow::anyhow!("Failed to join poll watcher thread"))?;
+    }
+    Ok(())
+  }
+}
+
+impl Drop for PollFileWatcher {
+  fn drop(&mut self) {
+    if let Err(e) = self.stop_watching() {
+      tracing::error!("Error stopping poll watcher in Drop: {}", e);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn file(len: u64) -> EntryState {
+    EntryState {
+      is_dir: false,
+      modified: None,
+      len,
+    }
+  }
+
+  fn dir() -> EntryState {
+    EntryState {
+      is_dir: true,
+      modified: None,
+      len: 0,
+    }
+  }
+
+  #[test]
+  fn diff_should_report_created_modified_and_deleted_entries() {
+    let previous = Snapshot::from([
+      (PathBuf::from("/root/a.txt"), file(1)),
+      (PathBuf::from("/root/b.txt"), file(1)),
+      (PathBuf::from("/root/old"), dir()),
+      (PathBuf::from("/root/old/c.txt"), file(1)),
+    ]);
+    let current = Snapshot::from([
+      (PathBuf::from("/root/a.txt"), file(2)),
+      (PathBuf::from("/root/d.txt"), file(1)),
+    ]);
+
+    let operations = diff(&previous, &current)
+      .into_iter()
+      .map(|op| format!("{:?}", op))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      operations,
+      vec![
+        r#"DirectoryDeleted("/root/old")"#,
+        r#"FileDeleted("/root/b.txt")"#,
+        r#"FileModified("/root/a.txt")"#,
+        r#"FileCreated("/root/d.txt")"#,
+      ]
+    );
+  }
+}
@@ -1,34 +1,93 @@
 use anyhow::Result;
-use std::{path::Path, thread, time::Duration};
+use std::{io, path::Path, thread, time::Duration};
 
 use crate::search::file::File;
 
-pub fn read_file_with_retry(path: &Path, max_retries: u32) -> Result<String> {
-  let mut last_error = None;
+/// How often a file that fails to load is read again, e.g. while an editor or a network drive is
+/// still writing it. The wait grows linearly: `backoff`, `2 * backoff`, ...
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+  pub max_retries: u32,
+  pub backoff: Duration,
+}
 
-  for attempt in 0..=max_retries {
-    match std::fs::read_to_string(path) {
-      Ok(content) => return Ok(content),
-      Err(e) => {
-        last_error = Some(e);
-        if attempt < max_retries {
-          thread::sleep(Duration::from_millis(10 * (attempt + 1) as u64));
+impl RetryPolicy {
+  pub fn new(max_retries: u32, backoff: Duration) -> Self {
+    Self {
+      max_retries,
+      backoff,
+    }
+  }
+
+  pub fn retry<T>(&self, mut operation: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut attempt = 0;
+    loop {
+      match operation() {
+        Ok(value) => return Ok(value),
+        Err(e) if attempt < self.max_retries && is_transient(&e) => {
+          attempt += 1;
+          tracing::debug!("Retrying read (attempt {}): {}", attempt + 1, e);
+          thread::sleep(self.backoff * attempt);
+        }
+        Err(e) if attempt > 0 => {
+          return Err(e.context(format!(
+            "Failed to read file after {} attempts",
+            attempt + 1
+          )));
         }
+        Err(e) => return Err(e),
       }
     }
   }
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    Self::new(3, Duration::from_millis(10))
+  }
+}
 
-  Err(anyhow::anyhow!(
-    "Failed to read file after {} attempts: {}",
-    max_retries + 1,
-    last_error.unwrap()
-  ))
+fn is_transient(error: &anyhow::Error) -> bool {
+  /* Content that is not valid text will not become valid by reading it again */
+  error
+    .downcast_ref::<io::Error>()
+    .is_some_and(|e| e.kind() != io::ErrorKind::InvalidData)
+}
+
+pub fn read_file_with_retry(path: &Path, retry_policy: &RetryPolicy) -> Result<String> {
+  retry_policy.retry(|| Ok(std::fs::read_to_string(path)?))
 }
 
 pub fn path_to_file(path: &Path) -> Result<File> {
-  let content = read_file_with_retry(path, 3)?;
-  Ok(File {
-    path: path.to_string_lossy().to_string(),
-    content,
-  })
+  let content = std::fs::read_to_string(path)?;
+  Ok(File::new(path.to_string_lossy().to_string(), content))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn retry_policy_should_retry_transient_errors_only() {
+    let policy = RetryPolicy::new(2, Duration::ZERO);
+
+    let mut attempts = 0;
+    let result = policy.retry(|| {
+      attempts += 1;
+      if attempts < 3 {
+        Err(io::Error::from(io::ErrorKind::NotFound).into())
+      } else {
+        Ok(attempts)
+      }
+    });
+    assert_eq!(result.unwrap(), 3);
+
+    let mut attempts = 0;
+    let result: Result<()> = policy.retry(|| {
+      attempts += 1;
+      Err(io::Error::from(io::ErrorKind::InvalidData).into())
+    });
+    assert!(result.is_err());
+    assert_eq!(attempts, 1);
+  }
 }
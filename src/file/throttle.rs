@@ -0,0 +1,85 @@
+use parking_lot::Mutex;
+use std::{
+  thread,
+  time::{Duration, Instant},
+};
+
+/// Spreads file loads evenly over time, shared by every thread crawling or loading files.
+pub struct RateLimiter {
+  interval: Duration,
+  next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+  pub fn new(per_second: u32) -> Self {
+    RateLimiter {
+      interval: Duration::from_secs(1) / per_second.max(1),
+      next_slot: Mutex::new(Instant::now()),
+    }
+  }
+
+  /// Blocks until the next load may start.
+  pub fn wait(&self) {
+    let now = Instant::now();
+    let slot = {
+      let mut next_slot = self.next_slot.lock();
+      let slot = (*next_slot).max(now);
+      *next_slot = slot + self.interval;
+      slot
+    };
+    if slot > now {
+      thread::sleep(slot - now);
+    }
+  }
+}
+
+/// Lowers the scheduling priority of the calling thread, and of the threads it starts later, so
+/// that indexing yields the CPU to the user. Only Linux can renice a single thread.
+#[cfg(target_os = "linux")]
+pub fn lower_thread_priority() {
+  /* With PRIO_PROCESS and 0, Linux applies the nice value to the calling thread only */
+  // SAFETY: setpriority reads no memory and only changes the scheduling of this thread
+  if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 10) } != 0 {
+    tracing::warn!(
+      "Failed to lower the indexing thread priority: {}",
+      std::io::Error::last_os_error()
+    );
+  }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn lower_thread_priority() {
+  tracing::debug!("Lowering the indexing thread priority is only supported on Linux");
+}
+
+/// Runs `operation` on a thread of lowered priority when `nice` is set, so that the priority of
+/// the calling thread is left alone.
+pub fn run_niced<T: Send>(nice: bool, operation: impl FnOnce() -> T + Send) -> T {
+  if !nice {
+    return operation();
+  }
+  thread::scope(|scope| {
+    scope
+      .spawn(|| {
+        lower_thread_priority();
+        operation()
+      })
+      .join()
+      .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn rate_limiter_should_space_out_loads() {
+    let rate_limiter = RateLimiter::new(100);
+    let started_at = Instant::now();
+    for _ in 0..5 {
+      rate_limiter.wait();
+    }
+    assert!(started_at.elapsed() >= Duration::from_millis(40));
+  }
+}
@@ -0,0 +1,138 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::{
+  collections::BTreeSet,
+  sync::{
+    Arc,
+    mpsc::{RecvTimeoutError, Sender, channel},
+  },
+  thread,
+  time::Duration,
+};
+
+use super::http_file_loader::HttpFileLoader;
+use crate::search::{
+  file::{FileOperation, FileOperationHandler},
+  index_backend::IndexBackend,
+};
+
+/// Periodically re-reads a URL source and re-fetches its pages, since remote documents cannot be
+/// watched for changes.
+pub struct UrlRefresh {
+  stop_tx: Option<Sender<()>>,
+  thread_handle: Option<thread::JoinHandle<()>>,
+}
+
+impl UrlRefresh {
+  pub fn start(
+    source: &str,
+    interval: Duration,
+    text_index: Arc<Mutex<dyn IndexBackend>>,
+    file_loader: Arc<HttpFileLoader>,
+    handler: Box<FileOperationHandler>,
+  ) -> Result<Self> {
+    let (stop_tx, stop_rx) = channel::<()>();
+    let source = source.to_string();
+
+    let thread_handle = thread::Builder::new()
+      .name("url-refresh".to_string())
+      .spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+          let urls = match file_loader.load_url_list(&source) {
+            Ok(urls) => urls,
+            Err(e) => {
+              tracing::error!("Failed to refresh URL source {}: {}", source, e);
+              continue;
+            }
+          };
+          let indexed = match text_index.lock().indexed_paths() {
+            Ok(indexed) => indexed,
+            Err(e) => {
+              tracing::error!("Failed to list indexed URLs of {}: {}", source, e);
+              continue;
+            }
+          };
+          let operations = refresh_operations(&indexed, &urls);
+          tracing::info!("Refreshing {} URLs of {}", operations.len(), source);
+          for op in operations {
+            if let Err(e) = handler(&op) {
+              tracing::error!("Error processing refreshed URL {:?}: {}", op, e);
+            }
+          }
+        }
+      })?;
+
+    Ok(Self {
+      stop_tx: Some(stop_tx),
+      thread_handle: Some(thread_handle),
+    })
+  }
+
+  pub fn stop(&mut self) -> Result<()> {
+    if let Some(stop_tx) = self.stop_tx.take() {
+      let _ = stop_tx.send(());
+    }
+    if let Some(handle) = self.thread_handle.take() {
+      handle
+        .join()
+        .map_err(|_| anyhow::anyhow!("Failed to join URL refresh thread"))?;
+    }
+    Ok(())
+  }
+}
+
+impl Drop for UrlRefresh {
+  fn drop(&mut self) {
+    if let Err(e) = self.stop() {
+      tracing::error!("Error stopping URL refresh in Drop: {}", e);
+    }
+  }
+}
+
+/// Removes URLs no longer listed by the source, and fetches every listed URL again.
+fn refresh_operations(indexed: &BTreeSet<String>, urls: &[String]) -> Vec<FileOperation> {
+  let listed = urls.iter().collect::<BTreeSet<_>>();
+  let mut operations = indexed
+    .iter()
+    .filter(|url| !listed.contains(url))
+    .map(|url| FileOperation::FileDeleted(url.clone()))
+    .collect::<Vec<_>>();
+  operations.extend(listed.into_iter().map(|url| {
+    if indexed.contains(url) {
+      FileOperation::FileModified(url.clone())
+    } else {
+      FileOperation::FileCreated(url.clone())
+    }
+  }));
+  operations
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn refresh_operations_should_delete_unlisted_and_refetch_listed_urls() {
+    let indexed = BTreeSet::from([
+      "https://docs.example.com/gone".to_string(),
+      "https://docs.example.com/kept".to_string(),
+    ]);
+    let urls = vec![
+      "https://docs.example.com/kept".to_string(),
+      "https://docs.example.com/new".to_string(),
+    ];
+
+    let operations = refresh_operations(&indexed, &urls)
+      .into_iter()
+      .map(|op| format!("{:?}", op))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      operations,
+      vec![
+        r#"FileDeleted("https://docs.example.com/gone")"#,
+        r#"FileModified("https://docs.example.com/kept")"#,
+        r#"FileCreated("https://docs.example.com/new")"#,
+      ]
+    );
+  }
+}
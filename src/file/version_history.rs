@@ -0,0 +1,193 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::{
+  collections::HashMap,
+  fs::{File, OpenOptions},
+  io::{BufRead, BufReader, Write},
+  path::{Path, PathBuf},
+};
+
+use super::file_info::hash_bytes;
+use crate::search::{file::FileOperation, index_backend::unix_time_ms};
+
+/// Files larger than this are not snapshotted.
+const MAX_VERSIONED_FILE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// A snapshot of a file, identified by the hash of its content.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FileVersion {
+  pub file_path: String,
+  /// Hash of the content as `fnv1a64:<hex>`, as in the `content_hash` of `get_file_info`
+  pub version: String,
+  pub size: u64,
+  pub snapshot_at_ms: u64,
+}
+
+/// Snapshots of watched files, taken whenever a change to them is seen. Contents are stored once
+/// per hash in `objects`, and versions appended as JSON lines to `versions.jsonl`, both in the
+/// history directory. Nothing is ever pruned.
+pub struct VersionHistory {
+  objects_dir: PathBuf,
+  versions_file: Mutex<File>,
+  versions: Mutex<HashMap<String, Vec<FileVersion>>>,
+}
+
+impl VersionHistory {
+  pub fn open(history_dir: &Path) -> Result<Self> {
+    let objects_dir = history_dir.join("objects");
+    std::fs::create_dir_all(&objects_dir)
+      .map_err(|e| anyhow::anyhow!("Cannot create version history {:?}: {}", history_dir, e))?;
+    let versions_path = history_dir.join("versions.jsonl");
+    let mut versions: HashMap<String, Vec<FileVersion>> = HashMap::new();
+    if let Ok(file) = File::open(&versions_path) {
+      /* A line cut short by a crash is skipped rather than losing the whole history */
+      for line in BufReader::new(file).lines() {
+        match serde_json::from_str::<FileVersion>(&line?) {
+          Ok(version) => versions
+            .entry(version.file_path.clone())
+            .or_default()
+            .push(version),
+          Err(e) => tracing::warn!("Skipping unreadable version in {:?}: {}", versions_path, e),
+        }
+      }
+    }
+    let versions_file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&versions_path)?;
+    Ok(VersionHistory {
+      objects_dir,
+      versions_file: Mutex::new(versions_file),
+      versions: Mutex::new(versions),
+    })
+  }
+
+  /// Snapshots the files an operation created or changed. Content equal to the latest version is
+  /// not snapshotted again.
+  pub fn snapshot_operation(&self, operation: &FileOperation) {
+    let path = match operation {
+      FileOperation::FileCreated(path) | FileOperation::FileModified(path) => path,
+      FileOperation::FileRenamed { new_path, .. } | FileOperation::PathRenamed { new_path, .. } => {
+        new_path
+      }
+      _ => return,
+    };
+    if let Err(e) = self.snapshot(path) {
+      tracing::warn!("Failed to snapshot {}: {}", path, e);
+    }
+  }
+
+  /// Snapshots a file, returning the new version, or `None` when it is not a file, is too large
+  /// or did not change.
+  pub fn snapshot(&self, file_path: &str) -> Result<Option<FileVersion>> {
+    let Ok(metadata) = std::fs::metadata(file_path) else {
+      return Ok(None);
+    };
+    if !metadata.is_file() || metadata.len() > MAX_VERSIONED_FILE_SIZE {
+      return Ok(None);
+    }
+    let content = std::fs::read(file_path)?;
+    let version = hash_bytes(&content);
+    let mut versions = self.versions.lock();
+    if versions
+      .get(file_path)
+      .and_then(|versions| versions.last())
+      .is_some_and(|latest| latest.version == version)
+    {
+      return Ok(None);
+    }
+    let object = self.object_path(&version);
+    if !object.exists() {
+      /* Written next to the object and renamed so that a crash never leaves a partial one */
+      let temporary_file = object.with_extension("tmp");
+      std::fs::write(&temporary_file, &content)?;
+      std::fs::rename(&temporary_file, &object)?;
+    }
+    let file_version = FileVersion {
+      file_path: file_path.to_string(),
+      version,
+      size: content.len() as u64,
+      snapshot_at_ms: unix_time_ms(),
+    };
+    let mut line = serde_json::to_string(&file_version)?;
+    line.push('\n');
+    self.versions_file.lock().write_all(line.as_bytes())?;
+    versions
+      .entry(file_path.to_string())
+      .or_default()
+      .push(file_version.clone());
+    Ok(Some(file_version))
+  }
+
+  /// Versions of a file, newest first.
+  pub fn list(&self, file_path: &str) -> Vec<FileVersion> {
+    self
+      .versions
+      .lock()
+      .get(file_path)
+      .map(|versions| versions.iter().rev().cloned().collect())
+      .unwrap_or_default()
+  }
+
+  /// Content of a version of a file. Only versions of that file can be loaded.
+  pub fn load(&self, file_path: &str, version: &str) -> Result<Vec<u8>> {
+    if !self
+      .list(file_path)
+      .iter()
+      .any(|file_version| file_version.version == version)
+    {
+      anyhow::bail!("{} has no version {}", file_path, version);
+    }
+    let content = std::fs::read(self.object_path(version))
+      .map_err(|e| anyhow::anyhow!("Cannot read version {}: {}", version, e))?;
+    if hash_bytes(&content) != version {
+      anyhow::bail!("Version {} is corrupted", version);
+    }
+    Ok(content)
+  }
+
+  fn object_path(&self, version: &str) -> PathBuf {
+    self.objects_dir.join(version.replace(':', "-"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn version_history_should_keep_each_distinct_content_once() {
+    let dir = std::env::temp_dir().join(format!("version-history-test-{}", std::process::id()));
+    let history_dir = dir.join("history");
+    std::fs::create_dir_all(&dir).unwrap();
+    let note = dir.join("note.md");
+    let note_path = note.to_str().unwrap();
+
+    let history = VersionHistory::open(&history_dir).unwrap();
+    std::fs::write(&note, "first").unwrap();
+    history.snapshot_operation(&FileOperation::FileCreated(note_path.to_string()));
+    std::fs::write(&note, "second").unwrap();
+    history.snapshot_operation(&FileOperation::FileModified(note_path.to_string()));
+    history.snapshot_operation(&FileOperation::FileModified(note_path.to_string()));
+    std::fs::write(&note, "first").unwrap();
+    history.snapshot_operation(&FileOperation::FileModified(note_path.to_string()));
+
+    /* Reopened to read the versions back from disk */
+    let history = VersionHistory::open(&history_dir).unwrap();
+    let versions = history.list(note_path);
+    assert_eq!(versions.len(), 3);
+    assert_eq!(versions[0].version, versions[2].version);
+    assert_eq!(
+      history.load(note_path, &versions[1].version).unwrap(),
+      b"second"
+    );
+    assert!(history.load("/elsewhere.md", &versions[1].version).is_err());
+    assert_eq!(
+      std::fs::read_dir(history_dir.join("objects"))
+        .unwrap()
+        .count(),
+      2
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
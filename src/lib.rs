@@ -1,4 +1,8 @@
 pub mod application;
 pub mod file;
+pub mod pidfile;
 pub mod search;
 pub mod servers;
+pub mod systemd;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
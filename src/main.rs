@@ -1,23 +1,289 @@
-use clap::Parser;
-use fs_text_search_mcp::application;
-use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use fs_text_search_mcp::{
+  application::{self, ApplicationConfig, Backend, WatchBackend},
+  file::extractor::ExtractorRegistry,
+  file::file_filter::DEFAULT_EXCLUDED_DIRECTORIES,
+  file::read_file::RetryPolicy,
+  pidfile::Pidfile,
+  search::{
+    export::ExportFormat,
+    extra_fields::ExtraField,
+    index_operation::ErrorPolicy,
+    maintenance::{CronSchedule, MaintenanceTask},
+    query_template::QueryTemplate,
+    saved_filters::SavedFilter,
+    semantic_index::Embedder,
+  },
+};
+use std::{
+  collections::HashMap,
+  path::PathBuf,
+  sync::Arc,
+  time::{Duration, Instant},
+};
 use tracing_subscriber::EnvFilter;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-  /// Directory to watch for file changes
-  #[arg(short, long, default_value = ".")]
-  watch_dir: PathBuf,
+  #[command(subcommand)]
+  command: Option<Command>,
+
+  /// Directories or single files to watch for file changes (repeatable, each gets its own index;
+  /// defaults to the current directory unless --project is given)
+  #[arg(short, long)]
+  watch_dir: Vec<PathBuf>,
+
+  /// Watch a directory as part of a named project, as NAME=DIR (repeatable; directories sharing
+  /// a name form one project)
+  #[arg(long, value_parser = parse_project)]
+  project: Vec<(String, PathBuf)>,
+
+  /// Query the run_query_template tool can run, as NAME(PARAM, ...)=QUERY with {PARAM}
+  /// placeholders, e.g. 'todo_in(path)=content:(TODO OR FIXME) AND file_path:{path}*' (repeatable)
+  #[arg(long, value_parser = parse_query_template)]
+  query_template: Vec<QueryTemplate>,
+
+  /// Filter searches can combine by name, as NAME=CONDITION;... with conditions path:DIR,...,
+  /// ext:EXT,... and modified:30d, e.g. 'work-notes=path:/home/me/work;ext:md' (repeatable)
+  #[arg(long = "filter", value_parser = parse_saved_filter)]
+  saved_filter: Vec<SavedFilter>,
+
+  /// Multiply the score of files with an extension by a factor, as EXT=FACTOR, e.g.
+  /// md=2.0,log=0.5 (repeatable)
+  #[arg(long, value_parser = parse_boost, value_delimiter = ',')]
+  boost: Vec<(String, f32)>,
 
   /// Directory to store the search index (if not specified, use in-memory)
   #[arg(short, long)]
   index_dir: Option<PathBuf>,
 
+  /// Pause indexing into --index-dir while less than this many megabytes are free on its disk
+  /// (0 to never pause)
+  #[arg(long, default_value_t = 256)]
+  min_free_disk_mb: u64,
+
+  /// Storage engine of the index (sqlite requires the `sqlite` cargo feature)
+  #[arg(long, value_enum, default_value_t = Backend::Tantivy)]
+  backend: Backend,
+
+  /// Read the new segments of an index after every commit, so that the first search after heavy
+  /// indexing does not wait for the disk (tantivy backend only)
+  #[arg(long)]
+  warm_up: bool,
+
+  /// Memory in megabytes shared out between index writers, queued operations, the search cache
+  /// and in-memory indexes
+  #[arg(long)]
+  max_memory_mb: Option<u64>,
+
   /// File extensions to include (comma-separated)
   #[arg(short, long, default_value = "txt,md")]
   extensions: String,
 
+  /// Do not skip well-known dependency and build directories (.git, node_modules, target, ...)
+  #[arg(long)]
+  no_default_excludes: bool,
+
+  /// Also index dotfiles and dot-directories
+  #[arg(long)]
+  include_hidden: bool,
+
+  /// Skip files larger than this many bytes (0 for no limit)
+  #[arg(long, default_value_t = 5 * 1024 * 1024)]
+  max_file_size: u64,
+
+  /// Index text files inside zip/tar/tar.gz archives as `archive.zip!/member` documents
+  #[arg(long)]
+  index_archives: bool,
+
+  /// Split files larger than this many bytes into overlapping chunks (0 to never split)
+  #[arg(long, default_value_t = 1024 * 1024)]
+  chunk_size: usize,
+
+  /// Index Markdown files as one `file.md#heading` document per heading section
+  #[arg(long)]
+  index_sections: bool,
+
+  /// How many times a file that fails to load is read again
+  #[arg(long, default_value_t = 3)]
+  read_retries: u32,
+
+  /// Milliseconds to wait before the first retry, growing linearly with each attempt
+  #[arg(long, default_value_t = 10)]
+  read_retry_backoff_ms: u64,
+
+  /// Milliseconds to wait before loading newly created files
+  #[arg(long, default_value_t = 0)]
+  created_grace_ms: u64,
+
+  /// What to do with files that fail to load, at startup and while watching: stop, or leave them
+  /// out of the index and list them with the list_index_errors tool
+  #[arg(long, value_enum, default_value_t = ErrorPolicy::Lenient)]
+  error_policy: ErrorPolicy,
+
+  /// Crawl and index on threads of lowered CPU priority (Linux), so that indexing a large
+  /// directory does not compete with interactive work
+  #[arg(long)]
+  index_nice: bool,
+
+  /// Load at most this many files per second while crawling and applying changes (0 for no limit)
+  #[arg(long, default_value_t = 0)]
+  index_max_files_per_sec: u32,
+
+  /// Also index files without a matching extension when their content is detected as text
+  #[arg(long)]
+  detect_text_content: bool,
+
+  /// Do not honor .gitignore, .ignore and .rgignore files
+  #[arg(long)]
+  no_ignore: bool,
+
+  /// Follow symbolic links while crawling (symlink loops are detected and skipped)
+  #[arg(long)]
+  follow_symlinks: bool,
+
+  /// Recognize text in png/jpg/tiff images with tesseract
+  #[cfg(feature = "ocr")]
+  #[arg(long)]
+  ocr: bool,
+
+  /// Tesseract language used by --ocr
+  #[cfg(feature = "ocr")]
+  #[arg(long, default_value = "eng")]
+  ocr_language: String,
+
+  /// Also index documents as embeddings computed locally, enabling the semantic_search tool
+  #[cfg(feature = "semantic")]
+  #[arg(long)]
+  semantic: bool,
+
+  /// How changes are detected: native events, periodic polling, or native with polling fallback
+  #[arg(long, value_enum, default_value_t = WatchBackend::Auto)]
+  watch_backend: WatchBackend,
+
+  /// Milliseconds between rescans of the poll watch backend
+  #[arg(long, default_value_t = 2000)]
+  poll_interval_ms: u64,
+
+  /// Seconds between full rescans comparing the files with the index, catching dropped events
+  #[arg(long)]
+  rescan_interval: Option<u64>,
+
+  /// Run maintenance at these times of the local clock, as a crontab schedule of minute, hour,
+  /// day of the month, month and day of the week, e.g. '0 3 * * *' for 3 AM every night
+  #[arg(long, value_parser = parse_cron_schedule)]
+  maintenance_schedule: Option<CronSchedule>,
+
+  /// Tasks of scheduled maintenance: full rescans of the watched directories and merges of index
+  /// segments (comma-separated)
+  #[arg(
+    long,
+    value_enum,
+    value_delimiter = ',',
+    default_value = "rescan,merge"
+  )]
+  maintenance_tasks: Vec<MaintenanceTask>,
+
+  /// Log searches taking longer than this many milliseconds as slow
+  #[arg(long, default_value_t = 500)]
+  slow_query_ms: u64,
+
+  /// Sitemap or file listing one HTTP(S) URL per line whose pages are indexed (repeatable)
+  #[arg(long)]
+  url_source: Vec<String>,
+
+  /// Seconds between re-fetches of the pages of each --url-source (0 to never refresh)
+  #[arg(long, default_value_t = 3600)]
+  url_refresh_interval: u64,
+
+  /// Directory outside the watched ones that the search_directory tool may search (repeatable)
+  #[arg(long)]
+  allow_search_dir: Vec<PathBuf>,
+
+  /// Write the process id to this file while the server runs, refusing to start while another
+  /// server holds its lock
+  #[arg(long)]
+  pidfile: Option<PathBuf>,
+
+  /// Refuse tool calls while this many are already running (0 for no limit)
+  #[arg(long, default_value_t = 0)]
+  max_concurrent_requests: usize,
+
+  /// Refuse tool calls beyond this many per second (0 for no limit)
+  #[arg(long, default_value_t = 0)]
+  max_requests_per_sec: u32,
+
+  /// Keep this many recent result sets for refine and load_result_set, saved next to the index
+  /// (0 to give searches no result set id)
+  #[arg(long, default_value_t = 64)]
+  max_result_sets: usize,
+
+  /// Markers the todo_report tool looks for unless given others (comma-separated)
+  #[arg(long, value_delimiter = ',', default_value = "TODO,FIXME,HACK")]
+  todo_markers: Vec<String>,
+
+  /// Directory the export_results tool may write result files into
+  #[arg(long)]
+  export_dir: Option<PathBuf>,
+
+  /// Index, write the results of this query to --export-output (stdout when not given) and exit
+  /// instead of serving
+  #[arg(long)]
+  export: Option<String>,
+
+  /// File --export writes to
+  #[arg(long, requires = "export")]
+  export_output: Option<PathBuf>,
+
+  /// Format of --export, defaulting to the extension of --export-output, else json
+  #[arg(long, value_enum, requires = "export")]
+  export_format: Option<ExportFormat>,
+
+  /// Enable the write_file, append_file, delete_file, move_file and undo_last_change tools, which
+  /// create, update, delete and move files inside the watched directories
+  #[arg(long)]
+  allow_write: bool,
+
+  /// Directory deleted and replaced files are moved to, so that changes can be undone. It is never
+  /// emptied automatically [default: trash in the index directory, else in the temp directory]
+  #[arg(long)]
+  trash_dir: Option<PathBuf>,
+
+  /// Keep the previously indexed content of every document in memory, so that the diff_file
+  /// tool can show what changed in a file since the index pass before
+  #[arg(long)]
+  keep_shadow_copies: bool,
+
+  /// Snapshot watched files whenever they change, into a content-addressed store next to the
+  /// index, for the list_versions and load_version tools. Needs --index-dir
+  #[arg(long)]
+  version_history: bool,
+
+  /// Add the author, date and id of the last commit of their file, and the branch and worktree,
+  /// to documents of watch roots in git work trees, and filter searches with author:name and
+  /// branch:name. Needs the git command
+  #[arg(long)]
+  git_metadata: bool,
+
+  /// Metadata field to extract from every document, as NAME=regex:PATTERN (each distinct match)
+  /// or NAME=front-matter:KEY, searchable as metadata.NAME:value (repeatable)
+  #[arg(long, value_parser = parse_extra_field)]
+  extra_field: Vec<ExtraField>,
+
+  /// Leave file content out of search results and only load files with the user's confirmation
+  #[arg(long)]
+  privacy_mode: bool,
+
+  /// Append every tool call with its parameters, client and number of results to this file
+  #[arg(long)]
+  audit_log: Option<PathBuf>,
+
+  /// Append every file operation queued for indexing to this file as a JSON line, to attach to
+  /// bug reports and run again with `replay`
+  #[arg(long)]
+  record_events: Option<PathBuf>,
+
   /// Enable verbose logging (debug level)
   #[arg(short, long)]
   verbose: bool,
@@ -27,6 +293,79 @@ struct Cli {
   quiet: bool,
 }
 
+#[derive(Subcommand)]
+enum Command {
+  /// Index a directory with the given options, then run a query workload against it and print
+  /// the indexing throughput and query latency percentiles as JSON, instead of serving
+  Bench {
+    /// Directory to index in place of --watch-dir and --project
+    dir: PathBuf,
+
+    /// Query to run (repeatable; defaults to words of the indexed file names)
+    #[arg(long)]
+    query: Vec<String>,
+
+    /// File with one query per line, run after the --query ones
+    #[arg(long)]
+    queries_file: Option<PathBuf>,
+
+    /// Times every query is run
+    #[arg(long, default_value_t = 10)]
+    iterations: usize,
+  },
+  /// Index the watched directories, then apply the file operations of a recording one at a time
+  /// and in order, and print what the given queries find as JSON, instead of serving
+  Replay {
+    /// Recording made with --record-events, or a JSON array of {"operation": ...} objects
+    recording: PathBuf,
+
+    /// Query to run once the operations are applied (repeatable)
+    #[arg(long)]
+    query: Vec<String>,
+  },
+}
+
+fn parse_project(value: &str) -> Result<(String, PathBuf), String> {
+  match value.split_once('=') {
+    Some((name, dir)) if !name.is_empty() && !dir.is_empty() => {
+      Ok((name.to_string(), PathBuf::from(dir)))
+    }
+    _ => Err(format!("expected NAME=DIR, got {}", value)),
+  }
+}
+
+fn parse_query_template(value: &str) -> Result<QueryTemplate, String> {
+  value.parse()
+}
+
+fn parse_cron_schedule(value: &str) -> Result<CronSchedule, String> {
+  value.parse()
+}
+
+fn parse_saved_filter(value: &str) -> Result<SavedFilter, String> {
+  value.parse()
+}
+
+fn parse_extra_field(value: &str) -> Result<ExtraField, String> {
+  value.parse()
+}
+
+fn parse_boost(value: &str) -> Result<(String, f32), String> {
+  let (extension, factor) = value
+    .split_once('=')
+    .ok_or_else(|| format!("expected EXT=FACTOR, got {}", value))?;
+  let factor = factor
+    .parse::<f32>()
+    .ok()
+    .filter(|factor| factor.is_finite() && *factor > 0.0)
+    .ok_or_else(|| format!("expected a positive factor, got {}", factor))?;
+  let extension = extension.trim_start_matches('.').to_lowercase();
+  if extension.is_empty() {
+    return Err(format!("expected EXT=FACTOR, got {}", value));
+  }
+  Ok((extension, factor))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
   let cli = Cli::parse();
@@ -45,7 +384,157 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     .with_ansi(false)
     .init();
 
-  let application = application::Application::new(cli.watch_dir, cli.index_dir, cli.extensions)?;
+  let _pidfile = cli.pidfile.as_deref().map(Pidfile::create).transpose()?;
+
+  let excluded_directories = if cli.no_default_excludes {
+    Vec::new()
+  } else {
+    DEFAULT_EXCLUDED_DIRECTORIES
+      .iter()
+      .map(|d| d.to_string())
+      .collect()
+  };
+
+  #[allow(unused_mut)]
+  let mut extensions = cli
+    .extensions
+    .split(",")
+    .map(|e| e.to_string())
+    .collect::<Vec<_>>();
+  #[allow(unused_mut)]
+  let mut extractors = ExtractorRegistry::new_with_default_extractors();
+  #[cfg(feature = "ocr")]
+  if cli.ocr {
+    extractors.register_ocr(&cli.ocr_language);
+    extensions.extend(
+      fs_text_search_mcp::file::extractor::ocr::OCR_EXTENSIONS
+        .iter()
+        .map(|e| e.to_string()),
+    );
+  }
+
+  #[allow(unused_mut)]
+  let mut embedder: Option<Arc<dyn Embedder + Send + Sync>> = None;
+  #[cfg(feature = "semantic")]
+  if cli.semantic {
+    /* Keep the downloaded model with the index rather than in the working directory */
+    let cache_dir = cli
+      .index_dir
+      .as_ref()
+      .map(|index_dir| index_dir.join(".fastembed_cache"));
+    embedder = Some(Arc::new(
+      fs_text_search_mcp::search::semantic_index::fast_embedder::FastEmbedder::new(cache_dir)?,
+    ));
+  }
+
+  let mut watch_dirs = cli.watch_dir;
+  let mut project_names = HashMap::new();
+  if watch_dirs.is_empty() && cli.project.is_empty() {
+    watch_dirs.push(PathBuf::from("."));
+  }
+  for (name, dir) in cli.project {
+    watch_dirs.push(dir.clone());
+    project_names.insert(dir, name);
+  }
+  if let Some(Command::Bench { dir, .. }) = &cli.command {
+    watch_dirs = vec![dir.clone()];
+    project_names.clear();
+  }
+
+  /* Indexing happens while the application is created, which the benchmark times */
+  let started = Instant::now();
+
+  let application = application::Application::new(ApplicationConfig {
+    watch_dirs,
+    project_names,
+    index_dir: cli.index_dir,
+    min_free_disk_space: cli.min_free_disk_mb * 1024 * 1024,
+    backend: cli.backend,
+    extensions,
+    excluded_directories,
+    include_hidden: cli.include_hidden,
+    detect_text_content: cli.detect_text_content,
+    follow_symlinks: cli.follow_symlinks,
+    respect_ignore_files: !cli.no_ignore,
+    max_file_size: (cli.max_file_size > 0).then_some(cli.max_file_size),
+    index_archives: cli.index_archives,
+    chunk_size: (cli.chunk_size > 0).then_some(cli.chunk_size),
+    index_sections: cli.index_sections,
+    retry_policy: RetryPolicy::new(
+      cli.read_retries,
+      Duration::from_millis(cli.read_retry_backoff_ms),
+    ),
+    created_grace_delay: Duration::from_millis(cli.created_grace_ms),
+    error_policy: cli.error_policy,
+    index_nice: cli.index_nice,
+    max_files_per_second: (cli.index_max_files_per_sec > 0).then_some(cli.index_max_files_per_sec),
+    watch_backend: cli.watch_backend,
+    poll_interval: Duration::from_millis(cli.poll_interval_ms),
+    rescan_interval: cli
+      .rescan_interval
+      .filter(|seconds| *seconds > 0)
+      .map(Duration::from_secs),
+    maintenance_schedule: cli.maintenance_schedule,
+    maintenance_tasks: cli.maintenance_tasks,
+    url_sources: cli.url_source,
+    url_refresh_interval: (cli.url_refresh_interval > 0)
+      .then(|| Duration::from_secs(cli.url_refresh_interval)),
+    allowed_search_dirs: cli.allow_search_dir,
+    embedder,
+    extractors,
+    slow_query_threshold: Duration::from_millis(cli.slow_query_ms),
+    extension_boosts: cli.boost.into_iter().collect(),
+    query_templates: cli.query_template,
+    saved_filters: cli.saved_filter,
+    audit_log: cli.audit_log,
+    privacy_mode: cli.privacy_mode,
+    max_concurrent_requests: (cli.max_concurrent_requests > 0)
+      .then_some(cli.max_concurrent_requests),
+    max_requests_per_second: (cli.max_requests_per_sec > 0).then_some(cli.max_requests_per_sec),
+    max_result_sets: cli.max_result_sets,
+    export_dir: cli.export_dir,
+    todo_markers: cli.todo_markers,
+    allow_write: cli.allow_write,
+    trash_dir: cli.trash_dir,
+    keep_shadow_copies: cli.keep_shadow_copies,
+    version_history: cli.version_history,
+    git_metadata: cli.git_metadata,
+    extra_fields: cli.extra_field,
+    warm_up: cli.warm_up,
+    max_memory_bytes: cli.max_memory_mb.map(|megabytes| megabytes * 1024 * 1024),
+    record_events: cli.record_events,
+  })?;
+  if let Some(Command::Bench {
+    mut query,
+    queries_file,
+    iterations,
+    ..
+  }) = cli.command
+  {
+    let index_duration = started.elapsed();
+    if let Some(queries_file) = queries_file {
+      query.extend(
+        std::fs::read_to_string(queries_file)?
+          .lines()
+          .map(str::trim)
+          .filter(|line| !line.is_empty())
+          .map(str::to_string),
+      );
+    }
+    let report = application.bench(index_duration, query, iterations)?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    return Ok(());
+  }
+  if let Some(Command::Replay { recording, query }) = &cli.command {
+    let report = application.replay(recording, query)?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    return Ok(());
+  }
+  if let Some(query) = &cli.export {
+    let results = application.export(query, cli.export_format, cli.export_output.as_deref())?;
+    tracing::info!("Exported {} results", results);
+    return Ok(());
+  }
   application.run().await?;
 
   Ok(())
@@ -0,0 +1,89 @@
+use anyhow::Result;
+use fs4::fs_std::FileExt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// File holding the id of the running server for service managers and scripts, locked for the
+/// lifetime of the server and removed when it shuts down.
+pub struct Pidfile {
+  path: PathBuf,
+  file: File,
+}
+
+impl Pidfile {
+  /// Writes the id of this process, refusing to start a second server while another one holds the
+  /// lock on the pidfile. A leftover file of a crashed server is unlocked and simply reused.
+  pub fn create(path: &Path) -> Result<Self> {
+    let mut file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(false)
+      .open(path)
+      .map_err(|e| anyhow::anyhow!("Cannot open pidfile {:?}: {}", path, e))?;
+    /* The lock is released by the OS when the process exits, so unlike a check of the recorded pid
+    it cannot race with a second server starting at the same time or trip over a reused pid. */
+    if !FileExt::try_lock_exclusive(&file)? {
+      let mut content = String::new();
+      file.read_to_string(&mut content).ok();
+      anyhow::bail!(
+        "Already running as process {} according to {:?}",
+        content.trim(),
+        path
+      );
+    }
+    file.set_len(0)?;
+    file.rewind()?;
+    writeln!(file, "{}", std::process::id())
+      .and_then(|_| file.flush())
+      .map_err(|e| anyhow::anyhow!("Cannot write pidfile {:?}: {}", path, e))?;
+    Ok(Pidfile {
+      path: path.to_path_buf(),
+      file,
+    })
+  }
+}
+
+impl Drop for Pidfile {
+  fn drop(&mut self) {
+    /* Removed while still locked so a starting server never locks the file of the old one. */
+    if let Err(e) = std::fs::remove_file(&self.path) {
+      tracing::warn!("Failed to remove pidfile {:?}: {}", self.path, e);
+    }
+    FileExt::unlock(&self.file).ok();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn pidfile_should_hold_process_id_until_dropped() {
+    let path = std::env::temp_dir().join(format!("pidfile-test-{}.pid", std::process::id()));
+    let pidfile = Pidfile::create(&path).unwrap();
+    assert_eq!(
+      std::fs::read_to_string(&path).unwrap(),
+      format!("{}\n", std::process::id())
+    );
+    drop(pidfile);
+    assert!(!path.exists());
+  }
+
+  #[test]
+  fn pidfile_should_refuse_second_server_while_locked() {
+    let path = std::env::temp_dir().join(format!("pidfile-lock-test-{}.pid", std::process::id()));
+    let pidfile = Pidfile::create(&path).unwrap();
+    let error = Pidfile::create(&path).err().unwrap();
+    assert!(error.to_string().contains("Already running"));
+    drop(pidfile);
+    std::fs::write(&path, "999999\n").unwrap();
+    let pidfile = Pidfile::create(&path).unwrap();
+    assert_eq!(
+      std::fs::read_to_string(&path).unwrap(),
+      format!("{}\n", std::process::id())
+    );
+    drop(pidfile);
+  }
+}
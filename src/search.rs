@@ -1,3 +1,38 @@
+pub mod bench;
+pub mod bookmarks;
+pub mod cancellation;
+pub mod commit_status;
+pub mod directory_tree;
+pub mod disk_space_guard;
+pub mod event_log;
+pub mod export;
+pub mod extra_fields;
 pub mod file;
+pub mod git_metadata;
+pub mod git_revision;
+pub mod index_backend;
 pub mod index_operation;
+pub mod language_index;
+pub mod line_diff;
+pub mod maintenance;
+pub mod match_mode;
+pub mod memory_budget;
+pub mod near_duplicates;
+pub mod operation_recording;
+pub mod path_completion;
+pub mod project_index;
+pub mod query_stats;
+pub mod query_syntax;
+pub mod query_template;
+pub mod result_sets;
+pub mod saved_filters;
+pub mod search_cache;
+pub mod search_in_dir;
+pub mod semantic_index;
+pub mod shadow_copies;
+pub mod sharded_index;
+pub mod skipped_files;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_index;
 pub mod text_index;
+pub mod todo_report;
@@ -0,0 +1,162 @@
+use anyhow::Result;
+use std::{
+  collections::BTreeSet,
+  path::Path,
+  time::{Duration, Instant},
+};
+
+use super::{
+  index_backend::SEARCH_FILE_LIMIT,
+  sharded_index::{SearchScope, ShardedIndex},
+};
+
+/// Queries taken from the names of the indexed files when none are given.
+const DEFAULT_QUERY_COUNT: usize = 20;
+
+/// Latency of the queries of a benchmark, over every run of every query.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct LatencySummary {
+  pub runs: usize,
+  pub mean_ms: f64,
+  pub p50_ms: f64,
+  pub p95_ms: f64,
+  pub p99_ms: f64,
+  pub max_ms: f64,
+}
+
+impl LatencySummary {
+  pub fn new(latencies: &[Duration]) -> Self {
+    let mut latencies = latencies
+      .iter()
+      .map(|latency| latency.as_secs_f64() * 1000.0)
+      .collect::<Vec<_>>();
+    latencies.sort_by(f64::total_cmp);
+    let percentile = |p: usize| {
+      latencies
+        .get((latencies.len() * p / 100).min(latencies.len().saturating_sub(1)))
+        .copied()
+        .unwrap_or_default()
+    };
+    LatencySummary {
+      runs: latencies.len(),
+      mean_ms: latencies.iter().sum::<f64>() / latencies.len().max(1) as f64,
+      p50_ms: percentile(50),
+      p95_ms: percentile(95),
+      p99_ms: percentile(99),
+      max_ms: latencies.last().copied().unwrap_or_default(),
+    }
+  }
+}
+
+/// What `bench` measured: how fast the directory was indexed and how fast the queries ran.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BenchReport {
+  pub files: usize,
+  pub bytes: u64,
+  pub index_seconds: f64,
+  pub files_per_second: f64,
+  pub megabytes_per_second: f64,
+  pub queries: Vec<String>,
+  pub latency: LatencySummary,
+}
+
+/// Runs every query `iterations` times against `index`, which took `index_duration` to build,
+/// and reports indexing throughput alongside query latency.
+pub fn run_bench(
+  index: &ShardedIndex,
+  index_duration: Duration,
+  queries: Vec<String>,
+  iterations: usize,
+) -> Result<BenchReport> {
+  let file_paths = index.indexed_paths(&SearchScope::default())?;
+  let bytes = file_paths
+    .iter()
+    .filter_map(|file_path| std::fs::metadata(file_path).ok())
+    .map(|metadata| metadata.len())
+    .sum::<u64>();
+  let queries = if queries.is_empty() {
+    default_queries(&file_paths)
+  } else {
+    queries
+  };
+  if queries.is_empty() {
+    anyhow::bail!("Nothing to query: no query given and no file indexed");
+  }
+
+  let mut latencies = Vec::with_capacity(queries.len() * iterations);
+  for _ in 0..iterations {
+    for query in &queries {
+      let started = Instant::now();
+      index.search_hits(query, SEARCH_FILE_LIMIT, &SearchScope::default())?;
+      latencies.push(started.elapsed());
+    }
+  }
+
+  let index_seconds = index_duration.as_secs_f64();
+  let per_second = |amount: f64| {
+    if index_seconds > 0.0 {
+      amount / index_seconds
+    } else {
+      0.0
+    }
+  };
+  Ok(BenchReport {
+    files: file_paths.len(),
+    bytes,
+    index_seconds,
+    files_per_second: per_second(file_paths.len() as f64),
+    megabytes_per_second: per_second(bytes as f64 / (1024.0 * 1024.0)),
+    queries,
+    latency: LatencySummary::new(&latencies),
+  })
+}
+
+/// Words of the names of the indexed files, which are likely to occur in their content too.
+fn default_queries(file_paths: &BTreeSet<String>) -> Vec<String> {
+  let mut words = BTreeSet::new();
+  for file_path in file_paths {
+    let Some(stem) = Path::new(file_path)
+      .file_stem()
+      .and_then(|stem| stem.to_str())
+    else {
+      continue;
+    };
+    /* Plain words only, so that no name is mistaken for query syntax */
+    words.extend(
+      stem
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= 3)
+        .map(str::to_lowercase),
+    );
+  }
+  words.into_iter().take(DEFAULT_QUERY_COUNT).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn latency_summary_should_report_percentiles() {
+    let latencies = (1..=100).map(Duration::from_millis).collect::<Vec<_>>();
+    let summary = LatencySummary::new(&latencies);
+    assert_eq!(summary.runs, 100);
+    assert_eq!(summary.mean_ms, 50.5);
+    assert_eq!(summary.p50_ms, 51.0);
+    assert_eq!(summary.p95_ms, 96.0);
+    assert_eq!(summary.p99_ms, 100.0);
+    assert_eq!(summary.max_ms, 100.0);
+
+    assert_eq!(LatencySummary::new(&[]).mean_ms, 0.0);
+
+    let file_paths = [
+      "/notes/release-plan.md",
+      "/notes/a.txt",
+      "/notes/Release(2).md",
+    ]
+    .into_iter()
+    .map(str::to_string)
+    .collect();
+    assert_eq!(default_queries(&file_paths), vec!["plan", "release"]);
+  }
+}
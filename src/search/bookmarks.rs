@@ -0,0 +1,159 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::{
+  cmp::Reverse,
+  collections::BTreeMap,
+  path::PathBuf,
+  sync::atomic::{AtomicU64, Ordering},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+  file::document_file_path,
+  index_backend::{SearchHit, next_generation},
+};
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Bookmark {
+  pub file_path: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub note: Option<String>,
+  /// Seconds since the Unix epoch
+  pub pinned_at: u64,
+}
+
+/// Documents pinned as important context, listed first among search results. Persisted to a
+/// JSON file when one is given so that they survive restarts.
+pub struct Bookmarks {
+  bookmarks: Mutex<BTreeMap<String, Bookmark>>,
+  bookmarks_file: Option<PathBuf>,
+  generation: AtomicU64,
+}
+
+impl Bookmarks {
+  pub fn new() -> Self {
+    Bookmarks {
+      bookmarks: Mutex::new(BTreeMap::new()),
+      bookmarks_file: None,
+      generation: AtomicU64::new(next_generation()),
+    }
+  }
+
+  pub fn new_with_file(bookmarks_file: PathBuf) -> Self {
+    let bookmarks = std::fs::read(&bookmarks_file)
+      .ok()
+      .and_then(|bytes| {
+        serde_json::from_slice::<Vec<Bookmark>>(&bytes)
+          .inspect_err(|e| {
+            tracing::warn!("Ignoring unreadable bookmarks {:?}: {}", bookmarks_file, e)
+          })
+          .ok()
+      })
+      .unwrap_or_default();
+    Bookmarks {
+      bookmarks: Mutex::new(
+        bookmarks
+          .into_iter()
+          .map(|bookmark| (bookmark.file_path.clone(), bookmark))
+          .collect(),
+      ),
+      bookmarks_file: Some(bookmarks_file),
+      generation: AtomicU64::new(next_generation()),
+    }
+  }
+
+  pub fn pin(&self, file_path: &str, note: Option<String>) -> Result<Bookmark> {
+    let pinned_at = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|duration| duration.as_secs())
+      .unwrap_or_default();
+    let bookmark = Bookmark {
+      file_path: file_path.to_string(),
+      note,
+      pinned_at,
+    };
+    let mut bookmarks = self.bookmarks.lock();
+    bookmarks.insert(file_path.to_string(), bookmark.clone());
+    self.save(&bookmarks)?;
+    Ok(bookmark)
+  }
+
+  /// Returns whether the document was pinned.
+  pub fn unpin(&self, file_path: &str) -> Result<bool> {
+    let mut bookmarks = self.bookmarks.lock();
+    let removed = bookmarks.remove(file_path).is_some();
+    if removed {
+      self.save(&bookmarks)?;
+    }
+    Ok(removed)
+  }
+
+  /// Pinned documents, most recently pinned first.
+  pub fn list(&self) -> Vec<Bookmark> {
+    let mut bookmarks = self.bookmarks.lock().values().cloned().collect::<Vec<_>>();
+    bookmarks.sort_by_key(|bookmark| Reverse(bookmark.pinned_at));
+    bookmarks
+  }
+
+  /// Whether the document, or the file a chunk or section belongs to, is pinned.
+  pub fn is_pinned(&self, file_path: &str) -> bool {
+    let bookmarks = self.bookmarks.lock();
+    bookmarks.contains_key(file_path) || bookmarks.contains_key(document_file_path(file_path))
+  }
+
+  /// Changes whenever a document is pinned or unpinned, so cached rankings can be invalidated.
+  pub fn generation(&self) -> u64 {
+    self.generation.load(Ordering::Relaxed)
+  }
+
+  /// Moves pinned hits before the others, keeping the order within both groups.
+  pub fn rank(&self, hits: &mut [SearchHit]) {
+    hits.sort_by_key(|hit| !self.is_pinned(&hit.file_path));
+  }
+
+  fn save(&self, bookmarks: &BTreeMap<String, Bookmark>) -> Result<()> {
+    self.generation.store(next_generation(), Ordering::Relaxed);
+    let Some(bookmarks_file) = &self.bookmarks_file else {
+      return Ok(());
+    };
+    /* Written next to the target and renamed over it so a crash never leaves a partial file */
+    let temporary_file = bookmarks_file.with_extension("tmp");
+    std::fs::write(
+      &temporary_file,
+      serde_json::to_vec(&bookmarks.values().collect::<Vec<_>>())?,
+    )?;
+    std::fs::rename(&temporary_file, bookmarks_file)?;
+    Ok(())
+  }
+}
+
+impl Default for Bookmarks {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bookmarks_should_persist_pins_across_instances() {
+    let bookmarks_file =
+      std::env::temp_dir().join(format!("bookmarks-test-{}.json", std::process::id()));
+    let bookmarks = Bookmarks::new_with_file(bookmarks_file.clone());
+    bookmarks
+      .pin("/notes/a.md", Some("roadmap".to_string()))
+      .unwrap();
+    bookmarks.pin("/logs/app.log", None).unwrap();
+    assert!(bookmarks.unpin("/notes/a.md").unwrap());
+    bookmarks.pin("/notes/b.md", None).unwrap();
+
+    let reopened = Bookmarks::new_with_file(bookmarks_file.clone());
+    assert!(reopened.is_pinned("/notes/b.md"));
+    assert!(reopened.is_pinned("/logs/app.log#chunk=4096"));
+    assert!(!reopened.is_pinned("/notes/a.md"));
+    assert_eq!(reopened.list().len(), 2);
+    std::fs::remove_file(&bookmarks_file).unwrap();
+  }
+}
@@ -0,0 +1,96 @@
+use anyhow::Result;
+use std::{
+  cell::RefCell,
+  future::Future,
+  pin::Pin,
+  task::{Context, Poll},
+};
+use tokio_util::sync::CancellationToken;
+
+thread_local! {
+  /* The token of the tool call running on this thread, seen by searches and scans deep below it
+  without passing it through every signature */
+  static CURRENT: RefCell<Option<CancellationToken>> = const { RefCell::new(None) };
+}
+
+/// Restores the token of the enclosing call, also when the work panics.
+struct Restore(Option<CancellationToken>);
+
+impl Drop for Restore {
+  fn drop(&mut self) {
+    CURRENT.set(self.0.take());
+  }
+}
+
+/// Runs `work` on this thread as part of the call `token` cancels.
+pub fn with_cancellation<T>(token: Option<CancellationToken>, work: impl FnOnce() -> T) -> T {
+  let _restore = Restore(CURRENT.replace(token));
+  work()
+}
+
+/// The token of the call running on this thread, if any.
+pub fn current_cancellation() -> Option<CancellationToken> {
+  CURRENT.with_borrow(|token| token.clone())
+}
+
+/// Fails once the call running on this thread was cancelled, for long searches and scans to
+/// check between steps so that a call the client gave up on stops using the server.
+pub fn check_cancelled() -> Result<()> {
+  if CURRENT.with_borrow(|token| token.as_ref().is_some_and(CancellationToken::is_cancelled)) {
+    anyhow::bail!("Cancelled by the client");
+  }
+  Ok(())
+}
+
+/// Polls a tool call with its token set, so that the synchronous work it does between awaits
+/// sees it.
+pub struct Cancellable<F> {
+  call: Pin<Box<F>>,
+  token: CancellationToken,
+}
+
+impl<F: Future> Cancellable<F> {
+  pub fn new(call: F, token: CancellationToken) -> Self {
+    Cancellable {
+      call: Box::pin(call),
+      token,
+    }
+  }
+}
+
+impl<F: Future> Future for Cancellable<F> {
+  type Output = F::Output;
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let token = self.token.clone();
+    with_cancellation(Some(token), || self.call.as_mut().poll(cx))
+  }
+}
+
+/// `tokio::task::spawn_blocking` carrying the token of the current call to the blocking thread.
+pub async fn spawn_blocking<T: Send + 'static>(
+  work: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, tokio::task::JoinError> {
+  let token = current_cancellation();
+  tokio::task::spawn_blocking(move || with_cancellation(token, work)).await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn check_cancelled_should_only_fail_inside_a_cancelled_call() {
+    let token = CancellationToken::new();
+    assert!(check_cancelled().is_ok());
+    with_cancellation(Some(token.clone()), || {
+      assert!(check_cancelled().is_ok());
+      token.cancel();
+      assert!(check_cancelled().is_err());
+      /* A nested call without a token is not cancelled with the outer one */
+      with_cancellation(None, || assert!(check_cancelled().is_ok()));
+      assert!(check_cancelled().is_err());
+    });
+    assert!(check_cancelled().is_ok());
+  }
+}
@@ -0,0 +1,81 @@
+use parking_lot::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Commits failing in a row since the last successful one.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CommitFailure {
+  /// Error of the latest failed commit
+  pub error: String,
+  /// Seconds since the Unix epoch of the first failure, since when searches may miss changes
+  pub failing_since: u64,
+  pub failed_commits: u64,
+}
+
+/// Outcome of the commits of an index, so that an index that cannot be written (e.g. on a full
+/// disk) is reported instead of silently serving stale results.
+pub struct CommitStatus {
+  failure: Mutex<Option<CommitFailure>>,
+}
+
+impl CommitStatus {
+  pub fn new() -> Self {
+    Self {
+      failure: Mutex::new(None),
+    }
+  }
+
+  pub fn record(&self, result: &anyhow::Result<()>) {
+    let mut failure = self.failure.lock();
+    let Err(e) = result else {
+      *failure = None;
+      return;
+    };
+    let error = format!("{:#}", e);
+    match failure.as_mut() {
+      Some(failure) => {
+        failure.error = error;
+        failure.failed_commits += 1;
+      }
+      None => {
+        let failing_since = SystemTime::now()
+          .duration_since(UNIX_EPOCH)
+          .map(|duration| duration.as_secs())
+          .unwrap_or_default();
+        *failure = Some(CommitFailure {
+          error,
+          failing_since,
+          failed_commits: 1,
+        });
+      }
+    }
+  }
+
+  /// The failure of the last commit, or `None` if it succeeded.
+  pub fn failure(&self) -> Option<CommitFailure> {
+    self.failure.lock().clone()
+  }
+}
+
+impl Default for CommitStatus {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn commit_status_should_count_failures_until_success() {
+    let status = CommitStatus::new();
+    status.record(&Err(anyhow::anyhow!("No space left on device")));
+    status.record(&Err(anyhow::anyhow!("No space left on device")));
+    let failure = status.failure().unwrap();
+    assert_eq!(failure.failed_commits, 2);
+    assert_eq!(failure.error, "No space left on device");
+
+    status.record(&Ok(()));
+    assert!(status.failure().is_none());
+  }
+}
@@ -0,0 +1,124 @@
+use std::path::{Component, Path};
+
+/// Directory levels shown below the root unless asked for another number.
+pub const DEFAULT_TREE_DEPTH: usize = 2;
+
+/// A directory with the indexed files in it and below it. Files deeper than the requested depth
+/// are counted in their ancestor at that depth.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct DirectoryNode {
+  pub path: String,
+  pub files: usize,
+  /// Total size of the files in bytes
+  pub size: u64,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub directories: Vec<DirectoryNode>,
+}
+
+impl DirectoryNode {
+  fn new(path: String) -> Self {
+    DirectoryNode {
+      path,
+      files: 0,
+      size: 0,
+      directories: Vec::new(),
+    }
+  }
+
+  fn add(&mut self, directories: &[String], size: u64) {
+    self.files += 1;
+    self.size += size;
+    let Some((name, rest)) = directories.split_first() else {
+      return;
+    };
+    let path = Path::new(&self.path)
+      .join(name)
+      .to_string_lossy()
+      .into_owned();
+    /* Kept sorted by path so that the tree reads like a directory listing */
+    let position = match self
+      .directories
+      .binary_search_by(|directory| directory.path.cmp(&path))
+    {
+      Ok(position) => position,
+      Err(position) => {
+        self.directories.insert(position, DirectoryNode::new(path));
+        position
+      }
+    };
+    self.directories[position].add(rest, size);
+  }
+}
+
+/// Tree of the directories under `root` holding `file_paths`, at most `max_depth` levels deep.
+/// Files outside `root` are ignored.
+pub fn directory_tree<'a>(
+  root: &str,
+  file_paths: impl IntoIterator<Item = &'a str>,
+  max_depth: usize,
+  file_size: impl Fn(&str) -> u64,
+) -> DirectoryNode {
+  let mut tree = DirectoryNode::new(root.to_string());
+  for file_path in file_paths {
+    let Ok(relative) = Path::new(file_path).strip_prefix(root) else {
+      continue;
+    };
+    let directories = relative
+      .parent()
+      .into_iter()
+      .flat_map(Path::components)
+      .filter_map(|component| match component {
+        Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+        _ => None,
+      })
+      .take(max_depth)
+      .collect::<Vec<_>>();
+    tree.add(&directories, file_size(file_path));
+  }
+  tree
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn directory_tree_should_count_files_up_to_max_depth() {
+    let file_paths = [
+      "/notes/a.md",
+      "/notes/garden/b.md",
+      "/notes/garden/2024/c.md",
+      "/notes/work/d.md",
+      "/elsewhere/e.md",
+    ];
+
+    let tree = directory_tree("/notes", file_paths, 1, |_| 10);
+    assert_eq!(
+      tree,
+      DirectoryNode {
+        path: "/notes".to_string(),
+        files: 4,
+        size: 40,
+        directories: vec![
+          DirectoryNode {
+            path: "/notes/garden".to_string(),
+            files: 2,
+            size: 20,
+            directories: Vec::new(),
+          },
+          DirectoryNode {
+            path: "/notes/work".to_string(),
+            files: 1,
+            size: 10,
+            directories: Vec::new(),
+          },
+        ],
+      }
+    );
+    assert!(
+      directory_tree("/notes", file_paths, 0, |_| 10)
+        .directories
+        .is_empty()
+    );
+  }
+}
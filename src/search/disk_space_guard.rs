@@ -0,0 +1,169 @@
+use anyhow::Result;
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  path::PathBuf,
+};
+
+use super::{
+  file::File,
+  index_backend::{IndexBackend, IndexMemory, SearchHit, SearchOptions},
+};
+
+/// Refuses to write new segments to an index directory on a disk with less than `min_free_bytes`
+/// available, so that a full disk pauses indexing instead of leaving a half-written index behind.
+/// Uncommitted changes stay pending and are committed by the first commit after space is freed.
+pub struct DiskSpaceGuardBackend<B: IndexBackend> {
+  inner: B,
+  /// `None` for in-memory indexes, which are never guarded
+  index_dir: Option<PathBuf>,
+  min_free_bytes: u64,
+}
+
+impl<B: IndexBackend> DiskSpaceGuardBackend<B> {
+  pub fn new(inner: B, index_dir: Option<PathBuf>, min_free_bytes: u64) -> Self {
+    DiskSpaceGuardBackend {
+      inner,
+      index_dir,
+      min_free_bytes,
+    }
+  }
+
+  fn check_free_space(&self) -> Result<()> {
+    let Some(index_dir) = self.index_dir.as_ref().filter(|_| self.min_free_bytes > 0) else {
+      return Ok(());
+    };
+    let available = fs4::available_space(index_dir)?;
+    if available < self.min_free_bytes {
+      tracing::error!(
+        "Indexing into {:?} is paused: {} bytes free, {} required",
+        index_dir,
+        available,
+        self.min_free_bytes
+      );
+      anyhow::bail!(
+        "Only {} bytes are free on the disk of {:?}, less than the {} bytes required to write the index. Indexing is paused until space is freed.",
+        available,
+        index_dir,
+        self.min_free_bytes
+      );
+    }
+    Ok(())
+  }
+}
+
+impl<B: IndexBackend> IndexBackend for DiskSpaceGuardBackend<B> {
+  fn add_doc(&mut self, file: &File) -> Result<()> {
+    self.inner.add_doc(file)
+  }
+
+  /// Checked up front because adding many documents commits them.
+  fn add_docs(&mut self, files: Box<dyn Iterator<Item = File> + '_>) -> Result<usize> {
+    self.check_free_space()?;
+    self.inner.add_docs(files)
+  }
+
+  fn replace_doc(&mut self, file: &File) -> Result<()> {
+    self.inner.replace_doc(file)
+  }
+
+  fn delete_doc(&mut self, file_path: &str) -> Result<()> {
+    self.inner.delete_doc(file_path)
+  }
+
+  fn delete_docs_by_path_prefix(&mut self, path_prefix: &str) -> Result<usize> {
+    self.inner.delete_docs_by_path_prefix(path_prefix)
+  }
+
+  fn has_doc(&self, file_path: &str) -> Result<bool> {
+    self.inner.has_doc(file_path)
+  }
+
+  fn has_docs_with_path_prefix(&self, path_prefix: &str) -> Result<bool> {
+    self.inner.has_docs_with_path_prefix(path_prefix)
+  }
+
+  fn indexed_paths(&self) -> Result<BTreeSet<String>> {
+    self.inner.indexed_paths()
+  }
+
+  fn indexed_at(&self, file_path: &str) -> Result<Option<u64>> {
+    self.inner.indexed_at(file_path)
+  }
+
+  fn commit(&mut self) -> Result<()> {
+    self.check_free_space()?;
+    self.inner.commit()
+  }
+
+  fn optimize(&mut self) -> Result<()> {
+    /* Merged segments are written before the old ones are removed */
+    self.check_free_space()?;
+    self.inner.optimize()
+  }
+
+  fn generation(&self) -> u64 {
+    self.inner.generation()
+  }
+
+  fn get_pending_operations(&self) -> usize {
+    self.inner.get_pending_operations()
+  }
+
+  fn num_docs(&self) -> u64 {
+    self.inner.num_docs()
+  }
+
+  fn memory_usage(&self) -> IndexMemory {
+    self.inner.memory_usage()
+  }
+
+  fn clear(&mut self) -> Result<()> {
+    self.inner.clear()
+  }
+
+  fn search_hits(&self, keyword: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_hits(keyword, limit)
+  }
+
+  fn search_hits_with(
+    &self,
+    keyword: &str,
+    limit: usize,
+    options: &SearchOptions,
+  ) -> Result<Vec<SearchHit>> {
+    self.inner.search_hits_with(keyword, limit, options)
+  }
+
+  fn tag_counts(&self) -> Result<BTreeMap<String, u64>> {
+    self.inner.tag_counts()
+  }
+
+  fn search_by_tag(&self, tag: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_by_tag(tag, limit)
+  }
+
+  fn search_by_link(&self, link: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_by_link(link, limit)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::search::text_index::TextIndex;
+
+  #[test]
+  fn disk_space_guard_should_keep_changes_pending_when_disk_is_full() {
+    let mut index = DiskSpaceGuardBackend::new(
+      TextIndex::new().unwrap(),
+      Some(std::env::temp_dir()),
+      u64::MAX,
+    );
+    index
+      .add_doc(&File::new("a.txt".to_string(), "content".to_string()))
+      .unwrap();
+    assert!(index.commit().is_err());
+    assert_eq!(index.num_docs(), 0);
+    assert!(index.get_pending_operations() > 0);
+  }
+}
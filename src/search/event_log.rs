@@ -0,0 +1,132 @@
+use parking_lot::Mutex;
+use std::{
+  collections::VecDeque,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{file::FileOperation, operation_recording::OperationRecorder};
+
+pub const DEFAULT_EVENT_LOG_CAPACITY: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventStage {
+  /// An event as reported by the file system watcher
+  Raw,
+  /// A file operation dropped because it cannot affect the index
+  Filtered,
+  /// A file operation handed to the index update worker
+  Queued,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct LoggedEvent {
+  pub timestamp_ms: u128,
+  pub stage: EventStage,
+  pub description: String,
+}
+
+/// Keeps the most recent watcher events and the file operations derived from them, so it can be
+/// traced why a file was or was not re-indexed.
+pub struct EventLog {
+  capacity: usize,
+  events: Mutex<VecDeque<LoggedEvent>>,
+  recorder: Option<OperationRecorder>,
+}
+
+impl EventLog {
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      capacity,
+      events: Mutex::new(VecDeque::with_capacity(capacity)),
+      recorder: None,
+    }
+  }
+
+  /// Also appends every queued operation to `recorder`, for `replay`.
+  pub fn with_recorder(mut self, recorder: OperationRecorder) -> Self {
+    self.recorder = Some(recorder);
+    self
+  }
+
+  /// Logs an operation handed to the index update worker.
+  pub fn record_queued(&self, operation: &FileOperation, prioritized: bool) {
+    let description = if prioritized {
+      format!("{:?} (prioritized)", operation)
+    } else {
+      format!("{:?}", operation)
+    };
+    self.record(EventStage::Queued, description);
+    if let Some(recorder) = &self.recorder
+      && let Err(e) = recorder.record(operation, prioritized)
+    {
+      tracing::warn!("Failed to record {:?}: {}", operation, e);
+    }
+  }
+
+  pub fn record(&self, stage: EventStage, description: String) {
+    let timestamp_ms = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|duration| duration.as_millis())
+      .unwrap_or_default();
+    let mut events = self.events.lock();
+    if events.len() >= self.capacity {
+      events.pop_front();
+    }
+    events.push_back(LoggedEvent {
+      timestamp_ms,
+      stage,
+      description,
+    });
+  }
+
+  /// Returns up to `limit` of the latest events, oldest first, optionally only those mentioning
+  /// `path_contains`.
+  pub fn recent(&self, limit: usize, path_contains: Option<&str>) -> Vec<LoggedEvent> {
+    let events = self.events.lock();
+    let mut recent = events
+      .iter()
+      .rev()
+      .filter(|event| path_contains.is_none_or(|path| event.description.contains(path)))
+      .take(limit)
+      .cloned()
+      .collect::<Vec<_>>();
+    recent.reverse();
+    recent
+  }
+}
+
+impl Default for EventLog {
+  fn default() -> Self {
+    Self::new(DEFAULT_EVENT_LOG_CAPACITY)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn event_log_should_keep_latest_events_up_to_capacity() {
+    let event_log = EventLog::new(3);
+    for i in 0..5 {
+      event_log.record(EventStage::Raw, format!("/root/{}.txt", i));
+    }
+
+    let descriptions = |events: Vec<LoggedEvent>| {
+      events
+        .into_iter()
+        .map(|event| event.description)
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(
+      descriptions(event_log.recent(10, None)),
+      vec!["/root/2.txt", "/root/3.txt", "/root/4.txt"]
+    );
+    assert_eq!(descriptions(event_log.recent(1, None)), vec!["/root/4.txt"]);
+    assert_eq!(
+      descriptions(event_log.recent(10, Some("3.txt"))),
+      vec!["/root/3.txt"]
+    );
+  }
+}
@@ -0,0 +1,192 @@
+use anyhow::Result;
+use std::{
+  fmt,
+  io::Write,
+  path::{Component, Path, PathBuf},
+};
+
+use super::index_backend::SearchHit;
+
+/// Most results an export writes unless asked for another number.
+pub const EXPORT_LIMIT: usize = 100;
+
+/// File format search results are exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ExportFormat {
+  #[default]
+  Json,
+  Csv,
+  Markdown,
+}
+
+impl ExportFormat {
+  /// The format matching the extension of `path`, if any.
+  pub fn from_path(path: &Path) -> Option<Self> {
+    match path.extension()?.to_str()?.to_lowercase().as_str() {
+      "json" => Some(ExportFormat::Json),
+      "csv" => Some(ExportFormat::Csv),
+      "md" | "markdown" => Some(ExportFormat::Markdown),
+      _ => None,
+    }
+  }
+}
+
+impl fmt::Display for ExportFormat {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ExportFormat::Json => write!(f, "json"),
+      ExportFormat::Csv => write!(f, "csv"),
+      ExportFormat::Markdown => write!(f, "markdown"),
+    }
+  }
+}
+
+/// Writes the hits of `query` with their paths, scores and snippets.
+pub fn write_hits(
+  writer: &mut impl Write,
+  query: &str,
+  hits: &[SearchHit],
+  format: ExportFormat,
+) -> Result<()> {
+  match format {
+    ExportFormat::Json => {
+      let export = serde_json::json!({ "query": query, "results": hits });
+      serde_json::to_writer_pretty(&mut *writer, &export)?;
+      writeln!(writer)?;
+    }
+    ExportFormat::Csv => {
+      writeln!(writer, "file_path,score,line_number,section,snippet")?;
+      for hit in hits {
+        writeln!(
+          writer,
+          "{},{},{},{},{}",
+          csv_field(&hit.file_path),
+          hit.score,
+          hit
+            .line_number
+            .map(|line| line.to_string())
+            .unwrap_or_default(),
+          csv_field(hit.section.as_deref().unwrap_or_default()),
+          csv_field(hit.snippet.as_deref().unwrap_or_default())
+        )?;
+      }
+    }
+    ExportFormat::Markdown => {
+      writeln!(writer, "# Results for {}\n", markdown_cell(query))?;
+      writeln!(writer, "| File | Score | Line | Snippet |")?;
+      writeln!(writer, "| --- | --- | --- | --- |")?;
+      for hit in hits {
+        writeln!(
+          writer,
+          "| {} | {:.3} | {} | {} |",
+          markdown_cell(&hit.file_path),
+          hit.score,
+          hit
+            .line_number
+            .map(|line| line.to_string())
+            .unwrap_or_default(),
+          markdown_cell(hit.snippet.as_deref().unwrap_or_default())
+        )?;
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Writes the hits of `query` to a file, creating its directory if needed.
+pub fn export_hits(
+  path: &Path,
+  query: &str,
+  hits: &[SearchHit],
+  format: ExportFormat,
+) -> Result<()> {
+  if let Some(parent) = path
+    .parent()
+    .filter(|parent| !parent.as_os_str().is_empty())
+  {
+    std::fs::create_dir_all(parent)?;
+  }
+  let mut content = Vec::new();
+  write_hits(&mut content, query, hits, format)?;
+  std::fs::write(path, content)
+    .map_err(|e| anyhow::anyhow!("Cannot write export {:?}: {}", path, e))
+}
+
+/// Where a client-given relative path lands inside the export directory. Absolute paths and
+/// `..` are refused so that clients cannot write anywhere else.
+pub fn export_path(export_dir: &Path, path: &str) -> Result<PathBuf> {
+  let relative = Path::new(path);
+  if path.is_empty()
+    || !relative
+      .components()
+      .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+  {
+    anyhow::bail!(
+      "Export path {} must be relative to the export directory, without ..",
+      path
+    );
+  }
+  Ok(export_dir.join(relative))
+}
+
+fn csv_field(value: &str) -> String {
+  if value.contains([',', '"', '\n', '\r']) {
+    format!("\"{}\"", value.replace('"', "\"\""))
+  } else {
+    value.to_string()
+  }
+}
+
+/// Text fitting in one table cell: pipes escaped and line breaks folded into spaces.
+fn markdown_cell(value: &str) -> String {
+  value
+    .replace('|', "\\|")
+    .split_whitespace()
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::BTreeMap;
+
+  #[test]
+  fn write_hits_should_quote_fields_of_each_format() {
+    let hits = vec![SearchHit {
+      file_path: "/notes/a.md".to_string(),
+      score: 1.5,
+      line_number: Some(3),
+      snippet: Some("plant \"tomatoes\",\nthen | water".to_string()),
+      section: None,
+      metadata: BTreeMap::new(),
+    }];
+    let export = |format| {
+      let mut content = Vec::new();
+      write_hits(&mut content, "garden", &hits, format).unwrap();
+      String::from_utf8(content).unwrap()
+    };
+
+    assert_eq!(
+      export(ExportFormat::Csv),
+      "file_path,score,line_number,section,snippet\n/notes/a.md,1.5,3,,\"plant \"\"tomatoes\"\",\nthen | water\"\n"
+    );
+    assert!(
+      export(ExportFormat::Markdown)
+        .contains("| /notes/a.md | 1.500 | 3 | plant \"tomatoes\", then \\| water |")
+    );
+    let json = serde_json::from_str::<serde_json::Value>(&export(ExportFormat::Json)).unwrap();
+    assert_eq!(json["results"][0]["line_number"], 3);
+
+    assert_eq!(
+      ExportFormat::from_path(Path::new("out.MD")),
+      Some(ExportFormat::Markdown)
+    );
+    assert!(export_path(Path::new("/exports"), "../secret.json").is_err());
+    assert!(export_path(Path::new("/exports"), "/etc/passwd").is_err());
+    assert_eq!(
+      export_path(Path::new("/exports"), "session/results.csv").unwrap(),
+      Path::new("/exports/session/results.csv")
+    );
+  }
+}
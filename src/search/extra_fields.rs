@@ -0,0 +1,257 @@
+use anyhow::Result;
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  str::FromStr,
+};
+use tantivy::tokenizer::{RegexTokenizer, TokenStream, Tokenizer};
+
+use super::{
+  file::File,
+  index_backend::{IndexBackend, IndexMemory, SearchHit, SearchOptions},
+};
+use crate::file::extractor::markdown::front_matter_values;
+
+/// Values kept per field and document, so that a pattern matching everywhere stays cheap.
+const MAX_FIELD_VALUES: usize = 32;
+
+#[derive(Clone)]
+enum FieldSource {
+  /// Every distinct match of a regular expression in the content
+  Pattern(RegexTokenizer),
+  /// A key of the YAML front matter of Markdown notes
+  FrontMatter(String),
+}
+
+/// A metadata field defined on the command line, as `NAME=regex:PATTERN` or
+/// `NAME=front-matter:KEY`, e.g. `ticket_id=regex:JIRA-\d+`, searchable as
+/// `metadata.NAME:value` like the fields of the built-in extractors.
+#[derive(Clone)]
+pub struct ExtraField {
+  pub name: String,
+  source: FieldSource,
+}
+
+impl ExtraField {
+  /// Values of the field in a document, one per line, or `None` when it has none.
+  fn extract(&self, content: &str) -> Option<String> {
+    let mut values = Vec::new();
+    match &self.source {
+      FieldSource::Pattern(tokenizer) => {
+        let mut tokenizer = tokenizer.clone();
+        let mut matches = tokenizer.token_stream(content);
+        while values.len() < MAX_FIELD_VALUES && matches.advance() {
+          let value = matches.token().text.clone();
+          if !values.contains(&value) {
+            values.push(value);
+          }
+        }
+      }
+      FieldSource::FrontMatter(key) => {
+        values = front_matter_values(content, key);
+        values.truncate(MAX_FIELD_VALUES);
+      }
+    }
+    (!values.is_empty()).then(|| values.join("\n"))
+  }
+}
+
+impl FromStr for ExtraField {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    let invalid = || {
+      format!(
+        "expected NAME=regex:PATTERN or NAME=front-matter:KEY, got {}",
+        value
+      )
+    };
+    let (name, source) = value.split_once('=').ok_or_else(invalid)?;
+    if name.is_empty()
+      || !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+      return Err(format!("invalid field name {:?}", name));
+    }
+    let source = if let Some(pattern) = source.strip_prefix("regex:") {
+      FieldSource::Pattern(
+        RegexTokenizer::new(pattern)
+          .map_err(|e| format!("invalid pattern of field {}: {}", name, e))?,
+      )
+    } else if let Some(key) = source.strip_prefix("front-matter:") {
+      if key.is_empty() {
+        return Err(invalid());
+      }
+      FieldSource::FrontMatter(key.to_string())
+    } else {
+      return Err(invalid());
+    };
+    Ok(ExtraField {
+      name: name.to_string(),
+      source,
+    })
+  }
+}
+
+/// Stamps every document written to the wrapped backend with the extra fields found in it. Fields
+/// never replace metadata an extractor already set under the same name.
+pub struct ExtraFieldBackend<B: IndexBackend> {
+  inner: B,
+  fields: Vec<ExtraField>,
+}
+
+impl<B: IndexBackend> ExtraFieldBackend<B> {
+  pub fn new(inner: B, fields: &[ExtraField]) -> Self {
+    ExtraFieldBackend {
+      inner,
+      fields: fields.to_vec(),
+    }
+  }
+}
+
+fn with_extra_fields(fields: &[ExtraField], mut file: File) -> File {
+  for field in fields {
+    if file.metadata.contains_key(&field.name) {
+      continue;
+    }
+    if let Some(values) = field.extract(&file.content) {
+      file = file.with_metadata(&field.name, &values);
+    }
+  }
+  file
+}
+
+impl<B: IndexBackend> IndexBackend for ExtraFieldBackend<B> {
+  fn add_doc(&mut self, file: &File) -> Result<()> {
+    self
+      .inner
+      .add_doc(&with_extra_fields(&self.fields, file.clone()))
+  }
+
+  fn add_docs(&mut self, files: Box<dyn Iterator<Item = File> + '_>) -> Result<usize> {
+    let fields = &self.fields;
+    self.inner.add_docs(Box::new(
+      files.map(move |file| with_extra_fields(fields, file)),
+    ))
+  }
+
+  fn replace_doc(&mut self, file: &File) -> Result<()> {
+    self
+      .inner
+      .replace_doc(&with_extra_fields(&self.fields, file.clone()))
+  }
+
+  fn delete_doc(&mut self, file_path: &str) -> Result<()> {
+    self.inner.delete_doc(file_path)
+  }
+
+  fn delete_docs_by_path_prefix(&mut self, path_prefix: &str) -> Result<usize> {
+    self.inner.delete_docs_by_path_prefix(path_prefix)
+  }
+
+  fn has_doc(&self, file_path: &str) -> Result<bool> {
+    self.inner.has_doc(file_path)
+  }
+
+  fn has_docs_with_path_prefix(&self, path_prefix: &str) -> Result<bool> {
+    self.inner.has_docs_with_path_prefix(path_prefix)
+  }
+
+  fn indexed_paths(&self) -> Result<BTreeSet<String>> {
+    self.inner.indexed_paths()
+  }
+
+  fn indexed_at(&self, file_path: &str) -> Result<Option<u64>> {
+    self.inner.indexed_at(file_path)
+  }
+
+  fn commit(&mut self) -> Result<()> {
+    self.inner.commit()
+  }
+
+  fn optimize(&mut self) -> Result<()> {
+    self.inner.optimize()
+  }
+
+  fn generation(&self) -> u64 {
+    self.inner.generation()
+  }
+
+  fn get_pending_operations(&self) -> usize {
+    self.inner.get_pending_operations()
+  }
+
+  fn num_docs(&self) -> u64 {
+    self.inner.num_docs()
+  }
+
+  fn memory_usage(&self) -> IndexMemory {
+    self.inner.memory_usage()
+  }
+
+  fn clear(&mut self) -> Result<()> {
+    self.inner.clear()
+  }
+
+  fn search_hits(&self, keyword: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_hits(keyword, limit)
+  }
+
+  fn search_hits_with(
+    &self,
+    keyword: &str,
+    limit: usize,
+    options: &SearchOptions,
+  ) -> Result<Vec<SearchHit>> {
+    self.inner.search_hits_with(keyword, limit, options)
+  }
+
+  fn tag_counts(&self) -> Result<BTreeMap<String, u64>> {
+    self.inner.tag_counts()
+  }
+
+  fn search_by_tag(&self, tag: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_by_tag(tag, limit)
+  }
+
+  fn search_by_link(&self, link: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_by_link(link, limit)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::search::{index_backend::SEARCH_FILE_LIMIT, text_index::TextIndex};
+
+  #[test]
+  fn extra_field_backend_should_make_fields_queryable() {
+    let fields = [
+      r"ticket_id=regex:JIRA-\d+".parse::<ExtraField>().unwrap(),
+      "owner=front-matter:owner".parse::<ExtraField>().unwrap(),
+    ];
+    let mut index = ExtraFieldBackend::new(TextIndex::new().unwrap(), &fields);
+    index
+      .add_doc(&File::new(
+        "/notes/outage.md".to_string(),
+        "---\nowner: platform\n---\nFixed in JIRA-42, see also JIRA-7 and JIRA-42.".to_string(),
+      ))
+      .unwrap();
+    index
+      .add_doc(&File::new(
+        "/notes/plan.md".to_string(),
+        "Roadmap without tickets".to_string(),
+      ))
+      .unwrap();
+    index.commit().unwrap();
+
+    let hits = index
+      .search_hits("metadata.ticket_id:JIRA-7", SEARCH_FILE_LIMIT)
+      .unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].metadata["ticket_id"], "JIRA-42\nJIRA-7");
+    assert_eq!(hits[0].metadata["owner"], "platform");
+    assert!("ticket_id=JIRA".parse::<ExtraField>().is_err());
+    assert!("ticket id=regex:x".parse::<ExtraField>().is_err());
+  }
+}
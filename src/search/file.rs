@@ -1,34 +1,233 @@
 use anyhow::Result;
+use std::{collections::BTreeMap, path::Path};
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FileSection {
+  pub offset: usize,
+  pub title: String,
+}
 
 #[derive(Debug, Clone)]
 pub struct File {
   pub path: String,
   pub content: String,
+  pub sections: Vec<FileSection>,
+  pub metadata: BTreeMap<String, String>,
 }
 
 impl File {
   pub fn new(path: String, content: String) -> Self {
-    Self { path, content }
+    Self {
+      path,
+      content,
+      sections: Vec::new(),
+      metadata: BTreeMap::new(),
+    }
+  }
+
+  pub fn with_sections(mut self, sections: Vec<FileSection>) -> Self {
+    self.sections = sections;
+    self
+  }
+
+  pub fn with_metadata(mut self, key: &str, value: &str) -> Self {
+    self.metadata.insert(key.to_string(), value.to_string());
+    self
+  }
+
+  /// Copies the metadata of the file a document was split from.
+  pub fn with_metadata_from(mut self, file: &File) -> Self {
+    self.metadata.extend(file.metadata.clone());
+    self
   }
 }
 
+/// Separates a container path (archive, mailbox) from a member inside it, e.g. `notes.zip!/a.txt`.
+pub const CONTAINER_MEMBER_SEPARATOR: &str = "!/";
+
+pub fn container_member_prefix(path: &str) -> String {
+  format!("{}{}", path, CONTAINER_MEMBER_SEPARATOR)
+}
+
+/// Separates a large file path from the byte offset of one of its chunks, e.g. `app.log#chunk=0`.
+pub const CHUNK_SEPARATOR: &str = "#chunk=";
+
+/// Metadata key holding the line number a chunk starts at within the whole file.
+pub const CHUNK_START_LINE_KEY: &str = "chunk_start_line";
+
+/// Separates a Markdown file path from the anchor of one of its heading sections, e.g.
+/// `guide.md#install`.
+pub const SECTION_SEPARATOR: &str = "#";
+
+/// Whether the file is split into heading sections when section indexing is enabled.
+pub fn is_section_file(path: &str) -> bool {
+  Path::new(path).extension().is_some_and(|extension| {
+    extension.eq_ignore_ascii_case("md") || extension.eq_ignore_ascii_case("markdown")
+  })
+}
+
+pub fn section_prefix(path: &str) -> String {
+  format!("{}{}", path, SECTION_SEPARATOR)
+}
+
+pub fn section_path(path: &str, anchor: &str) -> String {
+  format!("{}{}", section_prefix(path), anchor)
+}
+
+/// Splits `guide.md#install` into `("guide.md", "install")`. Chunk paths are not sections.
+pub fn parse_section_path(path: &str) -> Option<(&str, &str)> {
+  if parse_chunk_path(path).is_some() {
+    return None;
+  }
+  let (file_path, anchor) = path.rsplit_once(SECTION_SEPARATOR)?;
+  (!anchor.is_empty() && !anchor.contains(['/', '\\']) && is_section_file(file_path))
+    .then_some((file_path, anchor))
+}
+
+/// Metadata key holding the tags of a document, separated by spaces. Nested tags use `/`, e.g.
+/// `project/alpha`.
+pub const TAGS_METADATA_KEY: &str = "tags";
+
+/// Whether `tag` is `wanted` or nested below it, so `project/alpha` has the tag `project`.
+pub fn tag_matches(tag: &str, wanted: &str) -> bool {
+  tag
+    .strip_prefix(wanted)
+    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Metadata key holding the link targets of a document, one per line: absolute paths for relative
+/// links and `wikilink_key`s for `[[wikilinks]]`.
+pub const LINKS_METADATA_KEY: &str = "links";
+
+/// Target of a `[[wikilink]]`: the note name without folders or `.md` extension, lowercased, so
+/// that `[[Projects/Alpha.md]]` and `[[alpha]]` both point at `alpha.md`.
+pub fn wikilink_key(name: &str) -> String {
+  let name = name
+    .trim()
+    .rsplit(['/', '\\'])
+    .next()
+    .unwrap_or_default()
+    .to_lowercase();
+  format!("[[{}]]", name.strip_suffix(".md").unwrap_or(&name))
+}
+
+/// Link targets pointing at a file: its path and its wikilink name.
+pub fn link_keys(path: &str) -> Vec<String> {
+  vec![path.to_string(), wikilink_key(path)]
+}
+
+/// File a chunk or section document was split from, or the document path itself.
+pub fn document_file_path(path: &str) -> &str {
+  parse_chunk_path(path)
+    .map(|(path, _)| path)
+    .or_else(|| parse_section_path(path).map(|(path, _)| path))
+    .unwrap_or(path)
+}
+
+/// Lowercase extension of the file a document was split from, e.g. `md` for `Guide.MD#install`.
+pub fn document_extension(path: &str) -> Option<String> {
+  Path::new(document_file_path(path))
+    .extension()
+    .map(|extension| extension.to_string_lossy().to_lowercase())
+}
+
+pub fn chunk_prefix(path: &str) -> String {
+  format!("{}{}", path, CHUNK_SEPARATOR)
+}
+
+pub fn chunk_path(path: &str, offset: usize) -> String {
+  format!("{}{}", chunk_prefix(path), offset)
+}
+
+/// Splits `app.log#chunk=1024` into `("app.log", 1024)`.
+pub fn parse_chunk_path(path: &str) -> Option<(&str, usize)> {
+  let (file_path, offset) = path.rsplit_once(CHUNK_SEPARATOR)?;
+  offset.parse().ok().map(|offset| (file_path, offset))
+}
+
 pub trait FileFilter {
   fn is_target(&self, path: &str) -> bool;
 }
 
+#[async_trait::async_trait]
 pub trait FileLoader {
-  fn load_directory(&self, path: &str) -> Box<dyn Iterator<Item = Result<File>> + '_>;
-  fn load_file(&self, path: &str) -> Result<File>;
+  fn load_directory<'a>(
+    &'a self,
+    path: &str,
+    file_filter: &'a dyn FileFilter,
+  ) -> Box<dyn Iterator<Item = Result<File>> + 'a>;
+
+  /// Loads a file on the calling thread, as the indexing pipeline does on its own threads.
+  fn load_file_blocking(&self, path: &str) -> Result<File>;
+
+  /// Loads a file from async code; loaders whose reads block should override this so that they
+  /// do not stall the async runtime.
+  async fn load_file(&self, path: &str) -> Result<File> {
+    self.load_file_blocking(path)
+  }
+
+  /// Whether the file is expanded into several documents whose paths start with
+  /// `container_member_prefix(path)`.
+  fn is_container(&self, _path: &str) -> bool {
+    false
+  }
+
+  fn load_documents(&self, path: &str, _file_filter: &dyn FileFilter) -> Result<Vec<File>> {
+    self.load_file_blocking(path).map(|file| vec![file])
+  }
 }
 
-#[derive(Debug, Clone)]
+pub type FileChunks = Box<dyn Iterator<Item = Result<File>>>;
+
+pub trait ContentExtractor {
+  fn extract(&self, path: &Path) -> Result<File>;
+
+  /// Streams the file as documents of about `chunk_size` bytes without reading it into memory
+  /// at once, or returns `None` when the extractor needs the whole file.
+  fn extract_chunks(&self, _path: &Path, _chunk_size: usize) -> Result<Option<FileChunks>> {
+    Ok(None)
+  }
+}
+
+/// Serialized externally tagged, e.g. `{"file_renamed": {"old_path": "a", "new_path": "b"}}`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum FileOperation {
   FileCreated(String),
   FileModified(String),
-  FileRenamed { old_path: String, new_path: String },
-  DirectoryRenamed { old_path: String, new_path: String },
+  FileRenamed {
+    old_path: String,
+    new_path: String,
+  },
+  DirectoryRenamed {
+    old_path: String,
+    new_path: String,
+  },
+  /// A rename whose destination no longer exists, so whether it was a file or a directory is
+  /// decided from what the index holds for the old path.
+  PathRenamed {
+    old_path: String,
+    new_path: String,
+  },
   FileDeleted(String),
   DirectoryDeleted(String),
+  /// Re-indexes everything below the directory, e.g. after its watch had to be re-established.
+  DirectoryRescan(String),
+}
+
+impl FileOperation {
+  pub fn paths(&self) -> Vec<&str> {
+    match self {
+      FileOperation::FileCreated(path)
+      | FileOperation::FileModified(path)
+      | FileOperation::FileDeleted(path)
+      | FileOperation::DirectoryDeleted(path)
+      | FileOperation::DirectoryRescan(path) => vec![path],
+      FileOperation::FileRenamed { old_path, new_path }
+      | FileOperation::DirectoryRenamed { old_path, new_path }
+      | FileOperation::PathRenamed { old_path, new_path } => vec![old_path, new_path],
+    }
+  }
 }
 
 pub type FileOperationHandler = dyn Fn(&FileOperation) -> Result<()> + Send + Sync;
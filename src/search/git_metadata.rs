@@ -0,0 +1,594 @@
+use anyhow::Result;
+use std::{
+  collections::{BTreeMap, BTreeSet, HashMap},
+  io::{Read, Write},
+  path::{Path, PathBuf},
+  process::{Command, Stdio},
+};
+
+use super::{
+  file::{CONTAINER_MEMBER_SEPARATOR, File, document_file_path},
+  index_backend::{IndexBackend, IndexMemory, SearchHit, SearchOptions},
+};
+
+/// Metadata keys of the last commit that changed the file of a document.
+pub const GIT_AUTHOR_METADATA_KEY: &str = "git_author";
+pub const GIT_DATE_METADATA_KEY: &str = "git_date";
+pub const GIT_COMMIT_METADATA_KEY: &str = "git_commit";
+/// Metadata keys of the checkout holding a document: its branch, unless detached, and the name
+/// of its work tree directory, which tells apart several worktrees of one repository.
+pub const GIT_BRANCH_METADATA_KEY: &str = "git_branch";
+pub const GIT_WORKTREE_METADATA_KEY: &str = "git_worktree";
+
+/// Prefix of query tokens restricting a search to files last committed by some authors.
+pub const AUTHOR_FILTER_PREFIX: &str = "author:";
+
+/// Prefix of query tokens restricting a search to watch roots checked out on some branches or in
+/// some worktrees.
+pub const BRANCH_FILTER_PREFIX: &str = "branch:";
+
+/// Hits fetched per hit wanted when filtering by author, as the filter is applied afterwards.
+const AUTHOR_FILTER_OVERFETCH: usize = 10;
+
+/* Separators git never prints in the fields they delimit */
+const RECORD_SEPARATOR: char = '\x1e';
+const FIELD_SEPARATOR: char = '\x1f';
+const LOG_FORMAT: &str = "--format=%x1e%H%x1f%an <%ae>%x1f%aI";
+
+/// The last commit that changed a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastCommit {
+  pub commit: String,
+  /// `Name <email>`
+  pub author: String,
+  /// ISO 8601 author date
+  pub date: String,
+}
+
+/// The branch and work tree a watch root is checked out in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkout {
+  /// `None` when HEAD is detached
+  pub branch: Option<String>,
+  /// Last component of the work tree directory
+  pub worktree: String,
+}
+
+impl Checkout {
+  /// Whether a `branch:` filter value names this checkout's branch or worktree.
+  pub fn matches(&self, name: &str) -> bool {
+    self.branch.as_deref() == Some(name) || self.worktree == name
+  }
+}
+
+/// A watch root inside a git work tree, read with the `git` command.
+pub struct GitRepository {
+  root: PathBuf,
+}
+
+impl GitRepository {
+  /// The repository holding `root`, or `None` when it is not in one or git is not installed.
+  pub fn discover(root: &Path) -> Option<Self> {
+    let repository = GitRepository {
+      root: root.to_path_buf(),
+    };
+    match repository.git(&["rev-parse", "--is-inside-work-tree"]) {
+      Ok(output) if output.trim() == "true" => Some(repository),
+      Ok(_) => None,
+      Err(e) => {
+        tracing::debug!("No git metadata for {:?}: {}", root, e);
+        None
+      }
+    }
+  }
+
+  pub fn root(&self) -> &Path {
+    &self.root
+  }
+
+  fn command(&self, args: &[&str]) -> Command {
+    let mut command = Command::new("git");
+    command
+      .arg("-C")
+      .arg(&self.root)
+      .args(["-c", "core.quotepath=off"])
+      .args(args);
+    command
+  }
+
+  /// Output of git run in the root, failing when it exits unsuccessfully.
+  fn git(&self, args: &[&str]) -> Result<String> {
+    let output = self.command(args).output()?;
+    if !output.status.success() {
+      anyhow::bail!(
+        "git {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr).trim()
+      );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+  }
+
+  /// Path of a document's file relative to the root, or `None` when it is outside the root.
+  fn relative_path(&self, document_path: &str) -> Option<String> {
+    let file_path = document_file_path(document_path);
+    let file_path = file_path
+      .split_once(CONTAINER_MEMBER_SEPARATOR)
+      .map_or(file_path, |(container, _)| container);
+    Some(
+      Path::new(file_path)
+        .strip_prefix(&self.root)
+        .ok()?
+        .to_string_lossy()
+        .into_owned(),
+    )
+  }
+
+  /// The last commit of every file under the root, from one walk of the history.
+  pub fn last_commits(&self) -> Result<HashMap<String, LastCommit>> {
+    let log = self.git(&[
+      "log",
+      "--no-renames",
+      "--relative",
+      "--name-only",
+      LOG_FORMAT,
+    ])?;
+    let mut last_commits = HashMap::new();
+    /* The log lists the newest commits first, so the first commit naming a file is its last */
+    for record in log.split(RECORD_SEPARATOR) {
+      let mut lines = record.lines();
+      let Some(last_commit) = lines.next().and_then(parse_commit) else {
+        continue;
+      };
+      for path in lines.filter(|line| !line.is_empty()) {
+        last_commits
+          .entry(path.to_string())
+          .or_insert_with(|| last_commit.clone());
+      }
+    }
+    Ok(last_commits)
+  }
+
+  /// The last commit of one file, `None` when it was never committed.
+  pub fn last_commit(&self, relative_path: &str) -> Result<Option<LastCommit>> {
+    let log = self.git(&["log", "-1", LOG_FORMAT, "--", relative_path])?;
+    Ok(
+      log
+        .trim_start_matches(RECORD_SEPARATOR)
+        .lines()
+        .next()
+        .and_then(parse_commit),
+    )
+  }
+
+  /// The branch and work tree currently checked out.
+  pub fn checkout(&self) -> Result<Checkout> {
+    let top_level = self.git(&["rev-parse", "--show-toplevel"])?;
+    let worktree = Path::new(top_level.trim()).file_name().map_or_else(
+      || top_level.trim().to_string(),
+      |name| name.to_string_lossy().into_owned(),
+    );
+    /* Unlike rev-parse, symbolic-ref also names the branch of a repository without commits */
+    let branch = self
+      .git(&["symbolic-ref", "--short", "--quiet", "HEAD"])
+      .ok()
+      .map(|branch| branch.trim().to_string());
+    Ok(Checkout { branch, worktree })
+  }
+
+  /// Id of the commit a revision such as `v1.0`, `main~3` or a commit id names.
+  pub fn resolve(&self, revision: &str) -> Result<String> {
+    /* A revision starting with a dash would be taken as an option */
+    if revision.is_empty() || revision.starts_with('-') {
+      anyhow::bail!("Invalid revision {:?}", revision);
+    }
+    let commit = self
+      .git(&[
+        "rev-parse",
+        "--verify",
+        "--quiet",
+        &format!("{}^{{commit}}", revision),
+      ])
+      .map_err(|_| anyhow::anyhow!("Unknown revision {}", revision))?;
+    Ok(commit.trim().to_string())
+  }
+
+  /// Files under the root as of a commit, with their paths relative to the root, leaving out
+  /// those larger than `max_size` bytes. At most `limit` files are read.
+  pub fn files_at(
+    &self,
+    commit: &str,
+    max_size: u64,
+    limit: usize,
+  ) -> Result<Vec<(String, Vec<u8>)>> {
+    let tree = self.git(&["ls-tree", "-r", "-z", "-l", commit])?;
+    /* Entries are `<mode> <type> <object> <size>\t<path>`, sizes padded with spaces */
+    let blobs = tree
+      .split('\0')
+      .filter_map(|entry| {
+        let (fields, path) = entry.split_once('\t')?;
+        let [_, kind, object, size] = fields.split_whitespace().collect::<Vec<_>>()[..] else {
+          return None;
+        };
+        let size = size.parse::<u64>().ok()?;
+        (kind == "blob" && size <= max_size).then(|| (object.to_string(), path.to_string()))
+      })
+      .take(limit)
+      .collect::<Vec<_>>();
+
+    /* One cat-file process reads every blob, fed from another thread so that neither pipe fills */
+    let mut cat_file = self
+      .command(&["cat-file", "--batch"])
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::null())
+      .spawn()?;
+    let mut stdin = cat_file.stdin.take().expect("stdin is piped");
+    let mut stdout = cat_file.stdout.take().expect("stdout is piped");
+    let mut output = Vec::new();
+    std::thread::scope(|scope| -> Result<()> {
+      let objects = &blobs;
+      let writer = scope.spawn(move || -> std::io::Result<()> {
+        for (object, _) in objects {
+          writeln!(stdin, "{}", object)?;
+        }
+        Ok(())
+      });
+      stdout.read_to_end(&mut output)?;
+      writer
+        .join()
+        .map_err(|_| anyhow::anyhow!("git cat-file writer panicked"))??;
+      Ok(())
+    })?;
+    cat_file.wait()?;
+
+    let mut files = Vec::with_capacity(blobs.len());
+    let mut rest = output.as_slice();
+    for (_, path) in blobs {
+      /* Each blob is `<object> blob <size>\n<content>\n` */
+      let header_end = rest
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .ok_or_else(|| anyhow::anyhow!("Truncated output of git cat-file"))?;
+      let header = String::from_utf8_lossy(&rest[..header_end]);
+      let size = header
+        .rsplit(' ')
+        .next()
+        .and_then(|size| size.parse::<usize>().ok())
+        .ok_or_else(|| anyhow::anyhow!("Unexpected output of git cat-file: {}", header))?;
+      let content = rest
+        .get(header_end + 1..header_end + 1 + size)
+        .ok_or_else(|| anyhow::anyhow!("Truncated output of git cat-file"))?;
+      files.push((path, content.to_vec()));
+      rest = &rest[(header_end + 2 + size).min(rest.len())..];
+    }
+    Ok(files)
+  }
+}
+
+fn parse_commit(header: &str) -> Option<LastCommit> {
+  let mut fields = header.split(FIELD_SEPARATOR);
+  Some(LastCommit {
+    commit: fields.next()?.to_string(),
+    author: fields.next()?.to_string(),
+    date: fields.next()?.to_string(),
+  })
+}
+
+fn with_checkout(file: File, checkout: Option<&Checkout>) -> File {
+  let Some(checkout) = checkout else {
+    return file;
+  };
+  let file = file.with_metadata(GIT_WORKTREE_METADATA_KEY, &checkout.worktree);
+  match &checkout.branch {
+    Some(branch) => file.with_metadata(GIT_BRANCH_METADATA_KEY, branch),
+    None => file,
+  }
+}
+
+fn with_last_commit(file: File, last_commit: Option<&LastCommit>) -> File {
+  match last_commit {
+    Some(last_commit) => file
+      .with_metadata(GIT_AUTHOR_METADATA_KEY, &last_commit.author)
+      .with_metadata(GIT_DATE_METADATA_KEY, &last_commit.date)
+      .with_metadata(GIT_COMMIT_METADATA_KEY, &last_commit.commit),
+    None => file,
+  }
+}
+
+/// Splits `author:alice` filters off a query, returning the rest of the query and the wanted
+/// authors, lowercased. Tokens inside quoted phrases are left alone.
+pub fn split_author_filters(query: &str) -> (String, Vec<String>) {
+  let (rest, authors) = split_prefixed_filters(query, AUTHOR_FILTER_PREFIX);
+  (
+    rest,
+    authors.iter().map(|author| author.to_lowercase()).collect(),
+  )
+}
+
+/// Splits `branch:main` filters off a query, returning the rest of the query and the wanted
+/// branches or worktrees, as given. Tokens inside quoted phrases are left alone.
+pub fn split_branch_filters(query: &str) -> (String, Vec<String>) {
+  split_prefixed_filters(query, BRANCH_FILTER_PREFIX)
+}
+
+fn split_prefixed_filters(query: &str, prefix: &str) -> (String, Vec<String>) {
+  let mut rest = Vec::new();
+  let mut values = Vec::new();
+  let mut in_phrase = false;
+  for token in query.split_whitespace() {
+    match token.strip_prefix(prefix) {
+      Some(value) if !in_phrase && !value.is_empty() => values.push(value.to_string()),
+      _ => rest.push(token),
+    }
+    if token.matches('"').count() % 2 == 1 {
+      in_phrase = !in_phrase;
+    }
+  }
+  (rest.join(" "), values)
+}
+
+/// Stamps every document written to the wrapped backend with the last commit of its file and the
+/// branch and worktree of the checkout when the watch root is in a git repository, and filters
+/// searches with `author:name`, matching any part of the author's name or email, and with
+/// `branch:name`. The commit and branch are those at the time the file was indexed, while a
+/// branch filter checks the branch checked out when searching.
+pub struct GitMetadataBackend<B: IndexBackend> {
+  inner: B,
+  repository: Option<GitRepository>,
+  /// Commits looked up since the last commit of the index, as modified files are indexed in
+  /// several documents
+  last_commits: HashMap<String, Option<LastCommit>>,
+  /// Checkout looked up since the last commit of the index
+  checkout: Option<Checkout>,
+}
+
+impl<B: IndexBackend> GitMetadataBackend<B> {
+  pub fn new(inner: B, repository: Option<GitRepository>) -> Self {
+    GitMetadataBackend {
+      inner,
+      repository,
+      last_commits: HashMap::new(),
+      checkout: None,
+    }
+  }
+
+  fn current_checkout(&mut self) -> Option<Checkout> {
+    let repository = self.repository.as_ref()?;
+    if self.checkout.is_none() {
+      self.checkout = repository
+        .checkout()
+        .inspect_err(|e| {
+          tracing::warn!(
+            "Failed to read the checkout of {:?}: {}",
+            repository.root,
+            e
+          )
+        })
+        .ok();
+    }
+    self.checkout.clone()
+  }
+
+  fn with_git_metadata(&mut self, file: &File) -> File {
+    let checkout = self.current_checkout();
+    let Some(repository) = &self.repository else {
+      return file.clone();
+    };
+    let Some(relative_path) = repository.relative_path(&file.path) else {
+      return file.clone();
+    };
+    let last_commit = self
+      .last_commits
+      .entry(relative_path)
+      .or_insert_with_key(|relative_path| {
+        repository
+          .last_commit(relative_path)
+          .inspect_err(|e| tracing::warn!("Failed to read the last commit of {}: {}", file.path, e))
+          .ok()
+          .flatten()
+      });
+    with_checkout(
+      with_last_commit(file.clone(), last_commit.as_ref()),
+      checkout.as_ref(),
+    )
+  }
+}
+
+impl<B: IndexBackend> IndexBackend for GitMetadataBackend<B> {
+  fn add_doc(&mut self, file: &File) -> Result<()> {
+    let file = self.with_git_metadata(file);
+    self.inner.add_doc(&file)
+  }
+
+  /// Looks up the last commits of all files at once, as many files are added together.
+  fn add_docs(&mut self, files: Box<dyn Iterator<Item = File> + '_>) -> Result<usize> {
+    let checkout = self.current_checkout();
+    let Some(repository) = &self.repository else {
+      return self.inner.add_docs(files);
+    };
+    let last_commits = repository.last_commits().unwrap_or_else(|e| {
+      tracing::warn!(
+        "Failed to read the git history of {:?}: {}",
+        repository.root,
+        e
+      );
+      HashMap::new()
+    });
+    let files = files.map(|file| {
+      let last_commit = repository
+        .relative_path(&file.path)
+        .and_then(|relative_path| last_commits.get(&relative_path));
+      with_checkout(with_last_commit(file, last_commit), checkout.as_ref())
+    });
+    self.inner.add_docs(Box::new(files))
+  }
+
+  fn replace_doc(&mut self, file: &File) -> Result<()> {
+    let file = self.with_git_metadata(file);
+    self.inner.replace_doc(&file)
+  }
+
+  fn delete_doc(&mut self, file_path: &str) -> Result<()> {
+    self.inner.delete_doc(file_path)
+  }
+
+  fn delete_docs_by_path_prefix(&mut self, path_prefix: &str) -> Result<usize> {
+    self.inner.delete_docs_by_path_prefix(path_prefix)
+  }
+
+  fn has_doc(&self, file_path: &str) -> Result<bool> {
+    self.inner.has_doc(file_path)
+  }
+
+  fn has_docs_with_path_prefix(&self, path_prefix: &str) -> Result<bool> {
+    self.inner.has_docs_with_path_prefix(path_prefix)
+  }
+
+  fn indexed_paths(&self) -> Result<BTreeSet<String>> {
+    self.inner.indexed_paths()
+  }
+
+  fn indexed_at(&self, file_path: &str) -> Result<Option<u64>> {
+    self.inner.indexed_at(file_path)
+  }
+
+  fn commit(&mut self) -> Result<()> {
+    self.last_commits.clear();
+    self.checkout = None;
+    self.inner.commit()
+  }
+
+  fn optimize(&mut self) -> Result<()> {
+    self.inner.optimize()
+  }
+
+  fn generation(&self) -> u64 {
+    self.inner.generation()
+  }
+
+  fn get_pending_operations(&self) -> usize {
+    self.inner.get_pending_operations()
+  }
+
+  fn num_docs(&self) -> u64 {
+    self.inner.num_docs()
+  }
+
+  fn memory_usage(&self) -> IndexMemory {
+    self.inner.memory_usage()
+  }
+
+  fn clear(&mut self) -> Result<()> {
+    self.inner.clear()
+  }
+
+  fn search_hits(&self, keyword: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.search_hits_with(keyword, limit, &SearchOptions::default())
+  }
+
+  fn search_hits_with(
+    &self,
+    keyword: &str,
+    limit: usize,
+    options: &SearchOptions,
+  ) -> Result<Vec<SearchHit>> {
+    let (keyword, branches) = split_branch_filters(keyword);
+    let Some(repository) = &self.repository else {
+      /* Roots outside git work trees are on no branch */
+      if !branches.is_empty() {
+        return Ok(Vec::new());
+      }
+      return self.inner.search_hits_with(&keyword, limit, options);
+    };
+    /* A root is checked out on one branch, so the filter keeps or drops the whole shard */
+    if !branches.is_empty() {
+      let checkout = repository.checkout()?;
+      if !branches.iter().any(|branch| checkout.matches(branch)) {
+        return Ok(Vec::new());
+      }
+    }
+    let (keyword, authors) = split_author_filters(&keyword);
+    if authors.is_empty() {
+      return self.inner.search_hits_with(&keyword, limit, options);
+    }
+    /* Without other terms, candidates are the documents mentioning an author in their metadata */
+    let keyword = if keyword.trim().is_empty() {
+      authors
+        .iter()
+        .flat_map(|author| author.split(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("metadata.{}:{}", GIT_AUTHOR_METADATA_KEY, word))
+        .collect::<Vec<_>>()
+        .join(" ")
+    } else {
+      keyword
+    };
+    let mut hits = self.inner.search_hits_with(
+      &keyword,
+      limit.saturating_mul(AUTHOR_FILTER_OVERFETCH),
+      options,
+    )?;
+    hits.retain(|hit| {
+      hit
+        .metadata
+        .get(GIT_AUTHOR_METADATA_KEY)
+        .is_some_and(|author| {
+          let author = author.to_lowercase();
+          authors.iter().any(|wanted| author.contains(wanted))
+        })
+    });
+    hits.truncate(limit);
+    Ok(hits)
+  }
+
+  fn tag_counts(&self) -> Result<BTreeMap<String, u64>> {
+    self.inner.tag_counts()
+  }
+
+  fn search_by_tag(&self, tag: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_by_tag(tag, limit)
+  }
+
+  fn search_by_link(&self, link: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_by_link(link, limit)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn split_author_filters_should_leave_phrases_alone() {
+    assert_eq!(
+      split_author_filters(r#"runbook author:Alice "by author:bob" +deploy"#),
+      (
+        r#"runbook "by author:bob" +deploy"#.to_string(),
+        vec!["alice".to_string()]
+      )
+    );
+    assert_eq!(
+      parse_commit("abc123\u{1f}Alice <alice@example.com>\u{1f}2024-05-01T10:00:00+02:00"),
+      Some(LastCommit {
+        commit: "abc123".to_string(),
+        author: "Alice <alice@example.com>".to_string(),
+        date: "2024-05-01T10:00:00+02:00".to_string(),
+      })
+    );
+  }
+
+  #[test]
+  fn branch_filters_should_match_the_branch_or_worktree() {
+    let (rest, branches) = split_branch_filters("release notes branch:feature/login branch:");
+    assert_eq!(rest, "release notes branch:");
+    assert_eq!(branches, vec!["feature/login".to_string()]);
+    let checkout = Checkout {
+      branch: Some("feature/login".to_string()),
+      worktree: "app-login".to_string(),
+    };
+    assert!(checkout.matches("feature/login"));
+    assert!(checkout.matches("app-login"));
+    assert!(!checkout.matches("main"));
+  }
+}
@@ -0,0 +1,157 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::{collections::VecDeque, path::Path, sync::Arc};
+
+use super::{
+  cancellation::check_cancelled,
+  file::File,
+  git_metadata::GitRepository,
+  index_backend::{IndexBackend, SearchHit},
+  text_index::TextIndex,
+};
+
+/// Most files indexed from one revision, so that a huge repository cannot exhaust memory.
+pub const REVISION_FILE_LIMIT: usize = 20_000;
+
+/// Files larger than this are left out of revision indexes.
+const MAX_REVISION_FILE_SIZE: u64 = 1024 * 1024;
+
+/// Revision indexes kept for further searches of the same revision.
+const CACHED_REVISIONS: usize = 4;
+
+/// A temporary in-memory index of the text files under a watch root as of a commit.
+struct RevisionIndex {
+  root: String,
+  commit: String,
+  index: Mutex<TextIndex>,
+}
+
+impl RevisionIndex {
+  fn build(repository: &GitRepository, commit: &str) -> Result<Self> {
+    let mut index = TextIndex::new()?;
+    let files = repository
+      .files_at(commit, MAX_REVISION_FILE_SIZE, REVISION_FILE_LIMIT)?
+      .into_iter()
+      .take_while(|_| check_cancelled().is_ok())
+      .filter_map(|(path, content)| {
+        /* Only text is indexed, as the extractors of the working tree read files on disk */
+        let content = String::from_utf8(content).ok()?;
+        (!content.contains('\0')).then(|| {
+          File::new(
+            repository.root().join(path).to_string_lossy().into_owned(),
+            content,
+          )
+        })
+      });
+    let added_count = index.add_docs(files)?;
+    /* A cut short index must not be cached as the whole revision */
+    check_cancelled()?;
+    tracing::info!(
+      "Indexed {} files of {:?} at {}",
+      added_count,
+      repository.root(),
+      commit
+    );
+    Ok(RevisionIndex {
+      root: repository.root().to_string_lossy().into_owned(),
+      commit: commit.to_string(),
+      index: Mutex::new(index),
+    })
+  }
+}
+
+/// Searches watch roots in git work trees as of past revisions, building an in-memory index of
+/// a revision on its first search. The last few revision indexes are kept.
+#[derive(Default)]
+pub struct RevisionIndexes {
+  indexes: Mutex<VecDeque<Arc<RevisionIndex>>>,
+}
+
+impl RevisionIndexes {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Hits of `keyword` in the files under `root` as of `revision`, with paths as in the working
+  /// tree, and the id of the commit searched.
+  pub fn search(
+    &self,
+    root: &Path,
+    revision: &str,
+    keyword: &str,
+    limit: usize,
+  ) -> Result<(String, Vec<SearchHit>)> {
+    let repository = GitRepository::discover(root)
+      .ok_or_else(|| anyhow::anyhow!("{} is not in a git work tree", root.display()))?;
+    let commit = repository.resolve(revision)?;
+    let revision_index = self.revision_index(&repository, &commit)?;
+    let hits = revision_index.index.lock().search_hits(keyword, limit)?;
+    Ok((commit, hits))
+  }
+
+  fn revision_index(&self, repository: &GitRepository, commit: &str) -> Result<Arc<RevisionIndex>> {
+    let root = repository.root().to_string_lossy();
+    let cached = self
+      .indexes
+      .lock()
+      .iter()
+      .find(|index| index.root == root && index.commit == commit)
+      .cloned();
+    if let Some(cached) = cached {
+      return Ok(cached);
+    }
+    /* Built without holding the lock, as reading a large tree takes a while */
+    let revision_index = Arc::new(RevisionIndex::build(repository, commit)?);
+    let mut indexes = self.indexes.lock();
+    if indexes.len() >= CACHED_REVISIONS {
+      indexes.pop_front();
+    }
+    indexes.push_back(revision_index.clone());
+    Ok(revision_index)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::process::Command;
+
+  fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+      .arg("-C")
+      .arg(dir)
+      .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+      .args(args)
+      .status()
+      .unwrap();
+    assert!(status.success());
+  }
+
+  #[test]
+  fn revision_indexes_should_search_files_as_of_a_revision() {
+    let dir = std::env::temp_dir().join(format!("git-revision-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    git(&dir, &["init", "--quiet"]);
+    std::fs::write(dir.join("guide.md"), "install with floppy disks").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "--quiet", "-m", "v1"]);
+    git(&dir, &["tag", "v1.0"]);
+    std::fs::write(dir.join("guide.md"), "install with the network").unwrap();
+    git(&dir, &["commit", "--quiet", "-am", "v2"]);
+
+    let revision_indexes = RevisionIndexes::new();
+    let (commit, hits) = revision_indexes.search(&dir, "v1.0", "floppy", 10).unwrap();
+    assert_eq!(commit.len(), 40);
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].file_path, dir.join("guide.md").to_string_lossy());
+    let (_, hits) = revision_indexes.search(&dir, "HEAD", "floppy", 10).unwrap();
+    assert!(hits.is_empty());
+    assert!(revision_indexes.search(&dir, "v9.9", "floppy", 10).is_err());
+    assert!(
+      revision_indexes
+        .search(&dir, "--all", "floppy", 10)
+        .is_err()
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
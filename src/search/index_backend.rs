@@ -0,0 +1,191 @@
+use anyhow::Result;
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  path::Path,
+  sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+  },
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+  file::{File, document_extension, document_file_path},
+  saved_filters::CompiledFilter,
+};
+
+pub const SEARCH_FILE_LIMIT: usize = 10;
+
+/* Shared by all backends so a re-created index never repeats a generation of the one it replaces */
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Returns a generation no backend has used before, see `IndexBackend::generation`.
+pub fn next_generation() -> u64 {
+  NEXT_GENERATION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Milliseconds since the Unix epoch, as recorded when a document is indexed.
+pub fn unix_time_ms() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+/// Memory an index holds, reported by `get_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct IndexMemory {
+  /// Heap reserved for buffering writes until they are committed
+  pub writer_heap_bytes: u64,
+  /// Size of the index itself when it is kept in memory rather than on disk
+  pub in_memory_bytes: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SearchHit {
+  pub file_path: String,
+  pub score: f32,
+  pub line_number: Option<usize>,
+  pub snippet: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub section: Option<String>,
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  pub metadata: BTreeMap<String, String>,
+}
+
+impl SearchHit {
+  /// Only where the hit is, for results that must not reveal file content.
+  pub fn without_content(self) -> Self {
+    SearchHit {
+      snippet: None,
+      section: None,
+      metadata: BTreeMap::new(),
+      ..self
+    }
+  }
+}
+
+/// Hits fetched by backends filtering after searching, to make up for the hits they drop.
+const FILTER_OVERFETCH: usize = 4;
+
+/// How a keyword search narrows and ranks the documents it collects.
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+  /// Score factors by lowercase file extension, e.g. `md` => 2.0
+  pub extension_boosts: BTreeMap<String, f32>,
+  /// Directory or file the documents must lie under
+  pub path: Option<String>,
+  /// Saved filters the documents must pass
+  pub filter: Arc<CompiledFilter>,
+}
+
+impl SearchOptions {
+  /// Whether a hit found without the options is kept by them.
+  pub fn keeps(&self, file_path: &str) -> bool {
+    self
+      .path
+      .as_ref()
+      .is_none_or(|path| Path::new(document_file_path(file_path)).starts_with(path))
+      && self.filter.matches(file_path)
+  }
+
+  /// Factor of the score of a document, from the extension of the file it was split from.
+  pub fn boost(&self, file_path: &str) -> f32 {
+    document_extension(file_path)
+      .and_then(|extension| self.extension_boosts.get(&extension))
+      .copied()
+      .unwrap_or(1.0)
+  }
+}
+
+/// Storage engine holding the documents of one watch root. `TextIndex` (tantivy) is the default;
+/// other engines can be plugged in by handing an `Arc<Mutex<dyn IndexBackend>>` to
+/// `IndexOperation` and `ShardedIndex`.
+///
+/// Writes may be buffered until `commit`, and only then need to become visible to searches.
+pub trait IndexBackend: Send {
+  /// Adds the document of a file, replacing any document already indexed under its path.
+  fn add_doc(&mut self, file: &File) -> Result<()>;
+
+  /// Adds documents one by one as the iterator yields them, then commits once. Like `add_doc`,
+  /// a document replaces the one already indexed under its path.
+  fn add_docs(&mut self, files: Box<dyn Iterator<Item = File> + '_>) -> Result<usize>;
+
+  fn replace_doc(&mut self, file: &File) -> Result<()>;
+
+  fn delete_doc(&mut self, file_path: &str) -> Result<()>;
+
+  fn delete_docs_by_path_prefix(&mut self, path_prefix: &str) -> Result<usize>;
+
+  fn has_doc(&self, file_path: &str) -> Result<bool>;
+
+  fn has_docs_with_path_prefix(&self, path_prefix: &str) -> Result<bool>;
+
+  /// Paths of every live document, including chunk and container member paths.
+  fn indexed_paths(&self) -> Result<BTreeSet<String>>;
+
+  /// When the document was last added or replaced, in milliseconds since the Unix epoch, or
+  /// `None` when it is not indexed.
+  fn indexed_at(&self, file_path: &str) -> Result<Option<u64>>;
+
+  fn commit(&mut self) -> Result<()>;
+
+  /// Merges the committed documents into as few parts as possible for faster searches. Heavy, so
+  /// meant for scheduled maintenance.
+  fn optimize(&mut self) -> Result<()>;
+
+  /// Changes with every commit, so results computed at one generation are still valid while it
+  /// stays the same. New values should be taken from `next_generation`.
+  fn generation(&self) -> u64;
+
+  fn get_pending_operations(&self) -> usize;
+
+  fn num_docs(&self) -> u64;
+
+  fn memory_usage(&self) -> IndexMemory;
+
+  fn clear(&mut self) -> Result<()>;
+
+  fn search_hits(&self, keyword: &str, limit: usize) -> Result<Vec<SearchHit>>;
+
+  /// Best `limit` hits narrowed and ranked with `options`. Backends should apply them while
+  /// collecting, as `TextIndex` does, since the documents kept may rank below the hits
+  /// `search_hits` returns; by default its hits are filtered and ranked again.
+  fn search_hits_with(
+    &self,
+    keyword: &str,
+    limit: usize,
+    options: &SearchOptions,
+  ) -> Result<Vec<SearchHit>> {
+    let fetch_limit = if options.path.is_none() && options.filter.is_empty() {
+      limit
+    } else {
+      limit * FILTER_OVERFETCH
+    };
+    let mut hits = self.search_hits(keyword, fetch_limit)?;
+    hits.retain(|hit| options.keeps(&hit.file_path));
+    for hit in &mut hits {
+      hit.score *= options.boost(&hit.file_path);
+    }
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits.truncate(limit);
+    Ok(hits)
+  }
+
+  /// Number of documents carrying each tag, where a nested tag such as `project/alpha` also
+  /// counts towards `project`.
+  fn tag_counts(&self) -> Result<BTreeMap<String, u64>>;
+
+  /// Documents carrying `tag` or a tag nested below it.
+  fn search_by_tag(&self, tag: &str, limit: usize) -> Result<Vec<SearchHit>>;
+
+  /// Documents linking to `link`, a path or a `wikilink_key`, as listed in their links metadata.
+  fn search_by_link(&self, link: &str, limit: usize) -> Result<Vec<SearchHit>>;
+
+  fn search(&self, keyword: &str) -> Result<Vec<String>> {
+    self
+      .search_hits(keyword, SEARCH_FILE_LIMIT)?
+      .iter()
+      .map(|hit| serde_json::to_string(hit).map_err(anyhow::Error::from))
+      .collect()
+  }
+}
@@ -1,48 +1,176 @@
 use anyhow::Result;
+use parking_lot::Mutex;
 use std::{
-  sync::{Arc, Mutex, mpsc},
+  any::Any,
+  collections::HashSet,
+  sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+  },
   thread,
-  time::Duration,
+  time::{Duration, Instant},
 };
+use tokio::{
+  sync::mpsc,
+  task::{self, JoinHandle},
+  time::error::Elapsed,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::file::throttle::run_niced;
 
 use super::{
-  file::{FileFilter, FileLoader, FileOperation},
-  text_index::TextIndex,
+  commit_status::CommitStatus,
+  file::{
+    File, FileFilter, FileLoader, FileOperation, chunk_prefix, container_member_prefix,
+    is_section_file, section_prefix,
+  },
+  index_backend::IndexBackend,
 };
 
 const WAIT_MILLIS_FOR_NEXT_UPDATE_TO_BULK: u64 = 500;
+const MAX_MILLIS_UNCOMMITTED: u64 = 5000;
+/// Most operations handled in one batch unless a memory budget lowers it.
+pub const MAX_BULK_OPERATION_SIZE: usize = 256;
+const MAX_LOADER_THREADS: usize = 8;
+
+type OperationsHandler = dyn Fn(&Vec<FileOperation>) -> Result<()> + Send + Sync;
+
+/// What happens when a file fails to load, both while initializing the index and while applying
+/// watched changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ErrorPolicy {
+  /// Fail on the first file that cannot be loaded: initialization stops, and a change to the file
+  /// is reported as failed while the rest of its batch is still applied
+  Strict,
+  /// Leave files that cannot be loaded out of the index and carry on with the others
+  #[default]
+  Lenient,
+}
+
+impl ErrorPolicy {
+  /// The loaded value, or `None` when a file that failed to load is left out of the index.
+  fn apply<T>(self, path: &str, loaded: Result<T>) -> Result<Option<T>> {
+    match (self, loaded) {
+      (_, Ok(value)) => Ok(Some(value)),
+      (ErrorPolicy::Strict, Err(e)) => Err(e.context(format!("Failed to load {}", path))),
+      (ErrorPolicy::Lenient, Err(e)) => {
+        tracing::debug!("Leaving {} out of the index: {:#}", path, e);
+        Ok(None)
+      }
+    }
+  }
+}
+
+/// How an `IndexOperation` loads files and reports its commits.
+#[derive(Clone, Default)]
+pub struct IndexOperationOptions {
+  /// Wait before loading newly created files, giving slow writers time to finish the file
+  pub created_grace_delay: Duration,
+  pub error_policy: ErrorPolicy,
+  /// Updated after every commit of watched changes
+  pub commit_status: Arc<CommitStatus>,
+  /// Load and index files on threads of lowered priority
+  pub nice: bool,
+  /// Most operations handled in one batch, `MAX_BULK_OPERATION_SIZE` when unset
+  pub max_batch_operations: Option<usize>,
+  /// Operations queued and not handled yet, kept up to date by the worker
+  pub queued_operations: Arc<AtomicUsize>,
+}
+
+/// When the batch being handled started, `None` while idle.
+type BusySince = Mutex<Option<Instant>>;
+
+/// Marks the worker idle again once a batch is done, even if handling it panicked.
+struct BusyGuard<'a>(&'a BusySince);
+
+impl<'a> BusyGuard<'a> {
+  fn enter(busy_since: &'a BusySince) -> Self {
+    *busy_since.lock() = Some(Instant::now());
+    BusyGuard(busy_since)
+  }
+}
+
+impl Drop for BusyGuard<'_> {
+  fn drop(&mut self) {
+    *self.0.lock() = None;
+  }
+}
 
 pub struct IndexOperation {
-  index: Arc<Mutex<TextIndex>>,
-  sender: mpsc::Sender<FileOperation>,
-  _worker_handle: thread::JoinHandle<()>,
+  index: Arc<Mutex<dyn IndexBackend>>,
+  error_policy: ErrorPolicy,
+  nice: bool,
+  busy_since: Arc<BusySince>,
+  queued_operations: Arc<AtomicUsize>,
+  handler: Arc<OperationsHandler>,
+  sender: mpsc::UnboundedSender<FileOperation>,
+  priority_sender: mpsc::UnboundedSender<FileOperation>,
+  cancellation: CancellationToken,
+  _worker_handle: JoinHandle<()>,
 }
 
 impl IndexOperation {
   pub fn new(
-    text_index: Arc<Mutex<TextIndex>>,
+    text_index: Arc<Mutex<dyn IndexBackend>>,
     file_filter: Arc<dyn FileFilter + Send + Sync>,
     file_loader: Arc<dyn FileLoader + Send + Sync>,
   ) -> Result<Self> {
-    let (sender, receiver) = mpsc::channel::<FileOperation>();
-
-    let file_filter_clone = file_filter.clone();
-    let file_loader_clone = file_loader.clone();
-    let text_index_for_worker = text_index.clone();
-
-    let worker_handle = thread::Builder::new()
-      .name("index-update-worker".to_string())
-      .spawn(move || {
-        subscribe_operations(
-          receiver,
-          &process_operations(text_index_for_worker, file_filter_clone, file_loader_clone),
-        )
+    Self::new_with_options(
+      text_index,
+      file_filter,
+      file_loader,
+      IndexOperationOptions::default(),
+    )
+  }
+
+  pub fn new_with_options(
+    text_index: Arc<Mutex<dyn IndexBackend>>,
+    file_filter: Arc<dyn FileFilter + Send + Sync>,
+    file_loader: Arc<dyn FileLoader + Send + Sync>,
+    options: IndexOperationOptions,
+  ) -> Result<Self> {
+    let error_policy = options.error_policy;
+    let nice = options.nice;
+    let max_batch_operations = options
+      .max_batch_operations
+      .unwrap_or(MAX_BULK_OPERATION_SIZE);
+    let queued_operations = options.queued_operations.clone();
+    let (sender, receiver) = mpsc::unbounded_channel::<FileOperation>();
+    let (priority_sender, priority_receiver) = mpsc::unbounded_channel::<FileOperation>();
+    let cancellation = CancellationToken::new();
+    let process = process_operations(text_index.clone(), file_filter, file_loader, options);
+    let busy_since = Arc::new(BusySince::new(None));
+    let handler: Arc<OperationsHandler> = {
+      let busy_since = busy_since.clone();
+      Arc::new(move |operations: &Vec<FileOperation>| {
+        let _busy = BusyGuard::enter(&busy_since);
+        run_niced(nice, || process(operations))
       })
-      .expect("Failed to spawn index update worker");
+    };
+
+    /* Must be called from within the tokio runtime, which runs the worker */
+    let worker_handle = tokio::runtime::Handle::try_current()?.spawn(supervise_operations(
+      OperationReceivers {
+        operations: receiver,
+        prioritized: priority_receiver,
+      },
+      cancellation.clone(),
+      handler.clone(),
+      queued_operations.clone(),
+      max_batch_operations,
+    ));
 
     Ok(IndexOperation {
       index: text_index,
+      error_policy,
+      nice,
+      busy_since,
+      queued_operations,
+      handler,
       sender,
+      priority_sender,
+      cancellation,
       _worker_handle: worker_handle,
     })
   }
@@ -53,77 +181,237 @@ impl IndexOperation {
     file_filter: Arc<dyn FileFilter + Send + Sync>,
     file_loader: Arc<dyn FileLoader + Send + Sync>,
   ) -> Result<()> {
-    let mut index = match self.index.lock() {
-      Ok(guard) => guard,
-      Err(poisoned) => poisoned.into_inner(),
-    };
-    file_loader
-      .load_directory(target_dir)
-      .filter_map(Result::ok)
-      .filter(|file| file_filter.is_target(&file.path))
-      .try_for_each(|file| index.add_doc(&file))?;
-    index.commit()
+    let added_count = run_niced(self.nice, || {
+      add_directory(
+        &mut *self.index.lock(),
+        target_dir,
+        file_filter.as_ref(),
+        file_loader.as_ref(),
+        self.error_policy,
+      )
+    })?;
+    tracing::info!("Indexed {} files in {}", added_count, target_dir);
+    Ok(())
   }
 
-  pub fn enqueue(&self, operation: &FileOperation) -> Result<()> {
+  pub fn rebuild_index(
+    &self,
+    target_dir: &str,
+    file_filter: Arc<dyn FileFilter + Send + Sync>,
+    file_loader: Arc<dyn FileLoader + Send + Sync>,
+  ) -> Result<()> {
+    {
+      let mut index = self.index.lock();
+      index.clear()?;
+    }
+    self.initialize_index(target_dir, file_filter, file_loader)
+  }
+
+  /// How long the worker has been handling its current batch, zero while idle. A batch that never
+  /// ends means the worker is stuck.
+  pub fn busy_for(&self) -> Duration {
     self
-      .sender
-      .send(operation.clone())
-      .map_err(|e| anyhow::anyhow!("Failed to queue index operation: {}", e))
+      .busy_since
+      .lock()
+      .map_or(Duration::ZERO, |since| since.elapsed())
+  }
+
+  /// Applies `operations` as one batch on the calling thread rather than queueing them, so that
+  /// how they are batched does not depend on timing.
+  pub fn apply(&self, operations: &[FileOperation]) -> Result<()> {
+    (self.handler)(&operations.to_vec())
+  }
+
+  /// Operations queued and not handled yet, including those of the batch being collected.
+  pub fn queued_operations(&self) -> usize {
+    self.queued_operations.load(Ordering::Relaxed)
+  }
+
+  pub fn enqueue(&self, operation: &FileOperation) -> Result<()> {
+    self.send(&self.sender, operation)
+  }
+
+  /// Queues an operation ahead of the others, handled on its own as soon as the worker is free
+  /// rather than with the next batch.
+  pub fn enqueue_prioritized(&self, operation: &FileOperation) -> Result<()> {
+    self.send(&self.priority_sender, operation)
+  }
+
+  fn send(
+    &self,
+    sender: &mpsc::UnboundedSender<FileOperation>,
+    operation: &FileOperation,
+  ) -> Result<()> {
+    /* Counted first, so that the worker never handles an operation before it is counted */
+    self.queued_operations.fetch_add(1, Ordering::Relaxed);
+    sender.send(operation.clone()).map_err(|e| {
+      self.queued_operations.fetch_sub(1, Ordering::Relaxed);
+      anyhow::anyhow!("Failed to queue index operation: {}", e)
+    })
+  }
+}
+
+impl Drop for IndexOperation {
+  fn drop(&mut self) {
+    /* The worker flushes the operations already queued before it stops */
+    self.cancellation.cancel();
+  }
+}
+
+struct OperationReceivers {
+  operations: mpsc::UnboundedReceiver<FileOperation>,
+  prioritized: mpsc::UnboundedReceiver<FileOperation>,
+}
+
+type SharedReceiver = Arc<tokio::sync::Mutex<OperationReceivers>>;
+
+/// Restarts the worker if it panics; operations still in the channel are kept for the new one.
+async fn supervise_operations(
+  receivers: OperationReceivers,
+  cancellation: CancellationToken,
+  handler: Arc<OperationsHandler>,
+  queued_operations: Arc<AtomicUsize>,
+  max_batch_operations: usize,
+) {
+  let receiver: SharedReceiver = Arc::new(tokio::sync::Mutex::new(receivers));
+  loop {
+    let worker = tokio::spawn(subscribe_operations(
+      receiver.clone(),
+      cancellation.clone(),
+      handler.clone(),
+      queued_operations.clone(),
+      max_batch_operations,
+    ));
+    match worker.await {
+      Err(e) if e.is_panic() => tracing::error!(
+        "Index update worker panicked, restarting it: {}",
+        panic_message(e.into_panic())
+      ),
+      _ => break,
+    }
   }
 }
 
-fn subscribe_operations(
-  receiver: mpsc::Receiver<FileOperation>,
-  handler: &impl Fn(&Vec<FileOperation>) -> Result<()>,
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+  panic
+    .downcast_ref::<&str>()
+    .map(|message| message.to_string())
+    .or_else(|| panic.downcast_ref::<String>().cloned())
+    .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+async fn subscribe_operations(
+  receiver: SharedReceiver,
+  cancellation: CancellationToken,
+  handler: Arc<OperationsHandler>,
+  queued_operations: Arc<AtomicUsize>,
+  max_batch_operations: usize,
 ) {
-  fn receive_with_timeout(
-    receiver: &mpsc::Receiver<FileOperation>,
+  async fn receive_with_timeout(
+    receiver: &mut mpsc::UnboundedReceiver<FileOperation>,
     timeout: Option<Duration>,
-  ) -> Result<FileOperation, mpsc::RecvTimeoutError> {
+  ) -> Result<Option<FileOperation>, Elapsed> {
     match timeout {
-      Some(t) => receiver.recv_timeout(t),
-      None => receiver
-        .recv()
-        .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+      Some(t) => tokio::time::timeout(t, receiver.recv()).await,
+      None => Ok(receiver.recv().await),
+    }
+  }
+
+  /// Returns false if the handler panicked.
+  async fn run_handler(operations: Vec<FileOperation>, handler: &Arc<OperationsHandler>) -> bool {
+    /* Loading files and writing the index block, so they run off the async workers */
+    let handler = handler.clone();
+    match task::spawn_blocking(move || handler(&operations)).await {
+      Ok(Ok(())) => true,
+      Ok(Err(e)) => {
+        tracing::error!("Failed to handle operations: {}", e);
+        true
+      }
+      Err(e) if e.is_panic() => {
+        tracing::error!(
+          "Panicked while handling operations: {}",
+          panic_message(e.into_panic())
+        );
+        false
+      }
+      Err(e) => {
+        tracing::error!("Index update task failed: {}", e);
+        true
+      }
     }
   }
 
-  fn handle_operations(
+  async fn handle_operations(
     operations: &mut Vec<FileOperation>,
-    handler: &impl Fn(&Vec<FileOperation>) -> Result<()>,
+    handler: &Arc<OperationsHandler>,
+    queued_operations: &AtomicUsize,
   ) {
-    if let Err(e) = handler(operations) {
-      tracing::error!("Failed to handle operations: {}", e);
+    let operations = std::mem::take(operations);
+    let count = operations.len();
+    if !run_handler(operations.clone(), handler).await && count > 1 {
+      /* Retry one by one so a single bad operation does not lose the rest of the batch */
+      for operation in operations {
+        let description = format!("{:?}", operation);
+        if !run_handler(vec![operation], handler).await {
+          tracing::error!("Dropped {} after it panicked", description);
+        }
+      }
     }
-    operations.clear();
+    queued_operations.fetch_sub(count, Ordering::Relaxed);
   }
 
+  let mut receivers = receiver.lock().await;
+  let OperationReceivers {
+    operations: receiver,
+    prioritized,
+  } = &mut *receivers;
   let mut operations = Vec::new();
+  let mut oldest_pending_at: Option<Instant> = None;
 
   loop {
-    let timeout = if operations.is_empty() {
-      None
-    } else {
-      Some(Duration::from_millis(WAIT_MILLIS_FOR_NEXT_UPDATE_TO_BULK))
-    };
+    /* Keep waiting for more operations to bulk, but never past the uncommitted deadline */
+    let timeout = oldest_pending_at.map(|oldest| {
+      (oldest + Duration::from_millis(MAX_MILLIS_UNCOMMITTED))
+        .saturating_duration_since(Instant::now())
+        .min(Duration::from_millis(WAIT_MILLIS_FOR_NEXT_UPDATE_TO_BULK))
+    });
 
-    match receive_with_timeout(&receiver, timeout) {
-      Ok(operation) => {
+    let received = tokio::select! {
+      biased;
+      _ = cancellation.cancelled() => None,
+      Some(operation) = prioritized.recv() => {
+        /* The batch being collected waits, keeping its deadline */
+        handle_operations(&mut vec![operation], &handler, &queued_operations).await;
+        continue;
+      }
+      received = receive_with_timeout(receiver, timeout) => Some(received),
+    };
+    match received {
+      Some(Ok(Some(operation))) => {
         operations.push(operation);
-        const MAX_BULK_OPERATION_SIZE: usize = 10;
-        if operations.len() >= MAX_BULK_OPERATION_SIZE {
-          handle_operations(&mut operations, handler);
+        let oldest = *oldest_pending_at.get_or_insert_with(Instant::now);
+        if operations.len() >= max_batch_operations
+          || oldest.elapsed() >= Duration::from_millis(MAX_MILLIS_UNCOMMITTED)
+        {
+          handle_operations(&mut operations, &handler, &queued_operations).await;
+          oldest_pending_at = None;
         }
       }
-      Err(mpsc::RecvTimeoutError::Timeout) => {
+      Some(Err(_)) => {
         if !operations.is_empty() {
-          handle_operations(&mut operations, handler);
+          handle_operations(&mut operations, &handler, &queued_operations).await;
         }
+        oldest_pending_at = None;
       }
-      Err(mpsc::RecvTimeoutError::Disconnected) => {
+      Some(Ok(None)) | None => {
+        while let Ok(operation) = prioritized.try_recv() {
+          operations.push(operation);
+        }
+        while let Ok(operation) = receiver.try_recv() {
+          operations.push(operation);
+        }
         if !operations.is_empty() {
-          handle_operations(&mut operations, handler);
+          handle_operations(&mut operations, &handler, &queued_operations).await;
         }
         tracing::info!("Index update worker shutting down");
         break;
@@ -132,115 +420,326 @@ fn subscribe_operations(
   }
 }
 
-fn process_operations(
-  text_index: Arc<Mutex<TextIndex>>,
-  file_filter: Arc<dyn FileFilter>,
-  file_loader: Arc<dyn FileLoader>,
-) -> impl Fn(&Vec<FileOperation>) -> Result<()> {
-  move |operations| {
-    if let Ok(mut index) = text_index.lock() {
-      for op in operations {
-        match op {
-          FileOperation::FileCreated(path) => {
-            if file_filter.is_target(path) {
-              let file = file_loader.load_file(path)?;
-              index.add_doc(&file)?;
-            }
-          }
-          FileOperation::FileModified(path) => {
-            if file_filter.is_target(path) {
-              let file = file_loader.load_file(path)?;
-              index.replace_doc(&file)?;
-            }
-          }
-          FileOperation::FileDeleted(path) => {
-            index.delete_doc(path)?;
-          }
-          FileOperation::FileRenamed { old_path, new_path } => {
-            match (
-              file_filter.is_target(old_path),
-              file_filter.is_target(new_path),
-            ) {
-              (true, true) => {
-                let file = file_loader.load_file(new_path)?;
-                index.delete_doc(old_path)?;
-                index.add_doc(&file)?;
-              }
-              (true, false) => {
-                index.delete_doc(old_path)?;
-              }
-              (false, true) => {
-                let file = file_loader.load_file(new_path)?;
-                index.add_doc(&file)?;
+fn is_indexed(path: &str, file_filter: &dyn FileFilter, file_loader: &dyn FileLoader) -> bool {
+  file_filter.is_target(path) || file_loader.is_container(path)
+}
+
+fn delete_documents(
+  index: &mut dyn IndexBackend,
+  path: &str,
+  file_loader: &dyn FileLoader,
+) -> Result<()> {
+  index.delete_doc(path)?;
+  /* A file may have been indexed in chunks, whatever its current size, or in sections */
+  index.delete_docs_by_path_prefix(&chunk_prefix(path))?;
+  if is_section_file(path) {
+    index.delete_docs_by_path_prefix(&section_prefix(path))?;
+  }
+  if file_loader.is_container(path) {
+    index.delete_docs_by_path_prefix(&container_member_prefix(path))?;
+  }
+  Ok(())
+}
+
+/// Adds the files of a directory, stopping at the first one that fails to load under the strict
+/// policy.
+fn add_directory(
+  index: &mut dyn IndexBackend,
+  path: &str,
+  file_filter: &dyn FileFilter,
+  file_loader: &dyn FileLoader,
+  error_policy: ErrorPolicy,
+) -> Result<usize> {
+  let mut error = None;
+  let files = file_loader
+    .load_directory(path, file_filter)
+    .map_while(|file| match error_policy.apply(path, file) {
+      Ok(file) => Some(file),
+      Err(e) => {
+        error = Some(e);
+        None
+      }
+    })
+    .flatten();
+  let added_count = index.add_docs(Box::new(files))?;
+  match error {
+    Some(e) => Err(e),
+    None => Ok(added_count),
+  }
+}
+
+/// Loads the documents of every created, modified or renamed file of a batch on a pool of
+/// threads, returning them in the order of `operations`.
+fn load_operation_documents(
+  operations: &[FileOperation],
+  file_filter: &(dyn FileFilter + Send + Sync),
+  file_loader: &(dyn FileLoader + Send + Sync),
+) -> Vec<Option<Result<Vec<File>>>> {
+  let paths = operations
+    .iter()
+    .map(|op| match op {
+      FileOperation::FileCreated(path) | FileOperation::FileModified(path) => Some(path),
+      FileOperation::FileRenamed { new_path, .. } => Some(new_path),
+      _ => None,
+    })
+    .map(|path| path.filter(|path| is_indexed(path, file_filter, file_loader)))
+    .collect::<Vec<_>>();
+  let thread_count = thread::available_parallelism()
+    .map(usize::from)
+    .unwrap_or(1)
+    .min(MAX_LOADER_THREADS)
+    .min(paths.iter().flatten().count());
+
+  let next_index = &AtomicUsize::new(0);
+  let paths = &paths;
+  let mut documents = paths.iter().map(|_| None).collect::<Vec<_>>();
+  thread::scope(|scope| {
+    let workers = (0..thread_count)
+      .map(|_| {
+        scope.spawn(move || {
+          let mut loaded = Vec::new();
+          loop {
+            let index = next_index.fetch_add(1, Ordering::Relaxed);
+            match paths.get(index) {
+              Some(Some(path)) => {
+                loaded.push((index, file_loader.load_documents(path, file_filter)))
               }
-              (false, false) => {}
+              Some(None) => {}
+              None => break loaded,
             }
           }
-          FileOperation::DirectoryDeleted(path) => {
-            index.delete_docs_by_path_prefix(path)?;
-          }
-          FileOperation::DirectoryRenamed { old_path, new_path } => {
-            index.delete_docs_by_path_prefix(old_path)?;
-            file_loader
-              .load_directory(new_path)
-              .filter_map(Result::ok)
-              .filter(|file| file_filter.is_target(&file.path))
-              .for_each(|file| {
-                if let Err(e) = index.add_doc(&file) {
-                  tracing::error!("Failed to add document after directory rename: {}", e);
-                }
-              });
+        })
+      })
+      .collect::<Vec<_>>();
+    for worker in workers {
+      match worker.join() {
+        Ok(loaded) => {
+          for (index, files) in loaded {
+            documents[index] = Some(files);
           }
         }
+        Err(_) => tracing::error!("File loader thread panicked"),
       }
-      index.commit()?;
-    } else {
-      tracing::error!("Failed to lock text index for processing operations");
     }
-    Ok(())
-  }
+  });
+  documents
 }
 
-#[cfg(test)]
-mod tests {
-  use super::*;
-  use crate::search::file::{File, FileFilter, FileLoader};
-
-  struct MockFileFilter;
-
-  const ALLOWED_EXTENSION: &str = ".txt";
-
-  impl FileFilter for MockFileFilter {
-    fn is_target(&self, path: &str) -> bool {
-      path.ends_with(ALLOWED_EXTENSION)
-    }
+/// Resolves renames whose destination is already gone into file or directory renames, depending
+/// on whether the index holds the old path as a document or as a directory of documents.
+fn classify_renames(
+  operations: &[FileOperation],
+  text_index: &Mutex<dyn IndexBackend>,
+) -> Result<Vec<FileOperation>> {
+  if !operations
+    .iter()
+    .any(|op| matches!(op, FileOperation::PathRenamed { .. }))
+  {
+    return Ok(operations.to_vec());
   }
+  let index = text_index.lock();
+  operations
+    .iter()
+    .map(|op| match op {
+      FileOperation::PathRenamed { old_path, new_path } => {
+        let directory_prefix = format!("{}{}", old_path, std::path::MAIN_SEPARATOR);
+        let is_directory =
+          !index.has_doc(old_path)? && index.has_docs_with_path_prefix(&directory_prefix)?;
+        let (old_path, new_path) = (old_path.clone(), new_path.clone());
+        Ok(if is_directory {
+          FileOperation::DirectoryRenamed { old_path, new_path }
+        } else {
+          FileOperation::FileRenamed { old_path, new_path }
+        })
+      }
+      op => Ok(op.clone()),
+    })
+    .collect()
+}
 
-  struct MockFileLoader {
-    files: Vec<File>,
-    loaded_file_content: String,
+/// Editors such as Vim or VS Code save by writing a temporary file and renaming it over the
+/// original. A rename from a path that is not indexed onto an indexed path is therefore handled as
+/// a modification of the destination, and the creation of the temporary file is dropped.
+fn map_atomic_saves(
+  operations: Vec<FileOperation>,
+  file_filter: &dyn FileFilter,
+  file_loader: &dyn FileLoader,
+) -> Vec<FileOperation> {
+  let temporary_paths = operations
+    .iter()
+    .filter_map(|op| match op {
+      FileOperation::FileRenamed { old_path, new_path }
+        if !is_indexed(old_path, file_filter, file_loader)
+          && is_indexed(new_path, file_filter, file_loader) =>
+      {
+        Some(old_path.clone())
+      }
+      _ => None,
+    })
+    .collect::<HashSet<_>>();
+  if temporary_paths.is_empty() {
+    return operations;
   }
+  operations
+    .into_iter()
+    .filter_map(|op| match op {
+      FileOperation::FileCreated(path) | FileOperation::FileModified(path)
+        if temporary_paths.contains(&path) =>
+      {
+        None
+      }
+      FileOperation::FileRenamed { old_path, new_path } if temporary_paths.contains(&old_path) => {
+        tracing::debug!("Treating rename of {} as a save of {}", old_path, new_path);
+        Some(FileOperation::FileModified(new_path))
+      }
+      op => Some(op),
+    })
+    .collect()
+}
 
-  impl MockFileLoader {
-    pub fn new(files: Vec<File>, loaded_file_content: String) -> Self {
-      MockFileLoader {
-        files,
-        loaded_file_content,
+/// Applies one operation to the index, given the documents loaded for it.
+fn apply_operation(
+  index: &mut dyn IndexBackend,
+  op: &FileOperation,
+  files: Option<Result<Vec<File>>>,
+  file_filter: &dyn FileFilter,
+  file_loader: &dyn FileLoader,
+  error_policy: ErrorPolicy,
+) -> Result<()> {
+  /* Under the lenient policy a file that fails to load is skipped as if it were not a target */
+  let files = match (files, op) {
+    (Some(files), FileOperation::FileRenamed { new_path: path, .. })
+    | (Some(files), FileOperation::FileCreated(path) | FileOperation::FileModified(path)) => {
+      error_policy.apply(path, files)?
+    }
+    _ => None,
+  };
+  match op {
+    FileOperation::FileCreated(_) => {
+      if let Some(files) = files {
+        for file in files {
+          index.add_doc(&file)?;
+        }
       }
     }
-  }
-  impl FileLoader for MockFileLoader {
-    fn load_directory(&self, _path: &str) -> Box<dyn Iterator<Item = Result<File>> + '_> {
-      Box::new(self.files.iter().cloned().map(Ok))
+    FileOperation::FileModified(path) => {
+      if let Some(files) = files {
+        delete_documents(index, path, file_loader)?;
+        for file in files {
+          index.add_doc(&file)?;
+        }
+      }
+    }
+    FileOperation::FileDeleted(path) => {
+      delete_documents(index, path, file_loader)?;
+    }
+    FileOperation::FileRenamed { old_path, new_path } => {
+      match (is_indexed(old_path, file_filter, file_loader), files) {
+        (true, Some(files)) => {
+          delete_documents(index, old_path, file_loader)?;
+          /* The rename may have replaced an indexed file */
+          delete_documents(index, new_path, file_loader)?;
+          for file in files {
+            index.add_doc(&file)?;
+          }
+        }
+        (true, None) => {
+          delete_documents(index, old_path, file_loader)?;
+        }
+        (false, Some(files)) => {
+          for file in files {
+            index.add_doc(&file)?;
+          }
+        }
+        (false, None) => {}
+      }
+    }
+    FileOperation::DirectoryDeleted(path) => {
+      index.delete_docs_by_path_prefix(path)?;
     }
+    FileOperation::DirectoryRescan(path) => {
+      index.delete_docs_by_path_prefix(path)?;
+      add_directory(index, path, file_filter, file_loader, error_policy)?;
+    }
+    FileOperation::PathRenamed { .. } => {
+      unreachable!("renames are classified before processing")
+    }
+    FileOperation::DirectoryRenamed { old_path, new_path } => {
+      index.delete_docs_by_path_prefix(old_path)?;
+      add_directory(index, new_path, file_filter, file_loader, error_policy)?;
+    }
+  }
+  Ok(())
+}
 
-    fn load_file(&self, path: &str) -> Result<File> {
-      Ok(File::new(
-        path.to_string(),
-        self.loaded_file_content.clone(),
-      ))
+fn process_operations(
+  text_index: Arc<Mutex<dyn IndexBackend>>,
+  file_filter: Arc<dyn FileFilter + Send + Sync>,
+  file_loader: Arc<dyn FileLoader + Send + Sync>,
+  options: IndexOperationOptions,
+) -> impl Fn(&Vec<FileOperation>) -> Result<()> + Send + Sync + 'static {
+  let IndexOperationOptions {
+    created_grace_delay,
+    error_policy,
+    commit_status,
+    ..
+  } = options;
+  move |operations| {
+    let operations = &map_atomic_saves(
+      classify_renames(operations, &text_index)?,
+      file_filter.as_ref(),
+      file_loader.as_ref(),
+    );
+    if !created_grace_delay.is_zero()
+      && operations
+        .iter()
+        .any(|op| matches!(op, FileOperation::FileCreated(_)))
+    {
+      thread::sleep(created_grace_delay);
     }
+    /* Files are loaded in parallel before locking the index, then written in event order */
+    let documents =
+      load_operation_documents(operations, file_filter.as_ref(), file_loader.as_ref());
+    let mut index = text_index.lock();
+    /* A failing operation, e.g. on a file deleted since its event, must not lose the others */
+    let mut failed_count = 0;
+    for (op, files) in operations.iter().zip(documents) {
+      if let Err(e) = apply_operation(
+        &mut *index,
+        op,
+        files,
+        file_filter.as_ref(),
+        file_loader.as_ref(),
+        error_policy,
+      ) {
+        failed_count += 1;
+        tracing::error!("Failed to apply {:?}: {:#}", op, e);
+      }
+    }
+    let committed = index.commit();
+    commit_status.record(&committed);
+    committed?;
+    if failed_count > 0 {
+      tracing::warn!(
+        "Applied {} of {} operations",
+        operations.len() - failed_count,
+        operations.len()
+      );
+    }
+    Ok(())
   }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    search::{
+      file::{File, FileFilter, FileLoader},
+      text_index::TextIndex,
+    },
+    testing::{MockFileFilter, MockFileLoader},
+  };
 
   fn create_initialize_file_loader() -> Arc<dyn FileLoader + Send + Sync> {
     Arc::new(MockFileLoader::new(
@@ -274,10 +773,10 @@ mod tests {
     ))
   }
 
-  #[test]
-  fn index_operation_should_initialize_index_with_files_in_directory() {
+  #[tokio::test]
+  async fn index_operation_should_initialize_index_with_files_in_directory() {
     let text_index = Arc::new(Mutex::new(TextIndex::new().unwrap()));
-    let file_filter = Arc::new(MockFileFilter);
+    let file_filter = Arc::new(MockFileFilter::default());
     let file_loader = create_initialize_file_loader();
 
     let index_operation =
@@ -286,7 +785,7 @@ mod tests {
     index_operation
       .initialize_index("test_dir", file_filter.clone(), file_loader.clone())
       .expect("Failed to initialize index");
-    let index = text_index.lock().unwrap();
+    let index = text_index.lock();
     let results = index.search("content").expect("Failed to search index");
     assert_eq!(results.len(), 4);
     assert!(results.iter().any(|r| r.contains("add_at_initialize1.txt")));
@@ -313,10 +812,88 @@ mod tests {
     );
   }
 
-  #[test]
-  fn index_operation_should_update_index_on_file_created() {
+  #[tokio::test]
+  async fn index_operation_should_rebuild_index_from_scratch() {
+    let text_index = Arc::new(Mutex::new(TextIndex::new().unwrap()));
+    let file_filter = Arc::new(MockFileFilter::default());
+    let file_loader = create_initialize_file_loader();
+
+    let index_operation =
+      IndexOperation::new(text_index.clone(), file_filter.clone(), file_loader.clone())
+        .expect("Failed to create IndexOperation");
+    index_operation
+      .initialize_index("test_dir", file_filter.clone(), file_loader.clone())
+      .expect("Failed to initialize index");
+
+    let rebuilt_file_loader: Arc<dyn FileLoader + Send + Sync> = Arc::new(MockFileLoader::new(
+      vec![File::new(
+        "rebuilt.txt".to_string(),
+        "Must find content after rebuild".to_string(),
+      )],
+      "Loaded content.".to_string(),
+    ));
+    index_operation
+      .rebuild_index("test_dir", file_filter.clone(), rebuilt_file_loader)
+      .expect("Failed to rebuild index");
+
+    let index = text_index.lock();
+    let results = index.search("content").expect("Failed to search index");
+    assert_eq!(results.len(), 1);
+    assert!(results[0].contains("rebuilt.txt"));
+  }
+
+  #[tokio::test]
+  async fn index_operation_should_commit_steady_changes_within_deadline() {
+    let text_index = Arc::new(Mutex::new(TextIndex::new().unwrap()));
+    let index_operation = IndexOperation::new(
+      text_index.clone(),
+      Arc::new(MockFileFilter::default()),
+      create_initialize_file_loader(),
+    )
+    .unwrap();
+
+    /* Every change arrives before the wait for the next one to bulk with runs out */
+    let started = Instant::now();
+    let mut created = 0;
+    while text_index.lock().search("Loaded").unwrap().is_empty() {
+      assert!(
+        started.elapsed() < Duration::from_millis(MAX_MILLIS_UNCOMMITTED * 2),
+        "Changes were still uncommitted after {:?}",
+        started.elapsed()
+      );
+      index_operation
+        .enqueue(&FileOperation::FileCreated(format!(
+          "steady{}.txt",
+          created
+        )))
+        .unwrap();
+      created += 1;
+      if created == 1 {
+        assert_eq!(index_operation.queued_operations(), 1);
+      }
+      tokio::time::sleep(Duration::from_millis(
+        WAIT_MILLIS_FOR_NEXT_UPDATE_TO_BULK / 2,
+      ))
+      .await;
+    }
+    assert!(started.elapsed() >= Duration::from_millis(MAX_MILLIS_UNCOMMITTED));
+
+    tokio::time::sleep(Duration::from_millis(
+      WAIT_MILLIS_FOR_NEXT_UPDATE_TO_BULK * 2,
+    ))
+    .await;
+    assert_eq!(index_operation.queued_operations(), 0);
+    let hits = text_index
+      .lock()
+      .search_hits("Loaded", created + 1)
+      .unwrap();
+    assert_eq!(hits.len(), created);
+  }
+
+  #[tokio::test]
+  async fn index_operation_should_update_index_on_file_created() {
     let text_index = Arc::new(Mutex::new(TextIndex::new().unwrap()));
-    let file_filter = Arc::new(MockFileFilter);
+    let file_filter = Arc::new(MockFileFilter::default());
     let file_loader = create_initialize_file_loader();
 
     let index_operation =
@@ -330,20 +907,170 @@ mod tests {
       .enqueue(&FileOperation::FileCreated("added.txt".to_string()))
       .expect("Failed to enqueue operation");
 
-    thread::sleep(Duration::from_millis(
+    tokio::time::sleep(Duration::from_millis(
       WAIT_MILLIS_FOR_NEXT_UPDATE_TO_BULK * 2,
-    ));
+    ))
+    .await;
 
-    let index = text_index.lock().unwrap();
+    let index = text_index.lock();
     let results = index.search("Loaded").expect("Failed to search index");
     assert_eq!(results.len(), 1);
     assert!(results[0].contains("added.txt"));
   }
 
-  #[test]
-  fn index_operation_should_update_index_on_file_modified() {
+  struct PanickingFileFilter;
+
+  impl FileFilter for PanickingFileFilter {
+    fn is_target(&self, path: &str) -> bool {
+      if path == "panic.txt" {
+        panic!("Failed to filter {}", path);
+      }
+      MockFileFilter::default().is_target(path)
+    }
+  }
+
+  #[tokio::test]
+  async fn index_operation_should_keep_processing_after_operation_panics() {
+    let text_index = Arc::new(Mutex::new(TextIndex::new().unwrap()));
+    let file_filter = Arc::new(PanickingFileFilter);
+    let file_loader = create_initialize_file_loader();
+
+    let index_operation =
+      IndexOperation::new(text_index.clone(), file_filter.clone(), file_loader.clone())
+        .expect("Failed to create IndexOperation");
+
+    for path in ["panic.txt", "added.txt"] {
+      index_operation
+        .enqueue(&FileOperation::FileCreated(path.to_string()))
+        .expect("Failed to enqueue operation");
+    }
+    tokio::time::sleep(Duration::from_millis(
+      WAIT_MILLIS_FOR_NEXT_UPDATE_TO_BULK * 2,
+    ))
+    .await;
+
+    index_operation
+      .enqueue(&FileOperation::FileCreated("added_later.txt".to_string()))
+      .expect("Failed to enqueue operation");
+    tokio::time::sleep(Duration::from_millis(
+      WAIT_MILLIS_FOR_NEXT_UPDATE_TO_BULK * 2,
+    ))
+    .await;
+
+    let index = text_index.lock();
+    let results = index.search("Loaded").expect("Failed to search index");
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().any(|r| r.contains("added.txt")));
+    assert!(results.iter().any(|r| r.contains("added_later.txt")));
+  }
+
+  struct UnreadableFileLoader;
+
+  #[async_trait::async_trait]
+  impl FileLoader for UnreadableFileLoader {
+    fn load_directory<'a>(
+      &'a self,
+      _path: &str,
+      _file_filter: &'a dyn FileFilter,
+    ) -> Box<dyn Iterator<Item = Result<File>> + 'a> {
+      Box::new(
+        ["initial.txt", "unreadable.txt"]
+          .into_iter()
+          .map(|path| self.load_file_blocking(path)),
+      )
+    }
+
+    fn load_file_blocking(&self, path: &str) -> Result<File> {
+      if path.starts_with("unreadable") {
+        anyhow::bail!("Permission denied");
+      }
+      Ok(File::new(path.to_string(), "Loaded content.".to_string()))
+    }
+  }
+
+  #[tokio::test]
+  async fn index_operation_should_skip_unreadable_files_unless_strict() {
+    let file_filter = Arc::new(MockFileFilter::default());
+    let file_loader = Arc::new(UnreadableFileLoader);
+    let new_index_operation = |text_index, error_policy| {
+      IndexOperation::new_with_options(
+        text_index,
+        file_filter.clone(),
+        file_loader.clone(),
+        IndexOperationOptions {
+          error_policy,
+          ..Default::default()
+        },
+      )
+      .expect("Failed to create IndexOperation")
+    };
+
+    let strict_index = Arc::new(Mutex::new(TextIndex::new().unwrap()));
+    assert!(
+      new_index_operation(strict_index, ErrorPolicy::Strict)
+        .initialize_index("test_dir", file_filter.clone(), file_loader.clone())
+        .is_err()
+    );
+
+    let text_index = Arc::new(Mutex::new(TextIndex::new().unwrap()));
+    let index_operation = new_index_operation(text_index.clone(), ErrorPolicy::Lenient);
+    index_operation
+      .initialize_index("test_dir", file_filter.clone(), file_loader.clone())
+      .expect("Failed to initialize index");
+    for path in ["unreadable_later.txt", "added.txt"] {
+      index_operation
+        .enqueue(&FileOperation::FileCreated(path.to_string()))
+        .expect("Failed to enqueue operation");
+    }
+    tokio::time::sleep(Duration::from_millis(
+      WAIT_MILLIS_FOR_NEXT_UPDATE_TO_BULK * 2,
+    ))
+    .await;
+
+    let index = text_index.lock();
+    let results = index.search("Loaded").expect("Failed to search index");
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().any(|r| r.contains("initial.txt")));
+    assert!(results.iter().any(|r| r.contains("added.txt")));
+  }
+
+  #[tokio::test]
+  async fn index_operation_should_apply_rest_of_batch_when_operation_fails() {
+    let text_index = Arc::new(Mutex::new(TextIndex::new().unwrap()));
+    let file_filter = Arc::new(MockFileFilter::default());
+    let file_loader = Arc::new(UnreadableFileLoader);
+    let index_operation = IndexOperation::new_with_options(
+      text_index.clone(),
+      file_filter,
+      file_loader,
+      IndexOperationOptions {
+        error_policy: ErrorPolicy::Strict,
+        ..Default::default()
+      },
+    )
+    .expect("Failed to create IndexOperation");
+
+    for path in ["added_before.txt", "unreadable.txt", "added_after.txt"] {
+      index_operation
+        .enqueue(&FileOperation::FileCreated(path.to_string()))
+        .expect("Failed to enqueue operation");
+    }
+    tokio::time::sleep(Duration::from_millis(
+      WAIT_MILLIS_FOR_NEXT_UPDATE_TO_BULK * 2,
+    ))
+    .await;
+
+    let index = text_index.lock();
+    let results = index.search("Loaded").expect("Failed to search index");
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().any(|r| r.contains("added_before.txt")));
+    assert!(results.iter().any(|r| r.contains("added_after.txt")));
+  }
+
+  #[tokio::test]
+  async fn index_operation_should_update_index_on_file_modified() {
     let text_index = Arc::new(Mutex::new(TextIndex::new().unwrap()));
-    let file_filter = Arc::new(MockFileFilter);
+    let file_filter = Arc::new(MockFileFilter::default());
     let file_loader = create_initialize_file_loader();
 
     let index_operation =
@@ -357,20 +1084,21 @@ mod tests {
       .enqueue(&FileOperation::FileModified("modified.txt".to_string()))
       .expect("Failed to enqueue operation");
 
-    thread::sleep(Duration::from_millis(
+    tokio::time::sleep(Duration::from_millis(
       WAIT_MILLIS_FOR_NEXT_UPDATE_TO_BULK * 2,
-    ));
+    ))
+    .await;
 
-    let index = text_index.lock().unwrap();
+    let index = text_index.lock();
     let results = index.search("Loaded").expect("Failed to search index");
     assert_eq!(results.len(), 1);
     assert!(results[0].contains("modified.txt"));
   }
 
-  #[test]
-  fn index_operation_should_update_index_on_file_deleted() {
+  #[tokio::test]
+  async fn index_operation_should_update_index_on_file_deleted() {
     let text_index = Arc::new(Mutex::new(TextIndex::new().unwrap()));
-    let file_filter = Arc::new(MockFileFilter);
+    let file_filter = Arc::new(MockFileFilter::default());
     let file_loader = create_initialize_file_loader();
 
     let index_operation =
@@ -386,20 +1114,21 @@ mod tests {
       ))
       .expect("Failed to enqueue operation");
 
-    thread::sleep(Duration::from_millis(
+    tokio::time::sleep(Duration::from_millis(
       WAIT_MILLIS_FOR_NEXT_UPDATE_TO_BULK * 2,
-    ));
+    ))
+    .await;
 
-    let index = text_index.lock().unwrap();
+    let index = text_index.lock();
     let results = index.search("content").expect("Failed to search index");
     assert_eq!(results.len(), 3);
     assert!(!results.iter().any(|r| r.contains("add_at_initialize1.txt")));
   }
 
-  #[test]
-  fn index_operation_should_update_index_on_file_renamed() {
+  #[tokio::test]
+  async fn index_operation_should_update_index_on_file_renamed() {
     let text_index = Arc::new(Mutex::new(TextIndex::new().unwrap()));
-    let file_filter = Arc::new(MockFileFilter);
+    let file_filter = Arc::new(MockFileFilter::default());
     let file_loader = create_initialize_file_loader();
 
     let index_operation =
@@ -416,11 +1145,12 @@ mod tests {
       })
       .expect("Failed to enqueue operation");
 
-    thread::sleep(Duration::from_millis(
+    tokio::time::sleep(Duration::from_millis(
       WAIT_MILLIS_FOR_NEXT_UPDATE_TO_BULK * 2,
-    ));
+    ))
+    .await;
 
-    let index = text_index.lock().unwrap();
+    let index = text_index.lock();
     let results = index.search("Loaded").expect("Failed to search index");
     assert_eq!(results.len(), 1);
     assert!(results[0].contains("renamed.txt"));
@@ -429,10 +1159,80 @@ mod tests {
     assert!(!results.iter().any(|r| r.contains("add_at_initialize1.txt")));
   }
 
-  #[test]
-  fn index_operation_should_update_index_on_directory_deleted() {
+  #[tokio::test]
+  async fn index_operation_should_classify_rename_of_indexed_file_as_file_rename() {
     let text_index = Arc::new(Mutex::new(TextIndex::new().unwrap()));
-    let file_filter = Arc::new(MockFileFilter);
+    let file_filter = Arc::new(MockFileFilter::default());
+    let file_loader = create_initialize_file_loader();
+
+    let index_operation =
+      IndexOperation::new(text_index.clone(), file_filter.clone(), file_loader.clone())
+        .expect("Failed to create IndexOperation");
+    index_operation
+      .initialize_index("test_dir", file_filter.clone(), file_loader.clone())
+      .expect("Failed to initialize index");
+
+    index_operation
+      .enqueue(&FileOperation::PathRenamed {
+        old_path: "add_at_initialize1.txt".to_string(),
+        new_path: "renamed.txt".to_string(),
+      })
+      .expect("Failed to enqueue operation");
+
+    tokio::time::sleep(Duration::from_millis(
+      WAIT_MILLIS_FOR_NEXT_UPDATE_TO_BULK * 2,
+    ))
+    .await;
+
+    let index = text_index.lock();
+    let results = index.search("Loaded").expect("Failed to search index");
+    assert_eq!(results.len(), 1);
+    assert!(results[0].contains("renamed.txt"));
+    assert!(!index.has_doc("add_at_initialize1.txt").unwrap());
+  }
+
+  #[tokio::test]
+  async fn index_operation_should_treat_rename_of_temporary_file_as_modification() {
+    let text_index = Arc::new(Mutex::new(TextIndex::new().unwrap()));
+    let file_filter = Arc::new(MockFileFilter::default());
+    let file_loader = create_initialize_file_loader();
+
+    let index_operation =
+      IndexOperation::new(text_index.clone(), file_filter.clone(), file_loader.clone())
+        .expect("Failed to create IndexOperation");
+    index_operation
+      .initialize_index("test_dir", file_filter.clone(), file_loader.clone())
+      .expect("Failed to initialize index");
+
+    index_operation
+      .enqueue(&FileOperation::FileCreated(
+        "add_at_initialize1.txt.swp".to_string(),
+      ))
+      .expect("Failed to enqueue operation");
+    index_operation
+      .enqueue(&FileOperation::FileRenamed {
+        old_path: "add_at_initialize1.txt.swp".to_string(),
+        new_path: "add_at_initialize1.txt".to_string(),
+      })
+      .expect("Failed to enqueue operation");
+
+    tokio::time::sleep(Duration::from_millis(
+      WAIT_MILLIS_FOR_NEXT_UPDATE_TO_BULK * 2,
+    ))
+    .await;
+
+    let index = text_index.lock();
+    let results = index.search("Loaded").expect("Failed to search index");
+    assert_eq!(results.len(), 1);
+    assert!(results[0].contains("add_at_initialize1.txt"));
+    let results = index.search("content").expect("Failed to search index");
+    assert_eq!(results.len(), 4);
+  }
+
+  #[tokio::test]
+  async fn index_operation_should_update_index_on_directory_deleted() {
+    let text_index = Arc::new(Mutex::new(TextIndex::new().unwrap()));
+    let file_filter = Arc::new(MockFileFilter::default());
     let file_loader = create_initialize_file_loader();
 
     let index_operation =
@@ -446,11 +1246,12 @@ mod tests {
       .enqueue(&FileOperation::DirectoryDeleted("/indir".to_string()))
       .expect("Failed to enqueue operation");
 
-    thread::sleep(Duration::from_millis(
+    tokio::time::sleep(Duration::from_millis(
       WAIT_MILLIS_FOR_NEXT_UPDATE_TO_BULK * 2,
-    ));
+    ))
+    .await;
 
-    let index = text_index.lock().unwrap();
+    let index = text_index.lock();
     let results = index.search("content").expect("Failed to search index");
     assert_eq!(results.len(), 2);
     assert!(
@@ -465,10 +1266,10 @@ mod tests {
     );
   }
 
-  #[test]
-  fn index_operation_should_update_index_on_directory_renamed() {
+  #[tokio::test]
+  async fn index_operation_should_update_index_on_directory_renamed() {
     let text_index = Arc::new(Mutex::new(TextIndex::new().unwrap()));
-    let file_filter = Arc::new(MockFileFilter);
+    let file_filter = Arc::new(MockFileFilter::default());
     let file_loader = create_initialize_file_loader();
 
     let file_loader_for_rename = Arc::new(MockFileLoader::new(
@@ -502,11 +1303,12 @@ mod tests {
       })
       .expect("Failed to enqueue operation");
 
-    thread::sleep(Duration::from_millis(
+    tokio::time::sleep(Duration::from_millis(
       WAIT_MILLIS_FOR_NEXT_UPDATE_TO_BULK * 2,
-    ));
+    ))
+    .await;
 
-    let index = text_index.lock().unwrap();
+    let index = text_index.lock();
     let results = index.search("content").expect("Failed to search index");
     assert_eq!(results.len(), 4);
     assert!(
@@ -0,0 +1,278 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{
+  file::File,
+  index_backend::{IndexBackend, IndexMemory, SearchHit, SearchOptions},
+};
+
+/// Metadata key holding the ISO 639-1 code of the natural language of a document, e.g. `ja`,
+/// filtered on with `lang:ja` in queries.
+pub const LANGUAGE_METADATA_KEY: &str = "lang";
+
+/// Prefix of query tokens restricting a search to documents in some languages.
+pub const LANGUAGE_FILTER_PREFIX: &str = "lang:";
+
+/// Detection only looks at the start of long documents, which is enough to be reliable.
+const MAX_DETECTED_BYTES: usize = 4096;
+
+/// ISO 639-1 code for a whatlang ISO 639-3 code, or the 639-3 code for languages without one.
+fn iso_639_1(code: &'static str) -> &'static str {
+  match code {
+    "afr" => "af",
+    "aka" => "ak",
+    "amh" => "am",
+    "ara" => "ar",
+    "aze" => "az",
+    "bel" => "be",
+    "ben" => "bn",
+    "bul" => "bg",
+    "cat" => "ca",
+    "ces" => "cs",
+    "cmn" => "zh",
+    "dan" => "da",
+    "deu" => "de",
+    "ell" => "el",
+    "eng" => "en",
+    "epo" => "eo",
+    "est" => "et",
+    "fin" => "fi",
+    "fra" => "fr",
+    "guj" => "gu",
+    "heb" => "he",
+    "hin" => "hi",
+    "hrv" => "hr",
+    "hun" => "hu",
+    "hye" => "hy",
+    "ind" => "id",
+    "ita" => "it",
+    "jav" => "jv",
+    "jpn" => "ja",
+    "kan" => "kn",
+    "kat" => "ka",
+    "khm" => "km",
+    "kor" => "ko",
+    "lat" => "la",
+    "lav" => "lv",
+    "lit" => "lt",
+    "mal" => "ml",
+    "mar" => "mr",
+    "mkd" => "mk",
+    "mya" => "my",
+    "nep" => "ne",
+    "nld" => "nl",
+    "nob" => "nb",
+    "ori" => "or",
+    "pan" => "pa",
+    "pes" => "fa",
+    "pol" => "pl",
+    "por" => "pt",
+    "ron" => "ro",
+    "rus" => "ru",
+    "sin" => "si",
+    "slk" => "sk",
+    "slv" => "sl",
+    "sna" => "sn",
+    "spa" => "es",
+    "srp" => "sr",
+    "swe" => "sv",
+    "tam" => "ta",
+    "tel" => "te",
+    "tgl" => "tl",
+    "tha" => "th",
+    "tuk" => "tk",
+    "tur" => "tr",
+    "ukr" => "uk",
+    "urd" => "ur",
+    "uzb" => "uz",
+    "vie" => "vi",
+    "yid" => "yi",
+    "zul" => "zu",
+    code => code,
+  }
+}
+
+/// Natural language of the text, or `None` when it is too short or mixed to tell reliably.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+  let mut end = text.len().min(MAX_DETECTED_BYTES);
+  while !text.is_char_boundary(end) {
+    end -= 1;
+  }
+  whatlang::detect(&text[..end])
+    .filter(|info| info.is_reliable())
+    .map(|info| iso_639_1(info.lang().code()))
+}
+
+/// Splits `lang:ja` and `lang:en,de` filters off a query, returning the rest of the query and the
+/// wanted languages. Tokens inside quoted phrases are left alone.
+pub fn split_language_filters(query: &str) -> (String, Vec<String>) {
+  let mut rest = Vec::new();
+  let mut languages = Vec::new();
+  let mut in_phrase = false;
+  for token in query.split_whitespace() {
+    match token.strip_prefix(LANGUAGE_FILTER_PREFIX) {
+      Some(codes) if !in_phrase => languages.extend(
+        codes
+          .split(',')
+          .filter(|code| !code.is_empty())
+          .map(str::to_lowercase),
+      ),
+      _ => rest.push(token),
+    }
+    if token.matches('"').count() % 2 == 1 {
+      in_phrase = !in_phrase;
+    }
+  }
+  (rest.join(" "), languages)
+}
+
+/// Stamps every document written to the wrapped backend with its detected language.
+pub struct LanguageIndexBackend<B: IndexBackend> {
+  inner: B,
+}
+
+impl<B: IndexBackend> LanguageIndexBackend<B> {
+  pub fn new(inner: B) -> Self {
+    LanguageIndexBackend { inner }
+  }
+}
+
+fn with_language(file: File) -> File {
+  match detect_language(&file.content) {
+    Some(language) => file.with_metadata(LANGUAGE_METADATA_KEY, language),
+    None => file,
+  }
+}
+
+impl<B: IndexBackend> IndexBackend for LanguageIndexBackend<B> {
+  fn add_doc(&mut self, file: &File) -> Result<()> {
+    self.inner.add_doc(&with_language(file.clone()))
+  }
+
+  fn add_docs(&mut self, files: Box<dyn Iterator<Item = File> + '_>) -> Result<usize> {
+    self.inner.add_docs(Box::new(files.map(with_language)))
+  }
+
+  fn replace_doc(&mut self, file: &File) -> Result<()> {
+    self.inner.replace_doc(&with_language(file.clone()))
+  }
+
+  fn delete_doc(&mut self, file_path: &str) -> Result<()> {
+    self.inner.delete_doc(file_path)
+  }
+
+  fn delete_docs_by_path_prefix(&mut self, path_prefix: &str) -> Result<usize> {
+    self.inner.delete_docs_by_path_prefix(path_prefix)
+  }
+
+  fn has_doc(&self, file_path: &str) -> Result<bool> {
+    self.inner.has_doc(file_path)
+  }
+
+  fn has_docs_with_path_prefix(&self, path_prefix: &str) -> Result<bool> {
+    self.inner.has_docs_with_path_prefix(path_prefix)
+  }
+
+  fn indexed_paths(&self) -> Result<BTreeSet<String>> {
+    self.inner.indexed_paths()
+  }
+
+  fn indexed_at(&self, file_path: &str) -> Result<Option<u64>> {
+    self.inner.indexed_at(file_path)
+  }
+
+  fn commit(&mut self) -> Result<()> {
+    self.inner.commit()
+  }
+
+  fn optimize(&mut self) -> Result<()> {
+    self.inner.optimize()
+  }
+
+  fn generation(&self) -> u64 {
+    self.inner.generation()
+  }
+
+  fn get_pending_operations(&self) -> usize {
+    self.inner.get_pending_operations()
+  }
+
+  fn num_docs(&self) -> u64 {
+    self.inner.num_docs()
+  }
+
+  fn memory_usage(&self) -> IndexMemory {
+    self.inner.memory_usage()
+  }
+
+  fn clear(&mut self) -> Result<()> {
+    self.inner.clear()
+  }
+
+  fn search_hits(&self, keyword: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_hits(keyword, limit)
+  }
+
+  fn search_hits_with(
+    &self,
+    keyword: &str,
+    limit: usize,
+    options: &SearchOptions,
+  ) -> Result<Vec<SearchHit>> {
+    self.inner.search_hits_with(keyword, limit, options)
+  }
+
+  fn tag_counts(&self) -> Result<BTreeMap<String, u64>> {
+    self.inner.tag_counts()
+  }
+
+  fn search_by_tag(&self, tag: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_by_tag(tag, limit)
+  }
+
+  fn search_by_link(&self, link: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_by_link(link, limit)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::search::{index_backend::SEARCH_FILE_LIMIT, text_index::TextIndex};
+
+  #[test]
+  fn language_index_backend_should_make_language_filterable() {
+    let mut index = LanguageIndexBackend::new(TextIndex::new().unwrap());
+    for (path, content) in [
+      (
+        "/notes/en.txt",
+        "The meeting about the garden project moved to Thursday afternoon, please bring the plans.",
+      ),
+      (
+        "/notes/de.txt",
+        "Das Treffen über das Gartenprojekt wurde auf Donnerstagnachmittag verschoben, bitte bringt die Pläne mit.",
+      ),
+    ] {
+      index
+        .add_doc(&File::new(path.to_string(), content.to_string()))
+        .unwrap();
+    }
+    index.commit().unwrap();
+
+    let hits = index.search_hits("lang:de", SEARCH_FILE_LIMIT).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].file_path, "/notes/de.txt");
+    assert_eq!(hits[0].metadata[LANGUAGE_METADATA_KEY], "de");
+  }
+
+  #[test]
+  fn split_language_filters_should_leave_phrases_alone() {
+    assert_eq!(
+      split_language_filters(r#"garden lang:en,DE "see lang:ja" +plans"#),
+      (
+        r#"garden "see lang:ja" +plans"#.to_string(),
+        vec!["en".to_string(), "de".to_string()]
+      )
+    );
+  }
+}
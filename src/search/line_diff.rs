@@ -0,0 +1,170 @@
+/// Unchanged lines shown around each change unless asked for another number.
+pub const DEFAULT_DIFF_CONTEXT: usize = 3;
+
+/// Largest table of the longest common subsequence computed, in cells. Beyond it the changed
+/// middle of the texts is shown as removed and added as a whole.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+  Equal,
+  Delete,
+  Insert,
+}
+
+/// Line diff of two texts in the unified format, with `context` unchanged lines around each
+/// change, or an empty string when their lines are equal.
+pub fn unified_diff(old: &str, new: &str, context: usize) -> String {
+  let old = old.lines().collect::<Vec<_>>();
+  let new = new.lines().collect::<Vec<_>>();
+  let edits = edits(&old, &new);
+
+  /* Line numbers before each edit, to number the hunks */
+  let mut positions = Vec::with_capacity(edits.len() + 1);
+  let (mut old_line, mut new_line) = (0, 0);
+  for (edit, _) in &edits {
+    positions.push((old_line, new_line));
+    match edit {
+      Edit::Equal => {
+        old_line += 1;
+        new_line += 1;
+      }
+      Edit::Delete => old_line += 1,
+      Edit::Insert => new_line += 1,
+    }
+  }
+  positions.push((old_line, new_line));
+
+  let changes = edits
+    .iter()
+    .enumerate()
+    .filter(|(_, (edit, _))| *edit != Edit::Equal)
+    .map(|(index, _)| index)
+    .collect::<Vec<_>>();
+  let mut diff = String::new();
+  let mut group_start = 0;
+  while group_start < changes.len() {
+    /* Changes closer than twice the context share a hunk */
+    let mut group_end = group_start;
+    while group_end + 1 < changes.len()
+      && changes[group_end + 1] - changes[group_end] <= 2 * context + 1
+    {
+      group_end += 1;
+    }
+    let start = changes[group_start].saturating_sub(context);
+    let end = (changes[group_end] + context + 1).min(edits.len());
+    let (old_start, new_start) = positions[start];
+    let (old_end, new_end) = positions[end];
+    diff.push_str(&format!(
+      "@@ -{} +{} @@\n",
+      hunk_range(old_start, old_end - old_start),
+      hunk_range(new_start, new_end - new_start)
+    ));
+    for (edit, line) in &edits[start..end] {
+      let prefix = match edit {
+        Edit::Equal => ' ',
+        Edit::Delete => '-',
+        Edit::Insert => '+',
+      };
+      diff.push(prefix);
+      diff.push_str(line);
+      diff.push('\n');
+    }
+    group_start = group_end + 1;
+  }
+  diff
+}
+
+/// `start,count` of a hunk, where an empty range starts at the line before it.
+fn hunk_range(start: usize, count: usize) -> String {
+  match count {
+    0 => format!("{},0", start),
+    1 => format!("{}", start + 1),
+    _ => format!("{},{}", start + 1, count),
+  }
+}
+
+/// Edits turning `old` into `new`, keeping their longest common subsequence of lines.
+fn edits<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Edit, &'a str)> {
+  let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+  let suffix = old[prefix..]
+    .iter()
+    .rev()
+    .zip(new[prefix..].iter().rev())
+    .take_while(|(a, b)| a == b)
+    .count();
+  let old_middle = &old[prefix..old.len() - suffix];
+  let new_middle = &new[prefix..new.len() - suffix];
+
+  let mut edits = old[..prefix]
+    .iter()
+    .map(|line| (Edit::Equal, *line))
+    .collect::<Vec<_>>();
+  if old_middle.len().saturating_mul(new_middle.len()) > MAX_DIFF_CELLS {
+    edits.extend(old_middle.iter().map(|line| (Edit::Delete, *line)));
+    edits.extend(new_middle.iter().map(|line| (Edit::Insert, *line)));
+  } else {
+    /* lengths[i][j] is the length of the longest common subsequence of the suffixes at i and j */
+    let width = new_middle.len() + 1;
+    let mut lengths = vec![0u32; (old_middle.len() + 1) * width];
+    for i in (0..old_middle.len()).rev() {
+      for j in (0..new_middle.len()).rev() {
+        lengths[i * width + j] = if old_middle[i] == new_middle[j] {
+          lengths[(i + 1) * width + j + 1] + 1
+        } else {
+          lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+        };
+      }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < old_middle.len() || j < new_middle.len() {
+      if i < old_middle.len() && j < new_middle.len() && old_middle[i] == new_middle[j] {
+        edits.push((Edit::Equal, old_middle[i]));
+        i += 1;
+        j += 1;
+      } else if j == new_middle.len()
+        || (i < old_middle.len() && lengths[(i + 1) * width + j] >= lengths[i * width + j + 1])
+      {
+        edits.push((Edit::Delete, old_middle[i]));
+        i += 1;
+      } else {
+        edits.push((Edit::Insert, new_middle[j]));
+        j += 1;
+      }
+    }
+  }
+  edits.extend(
+    old[old.len() - suffix..]
+      .iter()
+      .map(|line| (Edit::Equal, *line)),
+  );
+  edits
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn unified_diff_should_show_changed_lines_with_context() {
+    let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj";
+    let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk";
+
+    assert_eq!(
+      unified_diff(old, new, 1),
+      "@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n@@ -10 +10,2 @@\n j\n+k\n"
+    );
+    assert_eq!(
+      unified_diff(old, new, 10),
+      format!(
+        "@@ -1,10 +1,11 @@\n a\n-b\n+B\n{}\n+k\n",
+        "c d e f g h i j"
+          .split(' ')
+          .map(|line| format!(" {}", line))
+          .collect::<Vec<_>>()
+          .join("\n")
+      )
+    );
+    assert_eq!(unified_diff(old, old, 3), "");
+  }
+}
@@ -0,0 +1,311 @@
+use anyhow::Result;
+use std::{
+  str::FromStr,
+  sync::{
+    Arc,
+    mpsc::{RecvTimeoutError, Sender, channel},
+  },
+  thread,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::{file::FileOperation, sharded_index::ShardedIndex};
+
+/// Heavy upkeep of the indexes, run at scheduled times rather than while the user works.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum MaintenanceTask {
+  /// Re-index every watched directory from scratch
+  Rescan,
+  /// Merge the segments of every index into as few as possible
+  Merge,
+}
+
+/// Minute, hour, day of the month (1-based), month (1-based) and day of the week (0 is Sunday).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+  pub minute: u32,
+  pub hour: u32,
+  pub day: u32,
+  pub month: u32,
+  pub weekday: u32,
+}
+
+impl LocalTime {
+  /// The time in the time zone of the host, so that schedules follow the user's clock.
+  #[cfg(unix)]
+  pub fn at(time: SystemTime) -> Self {
+    let seconds = time
+      .duration_since(UNIX_EPOCH)
+      .map(|duration| duration.as_secs() as libc::time_t)
+      .unwrap_or_default();
+    // SAFETY: tm is plain data, for which all zeroes (a null time zone name) is a valid value
+    let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+    // SAFETY: localtime_r only writes to tm, and both pointers outlive the call
+    if unsafe { libc::localtime_r(&seconds, &mut tm) }.is_null() {
+      return Self::utc(time);
+    }
+    LocalTime {
+      minute: tm.tm_min as u32,
+      hour: tm.tm_hour as u32,
+      day: tm.tm_mday as u32,
+      month: tm.tm_mon as u32 + 1,
+      weekday: tm.tm_wday as u32,
+    }
+  }
+
+  #[cfg(not(unix))]
+  pub fn at(time: SystemTime) -> Self {
+    Self::utc(time)
+  }
+
+  fn utc(time: SystemTime) -> Self {
+    let seconds = time
+      .duration_since(UNIX_EPOCH)
+      .map(|duration| duration.as_secs())
+      .unwrap_or_default();
+    let days = seconds / 86_400;
+    /* Days to civil dates as in Howard Hinnant's algorithm, shifted to start years in March */
+    let shifted = days + 719_468;
+    let day_of_era = shifted % 146_097;
+    let year_of_era =
+      (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let month = if month_from_march < 10 {
+      month_from_march + 3
+    } else {
+      month_from_march - 9
+    };
+    LocalTime {
+      minute: (seconds / 60 % 60) as u32,
+      hour: (seconds / 3_600 % 24) as u32,
+      day: (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32,
+      month: month as u32,
+      /* 1970-01-01 was a Thursday */
+      weekday: ((days + 4) % 7) as u32,
+    }
+  }
+}
+
+/// Values a field of a schedule matches, one bit each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CronField {
+  values: u64,
+  /// Written as `*`, which matters for how days of the month and of the week combine
+  any: bool,
+}
+
+impl CronField {
+  fn parse(field: &str, min: u32, max: u32) -> Result<Self, String> {
+    let mut values = 0u64;
+    for item in field.split(',') {
+      let (range, step) = match item.split_once('/') {
+        Some((range, step)) => (
+          range,
+          step
+            .parse::<u32>()
+            .ok()
+            .filter(|step| *step > 0)
+            .ok_or_else(|| format!("invalid step {:?}", step))?,
+        ),
+        None => (item, 1),
+      };
+      let number = |value: &str| {
+        value
+          .parse::<u32>()
+          .ok()
+          .filter(|value| (min..=max).contains(value))
+          .ok_or_else(|| format!("expected a number from {} to {}, got {:?}", min, max, value))
+      };
+      let (first, last) = match range {
+        "*" => (min, max),
+        range => match range.split_once('-') {
+          Some((first, last)) => (number(first)?, number(last)?),
+          None if step > 1 => (number(range)?, max),
+          None => (number(range)?, number(range)?),
+        },
+      };
+      for value in (first..=last).step_by(step as usize) {
+        values |= 1 << value;
+      }
+    }
+    Ok(CronField {
+      values,
+      any: field == "*",
+    })
+  }
+
+  fn matches(&self, value: u32) -> bool {
+    self.values & (1 << value) != 0
+  }
+}
+
+/// When maintenance runs, as the five fields of a crontab line: minute, hour, day of the month,
+/// month and day of the week, e.g. `0 3 * * *` for every night at 3 AM or `30 2 * * 0` for
+/// Sundays at 2:30. Fields take `*`, numbers, ranges `1-5`, lists `1,15` and steps `*/15`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CronSchedule {
+  minute: CronField,
+  hour: CronField,
+  day: CronField,
+  month: CronField,
+  weekday: CronField,
+}
+
+impl CronSchedule {
+  pub fn matches(&self, time: &LocalTime) -> bool {
+    /* As in cron, a day matches either restricted day field when both are restricted */
+    let day = match (self.day.any, self.weekday.any) {
+      (false, false) => self.day.matches(time.day) || self.weekday.matches(time.weekday),
+      _ => self.day.matches(time.day) && self.weekday.matches(time.weekday),
+    };
+    self.minute.matches(time.minute)
+      && self.hour.matches(time.hour)
+      && self.month.matches(time.month)
+      && day
+  }
+}
+
+impl FromStr for CronSchedule {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    let fields = value.split_whitespace().collect::<Vec<_>>();
+    let [minute, hour, day, month, weekday] = fields[..] else {
+      return Err(format!(
+        "expected 5 fields (minute hour day month weekday), got {:?}",
+        value
+      ));
+    };
+    let mut weekday = CronField::parse(weekday, 0, 7)?;
+    /* Both 0 and 7 are Sunday */
+    if weekday.matches(7) {
+      weekday.values |= 1;
+    }
+    Ok(CronSchedule {
+      minute: CronField::parse(minute, 0, 59)?,
+      hour: CronField::parse(hour, 0, 23)?,
+      day: CronField::parse(day, 1, 31)?,
+      month: CronField::parse(month, 1, 12)?,
+      weekday,
+    })
+  }
+}
+
+/// Runs maintenance tasks on every shard whenever the schedule matches the local time, checked
+/// at the start of every minute.
+pub struct MaintenanceScheduler {
+  stop_tx: Option<Sender<()>>,
+  thread_handle: Option<thread::JoinHandle<()>>,
+}
+
+impl MaintenanceScheduler {
+  pub fn start(
+    schedule: CronSchedule,
+    tasks: Vec<MaintenanceTask>,
+    index: Arc<ShardedIndex>,
+  ) -> Result<Self> {
+    let (stop_tx, stop_rx) = channel::<()>();
+    let thread_handle = thread::Builder::new()
+      .name("maintenance".to_string())
+      .spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(until_next_minute()) {
+          if schedule.matches(&LocalTime::at(SystemTime::now())) {
+            run_tasks(&tasks, &index);
+          }
+        }
+      })?;
+    Ok(Self {
+      stop_tx: Some(stop_tx),
+      thread_handle: Some(thread_handle),
+    })
+  }
+
+  pub fn stop(&mut self) -> Result<()> {
+    if let Some(stop_tx) = self.stop_tx.take() {
+      let _ = stop_tx.send(());
+    }
+    if let Some(handle) = self.thread_handle.take() {
+      handle
+        .join()
+        .map_err(|_| anyhow::anyhow!("Failed to join maintenance thread"))?;
+    }
+    Ok(())
+  }
+}
+
+impl Drop for MaintenanceScheduler {
+  fn drop(&mut self) {
+    if let Err(e) = self.stop() {
+      tracing::error!("Error stopping maintenance in Drop: {}", e);
+    }
+  }
+}
+
+/// Time left until the next minute starts, plus a little so that the wake-up lands inside it.
+fn until_next_minute() -> Duration {
+  let elapsed = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default();
+  Duration::from_secs(60 - elapsed.as_secs() % 60)
+    - Duration::from_nanos(elapsed.subsec_nanos() as u64)
+    + Duration::from_millis(100)
+}
+
+fn run_tasks(tasks: &[MaintenanceTask], index: &ShardedIndex) {
+  for task in tasks {
+    tracing::info!("Running scheduled maintenance: {:?}", task);
+    let result = match task {
+      MaintenanceTask::Rescan => index.roots().and_then(|roots| {
+        for root in roots {
+          index.enqueue_operation(&FileOperation::DirectoryRescan(root))?;
+        }
+        Ok(())
+      }),
+      MaintenanceTask::Merge => index.optimize(),
+    };
+    if let Err(e) = result {
+      tracing::error!("Scheduled maintenance {:?} failed: {}", task, e);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn cron_schedule_should_match_like_crontab() {
+    let time = |minute, hour, day, month, weekday| LocalTime {
+      minute,
+      hour,
+      day,
+      month,
+      weekday,
+    };
+    let nightly = "0 3 * * *".parse::<CronSchedule>().unwrap();
+    assert!(nightly.matches(&time(0, 3, 14, 6, 2)));
+    assert!(!nightly.matches(&time(1, 3, 14, 6, 2)));
+
+    let weekends = "*/15 1-4 * * 6,7".parse::<CronSchedule>().unwrap();
+    assert!(weekends.matches(&time(45, 2, 15, 6, 0)));
+    assert!(!weekends.matches(&time(50, 2, 15, 6, 0)));
+    assert!(!weekends.matches(&time(45, 2, 16, 6, 1)));
+
+    /* Restricted days of the month and of the week each match on their own */
+    let first_or_monday = "0 0 1 * 1".parse::<CronSchedule>().unwrap();
+    assert!(first_or_monday.matches(&time(0, 0, 1, 6, 4)));
+    assert!(first_or_monday.matches(&time(0, 0, 3, 6, 1)));
+    assert!(!first_or_monday.matches(&time(0, 0, 3, 6, 2)));
+
+    assert!("0 3 * *".parse::<CronSchedule>().is_err());
+    assert!("60 3 * * *".parse::<CronSchedule>().is_err());
+    assert!("0 3 * * */0".parse::<CronSchedule>().is_err());
+
+    /* 2024-02-29 12:34 UTC was a Thursday */
+    assert_eq!(
+      LocalTime::utc(UNIX_EPOCH + Duration::from_secs(1_709_210_040)),
+      time(34, 12, 29, 2, 4)
+    );
+  }
+}
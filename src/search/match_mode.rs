@@ -0,0 +1,127 @@
+use std::{fmt, str::FromStr};
+
+/// Prefix of query tokens setting how many terms of the query a document must match.
+pub const MATCH_MODE_PREFIX: &str = "match:";
+
+/// How many of the terms of a multi-term query a document must match, given in queries as
+/// `match:all` or `match:2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchMode {
+  /// Any term, ranking documents matching more terms higher
+  #[default]
+  Any,
+  All,
+  AtLeast(usize),
+}
+
+impl MatchMode {
+  /// Clauses a document must match out of `clauses` optional ones.
+  pub fn required_clauses(self, clauses: usize) -> usize {
+    match self {
+      MatchMode::Any => clauses.min(1),
+      MatchMode::All => clauses,
+      MatchMode::AtLeast(required) => required.min(clauses),
+    }
+  }
+}
+
+impl FromStr for MatchMode {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value.trim().to_lowercase().as_str() {
+      "any" => Ok(MatchMode::Any),
+      "all" => Ok(MatchMode::All),
+      number => match number.parse::<usize>() {
+        Ok(required) if required > 0 => Ok(MatchMode::AtLeast(required)),
+        _ => Err(format!(
+          "expected any, all or a number of terms, got {}",
+          value
+        )),
+      },
+    }
+  }
+}
+
+impl fmt::Display for MatchMode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      MatchMode::Any => write!(f, "any"),
+      MatchMode::All => write!(f, "all"),
+      MatchMode::AtLeast(required) => write!(f, "{}", required),
+    }
+  }
+}
+
+/// Adds the mode to a query as a `match:` token, leaving queries matching any term unchanged.
+pub fn with_match_mode(query: &str, match_mode: MatchMode) -> String {
+  match match_mode {
+    MatchMode::Any => query.to_string(),
+    match_mode => format!("{} {}{}", query, MATCH_MODE_PREFIX, match_mode),
+  }
+}
+
+/// Splits a `match:` token off a query, returning the rest of the query and the mode, the last
+/// one if several are given. Tokens inside quoted phrases and invalid modes are left alone.
+pub fn split_match_mode(query: &str) -> (String, MatchMode) {
+  let mut rest = Vec::new();
+  let mut match_mode = MatchMode::Any;
+  let mut in_phrase = false;
+  for token in query.split_whitespace() {
+    match token
+      .strip_prefix(MATCH_MODE_PREFIX)
+      .filter(|_| !in_phrase)
+      .and_then(|mode| mode.parse().ok())
+    {
+      Some(mode) => match_mode = mode,
+      None => rest.push(token),
+    }
+    if token.matches('"').count() % 2 == 1 {
+      in_phrase = !in_phrase;
+    }
+  }
+  (rest.join(" "), match_mode)
+}
+
+/// Splits a query into its words and quoted phrases, each phrase kept as one token.
+pub fn query_tokens(query: &str) -> Vec<String> {
+  let mut tokens: Vec<String> = Vec::new();
+  let mut in_phrase = false;
+  for token in query.split_whitespace() {
+    match tokens.last_mut() {
+      Some(phrase) if in_phrase => {
+        phrase.push(' ');
+        phrase.push_str(token);
+      }
+      _ => tokens.push(token.to_string()),
+    }
+    if token.matches('"').count() % 2 == 1 {
+      in_phrase = !in_phrase;
+    }
+  }
+  tokens
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn split_match_mode_should_read_mode_outside_phrases() {
+    assert_eq!(
+      split_match_mode(r#"kubernetes "see match:all" ingress match:2"#),
+      (
+        r#"kubernetes "see match:all" ingress"#.to_string(),
+        MatchMode::AtLeast(2)
+      )
+    );
+    assert_eq!(
+      split_match_mode(&with_match_mode("ingress tls", MatchMode::All)),
+      ("ingress tls".to_string(), MatchMode::All)
+    );
+    assert_eq!(
+      query_tokens(r#"+ingress "two words" tls"#),
+      vec!["+ingress", "\"two words\"", "tls"]
+    );
+  }
+}
@@ -0,0 +1,79 @@
+use super::{
+  index_backend::SEARCH_FILE_LIMIT, index_operation::MAX_BULK_OPERATION_SIZE,
+  search_cache::DEFAULT_SEARCH_CACHE_CAPACITY, text_index::MIN_WRITER_HEAP,
+};
+
+/// Size assumed of a loaded file when no `--max-file-size` bounds it.
+const ASSUMED_FILE_BYTES: u64 = 1024 * 1024;
+/// Size assumed of one cached search, `SEARCH_FILE_LIMIT` hits with snippets and metadata.
+const ASSUMED_CACHED_SEARCH_BYTES: u64 = SEARCH_FILE_LIMIT as u64 * 2 * 1024;
+
+/// How `--max-memory-mb` is shared out: the index writers get half, the indexes kept in memory
+/// a quarter, and the files loaded by queued operations and the search cache an eighth each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct MemoryBudget {
+  pub total_bytes: u64,
+  /// Heap of the index writer of each shard, never below what tantivy accepts
+  pub writer_heap_bytes: u64,
+  /// Operations handled in one batch, each possibly holding a whole loaded file
+  pub batch_operations: usize,
+  pub search_cache_entries: usize,
+  /// What the indexes kept in memory (without `--index-dir`) may hold together. They cannot give
+  /// memory back, so going over is only reported
+  pub in_memory_index_bytes: u64,
+}
+
+impl MemoryBudget {
+  pub fn new(total_bytes: u64, shards: usize, max_file_size: Option<u64>) -> Self {
+    let writer_heap_bytes = (total_bytes / 2 / shards.max(1) as u64).max(MIN_WRITER_HEAP as u64);
+    let file_bytes = max_file_size.unwrap_or(ASSUMED_FILE_BYTES).max(1);
+    MemoryBudget {
+      total_bytes,
+      writer_heap_bytes,
+      batch_operations: ((total_bytes / 8 / file_bytes) as usize).clamp(1, MAX_BULK_OPERATION_SIZE),
+      search_cache_entries: ((total_bytes / 8 / ASSUMED_CACHED_SEARCH_BYTES) as usize)
+        .min(DEFAULT_SEARCH_CACHE_CAPACITY),
+      in_memory_index_bytes: total_bytes / 4,
+    }
+  }
+
+  /// Megabytes as given on the command line.
+  pub fn from_megabytes(megabytes: u64, shards: usize, max_file_size: Option<u64>) -> Self {
+    Self::new(megabytes * 1024 * 1024, shards, max_file_size)
+  }
+}
+
+/// Memory the subsystems are given or hold, reported by `get_stats`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct MemoryUsage {
+  /// `None` without `--max-memory-mb`
+  pub budget: Option<MemoryBudget>,
+  /// Heaps of the index writers of every shard together
+  pub writer_heap_bytes: u64,
+  pub search_cache_entries: usize,
+  pub search_cache_bytes: u64,
+  /// Size of the indexes kept in memory
+  pub in_memory_index_bytes: u64,
+  /// Set when the indexes kept in memory outgrew their share of the budget
+  pub over_budget: bool,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn memory_budget_should_split_between_subsystems() {
+    let budget = MemoryBudget::from_megabytes(512, 4, Some(4 * 1024 * 1024));
+    assert_eq!(budget.writer_heap_bytes, 64 * 1024 * 1024);
+    assert_eq!(budget.batch_operations, 16);
+    assert_eq!(budget.search_cache_entries, DEFAULT_SEARCH_CACHE_CAPACITY);
+    assert_eq!(budget.in_memory_index_bytes, 128 * 1024 * 1024);
+
+    /* Small machines still get writers tantivy accepts and batches of at least one file */
+    let small = MemoryBudget::from_megabytes(8, 8, Some(64 * 1024 * 1024));
+    assert_eq!(small.writer_heap_bytes, MIN_WRITER_HEAP as u64);
+    assert_eq!(small.batch_operations, 1);
+    assert!(small.search_cache_entries < DEFAULT_SEARCH_CACHE_CAPACITY);
+  }
+}
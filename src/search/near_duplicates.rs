@@ -0,0 +1,155 @@
+use std::{
+  collections::{BTreeSet, HashMap},
+  hash::{DefaultHasher, Hash, Hasher},
+};
+
+/// Similarity above which two documents are reported unless asked for another one.
+pub const DEFAULT_SIMILARITY_THRESHOLD: f32 = 0.8;
+
+/// Most files compared by one call, so that a huge corpus cannot stall the server.
+pub const NEAR_DUPLICATE_FILE_LIMIT: usize = 10_000;
+
+/// Most pairs returned unless asked for another number.
+pub const NEAR_DUPLICATE_PAIR_LIMIT: usize = 50;
+
+/// Words per shingle: long enough that unrelated documents rarely share one.
+const SHINGLE_WORDS: usize = 5;
+
+/// Hash functions of a MinHash signature, split into bands of `ROWS_PER_BAND` for bucketing.
+const SIGNATURE_HASHES: usize = 128;
+const ROWS_PER_BAND: usize = 4;
+
+/// Two documents whose estimated share of common shingles is at least the threshold.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct NearDuplicate {
+  pub file_paths: [String; 2],
+  /// Estimated Jaccard similarity of their shingles, 1.0 for identical text
+  pub similarity: f32,
+}
+
+/// Finds near-duplicate documents by comparing MinHash signatures of their word shingles.
+/// Documents are only compared when a band of their signatures is equal, so that the cost grows
+/// with the number of documents rather than the number of pairs.
+pub struct NearDuplicates {
+  threshold: f32,
+  signatures: Vec<(String, Vec<u64>)>,
+}
+
+impl NearDuplicates {
+  pub fn new(threshold: f32) -> Self {
+    NearDuplicates {
+      threshold,
+      signatures: Vec::new(),
+    }
+  }
+
+  /// Adds a document, ignoring those without words.
+  pub fn add(&mut self, file_path: &str, content: &str) {
+    if let Some(signature) = signature(content) {
+      self.signatures.push((file_path.to_string(), signature));
+    }
+  }
+
+  /// Pairs at least as similar as the threshold, most similar first.
+  pub fn pairs(&self) -> Vec<NearDuplicate> {
+    let mut buckets: HashMap<(usize, &[u64]), Vec<usize>> = HashMap::new();
+    for (document, (_, signature)) in self.signatures.iter().enumerate() {
+      for (band, rows) in signature.chunks(ROWS_PER_BAND).enumerate() {
+        buckets.entry((band, rows)).or_default().push(document);
+      }
+    }
+    let candidates = buckets
+      .values()
+      .flat_map(|documents| {
+        documents.iter().enumerate().flat_map(|(position, &first)| {
+          documents[position + 1..]
+            .iter()
+            .map(move |&second| (first, second))
+        })
+      })
+      .collect::<BTreeSet<_>>();
+    let mut pairs = candidates
+      .into_iter()
+      .filter_map(|(first, second)| {
+        let (first_path, first_signature) = &self.signatures[first];
+        let (second_path, second_signature) = &self.signatures[second];
+        let similarity = similarity(first_signature, second_signature);
+        (similarity >= self.threshold).then(|| NearDuplicate {
+          file_paths: [first_path.clone(), second_path.clone()],
+          similarity,
+        })
+      })
+      .collect::<Vec<_>>();
+    pairs.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    pairs
+  }
+}
+
+/// MinHash signature of the word shingles of `content`, ignoring case and punctuation.
+fn signature(content: &str) -> Option<Vec<u64>> {
+  let words = content
+    .split(|c: char| !c.is_alphanumeric())
+    .filter(|word| !word.is_empty())
+    .map(str::to_lowercase)
+    .collect::<Vec<_>>();
+  if words.is_empty() {
+    return None;
+  }
+  let mut signature = vec![u64::MAX; SIGNATURE_HASHES];
+  /* Documents shorter than a shingle are one shingle of all their words */
+  for shingle in words.windows(SHINGLE_WORDS.min(words.len())) {
+    let mut hasher = DefaultHasher::new();
+    shingle.hash(&mut hasher);
+    let shingle_hash = hasher.finish();
+    for (seed, min_hash) in signature.iter_mut().enumerate() {
+      *min_hash = (*min_hash).min(mix(shingle_hash ^ seed as u64));
+    }
+  }
+  Some(signature)
+}
+
+/// Share of equal hashes, an estimate of the Jaccard similarity of the shingles.
+fn similarity(first: &[u64], second: &[u64]) -> f32 {
+  let equal = first.iter().zip(second).filter(|(a, b)| a == b).count();
+  equal as f32 / SIGNATURE_HASHES as f32
+}
+
+/// splitmix64, deriving one hash function per seed from a single shingle hash.
+fn mix(value: u64) -> u64 {
+  let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+  z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+  z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn near_duplicates_should_pair_only_similar_documents() {
+    let original = "Plant the tomatoes in early May once the soil is warm, water them every \
+      morning, stake them when they reach a foot tall and pinch off the suckers every week so \
+      the plants put their energy into fruit rather than leaves.";
+    let variant = original.replace("every week", "every few days");
+    let unrelated = "The quarterly report is due on Friday; collect the sales numbers from each \
+      region, reconcile them with finance and send the draft to the board for review.";
+
+    let mut near_duplicates = NearDuplicates::new(0.5);
+    near_duplicates.add("/notes/tomatoes.md", original);
+    near_duplicates.add("/notes/tomatoes copy.md", &variant);
+    near_duplicates.add("/notes/report.md", unrelated);
+    near_duplicates.add("/notes/empty.md", " - ");
+
+    let pairs = near_duplicates.pairs();
+    assert_eq!(pairs.len(), 1);
+    assert_eq!(
+      pairs[0].file_paths,
+      [
+        "/notes/tomatoes.md".to_string(),
+        "/notes/tomatoes copy.md".to_string()
+      ]
+    );
+    assert!(pairs[0].similarity >= 0.5 && pairs[0].similarity < 1.0);
+  }
+}
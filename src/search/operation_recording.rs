@@ -0,0 +1,142 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::{
+  fs::{File, OpenOptions},
+  io::Write,
+  path::Path,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::file::FileOperation;
+
+/// A file operation as it was handed to the index update worker.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RecordedOperation {
+  #[serde(default)]
+  pub timestamp_ms: u128,
+  /// Queued ahead of the others, e.g. to refresh a path a search looked at
+  #[serde(default)]
+  pub prioritized: bool,
+  pub operation: FileOperation,
+}
+
+/// Appends every queued file operation as a JSON line to a file, which `replay` applies again to
+/// reproduce watcher and indexing issues.
+pub struct OperationRecorder {
+  file: Mutex<File>,
+}
+
+impl OperationRecorder {
+  pub fn open(path: &Path) -> Result<Self> {
+    let file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(path)
+      .map_err(|e| anyhow::anyhow!("Cannot open event recording {:?}: {}", path, e))?;
+    Ok(OperationRecorder {
+      file: Mutex::new(file),
+    })
+  }
+
+  pub fn record(&self, operation: &FileOperation, prioritized: bool) -> Result<()> {
+    let recorded = RecordedOperation {
+      timestamp_ms: SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default(),
+      prioritized,
+      operation: operation.clone(),
+    };
+    let mut line = serde_json::to_string(&recorded)?;
+    line.push('\n');
+    /* One write per line keeps lines whole when several watchers queue at once */
+    self.file.lock().write_all(line.as_bytes())?;
+    Ok(())
+  }
+}
+
+/// Reads the operations of a recording, either JSON lines as recorded or a JSON array written by
+/// hand, in the order they were queued.
+pub fn read_recorded_operations(path: &Path) -> Result<Vec<FileOperation>> {
+  let content = std::fs::read_to_string(path)
+    .map_err(|e| anyhow::anyhow!("Cannot read event recording {:?}: {}", path, e))?;
+  let recorded = if content.trim_start().starts_with('[') {
+    serde_json::from_str::<Vec<RecordedOperation>>(&content)?
+  } else {
+    content
+      .lines()
+      .enumerate()
+      .filter(|(_, line)| !line.trim().is_empty())
+      .map(|(number, line)| {
+        serde_json::from_str::<RecordedOperation>(line)
+          .map_err(|e| anyhow::anyhow!("Invalid operation on line {}: {}", number + 1, e))
+      })
+      .collect::<Result<Vec<_>>>()?
+  };
+  Ok(
+    recorded
+      .into_iter()
+      .map(|recorded| recorded.operation)
+      .collect(),
+  )
+}
+
+/// Outcome of a replay, with the results of the queries run against the index afterwards.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ReplayReport {
+  pub applied: usize,
+  /// Operations on paths outside every watched directory
+  pub skipped: usize,
+  pub results: Vec<ReplayResults>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ReplayResults {
+  pub query: String,
+  pub file_paths: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn operation_recorder_should_write_operations_read_back_in_order() {
+    let path = std::env::temp_dir().join(format!("recording-test-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let recorder = OperationRecorder::open(&path).unwrap();
+    recorder
+      .record(
+        &FileOperation::FileCreated("/notes/a.md".to_string()),
+        false,
+      )
+      .unwrap();
+    recorder
+      .record(
+        &FileOperation::FileRenamed {
+          old_path: "/notes/a.md".to_string(),
+          new_path: "/notes/b.md".to_string(),
+        },
+        true,
+      )
+      .unwrap();
+
+    let operations = read_recorded_operations(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+      format!("{:?}", operations),
+      r#"[FileCreated("/notes/a.md"), FileRenamed { old_path: "/notes/a.md", new_path: "/notes/b.md" }]"#
+    );
+
+    /* Hand-written reports need neither timestamps nor one operation per line */
+    std::fs::write(
+      &path,
+      r#"[{"operation": {"file_deleted": "/notes/b.md"}}, {"operation": {"directory_rescan": "/notes"}}]"#,
+    )
+    .unwrap();
+    let operations = read_recorded_operations(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(operations.len(), 2);
+    assert!(matches!(&operations[1], FileOperation::DirectoryRescan(path) if path == "/notes"));
+  }
+}
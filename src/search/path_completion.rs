@@ -0,0 +1,88 @@
+use std::{collections::BTreeSet, path::MAIN_SEPARATOR};
+
+/// Most completions returned at once, as the MCP specification allows.
+pub const MAX_COMPLETIONS: usize = 100;
+
+/// Completions of a typed path, in path order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathCompletions {
+  pub values: Vec<String>,
+  /// Completions there are in all, more than `values` holds when it was cut short
+  pub total: usize,
+}
+
+/// Completes `prefix` one directory level at a time from the indexed file paths: a directory
+/// below the typed part is suggested with a trailing separator, a file by its whole path. A prefix
+/// of a watched directory completes to that directory, so that an empty one lists the roots.
+pub fn complete_path(
+  file_paths: &BTreeSet<String>,
+  roots: &[String],
+  prefix: &str,
+  limit: usize,
+) -> PathCompletions {
+  let mut candidates = roots
+    .iter()
+    .filter(|root| root.len() > prefix.len() && root.starts_with(prefix))
+    .map(|root| {
+      format!(
+        "{}{}",
+        root.trim_end_matches(MAIN_SEPARATOR),
+        MAIN_SEPARATOR
+      )
+    })
+    .collect::<BTreeSet<_>>();
+  if candidates.is_empty() {
+    candidates = file_paths
+      .range(prefix.to_string()..)
+      .take_while(|path| path.starts_with(prefix))
+      .map(|path| match path[prefix.len()..].find(MAIN_SEPARATOR) {
+        Some(separator) => path[..prefix.len() + separator + 1].to_string(),
+        None => path.clone(),
+      })
+      .collect();
+  }
+  PathCompletions {
+    total: candidates.len(),
+    values: candidates.into_iter().take(limit).collect(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn complete_path_should_suggest_the_next_level() {
+    let file_paths = [
+      "/notes/plan.md",
+      "/notes/projects/alpha.md",
+      "/notes/projects/beta.md",
+      "/notes/projects-archive.md",
+    ]
+    .into_iter()
+    .map(str::to_string)
+    .collect::<BTreeSet<_>>();
+    let roots = ["/notes".to_string(), "/src".to_string()];
+
+    assert_eq!(
+      complete_path(&file_paths, &roots, "", MAX_COMPLETIONS).values,
+      vec!["/notes/", "/src/"]
+    );
+    assert_eq!(
+      complete_path(&file_paths, &roots, "/notes/pro", MAX_COMPLETIONS).values,
+      vec!["/notes/projects-archive.md", "/notes/projects/"]
+    );
+    assert_eq!(
+      complete_path(&file_paths, &roots, "/notes/projects/", 1),
+      PathCompletions {
+        values: vec!["/notes/projects/alpha.md".to_string()],
+        total: 2,
+      }
+    );
+    assert!(
+      complete_path(&file_paths, &roots, "/elsewhere", MAX_COMPLETIONS)
+        .values
+        .is_empty()
+    );
+  }
+}
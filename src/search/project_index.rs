@@ -0,0 +1,167 @@
+use anyhow::Result;
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  path::Path,
+};
+
+use super::{
+  file::File,
+  index_backend::{IndexBackend, IndexMemory, SearchHit, SearchOptions},
+};
+
+/// Metadata key naming the project a document belongs to, searchable as `metadata.project:name`.
+pub const PROJECT_METADATA_KEY: &str = "project";
+
+/// Project of a watch root that was not given a name: the last component of its path.
+pub fn default_project_name(root: &str) -> String {
+  Path::new(root)
+    .file_name()
+    .map(|name| name.to_string_lossy().to_string())
+    .unwrap_or_else(|| root.to_string())
+}
+
+/// Stamps every document written to the wrapped backend with the project of its watch root.
+pub struct ProjectIndexBackend<B: IndexBackend> {
+  inner: B,
+  project: String,
+}
+
+impl<B: IndexBackend> ProjectIndexBackend<B> {
+  pub fn new(inner: B, project: &str) -> Self {
+    ProjectIndexBackend {
+      inner,
+      project: project.to_string(),
+    }
+  }
+
+  fn with_project(&self, file: &File) -> File {
+    file
+      .clone()
+      .with_metadata(PROJECT_METADATA_KEY, &self.project)
+  }
+}
+
+impl<B: IndexBackend> IndexBackend for ProjectIndexBackend<B> {
+  fn add_doc(&mut self, file: &File) -> Result<()> {
+    let file = self.with_project(file);
+    self.inner.add_doc(&file)
+  }
+
+  fn add_docs(&mut self, files: Box<dyn Iterator<Item = File> + '_>) -> Result<usize> {
+    let project = self.project.clone();
+    let files = files.map(move |file| file.with_metadata(PROJECT_METADATA_KEY, &project));
+    self.inner.add_docs(Box::new(files))
+  }
+
+  fn replace_doc(&mut self, file: &File) -> Result<()> {
+    let file = self.with_project(file);
+    self.inner.replace_doc(&file)
+  }
+
+  fn delete_doc(&mut self, file_path: &str) -> Result<()> {
+    self.inner.delete_doc(file_path)
+  }
+
+  fn delete_docs_by_path_prefix(&mut self, path_prefix: &str) -> Result<usize> {
+    self.inner.delete_docs_by_path_prefix(path_prefix)
+  }
+
+  fn has_doc(&self, file_path: &str) -> Result<bool> {
+    self.inner.has_doc(file_path)
+  }
+
+  fn has_docs_with_path_prefix(&self, path_prefix: &str) -> Result<bool> {
+    self.inner.has_docs_with_path_prefix(path_prefix)
+  }
+
+  fn indexed_paths(&self) -> Result<BTreeSet<String>> {
+    self.inner.indexed_paths()
+  }
+
+  fn indexed_at(&self, file_path: &str) -> Result<Option<u64>> {
+    self.inner.indexed_at(file_path)
+  }
+
+  fn commit(&mut self) -> Result<()> {
+    self.inner.commit()
+  }
+
+  fn optimize(&mut self) -> Result<()> {
+    self.inner.optimize()
+  }
+
+  fn generation(&self) -> u64 {
+    self.inner.generation()
+  }
+
+  fn get_pending_operations(&self) -> usize {
+    self.inner.get_pending_operations()
+  }
+
+  fn num_docs(&self) -> u64 {
+    self.inner.num_docs()
+  }
+
+  fn memory_usage(&self) -> IndexMemory {
+    self.inner.memory_usage()
+  }
+
+  fn clear(&mut self) -> Result<()> {
+    self.inner.clear()
+  }
+
+  fn search_hits(&self, keyword: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_hits(keyword, limit)
+  }
+
+  fn search_hits_with(
+    &self,
+    keyword: &str,
+    limit: usize,
+    options: &SearchOptions,
+  ) -> Result<Vec<SearchHit>> {
+    self.inner.search_hits_with(keyword, limit, options)
+  }
+
+  fn tag_counts(&self) -> Result<BTreeMap<String, u64>> {
+    self.inner.tag_counts()
+  }
+
+  fn search_by_tag(&self, tag: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_by_tag(tag, limit)
+  }
+
+  fn search_by_link(&self, link: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_by_link(link, limit)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::search::{index_backend::SEARCH_FILE_LIMIT, text_index::TextIndex};
+
+  #[test]
+  fn project_index_backend_should_make_project_searchable() {
+    let mut index = ProjectIndexBackend::new(TextIndex::new().unwrap(), "notes");
+    index
+      .add_doc(&File::new(
+        "/notes/a.txt".to_string(),
+        "keyword".to_string(),
+      ))
+      .unwrap();
+    index.commit().unwrap();
+
+    let hits = index
+      .search_hits("metadata.project:notes", SEARCH_FILE_LIMIT)
+      .unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].metadata.get(PROJECT_METADATA_KEY).unwrap(), "notes");
+  }
+
+  #[test]
+  fn default_project_name_should_be_last_path_component() {
+    assert_eq!(default_project_name("/home/user/notes"), "notes");
+    assert_eq!(default_project_name("/"), "/");
+  }
+}
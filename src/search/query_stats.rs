@@ -0,0 +1,174 @@
+use parking_lot::Mutex;
+use std::{
+  collections::VecDeque,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::language_index::split_language_filters;
+
+pub const DEFAULT_SLOW_QUERY_THRESHOLD: Duration = Duration::from_millis(500);
+const RECENT_QUERY_CAPACITY: usize = 1000;
+const SLOWEST_QUERY_COUNT: usize = 10;
+
+/// A search as it was run, with how long it took and how many results it found.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct QueryRecord {
+  pub timestamp_ms: u128,
+  /// Tool that ran the search, e.g. `search_index`
+  pub tool: String,
+  pub query: String,
+  /// Query terms once `lang:` filters are split off
+  pub terms: String,
+  pub languages: Vec<String>,
+  /// Restrictions of the search such as its mode, project, roots or path
+  pub scope: String,
+  pub latency_ms: f64,
+  pub results: usize,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct QueryStatsSummary {
+  pub queries: u64,
+  pub slow_queries: u64,
+  pub queries_without_results: u64,
+  pub slow_query_threshold_ms: u128,
+  /// Latency percentiles over the latest queries
+  pub p50_ms: f64,
+  pub p95_ms: f64,
+  pub max_ms: f64,
+  /// Slowest of the latest queries, slowest first
+  pub slowest: Vec<QueryRecord>,
+}
+
+#[derive(Default)]
+struct QueryLog {
+  queries: u64,
+  slow_queries: u64,
+  queries_without_results: u64,
+  recent: VecDeque<QueryRecord>,
+}
+
+/// Latency of the searches run by the server, logging slow ones, so that queries degrading an
+/// agent loop can be found with `get_stats`.
+pub struct QueryStats {
+  slow_query_threshold: Duration,
+  log: Mutex<QueryLog>,
+}
+
+impl QueryStats {
+  pub fn new(slow_query_threshold: Duration) -> Self {
+    Self {
+      slow_query_threshold,
+      log: Mutex::new(QueryLog::default()),
+    }
+  }
+
+  pub fn record(&self, tool: &str, query: &str, scope: String, latency: Duration, results: usize) {
+    let (terms, languages) = split_language_filters(query);
+    let record = QueryRecord {
+      timestamp_ms: SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default(),
+      tool: tool.to_string(),
+      query: query.to_string(),
+      terms,
+      languages,
+      scope,
+      latency_ms: latency.as_secs_f64() * 1000.0,
+      results,
+    };
+    let slow = latency >= self.slow_query_threshold;
+    if slow {
+      tracing::warn!(
+        "Slow query: {} {:?} ({}) took {:?} for {} results",
+        tool,
+        query,
+        record.scope,
+        latency,
+        results
+      );
+    } else {
+      tracing::debug!(
+        "{} {:?} took {:?} for {} results",
+        tool,
+        query,
+        latency,
+        results
+      );
+    }
+
+    let mut log = self.log.lock();
+    log.queries += 1;
+    log.slow_queries += u64::from(slow);
+    log.queries_without_results += u64::from(results == 0);
+    if log.recent.len() >= RECENT_QUERY_CAPACITY {
+      log.recent.pop_front();
+    }
+    log.recent.push_back(record);
+  }
+
+  pub fn summary(&self) -> QueryStatsSummary {
+    let log = self.log.lock();
+    let mut latencies = log
+      .recent
+      .iter()
+      .map(|record| record.latency_ms)
+      .collect::<Vec<_>>();
+    latencies.sort_by(f64::total_cmp);
+    let percentile = |p: usize| {
+      latencies
+        .get((latencies.len() * p / 100).min(latencies.len().saturating_sub(1)))
+        .copied()
+        .unwrap_or_default()
+    };
+    let mut slowest = log.recent.iter().cloned().collect::<Vec<_>>();
+    slowest.sort_by(|a, b| b.latency_ms.total_cmp(&a.latency_ms));
+    slowest.truncate(SLOWEST_QUERY_COUNT);
+    QueryStatsSummary {
+      queries: log.queries,
+      slow_queries: log.slow_queries,
+      queries_without_results: log.queries_without_results,
+      slow_query_threshold_ms: self.slow_query_threshold.as_millis(),
+      p50_ms: percentile(50),
+      p95_ms: percentile(95),
+      max_ms: latencies.last().copied().unwrap_or_default(),
+      slowest,
+    }
+  }
+}
+
+impl Default for QueryStats {
+  fn default() -> Self {
+    Self::new(DEFAULT_SLOW_QUERY_THRESHOLD)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn query_stats_should_count_slow_queries_and_list_slowest_first() {
+    let stats = QueryStats::new(Duration::from_millis(100));
+    for (query, millis, results) in [("fast", 5, 3), ("slow lang:de", 300, 0), ("medium", 50, 1)] {
+      stats.record(
+        "search_index",
+        query,
+        String::new(),
+        Duration::from_millis(millis),
+        results,
+      );
+    }
+
+    let summary = stats.summary();
+    assert_eq!(summary.queries, 3);
+    assert_eq!(summary.slow_queries, 1);
+    assert_eq!(summary.queries_without_results, 1);
+    assert_eq!(summary.max_ms, 300.0);
+    assert_eq!(summary.p50_ms, 50.0);
+    assert_eq!(summary.slowest[0].terms, "slow");
+    assert_eq!(summary.slowest[0].languages, vec!["de".to_string()]);
+    assert_eq!(summary.slowest[2].query, "fast");
+  }
+}
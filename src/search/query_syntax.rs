@@ -0,0 +1,273 @@
+use tantivy::schema::FieldType;
+
+use super::{
+  extra_fields::ExtraField,
+  file::{LINKS_METADATA_KEY, TAGS_METADATA_KEY},
+  git_metadata::{
+    AUTHOR_FILTER_PREFIX, BRANCH_FILTER_PREFIX, GIT_AUTHOR_METADATA_KEY, GIT_BRANCH_METADATA_KEY,
+    GIT_COMMIT_METADATA_KEY, GIT_DATE_METADATA_KEY, GIT_WORKTREE_METADATA_KEY,
+  },
+  language_index::{LANGUAGE_FILTER_PREFIX, LANGUAGE_METADATA_KEY},
+  match_mode::MATCH_MODE_PREFIX,
+  project_index::PROJECT_METADATA_KEY,
+  text_index::build_schema,
+};
+
+/// A piece of query syntax, with what it does and a query using it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct SyntaxElement {
+  pub syntax: String,
+  pub description: String,
+  pub example: String,
+}
+
+impl SyntaxElement {
+  pub fn new(syntax: &str, description: &str, example: &str) -> Self {
+    SyntaxElement {
+      syntax: syntax.to_string(),
+      description: description.to_string(),
+      example: example.to_string(),
+    }
+  }
+}
+
+/// A field of the index a query can name as `field:value`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct QueryField {
+  pub name: String,
+  /// How values are matched, as configured in the schema
+  pub matching: String,
+  pub example: String,
+}
+
+/// A metadata key documents may carry, searchable as `metadata.<key>:value`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct MetadataKey {
+  pub key: String,
+  pub description: String,
+}
+
+/// The query grammar `search_index` accepts with the configured backend, filters and metadata,
+/// returned by the query_syntax tool so that agents write valid queries.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct QuerySyntax {
+  /// Field searched by words without a field
+  pub default_field: String,
+  pub fields: Vec<QueryField>,
+  pub operators: Vec<SyntaxElement>,
+  /// Tokens taken out of the query before it is parsed, which restrict or tune the search
+  pub filters: Vec<SyntaxElement>,
+  pub metadata_keys: Vec<MetadataKey>,
+}
+
+impl Default for QuerySyntax {
+  fn default() -> Self {
+    Self::tantivy()
+  }
+}
+
+impl QuerySyntax {
+  /// The syntax of the Tantivy query parser, with the fields of the index schema.
+  pub fn tantivy() -> Self {
+    let fields = build_schema()
+      .fields()
+      .filter(|(_, entry)| entry.is_indexed())
+      .map(|(_, entry)| {
+        let name = entry.name();
+        let (matching, example) = match entry.field_type() {
+          FieldType::Str(options) => match options.get_indexing_options() {
+            Some(indexing) if indexing.tokenizer() == "raw" => (
+              "the whole value exactly, quoted when it has spaces or punctuation".to_string(),
+              format!("{}:\"/notes/plan.md\"", name),
+            ),
+            Some(indexing) => (
+              format!(
+                "words, split and lowercased by the {} tokenizer",
+                indexing.tokenizer()
+              ),
+              format!("{}:deploy", name),
+            ),
+            None => ("nothing".to_string(), String::new()),
+          },
+          FieldType::JsonObject(_) => (
+            format!("words of the value of a key, named as {}.<key>", name),
+            format!("{}.{}:draft", name, TAGS_METADATA_KEY),
+          ),
+          FieldType::Facet(_) => (
+            "a path and everything below it".to_string(),
+            format!("{}:/project", name),
+          ),
+          _ => ("the value exactly".to_string(), format!("{}:1", name)),
+        };
+        QueryField {
+          name: name.to_string(),
+          matching,
+          example,
+        }
+      })
+      .collect();
+    QuerySyntax {
+      default_field: "content".to_string(),
+      fields,
+      operators: vec![
+        SyntaxElement::new(
+          "word word",
+          "Documents containing any of the words, those with more of them ranking higher",
+          "deploy rollback",
+        ),
+        SyntaxElement::new(
+          "\"a phrase\"",
+          "The words next to each other",
+          "\"release notes\"",
+        ),
+        SyntaxElement::new(
+          "\"a phrase\"~N",
+          "The words at most N positions apart",
+          "\"release notes\"~2",
+        ),
+        SyntaxElement::new("+word", "The word must be present", "+deploy rollback"),
+        SyntaxElement::new("-word", "The word must be absent", "deploy -staging"),
+        SyntaxElement::new("a AND b", "Both must match", "deploy AND rollback"),
+        SyntaxElement::new("a OR b", "Either may match", "deploy OR release"),
+        SyntaxElement::new("( )", "Groups clauses", "(deploy OR release) AND rollback"),
+        SyntaxElement::new(
+          "word^N",
+          "Multiplies the score of a clause by N",
+          "deploy^2 rollback",
+        ),
+      ],
+      filters: common_filters(),
+      metadata_keys: builtin_metadata_keys(),
+    }
+  }
+
+  /// The subset of the syntax the SQLite backend translates into FTS5 queries.
+  #[cfg(feature = "sqlite")]
+  pub fn sqlite() -> Self {
+    QuerySyntax {
+      default_field: "content".to_string(),
+      fields: vec![QueryField {
+        name: "metadata".to_string(),
+        matching: "the words in the values of any metadata key, named as metadata.<key>"
+          .to_string(),
+        example: format!("metadata.{}:draft", TAGS_METADATA_KEY),
+      }],
+      operators: vec![
+        SyntaxElement::new(
+          "word word",
+          "Documents containing any of the words, those with more of them ranking higher",
+          "deploy rollback",
+        ),
+        SyntaxElement::new(
+          "\"a phrase\"",
+          "The words next to each other",
+          "\"release notes\"",
+        ),
+      ],
+      filters: common_filters(),
+      metadata_keys: builtin_metadata_keys(),
+    }
+  }
+
+  pub fn with_filter(mut self, filter: SyntaxElement) -> Self {
+    self.filters.push(filter);
+    self
+  }
+
+  pub fn with_metadata_key(mut self, key: &str, description: &str) -> Self {
+    self.metadata_keys.push(MetadataKey {
+      key: key.to_string(),
+      description: description.to_string(),
+    });
+    self
+  }
+
+  /// Adds the filters and metadata keys of `--git-metadata`.
+  pub fn with_git_metadata(self) -> Self {
+    self
+      .with_filter(SyntaxElement::new(
+        &format!("{}NAME", AUTHOR_FILTER_PREFIX),
+        "Only files last committed by an author whose name or email contains NAME",
+        &format!("deploy {}alice", AUTHOR_FILTER_PREFIX),
+      ))
+      .with_filter(SyntaxElement::new(
+        &format!("{}NAME", BRANCH_FILTER_PREFIX),
+        "Only watched directories checked out on this branch or in this worktree directory",
+        &format!("deploy {}main", BRANCH_FILTER_PREFIX),
+      ))
+      .with_metadata_key(
+        GIT_AUTHOR_METADATA_KEY,
+        "Author of the last commit of the file",
+      )
+      .with_metadata_key(GIT_DATE_METADATA_KEY, "Date of the last commit of the file")
+      .with_metadata_key(GIT_COMMIT_METADATA_KEY, "Id of the last commit of the file")
+      .with_metadata_key(GIT_BRANCH_METADATA_KEY, "Branch checked out when indexed")
+      .with_metadata_key(GIT_WORKTREE_METADATA_KEY, "Worktree directory name")
+  }
+
+  /// Adds the metadata keys of `--extra-field`.
+  pub fn with_extra_fields(self, extra_fields: &[ExtraField]) -> Self {
+    extra_fields.iter().fold(self, |syntax, field| {
+      syntax.with_metadata_key(&field.name, "Defined with --extra-field")
+    })
+  }
+}
+
+fn common_filters() -> Vec<SyntaxElement> {
+  vec![
+    SyntaxElement::new(
+      &format!("{}all", MATCH_MODE_PREFIX),
+      &format!(
+        "Only documents containing every word; {}N at least N of them",
+        MATCH_MODE_PREFIX
+      ),
+      &format!("deploy rollback staging {}2", MATCH_MODE_PREFIX),
+    ),
+    SyntaxElement::new(
+      &format!("{}CODE", LANGUAGE_FILTER_PREFIX),
+      "Only documents in these ISO 639-1 languages, comma separated",
+      &format!("deploy {}en,de", LANGUAGE_FILTER_PREFIX),
+    ),
+  ]
+}
+
+fn builtin_metadata_keys() -> Vec<MetadataKey> {
+  [
+    (TAGS_METADATA_KEY, "Tags of notes, space separated"),
+    (LINKS_METADATA_KEY, "Paths of the notes a note links to"),
+    (LANGUAGE_METADATA_KEY, "Detected ISO 639-1 language"),
+    (PROJECT_METADATA_KEY, "Project of the watched directory"),
+    ("title", "Title of Org documents and fetched web pages"),
+    ("subject", "Subject of emails"),
+    ("from", "Sender of emails"),
+    ("date", "Date of emails"),
+  ]
+  .into_iter()
+  .map(|(key, description)| MetadataKey {
+    key: key.to_string(),
+    description: description.to_string(),
+  })
+  .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn query_syntax_should_list_the_indexed_fields_of_the_schema() {
+    let syntax = QuerySyntax::tantivy();
+    let field = |name: &str| syntax.fields.iter().find(|field| field.name == name);
+    assert!(
+      field("content")
+        .unwrap()
+        .matching
+        .contains("default tokenizer")
+    );
+    assert!(field("file_path").unwrap().matching.contains("exactly"));
+    assert_eq!(field("metadata").unwrap().example, "metadata.tags:draft");
+    /* Stored only, so not searchable */
+    assert!(field("indexed_at").is_none());
+    assert!(field("line_offsets").is_none());
+  }
+}
@@ -0,0 +1,103 @@
+use anyhow::Result;
+use std::{collections::BTreeMap, str::FromStr};
+
+/// A named query with `{param}` placeholders, defined as `NAME(PARAM, ...)=QUERY`, e.g.
+/// `todo_in(path)=content:(TODO OR FIXME) AND file_path:{path}*`, so that agents run common
+/// lookups the same way every time.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct QueryTemplate {
+  pub name: String,
+  pub params: Vec<String>,
+  pub query: String,
+}
+
+impl QueryTemplate {
+  /// The query with every placeholder replaced by its argument. Every parameter needs an
+  /// argument, and arguments for unknown parameters are refused to catch typos.
+  pub fn expand(&self, args: &BTreeMap<String, String>) -> Result<String> {
+    if let Some(unknown) = args.keys().find(|arg| !self.params.contains(arg)) {
+      anyhow::bail!(
+        "Template {} has no parameter {}, expected {}",
+        self.name,
+        unknown,
+        self.signature()
+      );
+    }
+    let mut query = self.query.clone();
+    for param in &self.params {
+      let arg = args.get(param).ok_or_else(|| {
+        anyhow::anyhow!(
+          "Missing argument {} of template {}",
+          param,
+          self.signature()
+        )
+      })?;
+      query = query.replace(&format!("{{{}}}", param), arg);
+    }
+    Ok(query)
+  }
+
+  /// How the template is called, e.g. `todo_in(path)`.
+  pub fn signature(&self) -> String {
+    format!("{}({})", self.name, self.params.join(", "))
+  }
+}
+
+impl FromStr for QueryTemplate {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    let invalid = || format!("expected NAME(PARAM, ...)=QUERY, got {}", value);
+    let (signature, query) = value.split_once('=').ok_or_else(invalid)?;
+    let (name, params) = match signature.trim().split_once('(') {
+      Some((name, params)) => (name, params.strip_suffix(')').ok_or_else(invalid)?),
+      None => (signature.trim(), ""),
+    };
+    let is_identifier = |text: &str| {
+      !text.is_empty()
+        && text
+          .chars()
+          .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    };
+    let params = params
+      .split(',')
+      .map(str::trim)
+      .filter(|param| !param.is_empty())
+      .map(str::to_string)
+      .collect::<Vec<_>>();
+    if !is_identifier(name) || !params.iter().all(|param| is_identifier(param)) {
+      return Err(invalid());
+    }
+    if query.trim().is_empty() {
+      return Err(invalid());
+    }
+    Ok(QueryTemplate {
+      name: name.to_string(),
+      params,
+      query: query.trim().to_string(),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn query_template_should_expand_placeholders_with_arguments() {
+    let template = "todo_in(path)=content:(TODO OR FIXME) AND file_path:{path}*"
+      .parse::<QueryTemplate>()
+      .unwrap();
+    assert_eq!(template.signature(), "todo_in(path)");
+
+    let args = BTreeMap::from([("path".to_string(), "/notes".to_string())]);
+    assert_eq!(
+      template.expand(&args).unwrap(),
+      "content:(TODO OR FIXME) AND file_path:/notes*"
+    );
+    assert!(template.expand(&BTreeMap::new()).is_err());
+    let typo = BTreeMap::from([("pth".to_string(), "/notes".to_string())]);
+    assert!(template.expand(&typo).is_err());
+    assert!("no_query()=".parse::<QueryTemplate>().is_err());
+  }
+}
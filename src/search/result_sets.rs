@@ -0,0 +1,197 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::{
+  collections::VecDeque,
+  path::PathBuf,
+  sync::atomic::{AtomicU64, Ordering},
+};
+
+use super::sharded_index::SearchScope;
+
+pub const DEFAULT_RESULT_SET_CAPACITY: usize = 64;
+
+/// A search kept for follow-up tools: the queries every result must match, in the order they
+/// narrowed it. Results are found again from the current index whenever the set is used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResultSet {
+  pub queries: Vec<String>,
+  pub scope: SearchScope,
+}
+
+impl ResultSet {
+  /// The set of results also matching `query`.
+  pub fn refine(&self, query: &str) -> ResultSet {
+    let mut queries = self.queries.clone();
+    queries.push(query.to_string());
+    ResultSet {
+      queries,
+      scope: self.scope.clone(),
+    }
+  }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredResultSet {
+  id: String,
+  queries: Vec<String>,
+  scope: SearchScope,
+}
+
+/// Recent result sets by id, so that agents can refer to earlier results without sending their
+/// paths back. The least recently used sets are forgotten first. Persisted to a JSON file when
+/// one is given so that ids stay valid across restarts.
+pub struct ResultSets {
+  capacity: usize,
+  next_id: AtomicU64,
+  sets: Mutex<VecDeque<(String, ResultSet)>>,
+  result_sets_file: Option<PathBuf>,
+}
+
+impl ResultSets {
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      capacity,
+      next_id: AtomicU64::new(1),
+      sets: Mutex::new(VecDeque::with_capacity(capacity)),
+      result_sets_file: None,
+    }
+  }
+
+  pub fn new_with_file(capacity: usize, result_sets_file: PathBuf) -> Self {
+    let stored = std::fs::read(&result_sets_file)
+      .ok()
+      .and_then(|bytes| {
+        serde_json::from_slice::<Vec<StoredResultSet>>(&bytes)
+          .inspect_err(|e| {
+            tracing::warn!(
+              "Ignoring unreadable result sets {:?}: {}",
+              result_sets_file,
+              e
+            )
+          })
+          .ok()
+      })
+      .unwrap_or_default();
+    /* Ids of forgotten sets are never handed out again */
+    let next_id = stored
+      .iter()
+      .filter_map(|set| set.id.strip_prefix('r')?.parse::<u64>().ok())
+      .max()
+      .unwrap_or_default()
+      + 1;
+    let skipped = stored.len().saturating_sub(capacity);
+    let sets = stored
+      .into_iter()
+      .skip(skipped)
+      .map(|set| {
+        let result_set = ResultSet {
+          queries: set.queries,
+          scope: set.scope,
+        };
+        (set.id, result_set)
+      })
+      .collect();
+    Self {
+      capacity,
+      next_id: AtomicU64::new(next_id),
+      sets: Mutex::new(sets),
+      result_sets_file: Some(result_sets_file),
+    }
+  }
+
+  /// Whether sets are kept at all, see `--max-result-sets`.
+  pub fn is_enabled(&self) -> bool {
+    self.capacity > 0
+  }
+
+  /// Keeps the set, returning its id, or `None` when no sets are kept. A set that cannot be
+  /// saved is still kept until the server stops.
+  pub fn insert(&self, result_set: ResultSet) -> Option<String> {
+    if !self.is_enabled() {
+      return None;
+    }
+    let id = format!("r{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+    let mut sets = self.sets.lock();
+    if sets.len() >= self.capacity {
+      sets.pop_front();
+    }
+    sets.push_back((id.clone(), result_set));
+    if let Err(e) = self.save(&sets) {
+      tracing::warn!("Failed to save result sets: {}", e);
+    }
+    Some(id)
+  }
+
+  pub fn get(&self, id: &str) -> Option<ResultSet> {
+    let mut sets = self.sets.lock();
+    let position = sets.iter().position(|(set_id, _)| set_id == id)?;
+    let entry = sets.remove(position)?;
+    let result_set = entry.1.clone();
+    sets.push_back(entry);
+    Some(result_set)
+  }
+
+  fn save(&self, sets: &VecDeque<(String, ResultSet)>) -> Result<()> {
+    let Some(result_sets_file) = &self.result_sets_file else {
+      return Ok(());
+    };
+    let stored = sets
+      .iter()
+      .map(|(id, set)| StoredResultSet {
+        id: id.clone(),
+        queries: set.queries.clone(),
+        scope: set.scope.clone(),
+      })
+      .collect::<Vec<_>>();
+    /* Written next to the target and renamed over it so a crash never leaves a partial file */
+    let temporary_file = result_sets_file.with_extension("tmp");
+    std::fs::write(&temporary_file, serde_json::to_vec(&stored)?)?;
+    std::fs::rename(&temporary_file, result_sets_file)?;
+    Ok(())
+  }
+}
+
+impl Default for ResultSets {
+  fn default() -> Self {
+    Self::new(DEFAULT_RESULT_SET_CAPACITY)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn result_sets_should_forget_least_recently_used_sets() {
+    let result_sets = ResultSets::new(2);
+    let set = |query: &str| ResultSet {
+      queries: vec![query.to_string()],
+      scope: SearchScope::default(),
+    };
+    let first = result_sets.insert(set("a")).unwrap();
+    let second = result_sets.insert(set("b")).unwrap();
+    assert_eq!(result_sets.get(&first), Some(set("a")));
+
+    let third = result_sets.insert(set("a").refine("c")).unwrap();
+    assert_eq!(result_sets.get(&second), None);
+    assert_eq!(result_sets.get(&first), Some(set("a")));
+    assert_eq!(result_sets.get(&third).unwrap().queries, vec!["a", "c"]);
+  }
+
+  #[test]
+  fn result_sets_should_persist_sets_across_instances() {
+    let result_sets_file =
+      std::env::temp_dir().join(format!("result-sets-test-{}.json", std::process::id()));
+    let result_sets = ResultSets::new_with_file(2, result_sets_file.clone());
+    let set = ResultSet {
+      queries: vec!["garden".to_string()],
+      scope: SearchScope::default(),
+    };
+    let id = result_sets.insert(set.clone()).unwrap();
+
+    let reopened = ResultSets::new_with_file(2, result_sets_file.clone());
+    assert_eq!(reopened.get(&id), Some(set.clone()));
+    assert_ne!(reopened.insert(set).unwrap(), id);
+    std::fs::remove_file(&result_sets_file).unwrap();
+  }
+}
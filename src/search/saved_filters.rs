@@ -0,0 +1,461 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::{
+  collections::{BTreeSet, VecDeque},
+  ops::Bound,
+  path::{Path, PathBuf},
+  str::FromStr,
+  sync::Arc,
+  time::{Duration, SystemTime},
+};
+use tantivy::{
+  Term,
+  query::{AllQuery, BooleanQuery, Occur, Query, RangeQuery, TermQuery},
+  schema::{Field, IndexRecordOption},
+};
+
+use super::{
+  file::document_file_path,
+  index_backend::unix_time_ms,
+  text_index::{build_schema, path_query},
+};
+
+/// Combinations of filters kept compiled, so that agents repeating them pay the parsing once.
+const COMPILED_FILTER_CAPACITY: usize = 32;
+
+/// Prefix of a filter name that keeps the files it does not match.
+pub const NEGATED_FILTER_PREFIX: char = '-';
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FilterCondition {
+  /// Files under any of these directories, or these files
+  Paths(Vec<PathBuf>),
+  /// Files with any of these lowercase extensions
+  Extensions(BTreeSet<String>),
+  /// Files modified at most this long ago
+  ModifiedWithin(Duration),
+}
+
+/// Fields of the index schema the conditions query.
+struct FilterFields {
+  file_path: Field,
+  extension: Field,
+  modified_at: Field,
+}
+
+impl FilterFields {
+  fn new() -> Result<Self> {
+    let schema = build_schema();
+    Ok(FilterFields {
+      file_path: schema.get_field("file_path")?,
+      extension: schema.get_field("extension")?,
+      modified_at: schema.get_field("modified_at")?,
+    })
+  }
+}
+
+impl FilterCondition {
+  /// Date windows are measured from now, so their queries cannot be kept.
+  fn is_relative(&self) -> bool {
+    matches!(self, FilterCondition::ModifiedWithin(_))
+  }
+
+  fn query(&self, fields: &FilterFields) -> Box<dyn Query> {
+    match self {
+      FilterCondition::Paths(paths) => Box::new(BooleanQuery::new(
+        paths
+          .iter()
+          .map(|path| {
+            (
+              Occur::Should,
+              path_query(fields.file_path, &path.to_string_lossy()),
+            )
+          })
+          .collect(),
+      )),
+      FilterCondition::Extensions(extensions) => Box::new(BooleanQuery::new(
+        extensions
+          .iter()
+          .map(|extension| {
+            let term = Term::from_field_text(fields.extension, extension);
+            (
+              Occur::Should,
+              Box::new(TermQuery::new(term, IndexRecordOption::Basic)) as Box<dyn Query>,
+            )
+          })
+          .collect(),
+      )),
+      FilterCondition::ModifiedWithin(window) => {
+        let since = unix_time_ms().saturating_sub(window.as_millis() as u64);
+        Box::new(RangeQuery::new(
+          Bound::Included(Term::from_field_u64(fields.modified_at, since)),
+          Bound::Unbounded,
+        ))
+      }
+    }
+  }
+
+  fn matches(&self, file_path: &Path, now: SystemTime) -> bool {
+    match self {
+      FilterCondition::Paths(paths) => paths.iter().any(|path| file_path.starts_with(path)),
+      FilterCondition::Extensions(extensions) => file_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extensions.contains(&extension.to_lowercase())),
+      FilterCondition::ModifiedWithin(window) => std::fs::metadata(file_path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| now.duration_since(modified).is_ok_and(|age| age <= *window)),
+    }
+  }
+}
+
+fn parse_window(value: &str) -> Option<Duration> {
+  let unit = match value.chars().last()? {
+    'h' => 60 * 60,
+    'd' => 24 * 60 * 60,
+    'w' => 7 * 24 * 60 * 60,
+    _ => return None,
+  };
+  let count = value[..value.len() - 1].parse::<u64>().ok()?;
+  Some(Duration::from_secs(count.checked_mul(unit)?))
+}
+
+/// A named filter defined on the command line as `NAME=CONDITION;CONDITION...`, e.g.
+/// `work-notes=path:/home/me/work,/home/me/notes;ext:md,org` or `last-30-days=modified:30d`.
+/// A file passes when it meets every condition, and a condition when it matches any of its values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedFilter {
+  pub name: String,
+  /// The conditions as given, listed by the list_filters tool
+  pub definition: String,
+  conditions: Vec<FilterCondition>,
+}
+
+impl SavedFilter {
+  fn query(&self, fields: &FilterFields) -> Box<dyn Query> {
+    Box::new(BooleanQuery::new(
+      self
+        .conditions
+        .iter()
+        .map(|condition| (Occur::Must, condition.query(fields)))
+        .collect(),
+    ))
+  }
+
+  fn matches(&self, file_path: &Path, now: SystemTime) -> bool {
+    self
+      .conditions
+      .iter()
+      .all(|condition| condition.matches(file_path, now))
+  }
+}
+
+impl FromStr for SavedFilter {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    let invalid = || {
+      format!(
+        "expected NAME=CONDITION;... with conditions path:DIR,..., ext:EXT,... or modified:Nh|Nd|Nw, got {}",
+        value
+      )
+    };
+    let (name, definition) = value.split_once('=').ok_or_else(invalid)?;
+    if name.is_empty()
+      || name.starts_with(NEGATED_FILTER_PREFIX)
+      || !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+      return Err(format!("invalid filter name {:?}", name));
+    }
+    let conditions = definition
+      .split(';')
+      .map(|condition| {
+        let (kind, values) = condition.trim().split_once(':').ok_or_else(invalid)?;
+        let values = values
+          .split(',')
+          .map(str::trim)
+          .filter(|value| !value.is_empty())
+          .collect::<Vec<_>>();
+        if values.is_empty() {
+          return Err(invalid());
+        }
+        match kind {
+          "path" => Ok(FilterCondition::Paths(
+            values.into_iter().map(PathBuf::from).collect(),
+          )),
+          "ext" => Ok(FilterCondition::Extensions(
+            values
+              .into_iter()
+              .map(|extension| extension.trim_start_matches('.').to_lowercase())
+              .collect(),
+          )),
+          "modified" => match values[..] {
+            [window] => parse_window(window)
+              .map(FilterCondition::ModifiedWithin)
+              .ok_or_else(|| format!("expected a window such as 30d, got {}", window)),
+            _ => Err(invalid()),
+          },
+          _ => Err(invalid()),
+        }
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok(SavedFilter {
+      name: name.to_string(),
+      definition: definition.trim().to_string(),
+      conditions,
+    })
+  }
+}
+
+/// Saved filters combined for one search: files must pass every filter and fail every negated one.
+#[derive(Debug, Default)]
+pub struct CompiledFilter {
+  required: Vec<SavedFilter>,
+  excluded: Vec<SavedFilter>,
+  /// Query of the combination, kept unless a date window must be measured again for every search
+  query: Option<Box<dyn Query>>,
+}
+
+impl CompiledFilter {
+  fn new(required: Vec<SavedFilter>, excluded: Vec<SavedFilter>) -> Result<Self> {
+    let mut filter = CompiledFilter {
+      required,
+      excluded,
+      query: None,
+    };
+    let is_relative = filter
+      .required
+      .iter()
+      .chain(&filter.excluded)
+      .flat_map(|saved| &saved.conditions)
+      .any(FilterCondition::is_relative);
+    if !is_relative {
+      filter.query = Some(filter.build_query()?);
+    }
+    Ok(filter)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.required.is_empty() && self.excluded.is_empty()
+  }
+
+  /// The documents of the files passing, as a query on the fields of the index schema.
+  pub fn query(&self) -> Result<Box<dyn Query>> {
+    match &self.query {
+      Some(query) => Ok(query.box_clone()),
+      None => self.build_query(),
+    }
+  }
+
+  fn build_query(&self) -> Result<Box<dyn Query>> {
+    let fields = FilterFields::new()?;
+    /* Negated filters alone would leave nothing to subtract from */
+    let mut clauses = vec![(Occur::Must, Box::new(AllQuery) as Box<dyn Query>)];
+    clauses.extend(
+      self
+        .required
+        .iter()
+        .map(|saved| (Occur::Must, saved.query(&fields))),
+    );
+    clauses.extend(
+      self
+        .excluded
+        .iter()
+        .map(|saved| (Occur::MustNot, saved.query(&fields))),
+    );
+    Ok(Box::new(BooleanQuery::new(clauses)))
+  }
+
+  /// Whether the file of a hit passes, for backends searching without the query. Date windows
+  /// are measured from now, so a combination stays valid however long it is kept.
+  pub fn matches(&self, file_path: &str) -> bool {
+    let file_path = Path::new(document_file_path(file_path));
+    let now = SystemTime::now();
+    self
+      .required
+      .iter()
+      .all(|filter| filter.matches(file_path, now))
+      && !self
+        .excluded
+        .iter()
+        .any(|filter| filter.matches(file_path, now))
+  }
+}
+
+/// The filters searches can name, and the combinations recently named.
+#[derive(Default)]
+pub struct SavedFilters {
+  filters: Vec<SavedFilter>,
+  compiled: Mutex<VecDeque<(Vec<String>, Arc<CompiledFilter>)>>,
+}
+
+impl SavedFilters {
+  pub fn new(filters: Vec<SavedFilter>) -> Self {
+    SavedFilters {
+      filters,
+      compiled: Mutex::new(VecDeque::with_capacity(COMPILED_FILTER_CAPACITY)),
+    }
+  }
+
+  pub fn filters(&self) -> &[SavedFilter] {
+    &self.filters
+  }
+
+  /// The combination of the named filters, each negated when prefixed with `-`. Unknown names are
+  /// refused so that a typo does not silently widen a search.
+  pub fn compile(&self, names: &[String]) -> Result<Arc<CompiledFilter>> {
+    let mut compiled = self.compiled.lock();
+    let position = compiled.iter().position(|(key, _)| key == names);
+    if let Some(entry) = position.and_then(|position| compiled.remove(position)) {
+      let filter = entry.1.clone();
+      compiled.push_back(entry);
+      return Ok(filter);
+    }
+    let mut required = Vec::new();
+    let mut excluded = Vec::new();
+    for name in names {
+      let (negated, bare_name) = match name.strip_prefix(NEGATED_FILTER_PREFIX) {
+        Some(bare_name) => (true, bare_name),
+        None => (false, name.as_str()),
+      };
+      let saved = self
+        .filters
+        .iter()
+        .find(|saved| saved.name == bare_name)
+        .ok_or_else(|| {
+          anyhow::anyhow!(
+            "Unknown filter {}, expected one of: {}",
+            bare_name,
+            self
+              .filters
+              .iter()
+              .map(|saved| saved.name.as_str())
+              .collect::<Vec<_>>()
+              .join(", ")
+          )
+        })?;
+      if negated {
+        excluded.push(saved.clone());
+      } else {
+        required.push(saved.clone());
+      }
+    }
+    let filter = Arc::new(CompiledFilter::new(required, excluded)?);
+    if compiled.len() >= COMPILED_FILTER_CAPACITY {
+      compiled.pop_front();
+    }
+    compiled.push_back((names.to_vec(), filter.clone()));
+    Ok(filter)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn saved_filters_should_combine_named_filters() {
+    let saved_filters = SavedFilters::new(vec![
+      "work-notes=path:/work,/notes;ext:md,org".parse().unwrap(),
+      "drafts=path:/notes/drafts".parse().unwrap(),
+    ]);
+    let names = |names: &[&str]| {
+      names
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>()
+    };
+
+    let filter = saved_filters
+      .compile(&names(&["work-notes", "-drafts"]))
+      .unwrap();
+    assert!(filter.matches("/notes/plan.md"));
+    assert!(filter.matches("/work/TODO.ORG"));
+    assert!(!filter.matches("/work/main.rs"));
+    assert!(!filter.matches("/notes/drafts/idea.md"));
+    assert!(!filter.matches("/elsewhere/plan.md"));
+    assert!(Arc::ptr_eq(
+      &filter,
+      &saved_filters
+        .compile(&names(&["work-notes", "-drafts"]))
+        .unwrap()
+    ));
+    assert!(saved_filters.compile(&names(&["wrok-notes"])).is_err());
+
+    assert!(filter.query.is_some());
+    assert!("recent=modified:30d".parse::<SavedFilter>().is_ok());
+    assert!("recent=modified:30".parse::<SavedFilter>().is_err());
+    assert!("-recent=ext:md".parse::<SavedFilter>().is_err());
+  }
+
+  #[test]
+  fn compiled_filter_should_narrow_searches_of_text_index() {
+    use crate::search::{
+      file::File,
+      index_backend::{IndexBackend, SearchOptions},
+      text_index::TextIndex,
+    };
+
+    let root = std::env::temp_dir().join(format!("saved-filters-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("notes/drafts")).unwrap();
+    let mut files = Vec::new();
+    for name in [
+      "notes/plan.md",
+      "notes/main.rs",
+      "notes/drafts/idea.md",
+      "old.md",
+    ] {
+      let path = root.join(name);
+      std::fs::write(&path, "keyword").unwrap();
+      files.push(File::new(
+        path.to_string_lossy().to_string(),
+        "keyword".to_string(),
+      ));
+    }
+    std::fs::File::options()
+      .write(true)
+      .open(root.join("old.md"))
+      .unwrap()
+      .set_modified(SystemTime::now() - Duration::from_secs(3 * 24 * 60 * 60))
+      .unwrap();
+    let mut index = TextIndex::new().unwrap();
+    index.add_docs(files).unwrap();
+
+    let root = root.to_string_lossy().to_string();
+    let saved_filters = SavedFilters::new(vec![
+      format!("notes=path:{}/notes/;ext:md", root)
+        .parse()
+        .unwrap(),
+      format!("drafts=path:{}/notes/drafts", root)
+        .parse()
+        .unwrap(),
+      "recent=modified:1d".parse().unwrap(),
+    ]);
+    let search = |names: &[&str]| {
+      let names = names
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+      let options = SearchOptions {
+        filter: saved_filters.compile(&names).unwrap(),
+        ..SearchOptions::default()
+      };
+      let mut file_paths = index
+        .search_hits_with("keyword", 10, &options)
+        .unwrap()
+        .into_iter()
+        .map(|hit| hit.file_path.strip_prefix(&root).unwrap().to_string())
+        .collect::<Vec<_>>();
+      file_paths.sort();
+      file_paths
+    };
+
+    assert_eq!(search(&["notes", "-drafts"]), vec!["/notes/plan.md"]);
+    assert_eq!(search(&["-recent"]), vec!["/old.md"]);
+    assert_eq!(search(&["recent", "-notes"]), vec!["/notes/main.rs"]);
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+}
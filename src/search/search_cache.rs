@@ -0,0 +1,97 @@
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+
+pub const DEFAULT_SEARCH_CACHE_CAPACITY: usize = 64;
+
+/// Commit generation of each shard a result was computed from, keyed by root.
+pub type IndexGenerations = Vec<(String, u64)>;
+
+struct CacheEntry {
+  key: String,
+  generations: IndexGenerations,
+  results: Vec<String>,
+}
+
+/// Least recently used cache of search results. An entry is only returned while no shard has
+/// committed since it was stored, so results are never stale.
+pub struct SearchCache {
+  capacity: usize,
+  entries: Mutex<VecDeque<CacheEntry>>,
+}
+
+impl SearchCache {
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      capacity,
+      entries: Mutex::new(VecDeque::with_capacity(capacity)),
+    }
+  }
+
+  /// `key` must encode the query and every parameter that affects its results.
+  pub fn get(&self, key: &str, generations: &IndexGenerations) -> Option<Vec<String>> {
+    let mut entries = self.entries.lock();
+    let position = entries.iter().position(|entry| entry.key == key)?;
+    let entry = entries.remove(position)?;
+    if &entry.generations != generations {
+      return None;
+    }
+    let results = entry.results.clone();
+    entries.push_back(entry);
+    Some(results)
+  }
+
+  /// Number of cached results and their size in bytes.
+  pub fn usage(&self) -> (usize, u64) {
+    let entries = self.entries.lock();
+    let bytes = entries
+      .iter()
+      .flat_map(|entry| entry.results.iter().chain([&entry.key]))
+      .map(|text| text.len() as u64)
+      .sum();
+    (entries.len(), bytes)
+  }
+
+  pub fn insert(&self, key: String, generations: IndexGenerations, results: Vec<String>) {
+    if self.capacity == 0 {
+      return;
+    }
+    let mut entries = self.entries.lock();
+    entries.retain(|entry| entry.key != key);
+    if entries.len() >= self.capacity {
+      entries.pop_front();
+    }
+    entries.push_back(CacheEntry {
+      key,
+      generations,
+      results,
+    });
+  }
+}
+
+impl Default for SearchCache {
+  fn default() -> Self {
+    Self::new(DEFAULT_SEARCH_CACHE_CAPACITY)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn search_cache_should_evict_least_recently_used_and_stale_entries() {
+    let cache = SearchCache::new(2);
+    let generations = vec![("/notes".to_string(), 1)];
+    cache.insert("a".to_string(), generations.clone(), vec!["a".to_string()]);
+    cache.insert("b".to_string(), generations.clone(), vec!["b".to_string()]);
+    assert_eq!(cache.get("a", &generations), Some(vec!["a".to_string()]));
+
+    cache.insert("c".to_string(), generations.clone(), vec!["c".to_string()]);
+    assert_eq!(cache.get("b", &generations), None);
+    assert_eq!(cache.get("a", &generations), Some(vec!["a".to_string()]));
+
+    let committed = vec![("/notes".to_string(), 2)];
+    assert_eq!(cache.get("c", &committed), None);
+    assert_eq!(cache.get("c", &generations), None);
+  }
+}
@@ -0,0 +1,186 @@
+use anyhow::Result;
+use std::{
+  path::{Path, PathBuf},
+  sync::Arc,
+};
+
+use super::{
+  cancellation::check_cancelled,
+  file::{FileFilter, FileLoader},
+  index_backend::{IndexBackend, SearchHit},
+  text_index::TextIndex,
+};
+
+/// Indexes a directory into a throwaway in-memory index and searches it once.
+pub fn search_in_dir(
+  dir: &str,
+  keyword: &str,
+  file_filter: &dyn FileFilter,
+  file_loader: &dyn FileLoader,
+  limit: usize,
+) -> Result<Vec<SearchHit>> {
+  let mut index = TextIndex::new()?;
+  /* The loader yields files lazily; they are only read as add_docs consumes the iterator */
+  let files = file_loader
+    .load_directory(dir, file_filter)
+    .filter_map(|file| {
+      file
+        .inspect_err(|e| tracing::debug!("Skipping file while searching {}: {}", dir, e))
+        .ok()
+    })
+    .take_while(|_| check_cancelled().is_ok());
+  let added_count = index.add_docs(files)?;
+  check_cancelled()?;
+  tracing::debug!(
+    "Indexed {} files of {} for a one-off search",
+    added_count,
+    dir
+  );
+  index.search_hits(keyword, limit)
+}
+
+/// One-off searches of directories outside the watched roots, restricted to an allow-list.
+pub struct DirectorySearch {
+  allowed_dirs: Vec<PathBuf>,
+  watched_roots: Vec<PathBuf>,
+  file_filter: Arc<dyn FileFilter + Send + Sync>,
+  file_loader: Arc<dyn FileLoader + Send + Sync>,
+}
+
+impl DirectorySearch {
+  pub fn new(
+    allowed_dirs: &[PathBuf],
+    watched_roots: &[PathBuf],
+    file_filter: Arc<dyn FileFilter + Send + Sync>,
+    file_loader: Arc<dyn FileLoader + Send + Sync>,
+  ) -> Self {
+    let canonicalize = |paths: &[PathBuf]| {
+      paths
+        .iter()
+        .filter_map(|path| {
+          path
+            .canonicalize()
+            .inspect_err(|e| tracing::warn!("Ignoring directory {:?}: {}", path, e))
+            .ok()
+        })
+        .collect()
+    };
+    DirectorySearch {
+      allowed_dirs: canonicalize(allowed_dirs),
+      watched_roots: canonicalize(watched_roots),
+      file_filter,
+      file_loader,
+    }
+  }
+
+  /// Resolves `dir`, refusing directories outside the allow-list and watched roots, which are
+  /// searched through the persistent index instead.
+  pub fn resolve(&self, dir: &str) -> Result<PathBuf> {
+    let dir = Path::new(dir)
+      .canonicalize()
+      .map_err(|e| anyhow::anyhow!("Cannot access {}: {}", dir, e))?;
+    if !dir.is_dir() {
+      anyhow::bail!("{:?} is not a directory", dir);
+    }
+    if !self
+      .allowed_dirs
+      .iter()
+      .any(|allowed| dir.starts_with(allowed))
+    {
+      anyhow::bail!(
+        "{:?} is not below a directory allowed for ad-hoc search",
+        dir
+      );
+    }
+    if let Some(root) = self.watched_roots.iter().find(|root| dir.starts_with(root)) {
+      anyhow::bail!(
+        "{:?} is already indexed as part of {:?}, use search_index instead",
+        dir,
+        root
+      );
+    }
+    Ok(dir)
+  }
+
+  pub fn search(&self, dir: &str, keyword: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    let dir = self.resolve(dir)?;
+    search_in_dir(
+      &dir.to_string_lossy(),
+      keyword,
+      self.file_filter.as_ref(),
+      self.file_loader.as_ref(),
+      limit,
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::search::{file::File, index_backend::SEARCH_FILE_LIMIT};
+  use std::fs;
+
+  struct AllFiles;
+
+  impl FileFilter for AllFiles {
+    fn is_target(&self, _path: &str) -> bool {
+      true
+    }
+  }
+
+  struct LazyLoader;
+
+  impl FileLoader for LazyLoader {
+    fn load_directory<'a>(
+      &'a self,
+      path: &str,
+      _file_filter: &'a dyn FileFilter,
+    ) -> Box<dyn Iterator<Item = Result<File>> + 'a> {
+      let path = path.to_string();
+      Box::new((0..3).map(move |i| self.load_file_blocking(&format!("{}/{}.txt", path, i))))
+    }
+
+    fn load_file_blocking(&self, path: &str) -> Result<File> {
+      Ok(File::new(path.to_string(), format!("needle in {}", path)))
+    }
+  }
+
+  #[test]
+  fn search_in_dir_should_index_every_file_yielded_by_loader() {
+    let hits = search_in_dir(
+      "/adhoc",
+      "needle",
+      &AllFiles,
+      &LazyLoader,
+      SEARCH_FILE_LIMIT,
+    )
+    .unwrap();
+    assert_eq!(hits.len(), 3);
+  }
+
+  #[test]
+  fn directory_search_should_only_resolve_allowed_unwatched_directories() {
+    let base = std::env::temp_dir().join(format!("search-in-dir-test-{}", std::process::id()));
+    let (allowed, watched, other) = (
+      base.join("allowed"),
+      base.join("allowed/watched"),
+      base.join("other"),
+    );
+    for dir in [&allowed, &watched, &other] {
+      fs::create_dir_all(dir).unwrap();
+    }
+    let directory_search = DirectorySearch::new(
+      std::slice::from_ref(&allowed),
+      std::slice::from_ref(&watched),
+      Arc::new(AllFiles),
+      Arc::new(LazyLoader),
+    );
+
+    let resolve = |dir: &Path| directory_search.resolve(&dir.to_string_lossy());
+    assert_eq!(resolve(&allowed).unwrap(), allowed.canonicalize().unwrap());
+    assert!(resolve(&watched).is_err());
+    assert!(resolve(&other).is_err());
+    assert!(resolve(&base.join("missing")).is_err());
+    fs::remove_dir_all(&base).unwrap();
+  }
+}
@@ -0,0 +1,575 @@
+#[cfg(feature = "semantic")]
+pub mod fast_embedder;
+
+use anyhow::Result;
+use parking_lot::{Mutex, RwLock};
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  hash::{DefaultHasher, Hash, Hasher},
+  path::PathBuf,
+  sync::Arc,
+};
+
+use super::{
+  file::File,
+  index_backend::{IndexBackend, IndexMemory, SearchHit, SearchOptions},
+};
+
+/// Target size of the pieces documents are split into before embedding them.
+const MAX_CHUNK_CHARS: usize = 1000;
+const MAX_SNIPPET_LENGTH: usize = 200;
+/// Share of the vector similarity in the fused score of hybrid search, the rest being BM25.
+const HYBRID_VECTOR_WEIGHT: f32 = 0.5;
+
+/// Turns texts into vectors whose cosine similarity reflects how close their meanings are.
+pub trait Embedder {
+  fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>>;
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct EmbeddedChunk {
+  line_number: usize,
+  text: String,
+  vector: Vec<f32>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct EmbeddedDocument {
+  content_hash: u64,
+  chunks: Vec<EmbeddedChunk>,
+}
+
+/// Embedding vectors of the chunks of every document of one watch root, optionally persisted as
+/// JSON next to the full-text index so unchanged documents are not embedded again on restart.
+pub struct SemanticIndex {
+  embedder: Arc<dyn Embedder + Send + Sync>,
+  documents: BTreeMap<String, EmbeddedDocument>,
+  index_file: Option<PathBuf>,
+  dirty: bool,
+}
+
+impl SemanticIndex {
+  pub fn new(embedder: Arc<dyn Embedder + Send + Sync>) -> Self {
+    SemanticIndex {
+      embedder,
+      documents: BTreeMap::new(),
+      index_file: None,
+      dirty: false,
+    }
+  }
+
+  pub fn new_with_file(embedder: Arc<dyn Embedder + Send + Sync>, index_file: PathBuf) -> Self {
+    let documents = std::fs::read(&index_file)
+      .ok()
+      .and_then(|bytes| {
+        serde_json::from_slice(&bytes)
+          .inspect_err(|e| tracing::warn!("Ignoring unreadable vectors {:?}: {}", index_file, e))
+          .ok()
+      })
+      .unwrap_or_default();
+    SemanticIndex {
+      embedder,
+      documents,
+      index_file: Some(index_file),
+      dirty: false,
+    }
+  }
+
+  pub fn add_doc(&mut self, file: &File) -> Result<()> {
+    let content_hash = content_hash(&file.content);
+    if self
+      .documents
+      .get(&file.path)
+      .is_some_and(|document| document.content_hash == content_hash)
+    {
+      return Ok(());
+    }
+    let pieces = split_into_pieces(&file.content);
+    let texts = pieces
+      .iter()
+      .map(|(_, text)| text.to_string())
+      .collect::<Vec<_>>();
+    let vectors = if texts.is_empty() {
+      Vec::new()
+    } else {
+      self.embedder.embed(&texts)?
+    };
+    let chunks = pieces
+      .into_iter()
+      .zip(vectors)
+      .map(|((offset, text), vector)| EmbeddedChunk {
+        line_number: file.content[..offset].matches('\n').count() + 1,
+        text: text.to_string(),
+        vector,
+      })
+      .collect();
+    self.documents.insert(
+      file.path.clone(),
+      EmbeddedDocument {
+        content_hash,
+        chunks,
+      },
+    );
+    self.dirty = true;
+    Ok(())
+  }
+
+  pub fn delete_doc(&mut self, file_path: &str) {
+    self.dirty |= self.documents.remove(file_path).is_some();
+  }
+
+  pub fn delete_docs_by_path_prefix(&mut self, path_prefix: &str) {
+    let count = self.documents.len();
+    self
+      .documents
+      .retain(|path, _| !path.starts_with(path_prefix));
+    self.dirty |= self.documents.len() != count;
+  }
+
+  pub fn clear(&mut self) {
+    self.dirty |= !self.documents.is_empty();
+    self.documents.clear();
+  }
+
+  /// Drops vectors of documents the full-text index no longer holds, e.g. files deleted while the
+  /// server was not running.
+  pub fn retain_paths(&mut self, paths: &BTreeSet<String>) {
+    let count = self.documents.len();
+    self.documents.retain(|path, _| paths.contains(path));
+    self.dirty |= self.documents.len() != count;
+  }
+
+  pub fn save(&mut self) -> Result<()> {
+    let Some(index_file) = self.index_file.as_ref().filter(|_| self.dirty) else {
+      return Ok(());
+    };
+    /* Written next to the target and renamed over it so a crash never leaves a partial file */
+    let temporary_file = index_file.with_extension("tmp");
+    std::fs::write(&temporary_file, serde_json::to_vec(&self.documents)?)?;
+    std::fs::rename(&temporary_file, index_file)?;
+    self.dirty = false;
+    Ok(())
+  }
+
+  /// Similarity of the best matching chunk of a document, if it has been embedded.
+  pub fn similarity(&self, query: &[f32], file_path: &str) -> Option<f32> {
+    self.documents.get(file_path).and_then(|document| {
+      document
+        .chunks
+        .iter()
+        .map(|chunk| cosine_similarity(query, &chunk.vector))
+        .max_by(f32::total_cmp)
+    })
+  }
+
+  /// Best matching chunk of each document, most similar first.
+  pub fn search_vector(&self, query: &[f32], limit: usize) -> Vec<SearchHit> {
+    let mut hits = self
+      .documents
+      .iter()
+      .filter_map(|(path, document)| {
+        document
+          .chunks
+          .iter()
+          .map(|chunk| (cosine_similarity(query, &chunk.vector), chunk))
+          .max_by(|(a, _), (b, _)| a.total_cmp(b))
+          .map(|(score, chunk)| SearchHit {
+            file_path: path.clone(),
+            score,
+            line_number: Some(chunk.line_number),
+            snippet: Some(chunk.text.trim().chars().take(MAX_SNIPPET_LENGTH).collect()),
+            section: None,
+            metadata: BTreeMap::new(),
+          })
+      })
+      .collect::<Vec<_>>();
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits.truncate(limit);
+    hits
+  }
+}
+
+/* DefaultHasher is not stable across Rust releases; a changed hash only means re-embedding */
+fn content_hash(content: &str) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  content.hash(&mut hasher);
+  hasher.finish()
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+  let dot = a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+  let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+  let norms = norm(a) * norm(b);
+  if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+/// Splits content at paragraph boundaries into pieces of at most `MAX_CHUNK_CHARS` characters,
+/// returning each piece with its byte offset.
+fn split_into_pieces(content: &str) -> Vec<(usize, &str)> {
+  let mut pieces = Vec::new();
+  let mut piece_start = 0;
+  let mut piece_chars = 0;
+  let mut offset = 0;
+  for paragraph in content.split_inclusive("\n\n") {
+    let paragraph_chars = paragraph.chars().count();
+    if piece_chars > 0 && piece_chars + paragraph_chars > MAX_CHUNK_CHARS {
+      pieces.push((piece_start, &content[piece_start..offset]));
+      piece_start = offset;
+      piece_chars = 0;
+    }
+    piece_chars += paragraph_chars;
+    offset += paragraph.len();
+    /* A paragraph longer than a piece is cut at character boundaries */
+    while piece_chars > MAX_CHUNK_CHARS {
+      let cut = content[piece_start..]
+        .char_indices()
+        .nth(MAX_CHUNK_CHARS)
+        .map_or(offset, |(i, _)| piece_start + i);
+      pieces.push((piece_start, &content[piece_start..cut]));
+      piece_start = cut;
+      piece_chars -= MAX_CHUNK_CHARS;
+    }
+  }
+  if piece_start < content.len() {
+    pieces.push((piece_start, &content[piece_start..]));
+  }
+  pieces.retain(|(_, text)| !text.trim().is_empty());
+  pieces
+}
+
+/// Full-text backend that also keeps a `SemanticIndex` of the same documents up to date.
+pub struct SemanticIndexBackend<B: IndexBackend> {
+  inner: B,
+  semantic_index: Arc<Mutex<SemanticIndex>>,
+}
+
+impl<B: IndexBackend> SemanticIndexBackend<B> {
+  pub fn new(inner: B, semantic_index: Arc<Mutex<SemanticIndex>>) -> Self {
+    SemanticIndexBackend {
+      inner,
+      semantic_index,
+    }
+  }
+
+  fn embed(&self, file: &File) {
+    /* A document without vectors is still found by keyword, so embedding errors are not fatal */
+    if let Err(e) = self.semantic_index.lock().add_doc(file) {
+      tracing::error!("Failed to embed {}: {}", file.path, e);
+    }
+  }
+}
+
+impl<B: IndexBackend> IndexBackend for SemanticIndexBackend<B> {
+  fn add_doc(&mut self, file: &File) -> Result<()> {
+    self.inner.add_doc(file)?;
+    self.embed(file);
+    Ok(())
+  }
+
+  fn add_docs(&mut self, files: Box<dyn Iterator<Item = File> + '_>) -> Result<usize> {
+    let semantic_index = self.semantic_index.clone();
+    let files = files.inspect(move |file| {
+      if let Err(e) = semantic_index.lock().add_doc(file) {
+        tracing::error!("Failed to embed {}: {}", file.path, e);
+      }
+    });
+    let added_count = self.inner.add_docs(Box::new(files))?;
+    let mut semantic_index = self.semantic_index.lock();
+    semantic_index.retain_paths(&self.inner.indexed_paths()?);
+    semantic_index.save()?;
+    Ok(added_count)
+  }
+
+  fn replace_doc(&mut self, file: &File) -> Result<()> {
+    self.inner.replace_doc(file)?;
+    self.embed(file);
+    Ok(())
+  }
+
+  fn delete_doc(&mut self, file_path: &str) -> Result<()> {
+    self.inner.delete_doc(file_path)?;
+    self.semantic_index.lock().delete_doc(file_path);
+    Ok(())
+  }
+
+  fn delete_docs_by_path_prefix(&mut self, path_prefix: &str) -> Result<usize> {
+    let deleted_count = self.inner.delete_docs_by_path_prefix(path_prefix)?;
+    self
+      .semantic_index
+      .lock()
+      .delete_docs_by_path_prefix(path_prefix);
+    Ok(deleted_count)
+  }
+
+  fn has_doc(&self, file_path: &str) -> Result<bool> {
+    self.inner.has_doc(file_path)
+  }
+
+  fn has_docs_with_path_prefix(&self, path_prefix: &str) -> Result<bool> {
+    self.inner.has_docs_with_path_prefix(path_prefix)
+  }
+
+  fn indexed_paths(&self) -> Result<BTreeSet<String>> {
+    self.inner.indexed_paths()
+  }
+
+  fn indexed_at(&self, file_path: &str) -> Result<Option<u64>> {
+    self.inner.indexed_at(file_path)
+  }
+
+  fn commit(&mut self) -> Result<()> {
+    self.inner.commit()?;
+    self.semantic_index.lock().save()
+  }
+
+  fn optimize(&mut self) -> Result<()> {
+    self.inner.optimize()
+  }
+
+  fn generation(&self) -> u64 {
+    self.inner.generation()
+  }
+
+  fn get_pending_operations(&self) -> usize {
+    self.inner.get_pending_operations()
+  }
+
+  fn num_docs(&self) -> u64 {
+    self.inner.num_docs()
+  }
+
+  fn memory_usage(&self) -> IndexMemory {
+    self.inner.memory_usage()
+  }
+
+  fn clear(&mut self) -> Result<()> {
+    self.inner.clear()?;
+    self.semantic_index.lock().clear();
+    Ok(())
+  }
+
+  fn search_hits(&self, keyword: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_hits(keyword, limit)
+  }
+
+  fn search_hits_with(
+    &self,
+    keyword: &str,
+    limit: usize,
+    options: &SearchOptions,
+  ) -> Result<Vec<SearchHit>> {
+    self.inner.search_hits_with(keyword, limit, options)
+  }
+
+  fn tag_counts(&self) -> Result<BTreeMap<String, u64>> {
+    self.inner.tag_counts()
+  }
+
+  fn search_by_tag(&self, tag: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_by_tag(tag, limit)
+  }
+
+  fn search_by_link(&self, link: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_by_link(link, limit)
+  }
+}
+
+/// Semantic indexes of all watch roots, searched with one embedding of the query.
+pub struct SemanticSearch {
+  embedder: Arc<dyn Embedder + Send + Sync>,
+  shards: RwLock<Vec<(String, Arc<Mutex<SemanticIndex>>)>>,
+}
+
+impl SemanticSearch {
+  pub fn new(embedder: Arc<dyn Embedder + Send + Sync>) -> Self {
+    SemanticSearch {
+      embedder,
+      shards: RwLock::new(Vec::new()),
+    }
+  }
+
+  pub fn embedder(&self) -> Arc<dyn Embedder + Send + Sync> {
+    self.embedder.clone()
+  }
+
+  pub fn add_shard(&self, root: &str, semantic_index: Arc<Mutex<SemanticIndex>>) {
+    let mut shards = self.shards.write();
+    shards.retain(|(shard_root, _)| shard_root != root);
+    shards.push((root.to_string(), semantic_index));
+  }
+
+  fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+    self
+      .embedder
+      .embed(&[query.to_string()])?
+      .into_iter()
+      .next()
+      .ok_or_else(|| anyhow::anyhow!("Embedder returned no vector for the query"))
+  }
+
+  pub fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    let query_vector = self.embed_query(query)?;
+    let shards = self.shards.read();
+    let mut hits = shards
+      .iter()
+      .flat_map(|(_, semantic_index)| semantic_index.lock().search_vector(&query_vector, limit))
+      .collect::<Vec<_>>();
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits.truncate(limit);
+    Ok(hits)
+  }
+
+  /// Reranks keyword search `candidates` by a score fusing their BM25 score, normalized by the
+  /// best candidate, with the vector similarity of their best chunk to the query.
+  pub fn rerank(
+    &self,
+    query: &str,
+    candidates: Vec<SearchHit>,
+    limit: usize,
+  ) -> Result<Vec<SearchHit>> {
+    if candidates.is_empty() {
+      return Ok(candidates);
+    }
+    let query_vector = self.embed_query(query)?;
+    let max_score = candidates
+      .iter()
+      .map(|hit| hit.score)
+      .fold(f32::MIN, f32::max)
+      .max(f32::EPSILON);
+    let shards = self.shards.read();
+    let mut hits = candidates
+      .into_iter()
+      .map(|mut hit| {
+        let similarity = shards
+          .iter()
+          .find_map(|(_, semantic_index)| {
+            semantic_index
+              .lock()
+              .similarity(&query_vector, &hit.file_path)
+          })
+          .unwrap_or(0.0);
+        hit.score = (1.0 - HYBRID_VECTOR_WEIGHT) * (hit.score / max_score)
+          + HYBRID_VECTOR_WEIGHT * similarity;
+        hit
+      })
+      .collect::<Vec<_>>();
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits.truncate(limit);
+    Ok(hits)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::search::text_index::TextIndex;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  /// Counts letters, so texts sharing words end up close to each other.
+  #[derive(Default)]
+  struct LetterEmbedder {
+    embedded_texts: AtomicUsize,
+  }
+
+  impl Embedder for LetterEmbedder {
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+      self
+        .embedded_texts
+        .fetch_add(texts.len(), Ordering::Relaxed);
+      Ok(
+        texts
+          .iter()
+          .map(|text| {
+            let mut vector = vec![0.0; 26];
+            for c in text.to_lowercase().chars().filter(char::is_ascii_lowercase) {
+              vector[(c as u8 - b'a') as usize] += 1.0;
+            }
+            vector
+          })
+          .collect(),
+      )
+    }
+  }
+
+  #[test]
+  fn semantic_index_backend_should_keep_vectors_in_sync_with_documents() {
+    let embedder = Arc::new(LetterEmbedder::default());
+    let semantic_search = SemanticSearch::new(embedder.clone());
+    let semantic_index = Arc::new(Mutex::new(SemanticIndex::new(embedder.clone())));
+    semantic_search.add_shard("/notes", semantic_index.clone());
+    let mut backend = SemanticIndexBackend::new(TextIndex::new().unwrap(), semantic_index);
+
+    let zebra = File::new("/notes/zebra.txt".to_string(), "zebra zoo".to_string());
+    backend
+      .add_docs(Box::new(
+        vec![
+          zebra.clone(),
+          File::new("/notes/apple.txt".to_string(), "apple pie".to_string()),
+        ]
+        .into_iter(),
+      ))
+      .unwrap();
+    let hits = semantic_search.search("zoo zebras", 10).unwrap();
+    assert_eq!(hits[0].file_path, "/notes/zebra.txt");
+
+    let embedded_texts = embedder.embedded_texts.load(Ordering::Relaxed);
+    backend.replace_doc(&zebra).unwrap();
+    assert_eq!(
+      embedder.embedded_texts.load(Ordering::Relaxed),
+      embedded_texts
+    );
+
+    backend.delete_doc("/notes/zebra.txt").unwrap();
+    backend.commit().unwrap();
+    let hits = semantic_search.search("zoo zebras", 10).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].file_path, "/notes/apple.txt");
+  }
+
+  #[test]
+  fn semantic_search_should_rerank_keyword_candidates_by_similarity() {
+    let embedder = Arc::new(LetterEmbedder::default());
+    let semantic_search = SemanticSearch::new(embedder.clone());
+    let semantic_index = Arc::new(Mutex::new(SemanticIndex::new(embedder)));
+    semantic_search.add_shard("/notes", semantic_index.clone());
+    for (path, content) in [("/notes/a.txt", "xxxx yyyy"), ("/notes/b.txt", "zebra zoo")] {
+      semantic_index
+        .lock()
+        .add_doc(&File::new(path.to_string(), content.to_string()))
+        .unwrap();
+    }
+    let candidate = |file_path: &str, score: f32| SearchHit {
+      file_path: file_path.to_string(),
+      score,
+      line_number: None,
+      snippet: None,
+      section: None,
+      metadata: BTreeMap::new(),
+    };
+
+    let hits = semantic_search
+      .rerank(
+        "zebra zoo",
+        vec![
+          candidate("/notes/a.txt", 2.0),
+          candidate("/notes/b.txt", 1.6),
+        ],
+        10,
+      )
+      .unwrap();
+    assert_eq!(hits[0].file_path, "/notes/b.txt");
+    assert!((hits[0].score - (0.5 * 0.8 + 0.5)).abs() < 1e-5);
+    assert!((hits[1].score - 0.5).abs() < 1e-5);
+  }
+
+  #[test]
+  fn split_into_pieces_should_merge_paragraphs_up_to_piece_size() {
+    let long_paragraph = "a".repeat(MAX_CHUNK_CHARS + 10);
+    let content = format!("first\n\nsecond\n\n{}", long_paragraph);
+    let pieces = split_into_pieces(&content);
+    assert_eq!(pieces[0], (0, "first\n\nsecond\n\n"));
+    assert_eq!(pieces[1].0, "first\n\nsecond\n\n".len());
+    assert_eq!(pieces[1].1.chars().count(), MAX_CHUNK_CHARS);
+    assert_eq!(pieces[2].1, "a".repeat(10));
+  }
+}
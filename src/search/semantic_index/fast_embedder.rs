@@ -0,0 +1,28 @@
+use anyhow::Result;
+use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
+
+use super::Embedder;
+
+/// Computes embeddings locally with an ONNX model run by fastembed. The model is downloaded into
+/// `cache_dir` on first use.
+pub struct FastEmbedder {
+  model: TextEmbedding,
+}
+
+impl FastEmbedder {
+  pub fn new(cache_dir: Option<std::path::PathBuf>) -> Result<Self> {
+    let mut options = InitOptions::new(EmbeddingModel::AllMiniLML6V2);
+    if let Some(cache_dir) = cache_dir {
+      options = options.with_cache_dir(cache_dir);
+    }
+    Ok(FastEmbedder {
+      model: TextEmbedding::try_new(options)?,
+    })
+  }
+}
+
+impl Embedder for FastEmbedder {
+  fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+    self.model.embed(texts.to_vec(), None)
+  }
+}
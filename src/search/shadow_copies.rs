@@ -0,0 +1,291 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::{
+  collections::{BTreeMap, BTreeSet, HashMap},
+  path::Path,
+  sync::Arc,
+};
+
+use super::{
+  file::{CONTAINER_MEMBER_SEPARATOR, File, SECTION_SEPARATOR},
+  index_backend::{IndexBackend, IndexMemory, SearchHit, SearchOptions, unix_time_ms},
+  line_diff::unified_diff,
+};
+
+/// Documents larger than this are not copied, as diffs of them would not be read anyway.
+const MAX_SHADOW_COPY_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone)]
+struct Version {
+  content: String,
+  indexed_at_ms: u64,
+}
+
+#[derive(Debug, Clone)]
+struct ShadowCopy {
+  previous: Option<Version>,
+  current: Version,
+  /// Deleted from the index since the last commit, and forgotten then unless added again
+  deleted: bool,
+}
+
+/// What changed in a document between the two last index passes that changed it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct DocumentDiff {
+  pub document_path: String,
+  pub previous_indexed_at_ms: u64,
+  pub indexed_at_ms: u64,
+  /// Unified diff of the lines
+  pub diff: String,
+}
+
+/// The current and previous indexed content of every document of all watch roots, kept in memory
+/// so that agents can see what changed in a file since the index pass before. Only changes made
+/// while the server runs are known.
+#[derive(Default)]
+pub struct ShadowCopies {
+  copies: Mutex<HashMap<String, ShadowCopy>>,
+}
+
+impl ShadowCopies {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Keeps the content of a document just indexed. The content it replaces becomes the previous
+  /// version, unless it is the same.
+  fn record(&self, file: &File) {
+    let mut copies = self.copies.lock();
+    if file.content.len() > MAX_SHADOW_COPY_SIZE {
+      copies.remove(&file.path);
+      return;
+    }
+    let version = Version {
+      content: file.content.clone(),
+      indexed_at_ms: unix_time_ms(),
+    };
+    match copies.get_mut(&file.path) {
+      Some(copy) => {
+        copy.deleted = false;
+        if copy.current.content != file.content {
+          copy.previous = Some(std::mem::replace(&mut copy.current, version));
+        }
+      }
+      None => {
+        copies.insert(
+          file.path.clone(),
+          ShadowCopy {
+            previous: None,
+            current: version,
+            deleted: false,
+          },
+        );
+      }
+    }
+  }
+
+  /* Modified files are deleted and added again before a commit, which must not lose their copy */
+  fn mark_deleted(&self, is_deleted: impl Fn(&str) -> bool) {
+    for (path, copy) in self.copies.lock().iter_mut() {
+      if is_deleted(path) {
+        copy.deleted = true;
+      }
+    }
+  }
+
+  fn forget_deleted(&self) {
+    self.copies.lock().retain(|_, copy| !copy.deleted);
+  }
+
+  /// Diffs of the documents of a file: the file itself, or its chunks, sections or members, in
+  /// path order. Documents without a previous version are left out.
+  pub fn diffs(&self, file_path: &str, context: usize) -> Vec<DocumentDiff> {
+    let copies = self.copies.lock();
+    let mut diffs = copies
+      .iter()
+      .filter(|(path, _)| {
+        path.strip_prefix(file_path).is_some_and(|rest| {
+          rest.is_empty()
+            || rest.starts_with(SECTION_SEPARATOR)
+            || rest.starts_with(CONTAINER_MEMBER_SEPARATOR)
+        })
+      })
+      .filter_map(|(path, copy)| {
+        let previous = copy.previous.as_ref()?;
+        Some(DocumentDiff {
+          document_path: path.clone(),
+          previous_indexed_at_ms: previous.indexed_at_ms,
+          indexed_at_ms: copy.current.indexed_at_ms,
+          diff: unified_diff(&previous.content, &copy.current.content, context),
+        })
+      })
+      .collect::<Vec<_>>();
+    diffs.sort_by(|a, b| a.document_path.cmp(&b.document_path));
+    diffs
+  }
+}
+
+/// Keeps the `ShadowCopies` of the documents written to the wrapped backend up to date.
+pub struct ShadowCopyBackend<B: IndexBackend> {
+  inner: B,
+  root: String,
+  shadow_copies: Arc<ShadowCopies>,
+}
+
+impl<B: IndexBackend> ShadowCopyBackend<B> {
+  pub fn new(inner: B, root: &str, shadow_copies: Arc<ShadowCopies>) -> Self {
+    ShadowCopyBackend {
+      inner,
+      root: root.to_string(),
+      shadow_copies,
+    }
+  }
+}
+
+impl<B: IndexBackend> IndexBackend for ShadowCopyBackend<B> {
+  fn add_doc(&mut self, file: &File) -> Result<()> {
+    self.inner.add_doc(file)?;
+    self.shadow_copies.record(file);
+    Ok(())
+  }
+
+  fn add_docs(&mut self, files: Box<dyn Iterator<Item = File> + '_>) -> Result<usize> {
+    let shadow_copies = self.shadow_copies.clone();
+    let files = files.inspect(move |file| shadow_copies.record(file));
+    let added_count = self.inner.add_docs(Box::new(files))?;
+    self.shadow_copies.forget_deleted();
+    Ok(added_count)
+  }
+
+  fn replace_doc(&mut self, file: &File) -> Result<()> {
+    self.inner.replace_doc(file)?;
+    self.shadow_copies.record(file);
+    Ok(())
+  }
+
+  fn delete_doc(&mut self, file_path: &str) -> Result<()> {
+    self.inner.delete_doc(file_path)?;
+    self.shadow_copies.mark_deleted(|path| path == file_path);
+    Ok(())
+  }
+
+  fn delete_docs_by_path_prefix(&mut self, path_prefix: &str) -> Result<usize> {
+    let deleted_count = self.inner.delete_docs_by_path_prefix(path_prefix)?;
+    self
+      .shadow_copies
+      .mark_deleted(|path| path.starts_with(path_prefix));
+    Ok(deleted_count)
+  }
+
+  fn has_doc(&self, file_path: &str) -> Result<bool> {
+    self.inner.has_doc(file_path)
+  }
+
+  fn has_docs_with_path_prefix(&self, path_prefix: &str) -> Result<bool> {
+    self.inner.has_docs_with_path_prefix(path_prefix)
+  }
+
+  fn indexed_paths(&self) -> Result<BTreeSet<String>> {
+    self.inner.indexed_paths()
+  }
+
+  fn indexed_at(&self, file_path: &str) -> Result<Option<u64>> {
+    self.inner.indexed_at(file_path)
+  }
+
+  fn commit(&mut self) -> Result<()> {
+    self.inner.commit()?;
+    self.shadow_copies.forget_deleted();
+    Ok(())
+  }
+
+  fn optimize(&mut self) -> Result<()> {
+    self.inner.optimize()
+  }
+
+  fn generation(&self) -> u64 {
+    self.inner.generation()
+  }
+
+  fn get_pending_operations(&self) -> usize {
+    self.inner.get_pending_operations()
+  }
+
+  fn num_docs(&self) -> u64 {
+    self.inner.num_docs()
+  }
+
+  fn memory_usage(&self) -> IndexMemory {
+    self.inner.memory_usage()
+  }
+
+  /// Copies are only marked deleted, so that a rebuild still shows what changed meanwhile.
+  fn clear(&mut self) -> Result<()> {
+    self.inner.clear()?;
+    let root = Path::new(&self.root);
+    self
+      .shadow_copies
+      .mark_deleted(|path| Path::new(path).starts_with(root));
+    Ok(())
+  }
+
+  fn search_hits(&self, keyword: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_hits(keyword, limit)
+  }
+
+  fn search_hits_with(
+    &self,
+    keyword: &str,
+    limit: usize,
+    options: &SearchOptions,
+  ) -> Result<Vec<SearchHit>> {
+    self.inner.search_hits_with(keyword, limit, options)
+  }
+
+  fn tag_counts(&self) -> Result<BTreeMap<String, u64>> {
+    self.inner.tag_counts()
+  }
+
+  fn search_by_tag(&self, tag: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_by_tag(tag, limit)
+  }
+
+  fn search_by_link(&self, link: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    self.inner.search_by_link(link, limit)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::search::text_index::TextIndex;
+
+  #[test]
+  fn shadow_copies_should_diff_the_last_two_indexed_versions() {
+    let shadow_copies = Arc::new(ShadowCopies::new());
+    let mut index =
+      ShadowCopyBackend::new(TextIndex::new().unwrap(), "/notes", shadow_copies.clone());
+    let path = "/notes/plan.md".to_string();
+
+    index
+      .add_doc(&File::new(path.clone(), "water\nweed".to_string()))
+      .unwrap();
+    index.commit().unwrap();
+    assert!(shadow_copies.diffs(&path, 3).is_empty());
+
+    /* A modification deletes the document and adds it again before committing */
+    index.delete_doc(&path).unwrap();
+    index
+      .add_doc(&File::new(path.clone(), "water\nharvest".to_string()))
+      .unwrap();
+    index.commit().unwrap();
+    let diffs = shadow_copies.diffs(&path, 3);
+    assert_eq!(diffs.len(), 1);
+    assert_eq!(diffs[0].diff, "@@ -1,2 +1,2 @@\n water\n-weed\n+harvest\n");
+    assert!(shadow_copies.diffs("/notes/plan", 3).is_empty());
+
+    index.delete_doc(&path).unwrap();
+    index.commit().unwrap();
+    assert!(shadow_copies.diffs(&path, 3).is_empty());
+  }
+}
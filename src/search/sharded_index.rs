@@ -0,0 +1,1150 @@
+use anyhow::Result;
+use parking_lot::{Mutex, RwLock};
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  path::{Path, PathBuf},
+  sync::{
+    Arc,
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+  },
+};
+
+use super::{
+  bookmarks::Bookmarks,
+  cancellation::check_cancelled,
+  commit_status::{CommitFailure, CommitStatus},
+  file::{
+    FileOperation, FileOperationHandler, chunk_path, chunk_prefix, document_file_path,
+    is_section_file, link_keys, section_prefix, wikilink_key,
+  },
+  index_backend::{IndexBackend, IndexMemory, SEARCH_FILE_LIMIT, SearchHit, SearchOptions},
+  memory_budget::{MemoryBudget, MemoryUsage},
+  project_index::default_project_name,
+  saved_filters::SavedFilters,
+  search_cache::SearchCache,
+};
+use crate::file::{http_file_loader::is_url, path_normalizer::PathNormalizer};
+
+/// Health of a shard, updated by its file watcher and index worker and reported by `stats`.
+#[derive(Clone, Default)]
+pub struct ShardHealth {
+  pub watcher_restarts: Arc<AtomicU64>,
+  pub commit_status: Arc<CommitStatus>,
+  /// Changes queued for the index worker and not indexed yet
+  pub queued_operations: Arc<AtomicUsize>,
+}
+
+pub struct IndexShard {
+  project: String,
+  root: String,
+  /// Canonical form of the root, which the paths of its documents start with
+  path_root: PathBuf,
+  index: Arc<Mutex<dyn IndexBackend>>,
+  health: ShardHealth,
+  /// Queues operations of the shard ahead of the changes waiting to be indexed
+  reindex_handler: Option<Arc<FileOperationHandler>>,
+}
+
+impl IndexShard {
+  pub fn project(&self) -> &str {
+    &self.project
+  }
+
+  pub fn root(&self) -> &str {
+    &self.root
+  }
+
+  pub fn path_root(&self) -> &Path {
+    &self.path_root
+  }
+
+  fn holds(&self, path: &Path) -> bool {
+    path.starts_with(&self.path_root)
+  }
+
+  pub fn index(&self) -> Arc<Mutex<dyn IndexBackend>> {
+    self.index.clone()
+  }
+
+  pub fn health(&self) -> ShardHealth {
+    self.health.clone()
+  }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ShardStats {
+  pub project: String,
+  pub root: String,
+  pub num_docs: u64,
+  /// Changes queued for the index worker and not indexed yet
+  pub pending_operations: usize,
+  pub watcher_restarts: u64,
+  /// Set while changes cannot be committed, e.g. because the disk is full
+  pub commit_failure: Option<CommitFailure>,
+  pub memory: IndexMemory,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ProjectStats {
+  pub name: String,
+  pub roots: Vec<String>,
+  pub num_docs: u64,
+}
+
+/// A link of a note and the indexed files it points at, none if it is broken.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ResolvedLink {
+  pub link: String,
+  pub file_paths: Vec<String>,
+}
+
+/// Restricts a search to some shards. The default scope searches every shard.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SearchScope {
+  pub project: Option<String>,
+  /// Roots given by their full path or their last path component, e.g. `notes`
+  pub roots: Option<Vec<String>>,
+  /// Directory or file the results must lie under
+  pub path: Option<String>,
+  /// Saved filters the results must pass, or fail when prefixed with `-`
+  pub filters: Option<Vec<String>>,
+}
+
+impl SearchScope {
+  fn includes(&self, shard: &IndexShard) -> bool {
+    let in_project = self
+      .project
+      .as_ref()
+      .is_none_or(|project| shard.project == *project);
+    let in_roots = self.roots.as_ref().is_none_or(|roots| {
+      roots
+        .iter()
+        .any(|root| shard.root == *root || default_project_name(&shard.root) == *root)
+    });
+    let in_path = self.path.as_ref().is_none_or(|path| {
+      let path = document_path(path);
+      shard.holds(&path) || shard.path_root.starts_with(&path)
+    });
+    in_project && in_roots && in_path
+  }
+}
+
+/// Hits of each query intersected by `search_all`, so that files ranked low by one query can
+/// still be found by the query narrowing it.
+const NARROWING_CANDIDATE_LIMIT: usize = 1000;
+
+/// Holds one index backend per watch root and fans searches out across all of them.
+pub struct ShardedIndex {
+  shards: RwLock<Vec<IndexShard>>,
+  search_cache: SearchCache,
+  bookmarks: Option<Arc<Bookmarks>>,
+  /// Ranking of the keyword searches of every shard
+  search_options: SearchOptions,
+  saved_filters: Arc<SavedFilters>,
+  memory_budget: Option<MemoryBudget>,
+}
+
+impl ShardedIndex {
+  pub fn new() -> Self {
+    Self {
+      shards: RwLock::new(Vec::new()),
+      search_cache: SearchCache::default(),
+      bookmarks: None,
+      search_options: SearchOptions::default(),
+      saved_filters: Arc::new(SavedFilters::default()),
+      memory_budget: None,
+    }
+  }
+
+  /// Lists pinned documents first among the results of every search.
+  pub fn with_bookmarks(mut self, bookmarks: Arc<Bookmarks>) -> Self {
+    self.bookmarks = Some(bookmarks);
+    self
+  }
+
+  /// Multiplies the score of hits by the factor of their file extension, so that authoritative
+  /// document types rank above noisy ones.
+  pub fn with_extension_boosts(mut self, extension_boosts: BTreeMap<String, f32>) -> Self {
+    self.search_options.extension_boosts = extension_boosts;
+    self
+  }
+
+  /// Filters searches can name in their scope, e.g. `work-notes`.
+  pub fn with_saved_filters(mut self, saved_filters: Arc<SavedFilters>) -> Self {
+    self.saved_filters = saved_filters;
+    self
+  }
+
+  /// Sizes the search cache to the budget, and reports memory against it.
+  pub fn with_memory_budget(mut self, memory_budget: MemoryBudget) -> Self {
+    self.search_cache = SearchCache::new(memory_budget.search_cache_entries);
+    self.memory_budget = Some(memory_budget);
+    self
+  }
+
+  pub fn saved_filters(&self) -> Arc<SavedFilters> {
+    self.saved_filters.clone()
+  }
+
+  /// Adds the shard to the project named after the last component of its root.
+  pub fn add_shard(&self, root: &str, index: Arc<Mutex<dyn IndexBackend>>) -> Result<()> {
+    self.add_project_shard(&default_project_name(root), root, index)
+  }
+
+  pub fn add_project_shard(
+    &self,
+    project: &str,
+    root: &str,
+    index: Arc<Mutex<dyn IndexBackend>>,
+  ) -> Result<()> {
+    let mut shards = self.shards.write();
+    shards.retain(|shard| shard.root != root);
+    shards.push(IndexShard {
+      project: project.to_string(),
+      root: root.to_string(),
+      path_root: document_path(root),
+      index,
+      health: ShardHealth::default(),
+      reindex_handler: None,
+    });
+    Ok(())
+  }
+
+  pub fn remove_shard(&self, root: &str) -> Result<Option<Arc<Mutex<dyn IndexBackend>>>> {
+    let mut shards = self.shards.write();
+    let removed = shards
+      .iter()
+      .position(|shard| shard.root == root)
+      .map(|position| shards.remove(position).index);
+    Ok(removed)
+  }
+
+  pub fn shard(&self, root: &str) -> Result<Option<Arc<Mutex<dyn IndexBackend>>>> {
+    let shards = self.shards.read();
+    Ok(
+      shards
+        .iter()
+        .find(|shard| shard.root == root)
+        .map(IndexShard::index),
+    )
+  }
+
+  /// Health counters of the root for its watcher and index worker to update.
+  pub fn health(&self, root: &str) -> Result<Option<ShardHealth>> {
+    let shards = self.shards.read();
+    Ok(
+      shards
+        .iter()
+        .find(|shard| shard.root == root)
+        .map(IndexShard::health),
+    )
+  }
+
+  /// Lets searches queue re-indexing of the root with `prioritize`.
+  pub fn set_reindex_handler(&self, root: &str, handler: Box<FileOperationHandler>) -> Result<()> {
+    let mut shards = self.shards.write();
+    if let Some(shard) = shards.iter_mut().find(|shard| shard.root == root) {
+      shard.reindex_handler = Some(Arc::from(handler));
+    }
+    Ok(())
+  }
+
+  /// Queues the directory or file for re-indexing ahead of other changes in the shards in scope
+  /// holding it, so that a search for it soon sees it fresh. Returns whether any shard took it.
+  pub fn prioritize(&self, path: &str, scope: &SearchScope) -> Result<bool> {
+    let path = document_path(path);
+    let path_str = path.to_string_lossy().to_string();
+    let operation = match path.as_path() {
+      dir if dir.is_dir() => FileOperation::DirectoryRescan(path_str),
+      file if file.is_file() => FileOperation::FileModified(path_str),
+      _ => return Ok(false),
+    };
+    let shards = self.shards.read();
+    let mut queued = false;
+    for shard in select_shards(&shards, scope)? {
+      if let Some(handler) = &shard.reindex_handler
+        && shard.holds(&path)
+      {
+        handler(&operation)?;
+        queued = true;
+      }
+    }
+    Ok(queued)
+  }
+
+  /// Queues a change the server made itself, such as a file it deleted, ahead of other changes in
+  /// the shards holding its paths, rather than waiting for the watcher to report it. Returns
+  /// whether any shard took it.
+  pub fn enqueue_operation(&self, operation: &FileOperation) -> Result<bool> {
+    let shards = self.shards.read();
+    let mut queued = false;
+    for shard in shards.iter() {
+      let Some(handler) = &shard.reindex_handler else {
+        continue;
+      };
+      if operation
+        .paths()
+        .iter()
+        .any(|path| shard.holds(Path::new(path)) || shard.holds(&document_path(path)))
+      {
+        handler(operation)?;
+        queued = true;
+      }
+    }
+    Ok(queued)
+  }
+
+  /// Whether the shards in scope still have changes queued for their index worker.
+  pub fn has_pending_operations(&self, scope: &SearchScope) -> Result<bool> {
+    let shards = self.shards.read();
+    Ok(
+      select_shards(&shards, scope)?
+        .iter()
+        .any(|shard| shard.health.queued_operations.load(Ordering::Relaxed) > 0),
+    )
+  }
+
+  /// Roots in scope whose changes are failing to be committed, with the failure.
+  pub fn commit_failures(&self, scope: &SearchScope) -> Result<Vec<(String, CommitFailure)>> {
+    let shards = self.shards.read();
+    Ok(
+      select_shards(&shards, scope)?
+        .iter()
+        .filter_map(|shard| {
+          let failure = shard.health.commit_status.failure()?;
+          Some((shard.root.clone(), failure))
+        })
+        .collect(),
+    )
+  }
+
+  pub fn roots(&self) -> Result<Vec<String>> {
+    let shards = self.shards.read();
+    Ok(shards.iter().map(|shard| shard.root.clone()).collect())
+  }
+
+  /// Merges the segments of every shard, one at a time. A shard cannot be searched while its
+  /// merge runs.
+  pub fn optimize(&self) -> Result<()> {
+    let indexes = self
+      .shards
+      .read()
+      .iter()
+      .map(IndexShard::index)
+      .collect::<Vec<_>>();
+    for index in indexes {
+      index.lock().optimize()?;
+    }
+    Ok(())
+  }
+
+  pub fn stats(&self) -> Result<Vec<ShardStats>> {
+    let shards = self.shards.read();
+    shards
+      .iter()
+      .map(|shard| {
+        let index = shard.index.lock();
+        Ok(ShardStats {
+          project: shard.project.clone(),
+          root: shard.root.clone(),
+          num_docs: index.num_docs(),
+          pending_operations: shard.health.queued_operations.load(Ordering::Relaxed),
+          watcher_restarts: shard.health.watcher_restarts.load(Ordering::Relaxed),
+          commit_failure: shard.health.commit_status.failure(),
+          memory: index.memory_usage(),
+        })
+      })
+      .collect()
+  }
+
+  pub fn memory_usage(&self) -> Result<MemoryUsage> {
+    let (writer_heap_bytes, in_memory_index_bytes) = self
+      .shards
+      .read()
+      .iter()
+      .map(|shard| shard.index.lock().memory_usage())
+      .fold((0, 0), |(writer_heap, in_memory), memory| {
+        (
+          writer_heap + memory.writer_heap_bytes,
+          in_memory + memory.in_memory_bytes,
+        )
+      });
+    let (search_cache_entries, search_cache_bytes) = self.search_cache.usage();
+    let over_budget = self
+      .memory_budget
+      .is_some_and(|budget| in_memory_index_bytes > budget.in_memory_index_bytes);
+    Ok(MemoryUsage {
+      budget: self.memory_budget,
+      writer_heap_bytes,
+      search_cache_entries,
+      search_cache_bytes,
+      in_memory_index_bytes,
+      over_budget,
+    })
+  }
+
+  /// Roots and document count of every project, in the order their first root was added.
+  pub fn projects(&self) -> Result<Vec<ProjectStats>> {
+    let shards = self.shards.read();
+    let mut projects: Vec<ProjectStats> = Vec::new();
+    for shard in shards.iter() {
+      let num_docs = shard.index.lock().num_docs();
+      match projects
+        .iter_mut()
+        .find(|project| project.name == shard.project)
+      {
+        Some(project) => {
+          project.roots.push(shard.root.clone());
+          project.num_docs += num_docs;
+        }
+        None => projects.push(ProjectStats {
+          name: shard.project.clone(),
+          roots: vec![shard.root.clone()],
+          num_docs,
+        }),
+      }
+    }
+    Ok(projects)
+  }
+
+  pub fn search(&self, keyword: &str) -> Result<Vec<String>> {
+    self.search_scoped(keyword, &SearchScope::default())
+  }
+
+  pub fn search_scoped(&self, keyword: &str, scope: &SearchScope) -> Result<Vec<String>> {
+    let shards = self.shards.read();
+    let shards = select_shards(&shards, scope)?;
+
+    /* All shards stay locked so that no commit slips between validating and searching */
+    let indexes = shards
+      .iter()
+      .map(|shard| shard.index.lock())
+      .collect::<Vec<_>>();
+    let generations = shards
+      .iter()
+      .zip(&indexes)
+      .map(|(shard, index)| (shard.root.clone(), index.generation()))
+      .collect::<Vec<_>>();
+    let bookmarks_generation = self
+      .bookmarks
+      .as_ref()
+      .map(|bookmarks| bookmarks.generation());
+    let cache_key = format!(
+      "{}\0{}\0{:?}\0{:?}",
+      keyword, SEARCH_FILE_LIMIT, scope, bookmarks_generation
+    );
+    if let Some(results) = self.search_cache.get(&cache_key, &generations) {
+      return Ok(results);
+    }
+
+    let options = self.scoped_options(scope)?;
+    let mut hits = Vec::new();
+    for index in &indexes {
+      check_cancelled()?;
+      hits.extend(index.search_hits_with(keyword, SEARCH_FILE_LIMIT, &options)?);
+    }
+    let results = self
+      .top_hits(hits, SEARCH_FILE_LIMIT)
+      .iter()
+      .map(|hit| serde_json::to_string(hit).map_err(anyhow::Error::from))
+      .collect::<Result<Vec<_>>>()?;
+    self
+      .search_cache
+      .insert(cache_key, generations, results.clone());
+    Ok(results)
+  }
+
+  /// Best `limit` hits across the shards in scope, bypassing the result cache.
+  pub fn search_hits(
+    &self,
+    keyword: &str,
+    limit: usize,
+    scope: &SearchScope,
+  ) -> Result<Vec<SearchHit>> {
+    let options = self.scoped_options(scope)?;
+    let shards = self.shards.read();
+    let mut hits = Vec::new();
+    for shard in select_shards(&shards, scope)? {
+      check_cancelled()?;
+      hits.extend(
+        shard
+          .index
+          .lock()
+          .search_hits_with(keyword, limit, &options)?,
+      );
+    }
+    Ok(self.top_hits(hits, limit))
+  }
+
+  /// Best `limit` hits of files matching every query, ranked by the last one, e.g. a search
+  /// narrowed down by further queries.
+  pub fn search_all(
+    &self,
+    queries: &[String],
+    limit: usize,
+    scope: &SearchScope,
+  ) -> Result<Vec<SearchHit>> {
+    let Some((last, narrowed)) = queries.split_last() else {
+      return Ok(Vec::new());
+    };
+    let mut hits = self.search_hits(last, NARROWING_CANDIDATE_LIMIT, scope)?;
+    for query in narrowed {
+      let matching_files = self
+        .search_hits(query, NARROWING_CANDIDATE_LIMIT, scope)?
+        .iter()
+        .map(|hit| document_file_path(&hit.file_path).to_string())
+        .collect::<BTreeSet<_>>();
+      hits.retain(|hit| matching_files.contains(document_file_path(&hit.file_path)));
+    }
+    hits.truncate(limit);
+    Ok(hits)
+  }
+
+  /// Whether any shard holds the document, or chunks or sections of it.
+  pub fn has_doc(&self, file_path: &str) -> Result<bool> {
+    let shards = self.shards.read();
+    for shard in shards.iter() {
+      let index = shard.index.lock();
+      if index.has_doc(file_path)?
+        || index.has_docs_with_path_prefix(&chunk_prefix(file_path))?
+        || (is_section_file(file_path)
+          && index.has_docs_with_path_prefix(&section_prefix(file_path))?)
+      {
+        return Ok(true);
+      }
+    }
+    Ok(false)
+  }
+
+  /// When the file was last indexed, in milliseconds since the Unix epoch. A file split into
+  /// chunks is indexed all at once, so its first chunk tells.
+  pub fn indexed_at(&self, file_path: &str) -> Result<Option<u64>> {
+    let shards = self.shards.read();
+    for shard in shards.iter() {
+      let index = shard.index.lock();
+      if let Some(indexed_at) = index.indexed_at(file_path)? {
+        return Ok(Some(indexed_at));
+      }
+      if let Some(indexed_at) = index.indexed_at(&chunk_path(file_path, 0))? {
+        return Ok(Some(indexed_at));
+      }
+    }
+    Ok(None)
+  }
+
+  /// Ranking of every search with the path and saved filters of the scope.
+  fn scoped_options(&self, scope: &SearchScope) -> Result<SearchOptions> {
+    let filter = match &scope.filters {
+      Some(names) => self.saved_filters.compile(names)?,
+      None => Arc::default(),
+    };
+    Ok(SearchOptions {
+      path: scope
+        .path
+        .as_deref()
+        .map(|path| document_path(path).to_string_lossy().to_string()),
+      filter,
+      ..self.search_options.clone()
+    })
+  }
+
+  fn top_hits(&self, mut hits: Vec<SearchHit>, limit: usize) -> Vec<SearchHit> {
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    if let Some(bookmarks) = &self.bookmarks {
+      bookmarks.rank(&mut hits);
+    }
+    hits.truncate(limit);
+    hits
+  }
+
+  /// Documents linking to the file by its path or its wikilink name, one per linking file.
+  pub fn backlinks(
+    &self,
+    file_path: &str,
+    limit: usize,
+    scope: &SearchScope,
+  ) -> Result<Vec<SearchHit>> {
+    let shards = self.shards.read();
+    let mut hits = Vec::new();
+    for shard in select_shards(&shards, scope)? {
+      let index = shard.index.lock();
+      for link in link_keys(file_path) {
+        hits.extend(index.search_by_link(&link, limit)?);
+      }
+    }
+    /* Sections of a note share its links, so keep one hit per linking file */
+    let mut linking_files = BTreeSet::new();
+    hits.retain(|hit| {
+      let linking_file = document_file_path(&hit.file_path);
+      linking_file != file_path && linking_files.insert(linking_file.to_string())
+    });
+    Ok(self.top_hits(hits, limit))
+  }
+
+  /// Indexed files each link target points at: the file itself for a path, or every file of
+  /// that name for a wikilink.
+  pub fn resolve_links(&self, links: &[&str]) -> Result<Vec<ResolvedLink>> {
+    let mut files_by_name: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    if links.iter().any(|link| link.starts_with("[[")) {
+      let shards = self.shards.read();
+      for shard in shards.iter() {
+        for path in shard.index.lock().indexed_paths()? {
+          let file_path = document_file_path(&path);
+          files_by_name
+            .entry(wikilink_key(file_path))
+            .or_default()
+            .insert(file_path.to_string());
+        }
+      }
+    }
+    links
+      .iter()
+      .map(|link| {
+        let file_paths = if link.starts_with("[[") {
+          files_by_name
+            .get(*link)
+            .map(|paths| paths.iter().cloned().collect())
+            .unwrap_or_default()
+        } else if self.has_doc(link)? {
+          vec![link.to_string()]
+        } else {
+          Vec::new()
+        };
+        Ok(ResolvedLink {
+          link: link.to_string(),
+          file_paths,
+        })
+      })
+      .collect()
+  }
+
+  /// Document counts per tag, summed over the shards in scope.
+  pub fn tag_counts(&self, scope: &SearchScope) -> Result<BTreeMap<String, u64>> {
+    let shards = self.shards.read();
+    let mut counts = BTreeMap::new();
+    for shard in select_shards(&shards, scope)? {
+      for (tag, count) in shard.index.lock().tag_counts()? {
+        *counts.entry(tag).or_default() += count;
+      }
+    }
+    Ok(counts)
+  }
+
+  /// Paths of the files indexed in the shards in scope, chunks and sections counted as their
+  /// file.
+  pub fn indexed_paths(&self, scope: &SearchScope) -> Result<BTreeSet<String>> {
+    let shards = self.shards.read();
+    let mut file_paths = BTreeSet::new();
+    for shard in select_shards(&shards, scope)? {
+      for path in shard.index.lock().indexed_paths()? {
+        let file_path = document_file_path(&path);
+        let in_path = scope
+          .path
+          .as_ref()
+          .is_none_or(|path| Path::new(file_path).starts_with(path));
+        if in_path {
+          file_paths.insert(file_path.to_string());
+        }
+      }
+    }
+    Ok(file_paths)
+  }
+
+  pub fn search_by_tag(
+    &self,
+    tag: &str,
+    limit: usize,
+    scope: &SearchScope,
+  ) -> Result<Vec<SearchHit>> {
+    let shards = self.shards.read();
+    let mut hits = Vec::new();
+    for shard in select_shards(&shards, scope)? {
+      hits.extend(shard.index.lock().search_by_tag(tag, limit)?);
+    }
+    Ok(self.top_hits(hits, limit))
+  }
+}
+
+/// The form documents of `path` are indexed under: canonical for files, as is for URLs.
+pub fn document_path(path: &str) -> PathBuf {
+  if is_url(path) {
+    PathBuf::from(path)
+  } else {
+    PathNormalizer::new(Path::new(path)).root().to_path_buf()
+  }
+}
+
+fn select_shards<'a>(shards: &'a [IndexShard], scope: &SearchScope) -> Result<Vec<&'a IndexShard>> {
+  if let Some(project) = &scope.project
+    && !shards.iter().any(|shard| shard.project == *project)
+  {
+    anyhow::bail!("Unknown project: {}", project);
+  }
+  let selected = shards
+    .iter()
+    .filter(|shard| scope.includes(shard))
+    .collect::<Vec<_>>();
+  /* An empty scope would silently find nothing, which reads like a search without matches */
+  if selected.is_empty() {
+    anyhow::bail!("No watched directory matches {:?}", scope);
+  }
+  Ok(selected)
+}
+
+impl Default for ShardedIndex {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+pub fn shard_directory_name(root: &Path) -> String {
+  /* Each root gets its own subdirectory under --index-dir, named after its absolute path */
+  let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+  root
+    .to_string_lossy()
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+    .collect::<String>()
+    .trim_matches('_')
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::search::{file::File, text_index::TextIndex};
+
+  fn create_shard(files: &[(&str, &str)]) -> Arc<Mutex<dyn IndexBackend>> {
+    let mut index = TextIndex::new().unwrap();
+    for (path, content) in files {
+      index
+        .add_doc(&File::new(path.to_string(), content.to_string()))
+        .unwrap();
+    }
+    index.commit().unwrap();
+    Arc::new(Mutex::new(index))
+  }
+
+  #[test]
+  fn sharded_index_should_search_across_all_shards() {
+    let index = ShardedIndex::new();
+    index
+      .add_shard(
+        "/notes",
+        create_shard(&[("/notes/a.txt", "keyword in notes")]),
+      )
+      .unwrap();
+    index
+      .add_shard("/src", create_shard(&[("/src/b.txt", "keyword in src")]))
+      .unwrap();
+
+    let results = index.search("keyword").unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().any(|r| r.contains("/notes/a.txt")));
+    assert!(results.iter().any(|r| r.contains("/src/b.txt")));
+  }
+
+  #[test]
+  fn sharded_index_should_order_merged_results_by_score() {
+    let index = ShardedIndex::new();
+    index
+      .add_shard(
+        "/weak",
+        create_shard(&[
+          (
+            "/weak/a.txt",
+            "keyword among many other unrelated words in this file",
+          ),
+          ("/weak/b.txt", "nothing to see here"),
+        ]),
+      )
+      .unwrap();
+    index
+      .add_shard(
+        "/strong",
+        create_shard(&[
+          ("/strong/a.txt", "keyword keyword keyword"),
+          ("/strong/b.txt", "nothing to see here"),
+        ]),
+      )
+      .unwrap();
+
+    let results = index.search("keyword").unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(results[0].contains("/strong/a.txt"));
+    assert!(results[1].contains("/weak/a.txt"));
+  }
+
+  #[test]
+  fn sharded_index_should_drop_shard_without_touching_others() {
+    let index = ShardedIndex::new();
+    index
+      .add_shard(
+        "/notes",
+        create_shard(&[("/notes/a.txt", "keyword in notes")]),
+      )
+      .unwrap();
+    index
+      .add_shard("/src", create_shard(&[("/src/b.txt", "keyword in src")]))
+      .unwrap();
+
+    assert!(index.remove_shard("/notes").unwrap().is_some());
+    let results = index.search("keyword").unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].contains("/src/b.txt"));
+    assert_eq!(index.roots().unwrap(), vec!["/src".to_string()]);
+  }
+
+  #[test]
+  fn sharded_index_should_not_return_cached_results_after_commit() {
+    let shard = create_shard(&[("/notes/a.txt", "keyword in notes")]);
+    let index = ShardedIndex::new();
+    index.add_shard("/notes", shard.clone()).unwrap();
+    assert_eq!(index.search("keyword").unwrap().len(), 1);
+    assert_eq!(index.search("keyword").unwrap().len(), 1);
+
+    {
+      let mut shard = shard.lock();
+      shard
+        .add_doc(&File::new(
+          "/notes/b.txt".to_string(),
+          "keyword again".to_string(),
+        ))
+        .unwrap();
+      shard.commit().unwrap();
+    }
+    assert_eq!(index.search("keyword").unwrap().len(), 2);
+  }
+
+  #[test]
+  fn sharded_index_should_search_only_shards_of_requested_project() {
+    let index = ShardedIndex::new();
+    index
+      .add_project_shard(
+        "work",
+        "/work/a",
+        create_shard(&[("/work/a/1.txt", "keyword in a")]),
+      )
+      .unwrap();
+    index
+      .add_project_shard(
+        "work",
+        "/work/b",
+        create_shard(&[("/work/b/2.txt", "keyword in b")]),
+      )
+      .unwrap();
+    index
+      .add_shard(
+        "/notes",
+        create_shard(&[("/notes/3.txt", "keyword in notes")]),
+      )
+      .unwrap();
+
+    let project = |name: &str| SearchScope {
+      project: Some(name.to_string()),
+      roots: None,
+      path: None,
+      filters: None,
+    };
+    assert_eq!(
+      index
+        .search_scoped("keyword", &project("work"))
+        .unwrap()
+        .len(),
+      2
+    );
+    let results = index.search_scoped("keyword", &project("notes")).unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].contains("/notes/3.txt"));
+    assert_eq!(index.search("keyword").unwrap().len(), 3);
+    assert!(index.search_scoped("keyword", &project("missing")).is_err());
+
+    let projects = index.projects().unwrap();
+    assert_eq!(projects.len(), 2);
+    assert_eq!(projects[0].roots, vec!["/work/a", "/work/b"]);
+    assert_eq!(projects[0].num_docs, 2);
+  }
+
+  #[test]
+  fn sharded_index_should_search_only_requested_roots() {
+    let index = ShardedIndex::new();
+    for (root, path) in [
+      ("/home/notes", "/home/notes/a.txt"),
+      ("/home/src", "/home/src/b.txt"),
+      ("/home/docs", "/home/docs/c.txt"),
+    ] {
+      index
+        .add_shard(root, create_shard(&[(path, "keyword")]))
+        .unwrap();
+    }
+
+    let scope = SearchScope {
+      project: None,
+      roots: Some(vec!["notes".to_string(), "/home/src".to_string()]),
+      path: None,
+      filters: None,
+    };
+    let results = index.search_scoped("keyword", &scope).unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(!results.iter().any(|r| r.contains("/home/docs/c.txt")));
+
+    let scope = SearchScope {
+      project: None,
+      roots: Some(vec!["missing".to_string()]),
+      path: None,
+      filters: None,
+    };
+    assert!(index.search_scoped("keyword", &scope).is_err());
+  }
+
+  #[test]
+  fn sharded_index_should_search_and_reindex_only_requested_path() {
+    let root = std::env::temp_dir().to_string_lossy().to_string();
+    let index = ShardedIndex::new();
+    /* Better matches elsewhere must not crowd out the few under the path */
+    let mut files = (0..50)
+      .map(|number| (format!("{}/b/{}.txt", root, number), "keyword keyword"))
+      .collect::<Vec<_>>();
+    files.push((
+      format!("{}/a/1.txt", root),
+      "keyword among many other words",
+    ));
+    files.push((format!("{}/a-old/1.txt", root), "keyword"));
+    files.push((format!("{}/c.md#install", root), "keyword"));
+    let files = files
+      .iter()
+      .map(|(path, content)| (path.as_str(), *content))
+      .collect::<Vec<_>>();
+    index.add_shard(&root, create_shard(&files)).unwrap();
+    index
+      .add_shard("/other", create_shard(&[("/other/3.txt", "keyword")]))
+      .unwrap();
+    let scope = SearchScope {
+      path: Some(format!("{}/a/", root)),
+      ..Default::default()
+    };
+    let results = index.search_scoped("keyword", &scope).unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].contains("/a/1.txt"));
+    let scope = SearchScope {
+      path: Some(format!("{}/c.md", root)),
+      ..Default::default()
+    };
+    let hits = index.search_hits("keyword", 10, &scope).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert!(hits[0].file_path.ends_with("/c.md#install"));
+
+    let queued = Arc::new(Mutex::new(Vec::new()));
+    let handler_queued = queued.clone();
+    index
+      .set_reindex_handler(
+        &root,
+        Box::new(move |op| {
+          handler_queued.lock().push(op.clone());
+          Ok(())
+        }),
+      )
+      .unwrap();
+    let scope = SearchScope {
+      path: Some(root.clone()),
+      ..Default::default()
+    };
+    assert!(index.prioritize(&root, &scope).unwrap());
+    assert!(matches!(
+      queued.lock().as_slice(),
+      [FileOperation::DirectoryRescan(path)] if *path == root
+    ));
+
+    assert!(!index.has_pending_operations(&scope).unwrap());
+    let health = index.health(&root).unwrap().unwrap();
+    health.queued_operations.store(1, Ordering::Relaxed);
+    assert!(index.has_pending_operations(&scope).unwrap());
+  }
+
+  #[test]
+  fn sharded_index_should_scope_relative_root_by_canonical_paths() {
+    let dir = std::env::temp_dir().canonicalize().unwrap().join(format!(
+      "sharded-index-relative-test-{}",
+      std::process::id()
+    ));
+    std::fs::create_dir_all(dir.join("docs")).unwrap();
+    let current_dir = std::env::current_dir().unwrap().canonicalize().unwrap();
+    /* A watch dir given relative to the current dir, like -w proj */
+    let relative_root = current_dir
+      .components()
+      .skip(1)
+      .map(|_| Path::new(".."))
+      .collect::<PathBuf>()
+      .join(dir.strip_prefix("/").unwrap())
+      .to_string_lossy()
+      .to_string();
+    let canonical_docs = dir.join("docs").to_string_lossy().to_string();
+    let index = ShardedIndex::new();
+    index
+      .add_shard(
+        &relative_root,
+        create_shard(&[
+          (&format!("{}/a.txt", canonical_docs), "keyword"),
+          (&format!("{}/b.txt", dir.to_string_lossy()), "keyword"),
+        ]),
+      )
+      .unwrap();
+
+    for path in [canonical_docs.clone(), format!("{}/docs", relative_root)] {
+      let scope = SearchScope {
+        path: Some(path),
+        ..Default::default()
+      };
+      let hits = index.search_hits("keyword", 10, &scope).unwrap();
+      assert_eq!(hits.len(), 1);
+      assert_eq!(hits[0].file_path, format!("{}/a.txt", canonical_docs));
+    }
+
+    let queued = Arc::new(Mutex::new(Vec::new()));
+    let handler_queued = queued.clone();
+    index
+      .set_reindex_handler(
+        &relative_root,
+        Box::new(move |op| {
+          handler_queued.lock().push(op.clone());
+          Ok(())
+        }),
+      )
+      .unwrap();
+    assert!(
+      index
+        .prioritize(&canonical_docs, &SearchScope::default())
+        .unwrap()
+    );
+    assert_eq!(
+      queued.lock().as_slice(),
+      [FileOperation::DirectoryRescan(canonical_docs.clone())]
+    );
+    assert!(
+      index
+        .enqueue_operation(&FileOperation::FileDeleted(format!(
+          "{}/a.txt",
+          canonical_docs
+        )))
+        .unwrap()
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn sharded_index_should_keep_files_matching_every_query() {
+    let index = ShardedIndex::new();
+    index
+      .add_shard(
+        "/notes",
+        create_shard(&[
+          ("/notes/a.txt", "kubernetes ingress"),
+          ("/notes/b.txt", "kubernetes tls"),
+          ("/notes/c.txt", "ingress tls"),
+        ]),
+      )
+      .unwrap();
+    let queries = ["kubernetes".to_string(), "tls".to_string()];
+    let hits = index
+      .search_all(&queries, 10, &SearchScope::default())
+      .unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].file_path, "/notes/b.txt");
+  }
+
+  #[test]
+  fn sharded_index_should_list_pinned_documents_first() {
+    let bookmarks = Arc::new(Bookmarks::new());
+    let index = ShardedIndex::new().with_bookmarks(bookmarks.clone());
+    index
+      .add_shard(
+        "/notes",
+        create_shard(&[
+          ("/notes/strong.txt", "keyword keyword keyword"),
+          (
+            "/notes/weak.txt",
+            "keyword among many other unrelated words",
+          ),
+        ]),
+      )
+      .unwrap();
+    assert!(index.search("keyword").unwrap()[0].contains("/notes/strong.txt"));
+
+    bookmarks.pin("/notes/weak.txt", None).unwrap();
+    let results = index.search("keyword").unwrap();
+    assert!(results[0].contains("/notes/weak.txt"));
+    assert!(index.has_doc("/notes/weak.txt").unwrap());
+  }
+
+  #[test]
+  fn sharded_index_should_rank_boosted_extensions_first() {
+    let index = ShardedIndex::new().with_extension_boosts(BTreeMap::from([
+      ("md".to_string(), 10.0),
+      ("log".to_string(), 0.5),
+    ]));
+    index
+      .add_shard(
+        "/notes",
+        create_shard(&[
+          ("/notes/noisy.log", "keyword keyword keyword"),
+          (
+            "/notes/guide.MD",
+            "keyword among many other unrelated words",
+          ),
+        ]),
+      )
+      .unwrap();
+    let results = index.search("keyword").unwrap();
+    assert!(results[0].contains("/notes/guide.MD"));
+    assert!(results[1].contains("/notes/noisy.log"));
+  }
+
+  #[test]
+  fn sharded_index_should_find_backlinks_by_path_and_wikilink() {
+    use crate::search::file::LINKS_METADATA_KEY;
+
+    let mut vault = TextIndex::new().unwrap();
+    for (path, links) in [
+      ("/vault/plan.md", "/vault/other.md"),
+      ("/vault/daily.md", "[[plan]]"),
+      ("/vault/daily.md#todo", "[[plan]]"),
+      ("/vault/review.md", "/vault/plan.md\n[[missing]]"),
+    ] {
+      let file =
+        File::new(path.to_string(), "note".to_string()).with_metadata(LINKS_METADATA_KEY, links);
+      vault.add_doc(&file).unwrap();
+    }
+    vault.commit().unwrap();
+    let index = ShardedIndex::new();
+    index
+      .add_shard("/vault", Arc::new(Mutex::new(vault)))
+      .unwrap();
+
+    let backlinks = index
+      .backlinks("/vault/plan.md", SEARCH_FILE_LIMIT, &SearchScope::default())
+      .unwrap()
+      .into_iter()
+      .map(|hit| hit.file_path)
+      .collect::<BTreeSet<_>>();
+    assert_eq!(
+      backlinks,
+      BTreeSet::from([
+        "/vault/daily.md".to_string(),
+        "/vault/review.md".to_string()
+      ])
+    );
+
+    let resolved = index.resolve_links(&["[[plan]]", "[[missing]]"]).unwrap();
+    assert_eq!(resolved[0].file_paths, vec!["/vault/plan.md"]);
+    assert!(resolved[1].file_paths.is_empty());
+  }
+
+  #[test]
+  fn shard_directory_name_should_be_filesystem_safe() {
+    assert_eq!(
+      shard_directory_name(Path::new("/not/existing/dir-name")),
+      "not_existing_dir_name"
+    );
+  }
+}
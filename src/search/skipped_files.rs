@@ -0,0 +1,128 @@
+use parking_lot::Mutex;
+use std::{
+  collections::BTreeMap,
+  path::Path,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipKind {
+  /// Left out on purpose, e.g. because it is too large
+  Skipped,
+  /// Could not be read or extracted, e.g. for lack of permissions or an unknown encoding
+  Failed,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SkippedFile {
+  pub path: String,
+  pub kind: SkipKind,
+  pub reason: String,
+  /// Seconds since the Unix epoch
+  pub recorded_at: u64,
+}
+
+/// Files missing from the index, either left out on purpose or failing to load, keyed by path.
+/// A file is forgotten once it loads again or no longer exists.
+pub struct SkippedFiles {
+  files: Mutex<BTreeMap<String, SkippedFile>>,
+}
+
+impl SkippedFiles {
+  pub fn new() -> Self {
+    Self {
+      files: Mutex::new(BTreeMap::new()),
+    }
+  }
+
+  pub fn record(&self, path: &str, reason: &str) {
+    tracing::debug!("Skipped file {}: {}", path, reason);
+    self.insert(path, SkipKind::Skipped, reason);
+  }
+
+  pub fn record_failure(&self, path: &str, error: &anyhow::Error) {
+    let reason = format!("{:#}", error);
+    tracing::warn!("Failed to load {}: {}", path, reason);
+    self.insert(path, SkipKind::Failed, &reason);
+  }
+
+  fn insert(&self, path: &str, kind: SkipKind, reason: &str) {
+    let recorded_at = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|duration| duration.as_secs())
+      .unwrap_or_default();
+    let mut files = self.files.lock();
+    files.insert(
+      path.to_string(),
+      SkippedFile {
+        path: path.to_string(),
+        kind,
+        reason: reason.to_string(),
+        recorded_at,
+      },
+    );
+  }
+
+  pub fn remove(&self, path: &str) {
+    let mut files = self.files.lock();
+    files.remove(path);
+  }
+
+  /// Files of any kind, dropping those deleted since they were recorded.
+  pub fn list_all(&self) -> Vec<SkippedFile> {
+    let mut files = self.files.lock();
+    files.retain(|path, _| Path::new(path).exists());
+    files.values().cloned().collect()
+  }
+
+  /// Files left out on purpose.
+  pub fn list(&self) -> Vec<SkippedFile> {
+    self
+      .list_all()
+      .into_iter()
+      .filter(|file| file.kind == SkipKind::Skipped)
+      .collect()
+  }
+}
+
+impl Default for SkippedFiles {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn skipped_files_should_forget_deleted_files() {
+    let dir = std::env::temp_dir().join(format!("skipped-files-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let kept = dir.join("kept.txt");
+    let deleted = dir.join("deleted.txt");
+    std::fs::write(&kept, "kept").unwrap();
+    std::fs::write(&deleted, "deleted").unwrap();
+
+    let skipped_files = SkippedFiles::new();
+    skipped_files.record(kept.to_str().unwrap(), "too large");
+    skipped_files.record_failure(
+      deleted.to_str().unwrap(),
+      &anyhow::anyhow!("permission denied"),
+    );
+    assert_eq!(skipped_files.list_all().len(), 2);
+    assert!(
+      skipped_files
+        .list()
+        .iter()
+        .all(|file| file.kind == SkipKind::Skipped)
+    );
+
+    std::fs::remove_file(&deleted).unwrap();
+    let files = skipped_files.list_all();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].path, kept.to_str().unwrap());
+  }
+}
@@ -0,0 +1,618 @@
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension, params};
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  path::Path,
+};
+
+use super::{
+  file::{
+    CHUNK_START_LINE_KEY, File, FileSection, LINKS_METADATA_KEY, TAGS_METADATA_KEY, tag_matches,
+  },
+  index_backend::{IndexBackend, IndexMemory, SearchHit, next_generation, unix_time_ms},
+  language_index::{LANGUAGE_METADATA_KEY, split_language_filters},
+  match_mode::{MatchMode, split_match_mode},
+};
+
+const MAX_SNIPPET_LENGTH: usize = 200;
+
+/* Documents live in a plain table; the FTS5 table only indexes them and is kept in sync by
+ * triggers, so columns can be added to `files` without rebuilding the full-text index. */
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS files (
+  id INTEGER PRIMARY KEY,
+  path TEXT NOT NULL UNIQUE,
+  content TEXT NOT NULL,
+  metadata TEXT NOT NULL DEFAULT '{}',
+  sections TEXT NOT NULL DEFAULT '[]',
+  indexed_at INTEGER
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS files_fts USING fts5(
+  content, metadata, content='files', content_rowid='id'
+);
+CREATE TRIGGER IF NOT EXISTS files_after_insert AFTER INSERT ON files BEGIN
+  INSERT INTO files_fts(rowid, content, metadata) VALUES (new.id, new.content, new.metadata);
+END;
+CREATE TRIGGER IF NOT EXISTS files_after_delete AFTER DELETE ON files BEGIN
+  INSERT INTO files_fts(files_fts, rowid, content, metadata)
+    VALUES ('delete', old.id, old.content, old.metadata);
+END;
+CREATE TRIGGER IF NOT EXISTS files_after_update AFTER UPDATE ON files BEGIN
+  INSERT INTO files_fts(files_fts, rowid, content, metadata)
+    VALUES ('delete', old.id, old.content, old.metadata);
+  INSERT INTO files_fts(rowid, content, metadata) VALUES (new.id, new.content, new.metadata);
+END;
+";
+
+/// Index backend keeping all documents in a single SQLite database searched with FTS5.
+///
+/// Writes are grouped in a transaction that `commit` ends. Unlike tantivy, the connection sees
+/// its own uncommitted writes, so they are searchable right away.
+pub struct SqliteIndex {
+  connection: Connection,
+  pending_operations: usize,
+  generation: u64,
+}
+
+impl SqliteIndex {
+  pub fn new() -> Result<Self> {
+    Self::from_connection(Connection::open_in_memory()?)
+  }
+
+  pub fn new_with_file<P: AsRef<Path>>(index_file: P) -> Result<Self> {
+    if let Some(parent) = index_file.as_ref().parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+    tracing::info!("Opening SQLite index at {:?}", index_file.as_ref());
+    Self::from_connection(Connection::open(index_file)?)
+  }
+
+  fn from_connection(connection: Connection) -> Result<Self> {
+    connection.execute_batch(SCHEMA)?;
+    /* Databases created before a column existed get it added */
+    let has_indexed_at = connection
+      .query_row(
+        "SELECT 1 FROM pragma_table_info('files') WHERE name = 'indexed_at'",
+        [],
+        |_| Ok(()),
+      )
+      .optional()?
+      .is_some();
+    if !has_indexed_at {
+      connection.execute_batch("ALTER TABLE files ADD COLUMN indexed_at INTEGER")?;
+    }
+    Ok(SqliteIndex {
+      connection,
+      pending_operations: 0,
+      generation: next_generation(),
+    })
+  }
+
+  fn begin_write(&mut self, operations: usize) -> Result<()> {
+    if self.connection.is_autocommit() {
+      self.connection.execute_batch("BEGIN")?;
+    }
+    self.pending_operations += operations;
+    Ok(())
+  }
+
+  fn upsert(&self, file: &File) -> Result<()> {
+    self.connection.execute(
+      "INSERT INTO files (path, content, metadata, sections, indexed_at)
+       VALUES (?1, ?2, ?3, ?4, ?5)
+       ON CONFLICT(path) DO UPDATE SET
+         content = excluded.content, metadata = excluded.metadata, sections = excluded.sections,
+         indexed_at = excluded.indexed_at",
+      params![
+        file.path,
+        file.content,
+        serde_json::to_string(&file.metadata)?,
+        serde_json::to_string(&file.sections)?,
+        unix_time_ms() as i64,
+      ],
+    )?;
+    Ok(())
+  }
+}
+
+impl IndexBackend for SqliteIndex {
+  fn add_doc(&mut self, file: &File) -> Result<()> {
+    self.begin_write(1)?;
+    self.upsert(file)?;
+    tracing::debug!("Added document for file: {}", file.path);
+    Ok(())
+  }
+
+  fn add_docs(&mut self, files: Box<dyn Iterator<Item = File> + '_>) -> Result<usize> {
+    let mut added_count = 0;
+    for file in files {
+      self.begin_write(1)?;
+      self.upsert(&file)?;
+      added_count += 1;
+    }
+    tracing::debug!("Added {} documents", added_count);
+    self.commit()?;
+    Ok(added_count)
+  }
+
+  fn replace_doc(&mut self, file: &File) -> Result<()> {
+    self.begin_write(1)?;
+    self.upsert(file)?;
+    tracing::debug!("Replaced document for file: {}", file.path);
+    Ok(())
+  }
+
+  fn delete_doc(&mut self, file_path: &str) -> Result<()> {
+    self.begin_write(1)?;
+    self
+      .connection
+      .execute("DELETE FROM files WHERE path = ?1", params![file_path])?;
+    tracing::debug!("Deleted document for file: {}", file_path);
+    Ok(())
+  }
+
+  fn delete_docs_by_path_prefix(&mut self, path_prefix: &str) -> Result<usize> {
+    self.begin_write(0)?;
+    let deleted_count = self.connection.execute(
+      "DELETE FROM files WHERE substr(path, 1, length(?1)) = ?1",
+      params![path_prefix],
+    )?;
+    self.pending_operations += deleted_count;
+    tracing::debug!("Deleted {} documents under {}", deleted_count, path_prefix);
+    Ok(deleted_count)
+  }
+
+  fn has_doc(&self, file_path: &str) -> Result<bool> {
+    Ok(
+      self
+        .connection
+        .query_row(
+          "SELECT 1 FROM files WHERE path = ?1",
+          params![file_path],
+          |_| Ok(()),
+        )
+        .optional()?
+        .is_some(),
+    )
+  }
+
+  fn has_docs_with_path_prefix(&self, path_prefix: &str) -> Result<bool> {
+    Ok(
+      self
+        .connection
+        .query_row(
+          "SELECT 1 FROM files WHERE substr(path, 1, length(?1)) = ?1 LIMIT 1",
+          params![path_prefix],
+          |_| Ok(()),
+        )
+        .optional()?
+        .is_some(),
+    )
+  }
+
+  fn indexed_paths(&self) -> Result<BTreeSet<String>> {
+    let mut statement = self.connection.prepare("SELECT path FROM files")?;
+    let paths = statement
+      .query_map([], |row| row.get::<_, String>(0))?
+      .collect::<Result<BTreeSet<_>, _>>()?;
+    Ok(paths)
+  }
+
+  fn indexed_at(&self, file_path: &str) -> Result<Option<u64>> {
+    Ok(
+      self
+        .connection
+        .query_row(
+          "SELECT indexed_at FROM files WHERE path = ?1",
+          params![file_path],
+          |row| row.get::<_, Option<i64>>(0),
+        )
+        .optional()?
+        .flatten()
+        .map(|indexed_at| indexed_at as u64),
+    )
+  }
+
+  fn commit(&mut self) -> Result<()> {
+    if !self.connection.is_autocommit() {
+      self.connection.execute_batch("COMMIT")?;
+    }
+    if self.pending_operations > 0 {
+      self.pending_operations = 0;
+      self.generation = next_generation();
+    }
+    Ok(())
+  }
+
+  fn optimize(&mut self) -> Result<()> {
+    self.commit()?;
+    self
+      .connection
+      .execute("INSERT INTO files_fts(files_fts) VALUES ('optimize')", [])?;
+    tracing::debug!("Optimized the full-text index");
+    Ok(())
+  }
+
+  fn generation(&self) -> u64 {
+    self.generation
+  }
+
+  fn get_pending_operations(&self) -> usize {
+    self.pending_operations
+  }
+
+  fn num_docs(&self) -> u64 {
+    self
+      .connection
+      .query_row("SELECT count(*) FROM files", [], |row| row.get::<_, i64>(0))
+      .map(|count| count as u64)
+      .unwrap_or_default()
+  }
+
+  fn memory_usage(&self) -> IndexMemory {
+    /* SQLite writes through its page cache, so only a database without a file is in memory */
+    let in_memory_bytes = if self.connection.path().is_none_or(str::is_empty) {
+      self
+        .connection
+        .query_row(
+          "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+          [],
+          |row| row.get::<_, i64>(0),
+        )
+        .map(|bytes| bytes as u64)
+        .unwrap_or_default()
+    } else {
+      0
+    };
+    IndexMemory {
+      writer_heap_bytes: 0,
+      in_memory_bytes,
+    }
+  }
+
+  fn clear(&mut self) -> Result<()> {
+    self.begin_write(1)?;
+    self.connection.execute("DELETE FROM files", [])?;
+    tracing::debug!("Cleared all documents");
+    Ok(())
+  }
+
+  fn search_hits(&self, keyword: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    let (keyword, languages) = split_language_filters(keyword);
+    let (keyword, match_mode) = split_match_mode(&keyword);
+    let (query, terms) = to_fts_query(&keyword, match_mode);
+    if query.is_empty() {
+      let documents_in_language = self
+        .files_with_metadata(LANGUAGE_METADATA_KEY)?
+        .into_iter()
+        .filter(|(_, language)| languages.contains(language))
+        .map(|(file_path, _)| file_path);
+      return self.unranked_hits(documents_in_language, limit);
+    }
+    /* An empty JSON array of languages means no language filter */
+    let mut statement = self.connection.prepare(&format!(
+      "SELECT files.path, bm25(files_fts), files.content, files.metadata, files.sections
+       FROM files_fts JOIN files ON files.id = files_fts.rowid
+       WHERE files_fts MATCH ?1
+         AND (json_array_length(?3) = 0
+           OR json_extract(files.metadata, '$.{}') IN (SELECT value FROM json_each(?3)))
+       ORDER BY bm25(files_fts) LIMIT ?2",
+      LANGUAGE_METADATA_KEY
+    ))?;
+    let languages = serde_json::to_string(&languages)?;
+    let rows = statement.query_map(params![query, limit as i64, languages], |row| {
+      Ok((
+        row.get::<_, String>(0)?,
+        row.get::<_, f64>(1)?,
+        row.get::<_, String>(2)?,
+        row.get::<_, String>(3)?,
+        row.get::<_, String>(4)?,
+      ))
+    })?;
+    rows
+      .map(|row| {
+        let (file_path, rank, content, metadata, sections) = row?;
+        let metadata: BTreeMap<String, String> = serde_json::from_str(&metadata)?;
+        let sections: Vec<FileSection> = serde_json::from_str(&sections)?;
+        Ok(create_hit(
+          file_path, rank, &content, metadata, &sections, &terms,
+        ))
+      })
+      .collect()
+  }
+
+  fn tag_counts(&self) -> Result<BTreeMap<String, u64>> {
+    let mut counts = BTreeMap::new();
+    for tags in self.files_with_metadata(TAGS_METADATA_KEY)?.into_values() {
+      /* Count each document once per tag even when several of its tags share an ancestor */
+      let mut document_tags = BTreeSet::new();
+      for tag in tags.split_whitespace() {
+        let parts = tag.split('/').collect::<Vec<_>>();
+        for depth in 1..=parts.len() {
+          document_tags.insert(parts[..depth].join("/"));
+        }
+      }
+      for tag in document_tags {
+        *counts.entry(tag).or_default() += 1;
+      }
+    }
+    Ok(counts)
+  }
+
+  fn search_by_tag(&self, tag: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    let tagged_files = self
+      .files_with_metadata(TAGS_METADATA_KEY)?
+      .into_iter()
+      .filter(|(_, tags)| tags.split_whitespace().any(|t| tag_matches(t, tag)))
+      .map(|(file_path, _)| file_path);
+    self.unranked_hits(tagged_files, limit)
+  }
+
+  fn search_by_link(&self, link: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    let linking_files = self
+      .files_with_metadata(LINKS_METADATA_KEY)?
+      .into_iter()
+      .filter(|(_, links)| links.lines().any(|l| l == link))
+      .map(|(file_path, _)| file_path);
+    self.unranked_hits(linking_files, limit)
+  }
+}
+
+impl SqliteIndex {
+  /// Paths of documents with the metadata key, mapped to its value.
+  fn files_with_metadata(&self, key: &str) -> Result<BTreeMap<String, String>> {
+    let mut statement = self.connection.prepare(&format!(
+      "SELECT path, json_extract(metadata, '$.{0}') FROM files
+       WHERE json_extract(metadata, '$.{0}') IS NOT NULL",
+      key
+    ))?;
+    let files = statement
+      .query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+      })?
+      .collect::<Result<BTreeMap<_, _>, _>>()?;
+    Ok(files)
+  }
+
+  /// Hits for documents selected by their metadata rather than by a query, so all rank the same.
+  fn unranked_hits(
+    &self,
+    file_paths: impl Iterator<Item = String>,
+    limit: usize,
+  ) -> Result<Vec<SearchHit>> {
+    let mut statement = self
+      .connection
+      .prepare("SELECT content, metadata, sections FROM files WHERE path = ?1")?;
+    file_paths
+      .take(limit)
+      .map(|file_path| {
+        let (content, metadata, sections) = statement.query_row(params![file_path], |row| {
+          Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+          ))
+        })?;
+        let metadata: BTreeMap<String, String> = serde_json::from_str(&metadata)?;
+        let sections: Vec<FileSection> = serde_json::from_str(&sections)?;
+        Ok(create_hit(
+          file_path,
+          -1.0,
+          &content,
+          metadata,
+          &sections,
+          &[],
+        ))
+      })
+      .collect()
+  }
+}
+
+impl Drop for SqliteIndex {
+  fn drop(&mut self) {
+    if let Err(e) = self.commit() {
+      tracing::error!("Failed to commit pending operations: {}", e);
+    }
+  }
+}
+
+/// Translates the keyword syntax of the default backend into an FTS5 query: words match as many
+/// of them as the match mode requires, `"quoted phrases"` stay phrases and `metadata.<key>:word`
+/// searches the metadata column. Also returns the plain terms, used to locate the first match.
+fn to_fts_query(keyword: &str, match_mode: MatchMode) -> (String, Vec<String>) {
+  let mut clauses = Vec::new();
+  let mut terms = Vec::new();
+  let mut rest = keyword.trim();
+  while !rest.is_empty() {
+    let (token, remaining) = if let Some(quoted) = rest.strip_prefix('"') {
+      match quoted.split_once('"') {
+        Some((phrase, remaining)) => (phrase, remaining),
+        None => (quoted, ""),
+      }
+    } else {
+      rest.split_once(char::is_whitespace).unwrap_or((rest, ""))
+    };
+    rest = remaining.trim_start();
+
+    let (column, text) = match token.split_once(':') {
+      Some((field, text)) if field.starts_with("metadata.") => ("metadata : ", text),
+      _ => ("", token),
+    };
+    if text.trim().is_empty() {
+      continue;
+    }
+    clauses.push(format!("{}\"{}\"", column, text.replace('"', "\"\"")));
+    terms.extend(text.split_whitespace().map(str::to_lowercase));
+  }
+  let required = match_mode.required_clauses(clauses.len());
+  let query = if required <= 1 {
+    clauses.join(" OR ")
+  } else if required == clauses.len() {
+    clauses.join(" AND ")
+  } else {
+    /* FTS5 has no minimum-should-match, so list every combination of the required clauses */
+    combinations(&clauses, required)
+      .iter()
+      .map(|combination| format!("({})", combination.join(" AND ")))
+      .collect::<Vec<_>>()
+      .join(" OR ")
+  };
+  (query, terms)
+}
+
+fn combinations(clauses: &[String], size: usize) -> Vec<Vec<String>> {
+  match (size, clauses.split_first()) {
+    (0, _) => vec![Vec::new()],
+    (_, None) => Vec::new(),
+    (_, Some((first, rest))) => {
+      let mut with_first = combinations(rest, size - 1);
+      for combination in &mut with_first {
+        combination.insert(0, first.clone());
+      }
+      with_first.extend(combinations(rest, size));
+      with_first
+    }
+  }
+}
+
+fn create_hit(
+  file_path: String,
+  rank: f64,
+  content: &str,
+  metadata: BTreeMap<String, String>,
+  sections: &[FileSection],
+  terms: &[String],
+) -> SearchHit {
+  let lowercase = content.to_lowercase();
+  /* Lowercasing can change byte lengths, so only trust offsets that fall on the same text */
+  let match_offset = (lowercase.len() == content.len())
+    .then(|| terms.iter().filter_map(|term| lowercase.find(term)).min())
+    .flatten()
+    .filter(|offset| content.is_char_boundary(*offset));
+  let line_index = match_offset.map(|offset| content[..offset].matches('\n').count());
+  let snippet = match_offset.map(|offset| {
+    let start = content[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let end = content[offset..]
+      .find('\n')
+      .map_or(content.len(), |i| offset + i);
+    content[start..end]
+      .trim()
+      .chars()
+      .take(MAX_SNIPPET_LENGTH)
+      .collect::<String>()
+  });
+  let section = match_offset.and_then(|offset| {
+    sections
+      .iter()
+      .take_while(|section| section.offset <= offset)
+      .last()
+      .map(|section| section.title.clone())
+  });
+  /* Line numbers of a chunk are relative to the whole file */
+  let first_line = metadata
+    .get(CHUNK_START_LINE_KEY)
+    .and_then(|line| line.parse::<usize>().ok())
+    .unwrap_or(1);
+
+  SearchHit {
+    file_path,
+    /* bm25() is lower for better matches */
+    score: -rank as f32,
+    line_number: line_index.map(|line_index| line_index + first_line),
+    snippet,
+    section,
+    metadata,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::search::index_backend::SEARCH_FILE_LIMIT;
+
+  #[test]
+  fn sqlite_index_should_search_replace_and_delete_documents() {
+    let mut index = SqliteIndex::new().unwrap();
+    index
+      .add_docs(Box::new(
+        vec![
+          File::new(
+            "/notes/a.txt".to_string(),
+            "first line\nthe keyword is here".to_string(),
+          ),
+          File::new("/notes/b.txt".to_string(), "nothing to see".to_string()),
+          File::new("/other/c.txt".to_string(), "another keyword".to_string()),
+        ]
+        .into_iter(),
+      ))
+      .unwrap();
+    assert_eq!(index.num_docs(), 3);
+
+    let hits = index.search_hits("keyword", SEARCH_FILE_LIMIT).unwrap();
+    assert_eq!(hits.len(), 2);
+    let hit = hits.iter().find(|hit| hit.file_path == "/notes/a.txt");
+    assert_eq!(hit.and_then(|hit| hit.line_number), Some(2));
+    assert_eq!(
+      hit.and_then(|hit| hit.snippet.as_deref()),
+      Some("the keyword is here")
+    );
+
+    let generation = index.generation();
+    index
+      .replace_doc(&File::new(
+        "/notes/b.txt".to_string(),
+        "now with keyword".to_string(),
+      ))
+      .unwrap();
+    assert_eq!(index.delete_docs_by_path_prefix("/other/").unwrap(), 1);
+    index.commit().unwrap();
+    assert_ne!(index.generation(), generation);
+
+    assert_eq!(
+      index.indexed_paths().unwrap(),
+      BTreeSet::from(["/notes/a.txt".to_string(), "/notes/b.txt".to_string()])
+    );
+    assert!(index.has_docs_with_path_prefix("/notes/").unwrap());
+    assert!(!index.has_doc("/other/c.txt").unwrap());
+    assert_eq!(index.search("keyword").unwrap().len(), 2);
+  }
+
+  #[test]
+  fn sqlite_index_should_count_and_search_nested_tags() {
+    let mut index = SqliteIndex::new().unwrap();
+    for (path, tags) in [("a.md", "project/alpha project/beta"), ("b.md", "project")] {
+      let file =
+        File::new(path.to_string(), "note".to_string()).with_metadata(TAGS_METADATA_KEY, tags);
+      index.add_doc(&file).unwrap();
+    }
+    index.commit().unwrap();
+
+    let counts = index.tag_counts().unwrap();
+    assert_eq!(counts["project"], 2);
+    assert_eq!(counts["project/beta"], 1);
+    assert_eq!(
+      index
+        .search_by_tag("project", SEARCH_FILE_LIMIT)
+        .unwrap()
+        .len(),
+      2
+    );
+    let hits = index
+      .search_by_tag("project/alpha", SEARCH_FILE_LIMIT)
+      .unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].file_path, "a.md");
+  }
+
+  #[test]
+  fn to_fts_query_should_quote_words_phrases_and_metadata_fields() {
+    let (query, terms) = to_fts_query(
+      r#"alpha "two words" metadata.subject:report"#,
+      MatchMode::Any,
+    );
+    assert_eq!(query, r#""alpha" OR "two words" OR metadata : "report""#);
+    assert_eq!(terms, vec!["alpha", "two", "words", "report"]);
+    let (query, _) = to_fts_query("a b c", MatchMode::AtLeast(2));
+    assert_eq!(query, r#"("a" AND "b") OR ("a" AND "c") OR ("b" AND "c")"#);
+  }
+}
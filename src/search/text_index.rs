@@ -1,67 +1,166 @@
 use anyhow::Error;
-use std::path::Path;
-use tantivy::collector::TopDocs;
-use tantivy::query::QueryParser;
-use tantivy::{Index, IndexWriter, ReloadPolicy, TantivyDocument, Term, doc};
+use std::{
+  borrow::Borrow,
+  collections::{BTreeMap, BTreeSet, HashMap},
+  ops::Bound,
+  path::Path,
+  time::UNIX_EPOCH,
+};
+use tantivy::collector::{Count, FacetCollector, TopDocs};
+use tantivy::query::{
+  AllQuery, BooleanQuery, ConstScoreQuery, Occur, Query, QueryParser, RangeQuery, TermQuery,
+};
+use tantivy::tokenizer::TokenStream;
+use tantivy::{
+  DocId, Index, IndexWriter, ReloadPolicy, Score, SegmentReader, TantivyDocument, Term, doc,
+};
 use tantivy::{IndexReader, schema::*};
 
-use super::file::File;
+use super::{
+  cancellation::check_cancelled,
+  file::{
+    CHUNK_START_LINE_KEY, File, LINKS_METADATA_KEY, TAGS_METADATA_KEY, chunk_prefix,
+    document_extension, document_file_path, is_section_file, section_prefix,
+  },
+  index_backend::{
+    IndexBackend, IndexMemory, SearchHit, SearchOptions, next_generation, unix_time_ms,
+  },
+  language_index::{LANGUAGE_METADATA_KEY, split_language_filters},
+  match_mode::{MatchMode, query_tokens, split_match_mode},
+};
 
-const SEARCH_FILE_LIMIT: usize = 10;
+const MAX_SNIPPET_LENGTH: usize = 200;
 
 pub struct TextIndex {
   index: Index,
   file_path_field: Field,
   content_field: Field,
-  schema: Schema,
+  line_offsets_field: Field,
+  section_offsets_field: Field,
+  section_titles_field: Field,
+  metadata_field: Field,
+  tags_field: Field,
+  links_field: Field,
+  indexed_at_field: Field,
+  extension_field: Field,
+  modified_at_field: Field,
   writer: IndexWriter,
   reader: IndexReader,
   pending_operations: usize,
+  generation: u64,
+  /// Reads the new segments after every commit rather than at the first search
+  warm_up: bool,
+  writer_heap: usize,
+  in_memory: bool,
 }
 
-impl TextIndex {
-  pub fn new() -> Result<Self, Error> {
-    let mut schema_builder = Schema::builder();
-    let file_path_field = schema_builder.add_text_field("file_path", STRING | STORED);
-    let content_field = schema_builder.add_text_field("content", TEXT);
-    let schema = schema_builder.build();
+/// Heap of an index writer when no memory budget sets it.
+pub const DEFAULT_WRITER_HEAP: usize = 50_000_000;
+/// Least heap tantivy accepts for an index writer.
+pub const MIN_WRITER_HEAP: usize = 15_000_000;
 
-    let index = Index::create_in_ram(schema.clone());
-    let index_writer = index.writer(50_000_000)?;
+/// Schema of every Tantivy index, which also decides the fields queries can name.
+pub fn build_schema() -> Schema {
+  let mut schema_builder = Schema::builder();
+  schema_builder.add_text_field("file_path", STRING | STORED);
+  schema_builder.add_text_field(
+    "content",
+    TextOptions::default()
+      .set_indexing_options(
+        TextFieldIndexing::default()
+          .set_tokenizer("default")
+          .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+      )
+      .set_stored(),
+  );
+  /* Byte offset of the first character of every line, used to map matches to line numbers */
+  schema_builder.add_u64_field("line_offsets", STORED);
+  /* Parallel lists describing where each section (e.g. an EPUB chapter) of the content starts */
+  schema_builder.add_u64_field("section_offsets", STORED);
+  schema_builder.add_text_field("section_titles", STORED);
+  /* Format specific fields such as an email subject, searchable as `metadata.subject:word` */
+  schema_builder.add_json_field("metadata", TEXT | STORED);
+  /* Tags from the metadata again as facets, which match nested tags and can be counted */
+  schema_builder.add_facet_field("tags", FacetOptions::default());
+  /* Link targets from the metadata again, one untokenized term each, to look up backlinks */
+  schema_builder.add_text_field("links", STRING);
+  /* Milliseconds since the Unix epoch when the document was added, to tell how fresh it is */
+  schema_builder.add_u64_field("indexed_at", STORED);
+  /* Lowercase extension of the file, read while collecting hits to apply extension boosts */
+  schema_builder.add_text_field("extension", STRING | FAST);
+  /* Milliseconds since the Unix epoch when the file was last modified, for date window filters */
+  schema_builder.add_u64_field("modified_at", INDEXED | FAST);
+  schema_builder.build()
+}
 
-    let index_reader = index
-      .reader_builder()
-      .reload_policy(ReloadPolicy::OnCommitWithDelay)
-      .try_into()?;
+fn tag_facet(tag: &str) -> Facet {
+  Facet::from_path(tag.split('/').filter(|part| !part.is_empty()))
+}
 
-    Ok(TextIndex {
-      index,
-      file_path_field,
-      content_field,
-      schema,
-      writer: index_writer,
-      reader: index_reader,
-      pending_operations: 0,
-    })
+fn line_offsets(content: &str) -> Vec<u64> {
+  std::iter::once(0)
+    .chain(
+      content
+        .match_indices('\n')
+        .map(|(offset, _)| (offset + 1) as u64),
+    )
+    .collect()
+}
+
+impl TextIndex {
+  pub fn new() -> Result<Self, Error> {
+    Self::new_with_writer_heap(None, DEFAULT_WRITER_HEAP)
   }
 
   pub fn new_with_directory<P: AsRef<Path>>(index_dir: P) -> Result<Self, Error> {
-    let mut schema_builder = Schema::builder();
-    let file_path_field = schema_builder.add_text_field("file_path", STRING | STORED);
-    let content_field = schema_builder.add_text_field("content", TEXT);
-    let schema = schema_builder.build();
+    Self::new_with_writer_heap(Some(index_dir.as_ref()), DEFAULT_WRITER_HEAP)
+  }
 
-    std::fs::create_dir_all(&index_dir)?;
+  /// Opens the index in `index_dir`, or in memory without one, with a writer buffering up to
+  /// `writer_heap` bytes of documents before writing them out.
+  pub fn new_with_writer_heap(index_dir: Option<&Path>, writer_heap: usize) -> Result<Self, Error> {
+    let Some(index_dir) = index_dir else {
+      return Self::from_index(Index::create_in_ram(build_schema()), writer_heap, true);
+    };
+    let schema = build_schema();
 
-    let index = if index_dir.as_ref().join("meta.json").exists() {
-      tracing::info!("Opening existing index at {:?}", index_dir.as_ref());
-      Index::open_in_dir(&index_dir)?
+    std::fs::create_dir_all(index_dir)?;
+
+    let index = if index_dir.join("meta.json").exists() {
+      tracing::info!("Opening existing index at {:?}", index_dir);
+      let index = Index::open_in_dir(index_dir)?;
+      if index.schema() == schema {
+        index
+      } else {
+        tracing::warn!("Index schema at {:?} is outdated, recreating it", index_dir);
+        drop(index);
+        std::fs::remove_dir_all(index_dir)?;
+        std::fs::create_dir_all(index_dir)?;
+        Index::create_in_dir(index_dir, schema)?
+      }
     } else {
-      tracing::info!("Creating new index at {:?}", index_dir.as_ref());
-      Index::create_in_dir(&index_dir, schema.clone())?
+      tracing::info!("Creating new index at {:?}", index_dir);
+      Index::create_in_dir(index_dir, schema)?
     };
 
-    let index_writer = index.writer(50_000_000)?;
+    Self::from_index(index, writer_heap, false)
+  }
+
+  fn from_index(index: Index, writer_heap: usize, in_memory: bool) -> Result<Self, Error> {
+    let schema = index.schema();
+    let file_path_field = schema.get_field("file_path")?;
+    let content_field = schema.get_field("content")?;
+    let line_offsets_field = schema.get_field("line_offsets")?;
+    let section_offsets_field = schema.get_field("section_offsets")?;
+    let section_titles_field = schema.get_field("section_titles")?;
+    let metadata_field = schema.get_field("metadata")?;
+    let tags_field = schema.get_field("tags")?;
+    let links_field = schema.get_field("links")?;
+    let indexed_at_field = schema.get_field("indexed_at")?;
+    let extension_field = schema.get_field("extension")?;
+    let modified_at_field = schema.get_field("modified_at")?;
+
+    let index_writer = index.writer(writer_heap)?;
 
     let index_reader = index
       .reader_builder()
@@ -72,37 +171,272 @@ impl TextIndex {
       index,
       file_path_field,
       content_field,
-      schema,
+      line_offsets_field,
+      section_offsets_field,
+      section_titles_field,
+      metadata_field,
+      tags_field,
+      links_field,
+      indexed_at_field,
+      extension_field,
+      modified_at_field,
       writer: index_writer,
       reader: index_reader,
       pending_operations: 0,
+      generation: next_generation(),
+      warm_up: false,
+      writer_heap,
+      in_memory,
     })
   }
 
-  pub fn add_doc(&mut self, file: &File) -> Result<(), Error> {
-    self.writer.add_document(doc!(
+  /// Warms the searcher up after every commit, so that the first search after heavy indexing
+  /// does not wait for segment files to be read from disk.
+  pub fn with_warm_up(mut self, warm_up: bool) -> Self {
+    self.warm_up = warm_up;
+    self
+  }
+
+  /// Walks the term dictionaries of the searchable fields and counts the documents of every
+  /// segment, bringing the files a search reads into the page cache.
+  fn warm_up_searcher(&self) -> Result<(), Error> {
+    let searcher = self.reader.searcher();
+    for segment_reader in searcher.segment_readers() {
+      for field in [
+        self.file_path_field,
+        self.content_field,
+        self.metadata_field,
+        self.links_field,
+      ] {
+        let inverted_index = segment_reader.inverted_index(field)?;
+        let mut terms = inverted_index.terms().stream()?;
+        while terms.advance() {}
+      }
+    }
+    searcher.search(&AllQuery, &Count)?;
+    Ok(())
+  }
+
+  fn create_document(&self, file: &File) -> TantivyDocument {
+    let mut document = doc!(
       self.file_path_field => file.path,
       self.content_field => file.content,
-    ))?;
+      self.indexed_at_field => unix_time_ms(),
+    );
+    if let Some(extension) = document_extension(&file.path) {
+      document.add_text(self.extension_field, extension);
+    }
+    /* Members of archives and mailboxes have no modification time of their own */
+    if let Ok(modified) =
+      std::fs::metadata(document_file_path(&file.path)).and_then(|metadata| metadata.modified())
+      && let Ok(modified) = modified.duration_since(UNIX_EPOCH)
+    {
+      document.add_u64(self.modified_at_field, modified.as_millis() as u64);
+    }
+    for offset in line_offsets(&file.content) {
+      document.add_u64(self.line_offsets_field, offset);
+    }
+    for section in &file.sections {
+      document.add_u64(self.section_offsets_field, section.offset as u64);
+      document.add_text(self.section_titles_field, &section.title);
+    }
+    if !file.metadata.is_empty() {
+      document.add_object(
+        self.metadata_field,
+        file
+          .metadata
+          .iter()
+          .map(|(key, value)| (key.clone(), OwnedValue::Str(value.clone())))
+          .collect(),
+      );
+    }
+    if let Some(tags) = file.metadata.get(TAGS_METADATA_KEY) {
+      for tag in tags.split_whitespace() {
+        document.add_facet(self.tags_field, tag_facet(tag));
+      }
+    }
+    if let Some(links) = file.metadata.get(LINKS_METADATA_KEY) {
+      for link in links.lines() {
+        document.add_text(self.links_field, link);
+      }
+    }
+    document
+  }
+
+  /// Adds the document of a file after deleting the one already indexed under its path, so that a
+  /// file created again or indexed twice is found once. Deletions only apply to documents added
+  /// before them, so the new document is kept.
+  fn upsert(&mut self, file: &File) -> Result<(), Error> {
+    let term = Term::from_field_text(self.file_path_field, &file.path);
+    self.writer.delete_term(term);
+    self.writer.add_document(self.create_document(file))?;
     self.pending_operations += 1;
+    Ok(())
+  }
+
+  /// Adds documents one by one as the iterator yields them, then commits once.
+  pub fn add_docs<F: Borrow<File>>(
+    &mut self,
+    files: impl IntoIterator<Item = F>,
+  ) -> Result<usize, Error> {
+    let mut added_count = 0;
+    for file in files {
+      self.upsert(file.borrow())?;
+      added_count += 1;
+    }
+    tracing::debug!("Added {} documents", added_count);
+    self.commit()?;
+    Ok(added_count)
+  }
+
+  fn content_query_terms(&self, query: &dyn Query) -> Vec<String> {
+    let mut terms = Vec::new();
+    query.query_terms(&mut |term, _| {
+      if term.field() == self.content_field
+        && let Some(text) = term.value().as_str()
+      {
+        terms.push(text.to_string());
+      }
+    });
+    terms
+  }
+
+  fn create_hit(
+    &self,
+    score: f32,
+    doc: &TantivyDocument,
+    query_terms: &[String],
+  ) -> Result<SearchHit, Error> {
+    let file_path = doc
+      .get_first(self.file_path_field)
+      .and_then(|value| value.as_str())
+      .unwrap_or_default()
+      .to_string();
+    let content = doc
+      .get_first(self.content_field)
+      .and_then(|value| value.as_str())
+      .unwrap_or_default();
+    let line_offsets = doc
+      .get_all(self.line_offsets_field)
+      .filter_map(|value| value.as_u64())
+      .map(|offset| offset as usize)
+      .collect::<Vec<_>>();
+
+    let match_offset = self.first_match_offset(content, query_terms)?;
+    let line_index = match_offset.map(|offset| {
+      line_offsets
+        .partition_point(|line_start| *line_start <= offset)
+        .saturating_sub(1)
+    });
+    let snippet = line_index.map(|line_index| {
+      let start = line_offsets.get(line_index).copied().unwrap_or(0);
+      let end = line_offsets
+        .get(line_index + 1)
+        .copied()
+        .unwrap_or(content.len());
+      content
+        .get(start..end)
+        .unwrap_or_default()
+        .trim()
+        .chars()
+        .take(MAX_SNIPPET_LENGTH)
+        .collect::<String>()
+    });
+    let section = match_offset.and_then(|offset| {
+      doc
+        .get_all(self.section_offsets_field)
+        .filter_map(|value| value.as_u64())
+        .zip(
+          doc
+            .get_all(self.section_titles_field)
+            .filter_map(|value| value.as_str()),
+        )
+        .take_while(|(section_offset, _)| *section_offset as usize <= offset)
+        .last()
+        .map(|(_, title)| title.to_string())
+    });
+
+    let metadata: BTreeMap<String, String> = doc
+      .get_first(self.metadata_field)
+      .and_then(|value| value.as_object())
+      .map(|object| {
+        object
+          .filter_map(|(key, value)| {
+            value
+              .as_str()
+              .map(|value| (key.to_string(), value.to_string()))
+          })
+          .collect()
+      })
+      .unwrap_or_default();
+
+    /* Line numbers of a chunk are relative to the whole file */
+    let first_line = metadata
+      .get(CHUNK_START_LINE_KEY)
+      .and_then(|line| line.parse::<usize>().ok())
+      .unwrap_or(1);
+
+    Ok(SearchHit {
+      file_path,
+      score,
+      line_number: line_index.map(|line_index| line_index + first_line),
+      snippet,
+      section,
+      metadata,
+    })
+  }
+
+  fn search_term(&self, term: Term, limit: usize) -> Result<Vec<SearchHit>, Error> {
+    let searcher = self.reader.searcher();
+    let query = TermQuery::new(term, IndexRecordOption::Basic);
+    let top_docs = searcher.search(&query, &TopDocs::with_limit(limit))?;
+    top_docs
+      .iter()
+      .map(|(score, doc_address)| {
+        let doc: TantivyDocument = searcher.doc(*doc_address)?;
+        self.create_hit(*score, &doc, &[])
+      })
+      .collect()
+  }
+
+  fn first_match_offset(
+    &self,
+    content: &str,
+    query_terms: &[String],
+  ) -> Result<Option<usize>, Error> {
+    if query_terms.is_empty() {
+      return Ok(None);
+    }
+    let mut analyzer = self.index.tokenizer_for_field(self.content_field)?;
+    let mut token_stream = analyzer.token_stream(content);
+    while token_stream.advance() {
+      let token = token_stream.token();
+      if query_terms.contains(&token.text) {
+        return Ok(Some(token.offset_from));
+      }
+    }
+    Ok(None)
+  }
+}
+
+impl IndexBackend for TextIndex {
+  fn add_doc(&mut self, file: &File) -> Result<(), Error> {
+    self.upsert(file)?;
     tracing::debug!("Added document for file: {}", file.path);
     Ok(())
   }
 
-  pub fn replace_doc(&mut self, file: &File) -> Result<(), Error> {
-    let term = Term::from_field_text(self.file_path_field, &file.path);
-    self.writer.delete_term(term);
+  fn add_docs(&mut self, files: Box<dyn Iterator<Item = File> + '_>) -> Result<usize, Error> {
+    TextIndex::add_docs(self, files)
+  }
 
-    self.writer.add_document(doc!(
-      self.file_path_field => file.path,
-      self.content_field => file.content,
-    ))?;
-    self.pending_operations += 1;
+  fn replace_doc(&mut self, file: &File) -> Result<(), Error> {
+    self.upsert(file)?;
     tracing::debug!("Replaced document for file: {}", file.path);
     Ok(())
   }
 
-  pub fn delete_doc(&mut self, file_path: &str) -> Result<(), Error> {
+  fn delete_doc(&mut self, file_path: &str) -> Result<(), Error> {
     let term = Term::from_field_text(self.file_path_field, file_path);
     self.writer.delete_term(term);
     self.pending_operations += 1;
@@ -110,7 +444,7 @@ impl TextIndex {
     Ok(())
   }
 
-  pub fn delete_docs_by_path_prefix(&mut self, path_prefix: &str) -> Result<usize, Error> {
+  fn delete_docs_by_path_prefix(&mut self, path_prefix: &str) -> Result<usize, Error> {
     let reader = self.index.reader()?;
     let searcher = reader.searcher();
 
@@ -120,17 +454,19 @@ impl TextIndex {
       let inverted_index = segment_reader.inverted_index(self.file_path_field)?;
       let term_dict = inverted_index.terms();
 
-      let mut term_stream = term_dict.stream()?;
+      /* Terms are sorted, so matching paths form one contiguous range */
+      let mut term_stream = term_dict.range().ge(path_prefix.as_bytes()).into_stream()?;
 
       while term_stream.advance() {
         let term_bytes = term_stream.key();
+        if !term_bytes.starts_with(path_prefix.as_bytes()) {
+          break;
+        }
         if let Ok(term_str) = std::str::from_utf8(term_bytes) {
-          if term_str.starts_with(path_prefix) {
-            let term = Term::from_field_text(self.file_path_field, term_str);
-            self.writer.delete_term(term);
-            deleted_count += 1;
-            tracing::debug!("Deleted document for file: {}", term_str);
-          }
+          let term = Term::from_field_text(self.file_path_field, term_str);
+          self.writer.delete_term(term);
+          deleted_count += 1;
+          tracing::debug!("Deleted document for file: {}", term_str);
         }
       }
     }
@@ -142,34 +478,236 @@ impl TextIndex {
     Ok(deleted_count)
   }
 
-  pub fn commit(&mut self) -> Result<(), Error> {
+  fn has_doc(&self, file_path: &str) -> Result<bool, Error> {
+    /* Counting through a query skips deleted documents whose terms are still in the dictionary */
+    let term = Term::from_field_text(self.file_path_field, file_path);
+    let query = TermQuery::new(term, IndexRecordOption::Basic);
+    Ok(self.reader.searcher().search(&query, &Count)? > 0)
+  }
+
+  fn has_docs_with_path_prefix(&self, path_prefix: &str) -> Result<bool, Error> {
+    let searcher = self.reader.searcher();
+    for segment_reader in searcher.segment_readers() {
+      let inverted_index = segment_reader.inverted_index(self.file_path_field)?;
+      let mut term_stream = inverted_index
+        .terms()
+        .range()
+        .ge(path_prefix.as_bytes())
+        .into_stream()?;
+      while term_stream.advance() && term_stream.key().starts_with(path_prefix.as_bytes()) {
+        if let Ok(file_path) = std::str::from_utf8(term_stream.key())
+          && self.has_doc(file_path)?
+        {
+          return Ok(true);
+        }
+      }
+    }
+    Ok(false)
+  }
+
+  fn indexed_paths(&self) -> Result<BTreeSet<String>, Error> {
+    let searcher = self.reader.searcher();
+    let mut paths = BTreeSet::new();
+    for segment_reader in searcher.segment_readers() {
+      let store_reader = segment_reader.get_store_reader(1)?;
+      for document in store_reader.iter::<TantivyDocument>(segment_reader.alive_bitset()) {
+        if let Some(path) = document?
+          .get_first(self.file_path_field)
+          .and_then(|value| value.as_str())
+        {
+          paths.insert(path.to_string());
+        }
+      }
+    }
+    Ok(paths)
+  }
+
+  fn indexed_at(&self, file_path: &str) -> Result<Option<u64>, Error> {
+    let searcher = self.reader.searcher();
+    let term = Term::from_field_text(self.file_path_field, file_path);
+    let query = TermQuery::new(term, IndexRecordOption::Basic);
+    let Some((_, doc_address)) = searcher
+      .search(&query, &TopDocs::with_limit(1))?
+      .into_iter()
+      .next()
+    else {
+      return Ok(None);
+    };
+    let doc: TantivyDocument = searcher.doc(doc_address)?;
+    Ok(
+      doc
+        .get_first(self.indexed_at_field)
+        .and_then(|value| value.as_u64()),
+    )
+  }
+
+  fn commit(&mut self) -> Result<(), Error> {
     if self.pending_operations > 0 {
       self.writer.commit()?;
       self.pending_operations = 0;
       self.reader.reload()?;
+      self.generation = next_generation();
+      if self.warm_up {
+        /* A cold first search is only slower, so a failed warm-up does not fail the commit */
+        if let Err(e) = self.warm_up_searcher() {
+          tracing::warn!("Failed to warm up the index: {}", e);
+        }
+      }
+    }
+    Ok(())
+  }
+
+  fn optimize(&mut self) -> Result<(), Error> {
+    let segment_ids = self.index.searchable_segment_ids()?;
+    if segment_ids.len() > 1 {
+      self.writer.merge(&segment_ids).wait()?;
+      self.reader.reload()?;
+      tracing::debug!("Merged {} segments", segment_ids.len());
     }
     Ok(())
   }
 
-  pub fn get_pending_operations(&self) -> usize {
+  fn generation(&self) -> u64 {
+    self.generation
+  }
+
+  fn get_pending_operations(&self) -> usize {
     self.pending_operations
   }
 
-  pub fn search(&self, keyword: &str) -> Result<Vec<String>, Error> {
+  fn num_docs(&self) -> u64 {
+    self.reader.searcher().num_docs()
+  }
+
+  fn memory_usage(&self) -> IndexMemory {
+    let in_memory_bytes = if self.in_memory {
+      self
+        .reader
+        .searcher()
+        .space_usage()
+        .map(|usage| usage.total().get_bytes())
+        .unwrap_or_default()
+    } else {
+      0
+    };
+    IndexMemory {
+      writer_heap_bytes: self.writer_heap as u64,
+      in_memory_bytes,
+    }
+  }
+
+  fn clear(&mut self) -> Result<(), Error> {
+    self.writer.delete_all_documents()?;
+    self.pending_operations += 1;
+    tracing::debug!("Cleared all documents");
+    Ok(())
+  }
+
+  fn search_hits(&self, keyword: &str, limit: usize) -> Result<Vec<SearchHit>, Error> {
+    self.search_hits_with(keyword, limit, &SearchOptions::default())
+  }
+
+  fn search_hits_with(
+    &self,
+    keyword: &str,
+    limit: usize,
+    options: &SearchOptions,
+  ) -> Result<Vec<SearchHit>, Error> {
     let searcher = self.reader.searcher();
     let query_parser = QueryParser::for_index(&self.index, vec![self.content_field]);
-    let query = query_parser.parse_query(keyword)?;
-    let top_docs = searcher.search(&query, &TopDocs::with_limit(SEARCH_FILE_LIMIT))?;
+    let (keyword, languages) = split_language_filters(keyword);
+    let (keyword, match_mode) = split_match_mode(&keyword);
+    let query: Box<dyn Query> = if keyword.trim().is_empty() && !languages.is_empty() {
+      Box::new(AllQuery)
+    } else if match_mode == MatchMode::Any {
+      query_parser.parse_query(&keyword)?
+    } else {
+      Box::new(minimum_match_query(&query_parser, &keyword, match_mode)?)
+    };
+    /* A language filter must match, whatever the default conjunction of the other words is */
+    let query = if languages.is_empty() {
+      query
+    } else {
+      let language_query = languages
+        .iter()
+        .map(|language| format!("metadata.{}:{}", LANGUAGE_METADATA_KEY, language))
+        .collect::<Vec<_>>()
+        .join(" ");
+      Box::new(BooleanQuery::new(vec![
+        (Occur::Must, query),
+        (Occur::Must, query_parser.parse_query(&language_query)?),
+      ]))
+    };
+    /* The path and filters narrow the documents without taking part in their score */
+    let mut narrowing = Vec::new();
+    if let Some(path) = &options.path {
+      narrowing.push(path_query(self.file_path_field, path));
+    }
+    if !options.filter.is_empty() {
+      narrowing.push(options.filter.query()?);
+    }
+    let query: Box<dyn Query> = if narrowing.is_empty() {
+      query
+    } else {
+      let mut clauses = vec![(Occur::Must, query)];
+      clauses.extend(narrowing.into_iter().map(|narrowing| {
+        (
+          Occur::Must,
+          Box::new(ConstScoreQuery::new(narrowing, 0.0)) as Box<dyn Query>,
+        )
+      }));
+      Box::new(BooleanQuery::new(clauses))
+    };
+    check_cancelled()?;
+    let top_docs = if options.extension_boosts.is_empty() {
+      searcher.search(&query, &TopDocs::with_limit(limit))?
+    } else {
+      let collector = TopDocs::with_limit(limit).tweak_score(extension_boost(options));
+      searcher.search(&query, &collector)?
+    };
+    let query_terms = self.content_query_terms(query.as_ref());
 
-    let results = top_docs
+    /* Loading documents and building snippets is the slow part for large files */
+    top_docs
       .iter()
-      .map(|(_score, doc_address)| {
-        searcher
-          .doc(*doc_address)
-          .map(|doc: TantivyDocument| doc.to_json(&self.schema))
+      .map(|(score, doc_address)| {
+        check_cancelled()?;
+        let doc: TantivyDocument = searcher.doc(*doc_address)?;
+        self.create_hit(*score, &doc, &query_terms)
       })
-      .collect::<Result<Vec<String>, _>>()?;
-    Ok(results)
+      .collect()
+  }
+
+  fn tag_counts(&self) -> Result<BTreeMap<String, u64>, Error> {
+    let searcher = self.reader.searcher();
+    let mut counts = BTreeMap::new();
+    /* A collector only counts the direct children of its facets, so walk the tree level by level */
+    let mut parents = vec![Facet::root()];
+    while !parents.is_empty() {
+      let mut collector = FacetCollector::for_field("tags");
+      for parent in &parents {
+        collector.add_facet(parent.clone());
+      }
+      let facet_counts = searcher.search(&AllQuery, &collector)?;
+      let mut children = Vec::new();
+      for parent in &parents {
+        for (facet, count) in facet_counts.get(parent.clone()) {
+          counts.insert(facet.to_path().join("/"), count);
+          children.push(facet.clone());
+        }
+      }
+      parents = children;
+    }
+    Ok(counts)
+  }
+
+  fn search_by_tag(&self, tag: &str, limit: usize) -> Result<Vec<SearchHit>, Error> {
+    let term = Term::from_facet(self.tags_field, &tag_facet(tag));
+    self.search_term(term, limit)
+  }
+
+  fn search_by_link(&self, link: &str, limit: usize) -> Result<Vec<SearchHit>, Error> {
+    self.search_term(Term::from_field_text(self.links_field, link), limit)
   }
 }
 
@@ -183,18 +721,205 @@ impl Drop for TextIndex {
   }
 }
 
+/// Documents of the file or directory at `path`, including its chunks and sections, as
+/// `Path::starts_with` would keep them.
+pub fn path_query(file_path_field: Field, path: &str) -> Box<dyn Query> {
+  let path = path.trim_end_matches('/');
+  if path.is_empty() {
+    return prefix_query(file_path_field, "/");
+  }
+  let mut clauses = vec![
+    (
+      Occur::Should,
+      Box::new(TermQuery::new(
+        Term::from_field_text(file_path_field, path),
+        IndexRecordOption::Basic,
+      )) as Box<dyn Query>,
+    ),
+    (
+      Occur::Should,
+      prefix_query(file_path_field, &format!("{}/", path)),
+    ),
+    (
+      Occur::Should,
+      prefix_query(file_path_field, &chunk_prefix(path)),
+    ),
+  ];
+  if is_section_file(path) {
+    clauses.push((
+      Occur::Should,
+      prefix_query(file_path_field, &section_prefix(path)),
+    ));
+  }
+  Box::new(BooleanQuery::new(clauses))
+}
+
+/// Documents whose path starts with `prefix`, a range of the sorted path terms. Prefixes end with
+/// an ASCII separator, so the next character bounds the range.
+fn prefix_query(file_path_field: Field, prefix: &str) -> Box<dyn Query> {
+  let mut end = prefix.to_string();
+  let last = end.pop().map_or(0, u32::from);
+  end.push(char::from_u32(last + 1).unwrap_or(char::MAX));
+  Box::new(RangeQuery::new(
+    Bound::Included(Term::from_field_text(file_path_field, prefix)),
+    Bound::Excluded(Term::from_field_text(file_path_field, &end)),
+  ))
+}
+
+/// Multiplies the score of every collected document by the boost of its extension, so that the
+/// limit keeps the best documents once boosted.
+fn extension_boost(
+  options: &SearchOptions,
+) -> impl Fn(&SegmentReader) -> Box<dyn Fn(DocId, Score) -> Score> + Send + Sync + use<> {
+  let extension_boosts = options.extension_boosts.clone();
+  move |segment_reader: &SegmentReader| {
+    let Ok(Some(extensions)) = segment_reader.fast_fields().str("extension") else {
+      return Box::new(|_, score| score);
+    };
+    /* Each segment has its own dictionary, so the boosts are looked up by term ordinal */
+    let boosts = extension_boosts
+      .iter()
+      .filter_map(|(extension, boost)| {
+        let ordinal = extensions.dictionary().term_ord(extension).ok()??;
+        Some((ordinal, *boost))
+      })
+      .collect::<HashMap<_, _>>();
+    Box::new(move |doc, score| {
+      let boost = extensions
+        .term_ords(doc)
+        .next()
+        .and_then(|ordinal| boosts.get(&ordinal))
+        .copied()
+        .unwrap_or(1.0);
+      score * boost
+    })
+  }
+}
+
+/// Requires documents to match as many of the words and phrases of the query as the mode asks,
+/// while `+word` must and `-word` must not match as usual.
+fn minimum_match_query(
+  query_parser: &QueryParser,
+  keyword: &str,
+  match_mode: MatchMode,
+) -> Result<BooleanQuery, Error> {
+  let mut clauses = Vec::new();
+  for token in query_tokens(keyword) {
+    let (occur, token) = match token.split_at_checked(1) {
+      Some(("+", term)) => (Occur::Must, term),
+      Some(("-", term)) => (Occur::MustNot, term),
+      _ => (Occur::Should, token.as_str()),
+    };
+    clauses.push((occur, query_parser.parse_query(token)?));
+  }
+  let optional = clauses
+    .iter()
+    .filter(|(occur, _)| *occur == Occur::Should)
+    .count();
+  Ok(BooleanQuery::with_minimum_required_clauses(
+    clauses,
+    match_mode.required_clauses(optional),
+  ))
+}
+
 #[cfg(test)]
 mod tests {
   #[test]
-  fn text_index_should_able_to_search_and_get_file_name_by_keyword() {
+  fn text_index_should_require_as_many_terms_as_match_mode() {
+    use super::*;
+    use crate::search::file::File;
+
+    let mut index = TextIndex::new().unwrap();
+    for (path, content) in [
+      ("all.txt", "kubernetes ingress tls"),
+      ("two.txt", "kubernetes ingress"),
+      ("one.txt", "kubernetes"),
+    ] {
+      index
+        .add_doc(&File::new(path.to_string(), content.to_string()))
+        .unwrap();
+    }
+    index.commit().unwrap();
+    let count = |query: &str| index.search_hits(query, 10).unwrap().len();
+    assert_eq!(count("kubernetes ingress tls"), 3);
+    assert_eq!(count("kubernetes ingress tls match:2"), 2);
+    assert_eq!(count("kubernetes ingress tls match:all"), 1);
+    assert_eq!(count("kubernetes ingress -tls match:all"), 1);
+  }
+
+  #[test]
+  fn text_index_should_record_when_documents_were_indexed() {
     use super::*;
     use crate::search::file::File;
 
     let mut index = TextIndex::new().unwrap();
-    let file = File {
-      path: "test.txt".to_string(),
-      content: "This is a test file for indexing.".to_string(),
+    let before = unix_time_ms();
+    index
+      .add_doc(&File::new("a.txt".to_string(), "garden".to_string()))
+      .unwrap();
+    index.commit().unwrap();
+
+    let indexed_at = index.indexed_at("a.txt").unwrap().unwrap();
+    assert!(indexed_at >= before && indexed_at <= unix_time_ms());
+    assert_eq!(index.indexed_at("missing.txt").unwrap(), None);
+  }
+
+  #[test]
+  fn text_index_should_boost_extensions_before_limiting_hits() {
+    use super::*;
+    use crate::search::file::File;
+
+    let mut index = TextIndex::new().unwrap();
+    index
+      .add_docs([
+        File::new(
+          "/notes/noisy.log".to_string(),
+          "keyword keyword keyword".to_string(),
+        ),
+        File::new(
+          "/notes/guide.MD#install".to_string(),
+          "keyword among many other unrelated words".to_string(),
+        ),
+      ])
+      .unwrap();
+
+    let hits = index.search_hits("keyword", 1).unwrap();
+    assert_eq!(hits[0].file_path, "/notes/noisy.log");
+    let options = SearchOptions {
+      extension_boosts: BTreeMap::from([("md".to_string(), 10.0)]),
+      ..SearchOptions::default()
     };
+    let hits = index.search_hits_with("keyword", 1, &options).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].file_path, "/notes/guide.MD#install");
+  }
+
+  #[test]
+  fn text_index_should_search_after_warming_up() {
+    use super::*;
+    use crate::search::file::File;
+
+    let mut index = TextIndex::new().unwrap().with_warm_up(true);
+    for (path, content) in [("a.txt", "garden"), ("b.txt", "orchard")] {
+      index
+        .add_doc(&File::new(path.to_string(), content.to_string()))
+        .unwrap();
+      index.commit().unwrap();
+    }
+    assert!(index.warm_up_searcher().is_ok());
+    assert_eq!(index.search("orchard").unwrap().len(), 1);
+  }
+
+  #[test]
+  fn text_index_should_able_to_search_and_get_file_name_by_keyword() {
+    use super::*;
+    use crate::search::file::File;
+
+    let mut index = TextIndex::new().unwrap();
+    let file = File::new(
+      "test.txt".to_string(),
+      "This is a test file for indexing.".to_string(),
+    );
     index.add_doc(&file).unwrap();
     index.commit().unwrap();
     let results = index.search("test").unwrap();
@@ -208,16 +933,16 @@ mod tests {
     use crate::search::file::File;
 
     let mut index = TextIndex::new().unwrap();
-    let file = File {
-      path: "test.txt".to_string(),
-      content: "This is a test file for indexing.".to_string(),
-    };
+    let file = File::new(
+      "test.txt".to_string(),
+      "This is a test file for indexing.".to_string(),
+    );
     index.add_doc(&file).unwrap();
     index.commit().unwrap();
-    let updated_file = File {
-      path: "test.txt".to_string(),
-      content: "This is an updated test file for indexing.".to_string(),
-    };
+    let updated_file = File::new(
+      "test.txt".to_string(),
+      "This is an updated test file for indexing.".to_string(),
+    );
     index.replace_doc(&updated_file).unwrap();
     index.commit().unwrap();
     let results = index.search("updated").unwrap();
@@ -228,16 +953,32 @@ mod tests {
     assert!(results[0].contains("test.txt"));
   }
 
+  #[test]
+  fn text_index_should_keep_one_document_per_path() {
+    use super::*;
+    use crate::search::file::File;
+
+    let mut index = TextIndex::new().unwrap();
+    let file = File::new("test.txt".to_string(), "First version.".to_string());
+    index.add_doc(&file).unwrap();
+    index.commit().unwrap();
+    let updated_file = File::new("test.txt".to_string(), "Second version.".to_string());
+    index.add_doc(&updated_file).unwrap();
+    index.add_docs([&updated_file]).unwrap();
+    assert_eq!(index.search("version").unwrap().len(), 1);
+    assert!(index.search("First").unwrap().is_empty());
+  }
+
   #[test]
   fn text_index_should_able_to_delete_file() {
     use super::*;
     use crate::search::file::File;
 
     let mut index = TextIndex::new().unwrap();
-    let file = File {
-      path: "test.txt".to_string(),
-      content: "This is a test file for indexing.".to_string(),
-    };
+    let file = File::new(
+      "test.txt".to_string(),
+      "This is a test file for indexing.".to_string(),
+    );
     index.add_doc(&file).unwrap();
     index.commit().unwrap();
     index.delete_doc("test.txt").unwrap();
@@ -252,14 +993,14 @@ mod tests {
     use crate::search::file::File;
 
     let mut index = TextIndex::new().unwrap();
-    let file1 = File {
-      path: "/foo/test1.txt".to_string(),
-      content: "This is a test file 1 for indexing.".to_string(),
-    };
-    let file2 = File {
-      path: "/foo/test2.txt".to_string(),
-      content: "This is a test file 2 for indexing.".to_string(),
-    };
+    let file1 = File::new(
+      "/foo/test1.txt".to_string(),
+      "This is a test file 1 for indexing.".to_string(),
+    );
+    let file2 = File::new(
+      "/foo/test2.txt".to_string(),
+      "This is a test file 2 for indexing.".to_string(),
+    );
     index.add_doc(&file1).unwrap();
     index.add_doc(&file2).unwrap();
     index.commit().unwrap();
@@ -269,4 +1010,213 @@ mod tests {
     let results = index.search("test").unwrap();
     assert_eq!(results.len(), 0);
   }
+
+  #[test]
+  fn text_index_should_return_line_number_and_snippet_of_first_match() {
+    use super::*;
+    use crate::search::file::File;
+    use crate::search::index_backend::SEARCH_FILE_LIMIT;
+
+    let mut index = TextIndex::new().unwrap();
+    let file = File::new(
+      "test.txt".to_string(),
+      "first line\nsecond line\nthe keyword is here\nlast line".to_string(),
+    );
+    index.add_doc(&file).unwrap();
+    index.commit().unwrap();
+    let hits = index.search_hits("keyword", SEARCH_FILE_LIMIT).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].file_path, "test.txt");
+    assert_eq!(hits[0].line_number, Some(3));
+    assert_eq!(hits[0].snippet.as_deref(), Some("the keyword is here"));
+  }
+
+  #[test]
+  fn line_offsets_should_point_at_start_of_each_line() {
+    use super::*;
+
+    assert_eq!(line_offsets("ab\ncd\n\ne"), vec![0, 3, 6, 7]);
+    assert_eq!(line_offsets(""), vec![0]);
+  }
+
+  #[test]
+  fn text_index_should_add_many_files_at_once() {
+    use super::*;
+    use crate::search::file::File;
+
+    let mut index = TextIndex::new().unwrap();
+    let files = vec![
+      File::new("test1.txt".to_string(), "This is test file 1.".to_string()),
+      File::new("test2.txt".to_string(), "This is test file 2.".to_string()),
+    ];
+    let added_count = index.add_docs(&files).unwrap();
+    assert_eq!(added_count, 2);
+    assert_eq!(index.get_pending_operations(), 0);
+    let results = index.search("test").unwrap();
+    assert_eq!(results.len(), 2);
+  }
+
+  #[test]
+  fn text_index_should_return_section_of_first_match() {
+    use super::*;
+    use crate::search::file::{File, FileSection};
+    use crate::search::index_backend::SEARCH_FILE_LIMIT;
+
+    let mut index = TextIndex::new().unwrap();
+    let content = "Intro text\nChapter one text\nThe keyword in chapter two\n";
+    let file = File::new("book.epub".to_string(), content.to_string()).with_sections(vec![
+      FileSection {
+        offset: 0,
+        title: "Chapter 1".to_string(),
+      },
+      FileSection {
+        offset: content.find("The keyword").unwrap(),
+        title: "Chapter 2".to_string(),
+      },
+    ]);
+    index.add_doc(&file).unwrap();
+    index.commit().unwrap();
+    let hits = index.search_hits("keyword", SEARCH_FILE_LIMIT).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].section.as_deref(), Some("Chapter 2"));
+  }
+
+  #[test]
+  fn text_index_should_search_metadata_fields() {
+    use super::*;
+    use crate::search::file::File;
+    use crate::search::index_backend::SEARCH_FILE_LIMIT;
+
+    let mut index = TextIndex::new().unwrap();
+    let file = File::new("mail.eml".to_string(), "See attached.".to_string())
+      .with_metadata("subject", "Quarterly report");
+    index.add_doc(&file).unwrap();
+    index.commit().unwrap();
+    let hits = index
+      .search_hits("metadata.subject:quarterly", SEARCH_FILE_LIMIT)
+      .unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].metadata["subject"], "Quarterly report");
+  }
+
+  #[test]
+  fn text_index_should_count_and_search_nested_tags() {
+    use super::*;
+    use crate::search::index_backend::SEARCH_FILE_LIMIT;
+
+    let mut index = TextIndex::new().unwrap();
+    for (path, tags) in [("a.md", "project/alpha todo"), ("b.md", "project/beta")] {
+      let file =
+        File::new(path.to_string(), "note".to_string()).with_metadata(TAGS_METADATA_KEY, tags);
+      index.add_doc(&file).unwrap();
+    }
+    index.commit().unwrap();
+
+    let counts = index.tag_counts().unwrap();
+    assert_eq!(counts["project"], 2);
+    assert_eq!(counts["project/alpha"], 1);
+    assert_eq!(counts["todo"], 1);
+    assert_eq!(
+      index
+        .search_by_tag("project", SEARCH_FILE_LIMIT)
+        .unwrap()
+        .len(),
+      2
+    );
+    let hits = index
+      .search_by_tag("project/beta", SEARCH_FILE_LIMIT)
+      .unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].file_path, "b.md");
+  }
+
+  #[test]
+  fn text_index_should_find_documents_by_link_target() {
+    use super::*;
+    use crate::search::index_backend::SEARCH_FILE_LIMIT;
+
+    let mut index = TextIndex::new().unwrap();
+    for (path, links) in [
+      ("/vault/a.md", "[[plan]]\n/vault/b.md"),
+      ("/vault/b.md", "[[planning]]"),
+    ] {
+      let file =
+        File::new(path.to_string(), "note".to_string()).with_metadata(LINKS_METADATA_KEY, links);
+      index.add_doc(&file).unwrap();
+    }
+    index.commit().unwrap();
+
+    let hits = index.search_by_link("[[plan]]", SEARCH_FILE_LIMIT).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].file_path, "/vault/a.md");
+    assert_eq!(
+      index
+        .search_by_link("/vault/b.md", SEARCH_FILE_LIMIT)
+        .unwrap()
+        .len(),
+      1
+    );
+  }
+
+  #[test]
+  fn text_index_should_return_line_number_relative_to_whole_file_for_chunks() {
+    use super::*;
+    use crate::search::file::File;
+    use crate::search::index_backend::SEARCH_FILE_LIMIT;
+
+    let mut index = TextIndex::new().unwrap();
+    let file = File::new(
+      "/logs/app.log#chunk=4096".to_string(),
+      "first\nsecond keyword\n".to_string(),
+    )
+    .with_metadata(CHUNK_START_LINE_KEY, "100");
+    index.add_doc(&file).unwrap();
+    index.commit().unwrap();
+
+    let hits = index.search_hits("keyword", SEARCH_FILE_LIMIT).unwrap();
+    assert_eq!(hits[0].line_number, Some(101));
+  }
+
+  #[test]
+  fn text_index_should_list_paths_of_live_documents() {
+    use super::*;
+    use crate::search::file::File;
+
+    let mut index = TextIndex::new().unwrap();
+    index
+      .add_docs(vec![
+        File::new("/a.txt".to_string(), "alpha".to_string()),
+        File::new("/b.txt".to_string(), "beta".to_string()),
+      ])
+      .unwrap();
+    index.delete_doc("/a.txt").unwrap();
+    index.commit().unwrap();
+
+    assert_eq!(
+      index.indexed_paths().unwrap(),
+      BTreeSet::from(["/b.txt".to_string()])
+    );
+  }
+
+  #[test]
+  fn text_index_should_tell_whether_path_or_path_prefix_is_indexed() {
+    use super::*;
+    use crate::search::file::File;
+
+    let mut index = TextIndex::new().unwrap();
+    index
+      .add_doc(&File::new("/dir/a.txt".to_string(), "a".to_string()))
+      .unwrap();
+    index
+      .add_doc(&File::new("/dir/b.txt".to_string(), "b".to_string()))
+      .unwrap();
+    index.commit().unwrap();
+    index.delete_doc("/dir/a.txt").unwrap();
+    index.commit().unwrap();
+
+    assert!(!index.has_doc("/dir/a.txt").unwrap());
+    assert!(index.has_doc("/dir/b.txt").unwrap());
+    assert!(index.has_docs_with_path_prefix("/dir/").unwrap());
+    assert!(!index.has_docs_with_path_prefix("/other/").unwrap());
+  }
 }
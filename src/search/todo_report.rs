@@ -0,0 +1,103 @@
+/// Markers reported unless others are configured with `--todo-markers`.
+pub const DEFAULT_TODO_MARKERS: &[&str] = &["TODO", "FIXME", "HACK"];
+
+/// Most files a report covers unless asked for another number.
+pub const TODO_REPORT_FILE_LIMIT: usize = 100;
+
+/// Longest text of a line kept in a report, in characters.
+const MAX_ITEM_TEXT: usize = 200;
+
+/// A line mentioning a marker.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct TodoItem {
+  pub line_number: usize,
+  pub marker: String,
+  /// The line, left out in privacy mode
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub text: Option<String>,
+}
+
+/// The lines of a file mentioning a marker, in file order.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct TodoFile {
+  pub file_path: String,
+  pub items: Vec<TodoItem>,
+}
+
+/// Query finding the files that may mention any of the markers.
+pub fn marker_query(markers: &[String]) -> String {
+  markers
+    .iter()
+    .map(|marker| format!("\"{}\"", marker.replace('"', "")))
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+/// Lines mentioning a marker as a whole word. Markers are matched in their exact case so that
+/// prose such as "my todo list" is not reported.
+pub fn find_markers(content: &str, markers: &[String]) -> Vec<TodoItem> {
+  content
+    .lines()
+    .enumerate()
+    .filter_map(|(index, line)| {
+      let marker = markers
+        .iter()
+        .filter_map(|marker| Some((find_word(line, marker)?, marker)))
+        .min_by_key(|(position, _)| *position)?
+        .1;
+      Some(TodoItem {
+        line_number: index + 1,
+        marker: marker.clone(),
+        text: Some(line.trim().chars().take(MAX_ITEM_TEXT).collect()),
+      })
+    })
+    .collect()
+}
+
+/// Byte position of the first occurrence of `word` not part of a longer word.
+fn find_word(line: &str, word: &str) -> Option<usize> {
+  if word.is_empty() {
+    return None;
+  }
+  let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+  line
+    .match_indices(word)
+    .map(|(position, _)| position)
+    .find(|&position| {
+      let before = line[..position].chars().next_back();
+      let after = line[position + word.len()..].chars().next();
+      !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn find_markers_should_report_whole_word_markers_by_line() {
+    let markers = DEFAULT_TODO_MARKERS
+      .iter()
+      .map(|marker| marker.to_string())
+      .collect::<Vec<_>>();
+    let content = "fn main() {\n  // TODO: handle errors\n  let TODOS = 1; // my todo list\n  # FIXME(ann) HACK around it\n}";
+
+    let items = find_markers(content, &markers);
+    assert_eq!(
+      items,
+      vec![
+        TodoItem {
+          line_number: 2,
+          marker: "TODO".to_string(),
+          text: Some("// TODO: handle errors".to_string()),
+        },
+        TodoItem {
+          line_number: 4,
+          marker: "FIXME".to_string(),
+          text: Some("# FIXME(ann) HACK around it".to_string()),
+        },
+      ]
+    );
+    assert_eq!(marker_query(&markers), "\"TODO\" \"FIXME\" \"HACK\"");
+  }
+}
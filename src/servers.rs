@@ -1,2 +1,4 @@
+pub mod audit_log;
 pub mod error;
+pub mod request_limiter;
 pub mod search;
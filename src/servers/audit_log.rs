@@ -0,0 +1,93 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::{
+  fs::{File, OpenOptions},
+  io::Write,
+  path::Path,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A tool call as the client made it.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AuditEntry {
+  pub timestamp_ms: u128,
+  /// Name and version the client gave when connecting
+  pub client: Option<String>,
+  pub tool: String,
+  pub params: serde_json::Value,
+  /// Results returned, when the tool returns a list of them
+  pub hits: Option<usize>,
+  pub is_error: bool,
+}
+
+impl AuditEntry {
+  pub fn new(tool: &str, params: serde_json::Value) -> Self {
+    AuditEntry {
+      timestamp_ms: SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default(),
+      client: None,
+      tool: tool.to_string(),
+      params,
+      hits: None,
+      is_error: false,
+    }
+  }
+}
+
+/// Appends every tool call as a JSON line to a local file, so that users can audit what their
+/// client actually searched. The file is only readable by its owner.
+pub struct AuditLog {
+  file: Mutex<File>,
+}
+
+impl AuditLog {
+  pub fn open(path: &Path) -> Result<Self> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let file = options
+      .open(path)
+      .map_err(|e| anyhow::anyhow!("Cannot open audit log {:?}: {}", path, e))?;
+    Ok(AuditLog {
+      file: Mutex::new(file),
+    })
+  }
+
+  pub fn record(&self, entry: &AuditEntry) -> Result<()> {
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    /* One write per line keeps lines whole when several calls finish at once */
+    self.file.lock().write_all(line.as_bytes())?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn audit_log_should_append_one_line_per_call() {
+    let path = std::env::temp_dir().join(format!("audit-test-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let audit_log = AuditLog::open(&path).unwrap();
+    for keyword in ["garden", "plans"] {
+      let mut entry = AuditEntry::new("search_index", serde_json::json!({ "keyword": keyword }));
+      entry.hits = Some(2);
+      audit_log.record(&entry).unwrap();
+    }
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let lines = content
+      .lines()
+      .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+      .collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[1]["params"]["keyword"], "plans");
+    assert_eq!(lines[1]["hits"], 2);
+  }
+}
@@ -0,0 +1,123 @@
+use parking_lot::Mutex;
+use std::{
+  sync::atomic::{AtomicUsize, Ordering},
+  time::{Duration, Instant},
+};
+
+/// Why a request was refused, returned to the client as JSON so that it can back off.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RateLimited {
+  /// Always `rate_limited`
+  pub error: &'static str,
+  pub reason: String,
+  pub retry_after_ms: u64,
+}
+
+impl RateLimited {
+  fn new(reason: String, retry_after: Duration) -> Self {
+    RateLimited {
+      error: "rate_limited",
+      reason,
+      retry_after_ms: retry_after.as_millis().try_into().unwrap_or(u64::MAX),
+    }
+  }
+}
+
+struct TokenBucket {
+  tokens: f64,
+  refilled_at: Instant,
+}
+
+/// Caps the tool calls of a session running at once and started per second, so that an agent
+/// stuck in a tight retry loop cannot saturate the machine. Requests over a limit are refused
+/// rather than queued.
+pub struct RequestLimiter {
+  max_concurrent: Option<usize>,
+  max_per_second: Option<u32>,
+  running: AtomicUsize,
+  bucket: Mutex<TokenBucket>,
+}
+
+/// Counts its request as running until dropped.
+pub struct RequestPermit<'a> {
+  running: &'a AtomicUsize,
+}
+
+impl Drop for RequestPermit<'_> {
+  fn drop(&mut self) {
+    self.running.fetch_sub(1, Ordering::AcqRel);
+  }
+}
+
+impl RequestLimiter {
+  pub fn new(max_concurrent: Option<usize>, max_per_second: Option<u32>) -> Self {
+    RequestLimiter {
+      max_concurrent,
+      max_per_second,
+      running: AtomicUsize::new(0),
+      /* A full bucket allows a burst of one second worth of requests */
+      bucket: Mutex::new(TokenBucket {
+        tokens: max_per_second.unwrap_or_default().into(),
+        refilled_at: Instant::now(),
+      }),
+    }
+  }
+
+  pub fn acquire(&self) -> Result<RequestPermit<'_>, RateLimited> {
+    let running = self.running.fetch_add(1, Ordering::AcqRel);
+    let permit = RequestPermit {
+      running: &self.running,
+    };
+    if let Some(max_concurrent) = self.max_concurrent
+      && running >= max_concurrent
+    {
+      return Err(RateLimited::new(
+        format!("{} requests are already running", max_concurrent),
+        Duration::from_millis(100),
+      ));
+    }
+    if let Some(max_per_second) = self.max_per_second {
+      let rate = f64::from(max_per_second.max(1));
+      let mut bucket = self.bucket.lock();
+      let now = Instant::now();
+      bucket.tokens =
+        (bucket.tokens + now.duration_since(bucket.refilled_at).as_secs_f64() * rate).min(rate);
+      bucket.refilled_at = now;
+      if bucket.tokens < 1.0 {
+        return Err(RateLimited::new(
+          format!("More than {} requests per second", max_per_second),
+          Duration::from_secs_f64((1.0 - bucket.tokens) / rate),
+        ));
+      }
+      bucket.tokens -= 1.0;
+    }
+    Ok(permit)
+  }
+}
+
+impl Default for RequestLimiter {
+  fn default() -> Self {
+    Self::new(None, None)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn request_limiter_should_refuse_requests_over_limits() {
+    let limiter = RequestLimiter::new(Some(1), None);
+    let permit = limiter.acquire().unwrap();
+    assert!(limiter.acquire().is_err());
+    drop(permit);
+    assert!(limiter.acquire().is_ok());
+
+    let limiter = RequestLimiter::new(None, Some(2));
+    assert!(limiter.acquire().is_ok());
+    assert!(limiter.acquire().is_ok());
+    let limited = limiter.acquire().err().unwrap();
+    assert_eq!(limited.error, "rate_limited");
+    assert!(limited.retry_after_ms <= 500);
+  }
+}
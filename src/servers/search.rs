@@ -1,24 +1,93 @@
 use std::{
+  collections::{BTreeMap, BTreeSet},
   fmt::Debug,
-  sync::{Arc, Mutex},
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::Instant,
 };
 
 use rmcp::{
-  ServerHandler,
-  model::{Implementation, ProtocolVersion, ServerCapabilities, ServerInfo},
+  RoleServer, ServerHandler,
+  handler::server::tool::ToolCallContext,
+  model::{
+    CallToolRequestParam, CallToolResult, CompleteRequestParam, CompleteResult, CompletionInfo,
+    Content, Implementation, ListToolsResult, PaginatedRequestParam, ProtocolVersion,
+    ServerCapabilities, ServerInfo,
+  },
   schemars,
   schemars::JsonSchema,
+  service::RequestContext,
   tool,
 };
 
-use crate::search::{file::FileLoader, text_index::TextIndex};
+use crate::file::{
+  file_info::FileInfo,
+  file_writer::FileWriter,
+  outline::{OUTLINE_EXTENSIONS, outline, select_lines},
+  version_history::VersionHistory,
+};
+use crate::search::{
+  bookmarks::Bookmarks,
+  cancellation::{self, Cancellable, check_cancelled},
+  directory_tree::{DEFAULT_TREE_DEPTH, DirectoryNode, directory_tree},
+  event_log::EventLog,
+  export::{EXPORT_LIMIT, ExportFormat, export_hits, export_path},
+  file::{
+    CONTAINER_MEMBER_SEPARATOR, FileLoader, FileOperation, LINKS_METADATA_KEY, document_file_path,
+  },
+  git_metadata::GitRepository,
+  git_revision::RevisionIndexes,
+  index_backend::{SEARCH_FILE_LIMIT, SearchHit},
+  line_diff::DEFAULT_DIFF_CONTEXT,
+  match_mode::{MatchMode, with_match_mode},
+  near_duplicates::{
+    DEFAULT_SIMILARITY_THRESHOLD, NEAR_DUPLICATE_FILE_LIMIT, NEAR_DUPLICATE_PAIR_LIMIT,
+    NearDuplicates,
+  },
+  path_completion::{MAX_COMPLETIONS, complete_path},
+  project_index::default_project_name,
+  query_stats::QueryStats,
+  query_syntax::QuerySyntax,
+  query_template::QueryTemplate,
+  result_sets::{ResultSet, ResultSets},
+  search_in_dir::DirectorySearch,
+  semantic_index::SemanticSearch,
+  shadow_copies::ShadowCopies,
+  sharded_index::{SearchScope, ShardedIndex, document_path},
+  skipped_files::{SkipKind, SkippedFiles},
+  todo_report::{
+    DEFAULT_TODO_MARKERS, TODO_REPORT_FILE_LIMIT, TodoFile, find_markers, marker_query,
+  },
+};
 
-use super::error::ServerError;
+use super::{
+  audit_log::{AuditEntry, AuditLog},
+  error::ServerError,
+  request_limiter::RequestLimiter,
+};
 
 #[derive(Clone)]
 pub struct SearchServer {
-  index: Arc<Mutex<TextIndex>>,
+  index: Arc<ShardedIndex>,
   file_loader: Arc<dyn FileLoader + Send + Sync>,
+  skipped_files: Arc<SkippedFiles>,
+  event_log: Arc<EventLog>,
+  semantic_search: Option<Arc<SemanticSearch>>,
+  directory_search: Option<Arc<DirectorySearch>>,
+  bookmarks: Arc<Bookmarks>,
+  query_stats: Arc<QueryStats>,
+  audit_log: Option<Arc<AuditLog>>,
+  request_limiter: Arc<RequestLimiter>,
+  privacy_mode: bool,
+  query_templates: Arc<Vec<QueryTemplate>>,
+  result_sets: Arc<ResultSets>,
+  export_dir: Option<PathBuf>,
+  todo_markers: Arc<Vec<String>>,
+  file_writer: Option<Arc<FileWriter>>,
+  shadow_copies: Option<Arc<ShadowCopies>>,
+  version_history: Option<Arc<VersionHistory>>,
+  revision_indexes: Arc<RevisionIndexes>,
+  query_syntax: Arc<QuerySyntax>,
 }
 
 impl Debug for SearchServer {
@@ -27,62 +96,1776 @@ impl Debug for SearchServer {
   }
 }
 
+#[derive(JsonSchema, Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+  /// BM25 keyword ranking
+  Keyword,
+  /// BM25 candidates reranked by embedding similarity, for natural-language questions
+  Hybrid,
+}
+
 #[derive(JsonSchema, Debug, serde::Deserialize)]
 pub struct SearchParams {
   #[schemars(description = "Keyword to search for. Use space to separate multiple keywords.")]
   pub keyword: String,
+  #[schemars(
+    description = "\"keyword\" (default) or \"hybrid\", which reranks keyword matches by meaning and needs semantic search to be enabled."
+  )]
+  pub mode: Option<SearchMode>,
+  #[schemars(
+    description = "How many of the keywords a file must contain: \"any\" (default, files with more of them rank higher), \"all\", or a number such as \"2\"."
+  )]
+  pub match_mode: Option<String>,
+  #[schemars(
+    description = "Only search the watched directories of this project, see list_projects."
+  )]
+  pub project: Option<String>,
+  #[schemars(
+    description = "Only search these watched directories, each given by its path or last path component, e.g. [\"notes\", \"src\"]. Searches all when omitted."
+  )]
+  pub roots: Option<Vec<String>>,
+  #[schemars(
+    description = "Only return files under this directory, or this file. When nothing is found or the index is still catching up, it is re-indexed ahead of other changes."
+  )]
+  pub path: Option<String>,
+  #[schemars(
+    description = "Saved filters every result must pass, or fail when prefixed with \"-\", e.g. [\"work-notes\", \"last-30-days\", \"-drafts\"]; see list_filters."
+  )]
+  pub filters: Option<Vec<String>>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct MultiSearchParams {
+  #[schemars(
+    description = "Keywords to search for, one search each, e.g. [\"deploy\", \"release process\", \"rollout\"]. At most 10."
+  )]
+  pub queries: Vec<String>,
+  #[schemars(
+    description = "How many of the keywords of each query a file must contain: \"any\" (default), \"all\", or a number such as \"2\"."
+  )]
+  pub match_mode: Option<String>,
+  #[schemars(
+    description = "Only search the watched directories of this project, see list_projects."
+  )]
+  pub project: Option<String>,
+  #[schemars(
+    description = "Only search these watched directories, each given by its path or last path component."
+  )]
+  pub roots: Option<Vec<String>>,
+  #[schemars(description = "Only return files under this directory, or this file.")]
+  pub path: Option<String>,
+  #[schemars(
+    description = "Saved filters every result must pass, or fail when prefixed with \"-\"; see list_filters."
+  )]
+  pub filters: Option<Vec<String>>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct ListTagsParams {
+  #[schemars(description = "Only count tags in the watched directories of this project.")]
+  pub project: Option<String>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct SearchByTagParams {
+  #[schemars(
+    description = "Tag without the leading #, e.g. \"project\", which also finds nested tags such as \"project/alpha\"."
+  )]
+  pub tag: String,
+  #[schemars(description = "Only search the watched directories of this project.")]
+  pub project: Option<String>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct GetBacklinksParams {
+  #[schemars(description = "Path of the note that other notes link to.")]
+  pub file_path: String,
+  #[schemars(description = "Only search the watched directories of this project.")]
+  pub project: Option<String>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct GetOutgoingLinksParams {
+  #[schemars(description = "Path of the Markdown note whose links to list.")]
+  pub file_path: String,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct PinDocumentParams {
+  #[schemars(description = "Path of an indexed document, as returned by search results.")]
+  pub file_path: String,
+  #[schemars(description = "Why the document matters, shown by list_pinned.")]
+  pub note: Option<String>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct UnpinDocumentParams {
+  #[schemars(description = "Path of a pinned document.")]
+  pub file_path: String,
 }
 
 #[derive(JsonSchema, Debug, serde::Deserialize)]
 pub struct LoadFileParams {
   #[schemars(description = "Path to the file to load.")]
   pub file_path: String,
+  #[schemars(description = "First line to return, 1-based. Defaults to the first line.")]
+  pub start_line: Option<usize>,
+  #[schemars(
+    description = "Last line to return, inclusive, e.g. the end_line of a get_outline heading. Defaults to the last line."
+  )]
+  pub end_line: Option<usize>,
+  #[schemars(
+    description = "Set to true once the user agreed to share this file. Required when the server runs in privacy mode."
+  )]
+  pub confirm: Option<bool>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct GetOutlineParams {
+  #[schemars(description = "Path to a Markdown, Org or reStructuredText file.")]
+  pub file_path: String,
+  #[schemars(
+    description = "Set to true once the user agreed to share the headings of this file. Required when the server runs in privacy mode."
+  )]
+  pub confirm: Option<bool>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct SemanticSearchParams {
+  #[schemars(description = "Natural language description of what to find.")]
+  pub query: String,
+  #[schemars(description = "Maximum number of files to return. Defaults to 10.")]
+  pub limit: Option<usize>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct SearchRevisionParams {
+  #[schemars(description = "Keyword to search for. Use space to separate multiple keywords.")]
+  pub keyword: String,
+  #[schemars(
+    description = "Git revision to search as of: a tag, branch or commit, e.g. \"v1.0\" or \"HEAD~3\"."
+  )]
+  pub revision: String,
+  #[schemars(
+    description = "Watched directory to search, given by its path or last path component. Defaults to the first one in a git repository."
+  )]
+  pub root: Option<String>,
+  #[schemars(description = "Maximum number of files to return. Defaults to 10.")]
+  pub limit: Option<usize>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct SearchDirectoryParams {
+  #[schemars(description = "Directory to search, which must be allowed with --allow-search-dir.")]
+  pub directory: String,
+  #[schemars(description = "Keyword to search for. Use space to separate multiple keywords.")]
+  pub keyword: String,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct RefineParams {
+  #[schemars(
+    description = "Id of the result set to narrow, as given after the results of search_index or refine, e.g. \"r3\"."
+  )]
+  pub result_set: String,
+  #[schemars(description = "Keyword the files must also match, in the syntax of search_index.")]
+  pub keyword: String,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct LoadResultSetParams {
+  #[schemars(
+    description = "Id of the result set, as given after the results of search_index or refine, e.g. \"r3\"."
+  )]
+  pub result_set: String,
+  #[schemars(description = "Most files to load, best ranked first. Defaults to 10.")]
+  pub limit: Option<usize>,
+  #[schemars(
+    description = "Set to true once the user agreed to share these files. Required when the server runs in privacy mode."
+  )]
+  pub confirm: Option<bool>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct ExportResultsParams {
+  #[schemars(
+    description = "Id of an earlier result set to export, e.g. \"r3\". Either this or keyword is required."
+  )]
+  pub result_set: Option<String>,
+  #[schemars(description = "Query whose results are exported, in the syntax of search_index.")]
+  pub keyword: Option<String>,
+  #[schemars(
+    description = "File to write, relative to the export directory, e.g. \"findings/garden.md\"."
+  )]
+  pub path: String,
+  #[schemars(
+    description = "json, csv or markdown. Defaults to the format matching the extension of path, else json."
+  )]
+  pub format: Option<String>,
+  #[schemars(description = "Most results to export. Defaults to 100.")]
+  pub limit: Option<usize>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct TodoReportParams {
+  #[schemars(
+    description = "Markers to look for, matched as whole words in their exact case, e.g. [\"TODO\", \"XXX\"]. Defaults to the markers the server was started with."
+  )]
+  pub markers: Option<Vec<String>>,
+  #[schemars(description = "Only report files in the watched directories of this project.")]
+  pub project: Option<String>,
+  #[schemars(
+    description = "Only report files in these watched directories, each given by its path or last path component."
+  )]
+  pub roots: Option<Vec<String>>,
+  #[schemars(description = "Only report files under this directory, or this file.")]
+  pub path: Option<String>,
+  #[schemars(description = "Most files to report. Defaults to 100.")]
+  pub limit: Option<usize>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct FindNearDuplicatesParams {
+  #[schemars(
+    description = "Least share of common text, from 0 to 1, for two files to be reported. Defaults to 0.8."
+  )]
+  pub threshold: Option<f32>,
+  #[schemars(description = "Only compare files in the watched directories of this project.")]
+  pub project: Option<String>,
+  #[schemars(
+    description = "Only compare files in these watched directories, each given by its path or last path component."
+  )]
+  pub roots: Option<Vec<String>>,
+  #[schemars(description = "Only compare files under this directory.")]
+  pub path: Option<String>,
+  #[schemars(description = "Most pairs to return, most similar first. Defaults to 50.")]
+  pub limit: Option<usize>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct WriteFileParams {
+  #[schemars(description = "Absolute path of the file, inside a watched directory.")]
+  pub file_path: String,
+  pub content: String,
+  #[schemars(description = "Replace the file if it exists. Defaults to false.")]
+  pub overwrite: Option<bool>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct AppendFileParams {
+  #[schemars(
+    description = "Absolute path of the file, inside a watched directory. Created if missing."
+  )]
+  pub file_path: String,
+  #[schemars(description = "Text to add at the end of the file, including any leading newline.")]
+  pub content: String,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct DeleteFileParams {
+  #[schemars(description = "Absolute path of the file, inside a watched directory.")]
+  pub file_path: String,
+  #[schemars(
+    description = "Set to true once the user agreed to delete the file. Without it nothing is deleted."
+  )]
+  pub confirm: Option<bool>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct MoveFileParams {
+  #[schemars(description = "Absolute path of the file to move, inside a watched directory.")]
+  pub from_path: String,
+  #[schemars(description = "Absolute new path of the file, inside a watched directory.")]
+  pub to_path: String,
+  #[schemars(description = "Replace a file already at to_path. Defaults to false.")]
+  pub overwrite: Option<bool>,
+  #[schemars(
+    description = "Set to true once the user agreed to move the file. Without it nothing is moved."
+  )]
+  pub confirm: Option<bool>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct GetFileInfoParams {
+  #[schemars(description = "Path of the file.")]
+  pub file_path: String,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct ListVersionsParams {
+  #[schemars(description = "Path of the file.")]
+  pub file_path: String,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct LoadVersionParams {
+  #[schemars(description = "Path of the file.")]
+  pub file_path: String,
+  #[schemars(description = "Version as returned by list_versions, e.g. fnv1a64:af63dc4c8601ec8c.")]
+  pub version: String,
+  #[schemars(
+    description = "Set to true once the user agreed to share this file. Required when the server runs in privacy mode."
+  )]
+  pub confirm: Option<bool>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct DiffFileParams {
+  #[schemars(description = "Path of the file.")]
+  pub file_path: String,
+  #[schemars(description = "Unchanged lines shown around each change. Defaults to 3.")]
+  pub context: Option<usize>,
+  #[schemars(
+    description = "Set to true once the user agreed to share this file. Required when the server runs in privacy mode."
+  )]
+  pub confirm: Option<bool>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct TreeParams {
+  #[schemars(
+    description = "Directory to summarize. Defaults to every watched directory, one tree each."
+  )]
+  pub path: Option<String>,
+  #[schemars(description = "Only summarize the watched directories of this project.")]
+  pub project: Option<String>,
+  #[schemars(
+    description = "Directory levels to show below each root; deeper files are counted in their ancestor. Defaults to 2."
+  )]
+  pub depth: Option<usize>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct LoadedFile {
+  file_path: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  content: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  error: Option<String>,
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct RunQueryTemplateParams {
+  #[schemars(description = "Name of the template, see list_query_templates.")]
+  pub name: String,
+  #[schemars(
+    description = "Argument of every parameter of the template, e.g. {\"path\": \"/notes\"}."
+  )]
+  pub args: Option<BTreeMap<String, String>>,
+  #[schemars(description = "Only search the watched directories of this project.")]
+  pub project: Option<String>,
+  #[schemars(
+    description = "Only search these watched directories, each given by its path or last path component."
+  )]
+  pub roots: Option<Vec<String>>,
+}
+
+#[derive(JsonSchema, Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexErrorKind {
+  /// Left out on purpose, e.g. because it is too large
+  Skipped,
+  /// Could not be read or extracted
+  Failed,
+}
+
+impl From<IndexErrorKind> for SkipKind {
+  fn from(kind: IndexErrorKind) -> Self {
+    match kind {
+      IndexErrorKind::Skipped => SkipKind::Skipped,
+      IndexErrorKind::Failed => SkipKind::Failed,
+    }
+  }
+}
+
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct ListIndexErrorsParams {
+  #[schemars(
+    description = "\"failed\" or \"skipped\" to list only files of that kind. Lists both when omitted."
+  )]
+  pub kind: Option<IndexErrorKind>,
 }
 
+#[derive(JsonSchema, Debug, serde::Deserialize)]
+pub struct RecentEventsParams {
+  #[schemars(description = "Maximum number of events to return, latest last. Defaults to 100.")]
+  pub limit: Option<usize>,
+  #[schemars(description = "Only return events mentioning this path or part of a path.")]
+  pub path_contains: Option<String>,
+}
+
+const DEFAULT_RECENT_EVENTS_LIMIT: usize = 100;
+/// Keyword matches considered for reranking in hybrid mode.
+const HYBRID_CANDIDATE_LIMIT: usize = 50;
+/// Most queries one multi_search call runs.
+const MULTI_SEARCH_QUERY_LIMIT: usize = 10;
+
+/// Refuses until the client calls `tool` again with `confirm` set to true after asking the user
+/// whether `question`. This is the one confirmation step of every tool: the server cannot reach
+/// the user itself, so it relies on the client to put the question to them.
+fn require_confirmation(
+  confirm: Option<bool>,
+  tool: &str,
+  question: impl FnOnce() -> String,
+) -> Result<(), ServerError> {
+  if confirm == Some(true) {
+    return Ok(());
+  }
+  Err(ServerError(anyhow::anyhow!(
+    "Ask the user whether {}, and only if they agree call {} again with confirm set to true.",
+    question(),
+    tool
+  )))
+}
+
+/// Arguments taking paths of watched files, completed from the index.
+const PATH_ARGUMENTS: &[&str] = &["file_path", "path"];
+
 #[tool(tool_box)]
 impl SearchServer {
-  pub fn new(index: Arc<Mutex<TextIndex>>, file_loader: Arc<dyn FileLoader + Send + Sync>) -> Self {
-    SearchServer { index, file_loader }
+  pub fn new(
+    index: Arc<ShardedIndex>,
+    file_loader: Arc<dyn FileLoader + Send + Sync>,
+    skipped_files: Arc<SkippedFiles>,
+    event_log: Arc<EventLog>,
+  ) -> Self {
+    SearchServer {
+      index,
+      file_loader,
+      skipped_files,
+      event_log,
+      semantic_search: None,
+      directory_search: None,
+      bookmarks: Arc::new(Bookmarks::new()),
+      query_stats: Arc::new(QueryStats::default()),
+      audit_log: None,
+      request_limiter: Arc::new(RequestLimiter::default()),
+      privacy_mode: false,
+      query_templates: Arc::new(Vec::new()),
+      result_sets: Arc::new(ResultSets::default()),
+      export_dir: None,
+      todo_markers: Arc::new(
+        DEFAULT_TODO_MARKERS
+          .iter()
+          .map(|marker| marker.to_string())
+          .collect(),
+      ),
+      file_writer: None,
+      shadow_copies: None,
+      version_history: None,
+      revision_indexes: Arc::new(RevisionIndexes::new()),
+      query_syntax: Arc::new(QuerySyntax::default()),
+    }
+  }
+
+  pub fn with_semantic_search(mut self, semantic_search: Option<Arc<SemanticSearch>>) -> Self {
+    self.semantic_search = semantic_search;
+    self
+  }
+
+  pub fn with_directory_search(mut self, directory_search: Option<Arc<DirectorySearch>>) -> Self {
+    self.directory_search = directory_search;
+    self
+  }
+
+  /// Pins shared with the index, which lists pinned documents first.
+  pub fn with_bookmarks(mut self, bookmarks: Arc<Bookmarks>) -> Self {
+    self.bookmarks = bookmarks;
+    self
+  }
+
+  pub fn with_query_stats(mut self, query_stats: Arc<QueryStats>) -> Self {
+    self.query_stats = query_stats;
+    self
+  }
+
+  pub fn with_audit_log(mut self, audit_log: Option<Arc<AuditLog>>) -> Self {
+    self.audit_log = audit_log;
+    self
+  }
+
+  pub fn with_request_limiter(mut self, request_limiter: Arc<RequestLimiter>) -> Self {
+    self.request_limiter = request_limiter;
+    self
+  }
+
+  /// Returns search results without snippets, sections and metadata, and only loads files the
+  /// client confirmed, for indexes of sensitive material.
+  pub fn with_privacy_mode(mut self, privacy_mode: bool) -> Self {
+    self.privacy_mode = privacy_mode;
+    self
+  }
+
+  pub fn with_query_templates(mut self, query_templates: Arc<Vec<QueryTemplate>>) -> Self {
+    self.query_templates = query_templates;
+    self
+  }
+
+  pub fn with_query_syntax(mut self, query_syntax: Arc<QuerySyntax>) -> Self {
+    self.query_syntax = query_syntax;
+    self
+  }
+
+  pub fn with_result_sets(mut self, result_sets: Arc<ResultSets>) -> Self {
+    self.result_sets = result_sets;
+    self
+  }
+
+  pub fn with_export_dir(mut self, export_dir: Option<PathBuf>) -> Self {
+    self.export_dir = export_dir;
+    self
+  }
+
+  pub fn with_file_writer(mut self, file_writer: Option<Arc<FileWriter>>) -> Self {
+    self.file_writer = file_writer;
+    self
+  }
+
+  pub fn with_shadow_copies(mut self, shadow_copies: Option<Arc<ShadowCopies>>) -> Self {
+    self.shadow_copies = shadow_copies;
+    self
+  }
+
+  pub fn with_version_history(mut self, version_history: Option<Arc<VersionHistory>>) -> Self {
+    self.version_history = version_history;
+    self
+  }
+
+  pub fn with_todo_markers(mut self, todo_markers: Arc<Vec<String>>) -> Self {
+    self.todo_markers = todo_markers;
+    self
+  }
+
+  fn result_set(&self, id: &str) -> Result<ResultSet, ServerError> {
+    if !self.result_sets.is_enabled() {
+      return Err(ServerError(anyhow::anyhow!(
+        "Result sets are disabled. Start with --max-result-sets above 0 to enable them."
+      )));
+    }
+    self.result_sets.get(id).ok_or_else(|| {
+      ServerError(anyhow::anyhow!(
+        "Unknown result set {}, it may have expired; search again",
+        id
+      ))
+    })
+  }
+
+  /// Keeps the results for follow-up tools, returning the line telling the client their id.
+  fn keep_result_set(&self, result_set: ResultSet) -> String {
+    match self.result_sets.insert(result_set) {
+      Some(id) => format!(
+        "\nResult set: {} (narrow it down with refine, load its files with load_result_set)",
+        id
+      ),
+      None => String::new(),
+    }
+  }
+
+  /// The watched directory `root` names, or the first one in a git repository.
+  fn revision_root(&self, root: Option<&str>) -> Result<String, ServerError> {
+    let roots = self.index.roots().map_err(ServerError)?;
+    match root {
+      Some(root) => roots
+        .into_iter()
+        .find(|watched| watched == root || default_project_name(watched) == root)
+        .ok_or_else(|| ServerError(anyhow::anyhow!("{} is not a watched directory", root))),
+      None => roots
+        .into_iter()
+        .find(|watched| GitRepository::discover(Path::new(watched)).is_some())
+        .ok_or_else(|| {
+          ServerError(anyhow::anyhow!(
+            "No watched directory is in a git repository"
+          ))
+        }),
+    }
+  }
+
+  /// Asks for confirmation like `require_confirmation`, but only in privacy mode.
+  fn require_privacy_confirmation(
+    &self,
+    confirm: Option<bool>,
+    tool: &str,
+    question: impl FnOnce() -> String,
+  ) -> Result<(), ServerError> {
+    if !self.privacy_mode {
+      return Ok(());
+    }
+    require_confirmation(confirm, tool, || {
+      format!("{} although privacy mode is on", question())
+    })
+  }
+
+  /// Hits as they may be shown to the client.
+  fn visible_hits(&self, hits: Vec<SearchHit>) -> Vec<SearchHit> {
+    if !self.privacy_mode {
+      return hits;
+    }
+    hits.into_iter().map(SearchHit::without_content).collect()
+  }
+
+  fn keyword_search(&self, keyword: &str, scope: &SearchScope) -> Result<Vec<String>, ServerError> {
+    if !self.privacy_mode {
+      return self
+        .index
+        .search_scoped(keyword, scope)
+        .map_err(ServerError);
+    }
+    /* The cached results carry content, so search again rather than strip it from them */
+    let hits = self
+      .index
+      .search_hits(keyword, SEARCH_FILE_LIMIT, scope)
+      .map_err(ServerError)?;
+    self
+      .visible_hits(hits)
+      .iter()
+      .map(|hit| serde_json::to_string(hit).map_err(|e| ServerError(e.into())))
+      .collect()
+  }
+
+  async fn call_limited_tool(
+    &self,
+    request: CallToolRequestParam,
+    context: RequestContext<RoleServer>,
+  ) -> Result<CallToolResult, rmcp::Error> {
+    let _permit = match self.request_limiter.acquire() {
+      Ok(permit) => permit,
+      Err(limited) => {
+        tracing::warn!("Refused {}: {}", request.name, limited.reason);
+        let limited = serde_json::to_string(&limited).map_err(|e| {
+          rmcp::Error::internal_error(format!("Failed to serialize error: {}", e), None)
+        })?;
+        return Ok(CallToolResult::error(vec![Content::text(limited)]));
+      }
+    };
+    let token = context.ct.clone();
+    let call = Cancellable::new(
+      Self::tool_box().call(ToolCallContext::new(self, request, context)),
+      token.clone(),
+    );
+    /* Work between awaits stops at its next check, while this returns as soon as asked */
+    tokio::select! {
+      result = call => result,
+      _ = token.cancelled() => {
+        Ok(CallToolResult::error(vec![Content::text("Cancelled by the client")]))
+      }
+    }
+  }
+
+  fn require_semantic_search(&self) -> Result<Arc<SemanticSearch>, ServerError> {
+    self.semantic_search.clone().ok_or_else(|| {
+      ServerError(anyhow::anyhow!(
+        "Semantic search is disabled. Build with the semantic feature and start with --semantic."
+      ))
+    })
+  }
+
+  fn require_file_writer(&self) -> Result<Arc<FileWriter>, ServerError> {
+    self.file_writer.clone().ok_or_else(|| {
+      ServerError(anyhow::anyhow!(
+        "Writing files is disabled. Start with --allow-write to enable it."
+      ))
+    })
+  }
+
+  fn require_shadow_copies(&self) -> Result<Arc<ShadowCopies>, ServerError> {
+    self.shadow_copies.clone().ok_or_else(|| {
+      ServerError(anyhow::anyhow!(
+        "Diffs are disabled. Start with --keep-shadow-copies to enable them."
+      ))
+    })
+  }
+
+  fn require_version_history(&self) -> Result<Arc<VersionHistory>, ServerError> {
+    self.version_history.clone().ok_or_else(|| {
+      ServerError(anyhow::anyhow!(
+        "Version history is disabled. Start with --version-history to enable it."
+      ))
+    })
+  }
+
+  /// Queues changes the server made to files right away; the watcher reports them again later,
+  /// which is harmless, but searching in between would see the old paths.
+  fn queue_operations(&self, operations: &[FileOperation]) {
+    for operation in operations {
+      if let Err(e) = self.index.enqueue_operation(operation) {
+        tracing::warn!("Failed to queue {:?}: {}", operation, e);
+      }
+    }
+  }
+
+  /// Warns that results may miss recent changes when an index in scope cannot be committed.
+  fn stale_index_warning(&self, scope: &SearchScope) -> Result<Option<String>, ServerError> {
+    let failures = self.index.commit_failures(scope).map_err(ServerError)?;
+    Ok((!failures.is_empty()).then(|| {
+      let roots = failures
+        .iter()
+        .map(|(root, failure)| format!("{} ({})", root, failure.error))
+        .collect::<Vec<_>>();
+      format!(
+        "Warning: changes could not be saved to the index of {}, results may be stale. See get_stats.",
+        roots.join(", ")
+      )
+    }))
+  }
+
+  /// Re-indexes the searched path ahead of other changes when nothing was found or its index is
+  /// still catching up, so that searching again shortly sees its latest content.
+  fn prioritize_searched_path(
+    &self,
+    scope: &SearchScope,
+    missed: bool,
+  ) -> Result<Option<String>, ServerError> {
+    let Some(path) = &scope.path else {
+      return Ok(None);
+    };
+    let cold = self
+      .index
+      .has_pending_operations(scope)
+      .map_err(ServerError)?;
+    if !missed && !cold {
+      return Ok(None);
+    }
+    let queued = self.index.prioritize(path, scope).map_err(ServerError)?;
+    Ok(queued.then(|| {
+      format!(
+        "Note: {} is being re-indexed first, search again shortly for its latest content.",
+        path
+      )
+    }))
+  }
+
+  async fn hybrid_search(
+    &self,
+    query: String,
+    scope: &SearchScope,
+  ) -> Result<Vec<String>, ServerError> {
+    let semantic_search = self.require_semantic_search()?;
+    let candidates = self
+      .index
+      .search_hits(&query, HYBRID_CANDIDATE_LIMIT, scope)
+      .map_err(ServerError)?;
+    /* Embedding the query runs the model, which blocks */
+    let mut hits = cancellation::spawn_blocking(move || {
+      semantic_search.rerank(&query, candidates, SEARCH_FILE_LIMIT)
+    })
+    .await
+    .map_err(|e| ServerError(e.into()))?
+    .map_err(ServerError)?;
+    self.bookmarks.rank(&mut hits);
+    self
+      .visible_hits(hits)
+      .iter()
+      .map(|hit| serde_json::to_string(hit).map_err(|e| ServerError(e.into())))
+      .collect()
   }
 
   #[tool(description = "Search for a string in a file")]
   async fn search_index(&self, #[tool(aggr)] params: SearchParams) -> Result<String, ServerError> {
-    let index = self
+    let scope = SearchScope {
+      project: params.project,
+      roots: params.roots,
+      path: params.path,
+      filters: params.filters,
+    };
+    let mode = params.mode.unwrap_or(SearchMode::Keyword);
+    let match_mode = params
+      .match_mode
+      .as_deref()
+      .map(str::parse::<MatchMode>)
+      .transpose()
+      .map_err(|e| ServerError(anyhow::anyhow!("Invalid match_mode: {}", e)))?
+      .unwrap_or_default();
+    let keyword = with_match_mode(&params.keyword, match_mode);
+    let started_at = Instant::now();
+    let results = match mode {
+      SearchMode::Keyword => self.keyword_search(&keyword, &scope),
+      SearchMode::Hybrid => self.hybrid_search(keyword.clone(), &scope).await,
+    };
+    let results = results?;
+    self.query_stats.record(
+      "search_index",
+      &keyword,
+      format!("{:?} {:?}", mode, scope),
+      started_at.elapsed(),
+      results.len(),
+    );
+    let notes = [
+      self.stale_index_warning(&scope)?,
+      self.prioritize_searched_path(&scope, results.is_empty())?,
+    ]
+    .into_iter()
+    .flatten()
+    .map(|note| format!("\n{}", note))
+    .collect::<String>();
+    if results.is_empty() {
+      return Err(ServerError(anyhow::anyhow!("No results found.{}", notes)));
+    }
+    let result_set_note = self.keep_result_set(ResultSet {
+      queries: vec![keyword],
+      scope,
+    });
+    Ok(format!(
+      "[{}]{}{}",
+      results.join(", "),
+      notes,
+      result_set_note
+    ))
+  }
+
+  #[tool(
+    description = "Run several keyword searches in one call, e.g. the candidate keywords a question expands into, and get the results of each query"
+  )]
+  async fn multi_search(
+    &self,
+    #[tool(aggr)] params: MultiSearchParams,
+  ) -> Result<String, ServerError> {
+    if params.queries.is_empty() || params.queries.len() > MULTI_SEARCH_QUERY_LIMIT {
+      return Err(ServerError(anyhow::anyhow!(
+        "Give between 1 and {} queries, got {}",
+        MULTI_SEARCH_QUERY_LIMIT,
+        params.queries.len()
+      )));
+    }
+    let scope = SearchScope {
+      project: params.project,
+      roots: params.roots,
+      path: params.path,
+      filters: params.filters,
+    };
+    let match_mode = params
+      .match_mode
+      .as_deref()
+      .map(str::parse::<MatchMode>)
+      .transpose()
+      .map_err(|e| ServerError(anyhow::anyhow!("Invalid match_mode: {}", e)))?
+      .unwrap_or_default();
+    let mut searches = Vec::new();
+    for query in &params.queries {
+      let keyword = with_match_mode(query, match_mode);
+      let started_at = Instant::now();
+      let results = self.keyword_search(&keyword, &scope)?;
+      self.query_stats.record(
+        "multi_search",
+        &keyword,
+        format!("{:?}", scope),
+        started_at.elapsed(),
+        results.len(),
+      );
+      let results = results
+        .iter()
+        .map(|result| serde_json::from_str::<serde_json::Value>(result))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| ServerError(e.into()))?;
+      searches.push(serde_json::json!({ "query": query, "results": results }));
+    }
+    let note = self
+      .stale_index_warning(&scope)?
+      .map(|note| format!("\n{}", note))
+      .unwrap_or_default();
+    if searches
+      .iter()
+      .all(|search| search["results"].as_array().is_none_or(Vec::is_empty))
+    {
+      return Err(ServerError(anyhow::anyhow!("No results found.{}", note)));
+    }
+    let searches = serde_json::to_string(&searches).map_err(|e| ServerError(e.into()))?;
+    Ok(format!("{}{}", searches, note))
+  }
+
+  #[tool(
+    description = "Search files by meaning rather than exact words, e.g. \"notes about planning a trip\""
+  )]
+  async fn semantic_search(
+    &self,
+    #[tool(aggr)] params: SemanticSearchParams,
+  ) -> Result<String, ServerError> {
+    let semantic_search = self.require_semantic_search()?;
+    let limit = params.limit.unwrap_or(SEARCH_FILE_LIMIT);
+    let started_at = Instant::now();
+    let query = params.query.clone();
+    /* Embedding the query runs the model, which blocks */
+    let hits = cancellation::spawn_blocking(move || semantic_search.search(&query, limit))
+      .await
+      .map_err(|e| ServerError(e.into()))?
+      .map_err(ServerError)?;
+    self.query_stats.record(
+      "semantic_search",
+      &params.query,
+      format!("limit {}", limit),
+      started_at.elapsed(),
+      hits.len(),
+    );
+    if hits.is_empty() {
+      return Err(ServerError(anyhow::anyhow!("No results found.")));
+    }
+    serde_json::to_string(&self.visible_hits(hits)).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(
+    description = "Search the watched files as they were at a git revision, such as a release tag, to compare them with the current ones. The first search of a revision indexes it in memory, which takes a while in large repositories"
+  )]
+  async fn search_revision(
+    &self,
+    #[tool(aggr)] params: SearchRevisionParams,
+  ) -> Result<String, ServerError> {
+    let root = self.revision_root(params.root.as_deref())?;
+    let revision_indexes = self.revision_indexes.clone();
+    let revision = params.revision.clone();
+    let limit = params.limit.unwrap_or(SEARCH_FILE_LIMIT);
+    /* Reading the tree out of git and indexing it blocks */
+    let (commit, hits) = cancellation::spawn_blocking(move || {
+      revision_indexes.search(Path::new(&root), &revision, &params.keyword, limit)
+    })
+    .await
+    .map_err(|e| ServerError(e.into()))?
+    .map_err(ServerError)?;
+    if hits.is_empty() {
+      return Err(ServerError(anyhow::anyhow!(
+        "No results found at {} ({}).",
+        params.revision,
+        commit
+      )));
+    }
+    let hits =
+      serde_json::to_string(&self.visible_hits(hits)).map_err(|e| ServerError(e.into()))?;
+    Ok(format!(
+      "Revision {} ({}):\n{}",
+      params.revision, commit, hits
+    ))
+  }
+
+  #[tool(
+    description = "Search a directory outside the watched directories by indexing it in memory for this query only. Slower than search_index, use it for directories that are not watched."
+  )]
+  async fn search_directory(
+    &self,
+    #[tool(aggr)] params: SearchDirectoryParams,
+  ) -> Result<String, ServerError> {
+    let directory_search = self.directory_search.clone().ok_or_else(|| {
+      ServerError(anyhow::anyhow!(
+        "Directory search is disabled. Start with --allow-search-dir to allow directories."
+      ))
+    })?;
+    /* Walking and indexing the directory reads every file, which blocks */
+    let hits = cancellation::spawn_blocking(move || {
+      directory_search.search(&params.directory, &params.keyword, SEARCH_FILE_LIMIT)
+    })
+    .await
+    .map_err(|e| ServerError(e.into()))?
+    .map_err(ServerError)?;
+    if hits.is_empty() {
+      return Err(ServerError(anyhow::anyhow!("No results found.")));
+    }
+    serde_json::to_string(&self.visible_hits(hits)).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(
+    description = "Get statistics of the search index for each watched directory, including how many changes are queued and not indexed yet, how often its file watcher was restarted and whether changes are failing to be committed, and the latency of recent searches with the slowest queries, and the memory held by index writers, the search cache and in-memory indexes against the memory budget"
+  )]
+  async fn get_stats(&self) -> Result<String, ServerError> {
+    let shards = self.index.stats().map_err(ServerError)?;
+    let memory = self.index.memory_usage().map_err(ServerError)?;
+    let stats = serde_json::json!({
+      "shards": shards,
+      "queries": self.query_stats.summary(),
+      "memory": memory,
+    });
+    serde_json::to_string(&stats).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(
+    description = "List the tags of Markdown notes, from front matter and inline #tags, with the number of notes carrying each"
+  )]
+  async fn list_tags(&self, #[tool(aggr)] params: ListTagsParams) -> Result<String, ServerError> {
+    let scope = SearchScope {
+      project: params.project,
+      roots: None,
+      path: None,
+      filters: None,
+    };
+    self
       .index
-      .lock()
-      .map_err(|_| ServerError(anyhow::anyhow!("Failed to lock index")))?;
-    index
-      .search(&params.keyword)
+      .tag_counts(&scope)
       .map_err(ServerError)
-      .and_then(|results| {
-        if results.is_empty() {
-          Err(ServerError(anyhow::anyhow!("No results found.")))
-        } else {
-          Ok(format!("[{}]", results.join(", ")))
+      .and_then(|counts| serde_json::to_string(&counts).map_err(|e| ServerError(e.into())))
+  }
+
+  #[tool(description = "Find notes carrying a tag or a tag nested below it")]
+  async fn search_by_tag(
+    &self,
+    #[tool(aggr)] params: SearchByTagParams,
+  ) -> Result<String, ServerError> {
+    let scope = SearchScope {
+      project: params.project,
+      roots: None,
+      path: None,
+      filters: None,
+    };
+    let tag = params.tag.trim_start_matches('#');
+    let hits = self
+      .index
+      .search_by_tag(tag, SEARCH_FILE_LIMIT, &scope)
+      .map_err(ServerError)?;
+    if hits.is_empty() {
+      return Err(ServerError(anyhow::anyhow!("No results found.")));
+    }
+    serde_json::to_string(&self.visible_hits(hits)).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(
+    description = "Find notes linking to a file, by [[wikilink]] to its name or by a relative Markdown link to its path"
+  )]
+  async fn get_backlinks(
+    &self,
+    #[tool(aggr)] params: GetBacklinksParams,
+  ) -> Result<String, ServerError> {
+    let scope = SearchScope {
+      project: params.project,
+      roots: None,
+      path: None,
+      filters: None,
+    };
+    let hits = self
+      .index
+      .backlinks(&params.file_path, SEARCH_FILE_LIMIT, &scope)
+      .map_err(ServerError)?;
+    serde_json::to_string(&self.visible_hits(hits)).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(
+    description = "List the [[wikilinks]] and relative Markdown links of a note with the indexed files each points at, empty for broken links"
+  )]
+  async fn get_outgoing_links(
+    &self,
+    #[tool(aggr)] params: GetOutgoingLinksParams,
+  ) -> Result<String, ServerError> {
+    let file = self
+      .file_loader
+      .load_file(&params.file_path)
+      .await
+      .map_err(|e| ServerError(anyhow::anyhow!("Failed to load file: {}", e)))?;
+    let links = file
+      .metadata
+      .get(LINKS_METADATA_KEY)
+      .map(|links| links.lines().collect::<Vec<_>>())
+      .unwrap_or_default();
+    let resolved_links = self.index.resolve_links(&links).map_err(ServerError)?;
+    serde_json::to_string(&resolved_links).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(
+    description = "Narrow the results of an earlier search_index or refine call down to files also matching a keyword, without sending their paths back"
+  )]
+  async fn refine(&self, #[tool(aggr)] params: RefineParams) -> Result<String, ServerError> {
+    let result_set = self.result_set(&params.result_set)?.refine(&params.keyword);
+    let hits = self
+      .index
+      .search_all(&result_set.queries, SEARCH_FILE_LIMIT, &result_set.scope)
+      .map_err(ServerError)?;
+    if hits.is_empty() {
+      return Err(ServerError(anyhow::anyhow!("No results found.")));
+    }
+    let hits =
+      serde_json::to_string(&self.visible_hits(hits)).map_err(|e| ServerError(e.into()))?;
+    Ok(format!("{}{}", hits, self.keep_result_set(result_set)))
+  }
+
+  #[tool(
+    description = "Load the content of the best ranked files of an earlier search_index or refine call at once"
+  )]
+  async fn load_result_set(
+    &self,
+    #[tool(aggr)] params: LoadResultSetParams,
+  ) -> Result<String, ServerError> {
+    self.require_privacy_confirmation(params.confirm, "load_result_set", || {
+      format!(
+        "the files of result set {} may be loaded",
+        params.result_set
+      )
+    })?;
+    let result_set = self.result_set(&params.result_set)?;
+    let limit = params.limit.unwrap_or(SEARCH_FILE_LIMIT);
+    let hits = self
+      .index
+      .search_all(&result_set.queries, usize::MAX, &result_set.scope)
+      .map_err(ServerError)?;
+    /* Chunks and sections of a file are loaded once, as the whole file */
+    let mut file_paths = Vec::new();
+    for hit in &hits {
+      let file_path = document_file_path(&hit.file_path);
+      if !file_paths.contains(&file_path) {
+        file_paths.push(file_path);
+      }
+    }
+    let mut files = Vec::new();
+    for file_path in file_paths.into_iter().take(limit) {
+      files.push(match self.file_loader.load_file(file_path).await {
+        Ok(file) => LoadedFile {
+          file_path: file_path.to_string(),
+          content: Some(file.content),
+          error: None,
+        },
+        Err(e) => LoadedFile {
+          file_path: file_path.to_string(),
+          content: None,
+          error: Some(format!("Failed to load file: {}", e)),
+        },
+      });
+    }
+    serde_json::to_string(&files).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(
+    description = "Write the results of a result set or query, with their paths, scores and snippets, to a JSON, CSV or markdown file in the export directory"
+  )]
+  async fn export_results(
+    &self,
+    #[tool(aggr)] params: ExportResultsParams,
+  ) -> Result<String, ServerError> {
+    let export_dir = self.export_dir.as_deref().ok_or_else(|| {
+      ServerError(anyhow::anyhow!(
+        "Exports are disabled. Start with --export-dir to enable them."
+      ))
+    })?;
+    let path = export_path(export_dir, &params.path).map_err(ServerError)?;
+    let format = match &params.format {
+      Some(format) => <ExportFormat as clap::ValueEnum>::from_str(format, true)
+        .map_err(|e| ServerError(anyhow::anyhow!("Invalid format: {}", e)))?,
+      None => ExportFormat::from_path(&path).unwrap_or_default(),
+    };
+    let limit = params.limit.unwrap_or(EXPORT_LIMIT);
+    let (query, hits) = match (&params.result_set, &params.keyword) {
+      (Some(id), _) => {
+        let result_set = self.result_set(id)?;
+        let hits = self
+          .index
+          .search_all(&result_set.queries, limit, &result_set.scope)
+          .map_err(ServerError)?;
+        (result_set.queries.join(", refined by "), hits)
+      }
+      (None, Some(keyword)) => {
+        let hits = self
+          .index
+          .search_hits(keyword, limit, &SearchScope::default())
+          .map_err(ServerError)?;
+        (keyword.clone(), hits)
+      }
+      (None, None) => {
+        return Err(ServerError(anyhow::anyhow!(
+          "Give either result_set or keyword"
+        )));
+      }
+    };
+    /* An export reveals no more than the searches it was made of */
+    let hits = self.visible_hits(hits);
+    export_hits(&path, &query, &hits, format).map_err(ServerError)?;
+    Ok(
+      serde_json::json!({
+        "path": path,
+        "format": format.to_string(),
+        "results": hits.len(),
+      })
+      .to_string(),
+    )
+  }
+
+  #[tool(
+    description = "List the TODO, FIXME and HACK comments (or other markers) of the indexed files, grouped by file with their line numbers"
+  )]
+  async fn todo_report(
+    &self,
+    #[tool(aggr)] params: TodoReportParams,
+  ) -> Result<String, ServerError> {
+    let markers = params
+      .markers
+      .filter(|markers| !markers.is_empty())
+      .unwrap_or_else(|| self.todo_markers.to_vec());
+    let scope = SearchScope {
+      project: params.project,
+      roots: params.roots,
+      path: params.path,
+      filters: None,
+    };
+    let limit = params.limit.unwrap_or(TODO_REPORT_FILE_LIMIT);
+    let hits = self
+      .index
+      .search_hits(&marker_query(&markers), limit, &scope)
+      .map_err(ServerError)?;
+    /* The index ignores case and punctuation, so the files found are scanned for exact markers */
+    let mut file_paths = Vec::new();
+    for hit in &hits {
+      let file_path = document_file_path(&hit.file_path);
+      if !file_paths.contains(&file_path) {
+        file_paths.push(file_path);
+      }
+    }
+    let mut files = Vec::new();
+    for file_path in file_paths {
+      check_cancelled().map_err(ServerError)?;
+      let file = match self.file_loader.load_file(file_path).await {
+        Ok(file) => file,
+        Err(e) => {
+          tracing::warn!("Failed to load {} for the TODO report: {}", file_path, e);
+          continue;
         }
+      };
+      let mut items = find_markers(&file.content, &markers);
+      if self.privacy_mode {
+        items.iter_mut().for_each(|item| item.text = None);
+      }
+      if !items.is_empty() {
+        files.push(TodoFile {
+          file_path: file_path.to_string(),
+          items,
+        });
+      }
+    }
+    if files.is_empty() {
+      return Err(ServerError(anyhow::anyhow!(
+        "No {} found.",
+        markers.join(", ")
+      )));
+    }
+    serde_json::to_string(&files).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(
+    description = "Find pairs of indexed files with nearly the same text, e.g. copy-pasted variants of a note, most similar first"
+  )]
+  async fn find_near_duplicates(
+    &self,
+    #[tool(aggr)] params: FindNearDuplicatesParams,
+  ) -> Result<String, ServerError> {
+    let threshold = params.threshold.unwrap_or(DEFAULT_SIMILARITY_THRESHOLD);
+    if !(0.0..=1.0).contains(&threshold) {
+      return Err(ServerError(anyhow::anyhow!(
+        "threshold must be between 0 and 1, got {}",
+        threshold
+      )));
+    }
+    let scope = SearchScope {
+      project: params.project,
+      roots: params.roots,
+      path: params.path,
+      filters: None,
+    };
+    let file_paths = self.index.indexed_paths(&scope).map_err(ServerError)?;
+    let mut near_duplicates = NearDuplicates::new(threshold);
+    for file_path in file_paths.iter().take(NEAR_DUPLICATE_FILE_LIMIT) {
+      check_cancelled().map_err(ServerError)?;
+      match self.file_loader.load_file(file_path).await {
+        Ok(file) => near_duplicates.add(file_path, &file.content),
+        Err(e) => tracing::warn!("Failed to load {} for comparison: {}", file_path, e),
+      }
+    }
+    let mut pairs = near_duplicates.pairs();
+    pairs.truncate(params.limit.unwrap_or(NEAR_DUPLICATE_PAIR_LIMIT));
+    let note = if file_paths.len() > NEAR_DUPLICATE_FILE_LIMIT {
+      format!(
+        "\nOnly the first {} of {} files were compared; narrow the search down with project, roots or path.",
+        NEAR_DUPLICATE_FILE_LIMIT,
+        file_paths.len()
+      )
+    } else {
+      String::new()
+    };
+    let pairs = serde_json::to_string(&pairs).map_err(|e| ServerError(e.into()))?;
+    Ok(format!("{}{}", pairs, note))
+  }
+
+  #[tool(
+    description = "Show the directory structure of the watched directories with the number and total size of the indexed files in each directory, to get oriented in an unfamiliar corpus"
+  )]
+  async fn tree(&self, #[tool(aggr)] params: TreeParams) -> Result<String, ServerError> {
+    let depth = params.depth.unwrap_or(DEFAULT_TREE_DEPTH);
+    let roots = match params.path {
+      Some(path) => vec![path],
+      None => self
+        .index
+        .stats()
+        .map_err(ServerError)?
+        .into_iter()
+        .filter(|shard| {
+          params
+            .project
+            .as_ref()
+            .is_none_or(|project| shard.project == *project)
+        })
+        /* URL sources have no directories */
+        .filter(|shard| Path::new(&shard.root).exists())
+        .map(|shard| shard.root)
+        .collect(),
+    };
+    let mut trees: Vec<DirectoryNode> = Vec::new();
+    for root in roots {
+      /* Indexed paths start with the canonical root, whichever form the root was given in */
+      let root = document_path(&root).to_string_lossy().to_string();
+      let scope = SearchScope {
+        project: params.project.clone(),
+        roots: None,
+        path: Some(root.clone()),
+        filters: None,
+      };
+      /* Members of an archive or mailbox are counted as the one file holding them */
+      let file_paths = self
+        .index
+        .indexed_paths(&scope)
+        .map_err(ServerError)?
+        .iter()
+        .map(|path| {
+          path
+            .split_once(CONTAINER_MEMBER_SEPARATOR)
+            .map_or(path.as_str(), |(container_path, _)| container_path)
+            .to_string()
+        })
+        .collect::<BTreeSet<_>>();
+      trees.push(directory_tree(
+        &root,
+        file_paths.iter().map(String::as_str),
+        depth,
+        |file_path| std::fs::metadata(file_path).map_or(0, |metadata| metadata.len()),
+      ));
+    }
+    serde_json::to_string(&trees).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(
+    description = "Get the query syntax search_index accepts: the fields, operators and filters of the configured index, and the metadata keys documents may carry. Read it before writing queries with more than plain words"
+  )]
+  async fn query_syntax(&self) -> Result<String, ServerError> {
+    serde_json::to_string(self.query_syntax.as_ref()).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(description = "List the query templates defined with --query-template")]
+  async fn list_query_templates(&self) -> Result<String, ServerError> {
+    serde_json::to_string(self.query_templates.as_ref()).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(description = "List the saved filters defined with --filter, usable in search_index")]
+  async fn list_filters(&self) -> Result<String, ServerError> {
+    let filters = self
+      .index
+      .saved_filters()
+      .filters()
+      .iter()
+      .map(|filter| serde_json::json!({ "name": filter.name, "definition": filter.definition }))
+      .collect::<Vec<_>>();
+    serde_json::to_string(&filters).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(
+    description = "Search with a query template, e.g. a lookup of TODOs below a path, filling in its parameters; see list_query_templates"
+  )]
+  async fn run_query_template(
+    &self,
+    #[tool(aggr)] params: RunQueryTemplateParams,
+  ) -> Result<String, ServerError> {
+    let template = self
+      .query_templates
+      .iter()
+      .find(|template| template.name == params.name)
+      .ok_or_else(|| ServerError(anyhow::anyhow!("Unknown query template: {}", params.name)))?;
+    let keyword = template
+      .expand(&params.args.unwrap_or_default())
+      .map_err(ServerError)?;
+    self
+      .search_index(SearchParams {
+        keyword,
+        mode: None,
+        match_mode: None,
+        project: params.project,
+        roots: params.roots,
+        path: None,
+        filters: None,
       })
+      .await
+  }
+
+  #[tool(
+    description = "List projects, each a named group of watched directories, with their document counts"
+  )]
+  async fn list_projects(&self) -> Result<String, ServerError> {
+    self
+      .index
+      .projects()
+      .map_err(ServerError)
+      .and_then(|projects| serde_json::to_string(&projects).map_err(|e| ServerError(e.into())))
+  }
+
+  #[tool(description = "List files that were skipped from indexing and why")]
+  async fn list_skipped_files(&self) -> Result<String, ServerError> {
+    serde_json::to_string(&self.skipped_files.list()).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(
+    description = "List files missing from the index with the reason: skipped on purpose (e.g. too large) or failed to load (e.g. permission denied, undecodable content)"
+  )]
+  async fn list_index_errors(
+    &self,
+    #[tool(aggr)] params: ListIndexErrorsParams,
+  ) -> Result<String, ServerError> {
+    let files = self
+      .skipped_files
+      .list_all()
+      .into_iter()
+      .filter(|file| {
+        params
+          .kind
+          .is_none_or(|kind| file.kind == SkipKind::from(kind))
+      })
+      .collect::<Vec<_>>();
+    serde_json::to_string(&files).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(
+    description = "List recent file system events and the index operations derived from them (raw, filtered or queued), to debug why a file was not re-indexed"
+  )]
+  async fn recent_events(
+    &self,
+    #[tool(aggr)] params: RecentEventsParams,
+  ) -> Result<String, ServerError> {
+    let events = self.event_log.recent(
+      params.limit.unwrap_or(DEFAULT_RECENT_EVENTS_LIMIT),
+      params.path_contains.as_deref(),
+    );
+    serde_json::to_string(&events).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(
+    description = "Pin a document as important context. Pinned documents are listed first when they match a search, and kept across sessions."
+  )]
+  async fn pin_document(
+    &self,
+    #[tool(aggr)] params: PinDocumentParams,
+  ) -> Result<String, ServerError> {
+    if !self.index.has_doc(&params.file_path).map_err(ServerError)? {
+      return Err(ServerError(anyhow::anyhow!(
+        "{} is not indexed",
+        params.file_path
+      )));
+    }
+    let bookmark = self
+      .bookmarks
+      .pin(&params.file_path, params.note)
+      .map_err(ServerError)?;
+    serde_json::to_string(&bookmark).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(description = "Unpin a document pinned with pin_document")]
+  async fn unpin_document(
+    &self,
+    #[tool(aggr)] params: UnpinDocumentParams,
+  ) -> Result<String, ServerError> {
+    if self
+      .bookmarks
+      .unpin(&params.file_path)
+      .map_err(ServerError)?
+    {
+      Ok(format!("Unpinned {}", params.file_path))
+    } else {
+      Err(ServerError(anyhow::anyhow!(
+        "{} is not pinned",
+        params.file_path
+      )))
+    }
+  }
+
+  #[tool(description = "List pinned documents, most recently pinned first")]
+  async fn list_pinned(&self) -> Result<String, ServerError> {
+    serde_json::to_string(&self.bookmarks.list()).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(
+    description = "Create a file inside a watched directory, or replace it with overwrite; it is re-indexed like any other edit. Needs --allow-write"
+  )]
+  async fn write_file(&self, #[tool(aggr)] params: WriteFileParams) -> Result<String, ServerError> {
+    let file_writer = self.require_file_writer()?;
+    let path = file_writer
+      .write(
+        &params.file_path,
+        &params.content,
+        params.overwrite.unwrap_or(false),
+      )
+      .map_err(ServerError)?;
+    Ok(format!(
+      "Wrote {} bytes to {}",
+      params.content.len(),
+      path.display()
+    ))
+  }
+
+  #[tool(
+    description = "Append text to a file inside a watched directory, creating it if missing; it is re-indexed like any other edit. Needs --allow-write"
+  )]
+  async fn append_file(
+    &self,
+    #[tool(aggr)] params: AppendFileParams,
+  ) -> Result<String, ServerError> {
+    let file_writer = self.require_file_writer()?;
+    let path = file_writer
+      .append(&params.file_path, &params.content)
+      .map_err(ServerError)?;
+    Ok(format!(
+      "Appended {} bytes to {}",
+      params.content.len(),
+      path.display()
+    ))
+  }
+
+  #[tool(
+    description = "Delete a file inside a watched directory by moving it to the trash, and drop it from the index. Needs --allow-write and the user's confirmation"
+  )]
+  async fn delete_file(
+    &self,
+    #[tool(aggr)] params: DeleteFileParams,
+  ) -> Result<String, ServerError> {
+    let file_writer = self.require_file_writer()?;
+    let path = file_writer
+      .resolve(&params.file_path)
+      .map_err(ServerError)?;
+    require_confirmation(params.confirm, "delete_file", || {
+      format!(
+        "{} may be deleted (it is moved to the trash and undo_last_change restores it)",
+        path.display()
+      )
+    })?;
+    let path = file_writer.delete(&params.file_path).map_err(ServerError)?;
+    let path = path.to_string_lossy().to_string();
+    self.queue_operations(&[FileOperation::FileDeleted(path.clone())]);
+    Ok(format!("Deleted {}", path))
+  }
+
+  #[tool(
+    description = "Move or rename a file inside the watched directories, updating the index. Needs --allow-write and the user's confirmation"
+  )]
+  async fn move_file(&self, #[tool(aggr)] params: MoveFileParams) -> Result<String, ServerError> {
+    let file_writer = self.require_file_writer()?;
+    require_confirmation(params.confirm, "move_file", || {
+      format!("{} may be moved to {}", params.from_path, params.to_path)
+    })?;
+    let (from, to) = file_writer
+      .rename(
+        &params.from_path,
+        &params.to_path,
+        params.overwrite.unwrap_or(false),
+      )
+      .map_err(ServerError)?;
+    let from = from.to_string_lossy().to_string();
+    let to = to.to_string_lossy().to_string();
+    /* As a deletion and a creation, so that a move between roots updates both shards */
+    self.queue_operations(&[
+      FileOperation::FileDeleted(from.clone()),
+      FileOperation::FileCreated(to.clone()),
+    ]);
+    Ok(format!("Moved {} to {}", from, to))
+  }
+
+  #[tool(
+    description = "Undo the most recent file change made by write_file, append_file, delete_file or move_file, restoring replaced or deleted content from the trash. Call it again to undo earlier changes. Needs --allow-write"
+  )]
+  async fn undo_last_change(&self) -> Result<String, ServerError> {
+    let file_writer = self.require_file_writer()?;
+    let change = file_writer.undo_last().map_err(ServerError)?;
+    self.queue_operations(&change.undo_operations());
+    Ok(change.describe_undo())
+  }
+
+  #[tool(
+    description = "Get the size, modification and change times, extension, content hash and index status of a file, to decide whether it needs to be read again"
+  )]
+  async fn get_file_info(
+    &self,
+    #[tool(aggr)] params: GetFileInfoParams,
+  ) -> Result<String, ServerError> {
+    let file_path = params.file_path.as_str();
+    let indexed = self.index.has_doc(file_path).map_err(ServerError)?;
+    let indexed_at = self.index.indexed_at(file_path).map_err(ServerError)?;
+    let info = FileInfo::read(file_path)
+      .map_err(ServerError)?
+      .with_indexed_at(indexed, indexed_at);
+    serde_json::to_string(&info).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(
+    description = "Show what changed in a file between the two last index passes that changed it, as a unified diff per document (the file, or its chunks, sections or archive members). Needs --keep-shadow-copies"
+  )]
+  async fn diff_file(&self, #[tool(aggr)] params: DiffFileParams) -> Result<String, ServerError> {
+    let shadow_copies = self.require_shadow_copies()?;
+    self.require_privacy_confirmation(params.confirm, "diff_file", || {
+      format!("changes to {} may be shown", params.file_path)
+    })?;
+    let context = params.context.unwrap_or(DEFAULT_DIFF_CONTEXT);
+    let diffs = shadow_copies.diffs(&params.file_path, context);
+    if diffs.is_empty() {
+      return Err(ServerError(anyhow::anyhow!(
+        "No earlier indexed version of {} is kept: only changes indexed since the server started can be shown.",
+        params.file_path
+      )));
+    }
+    serde_json::to_string(&diffs).map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(
+    description = "List the snapshots of a file taken whenever it changed, newest first. Needs --version-history"
+  )]
+  async fn list_versions(
+    &self,
+    #[tool(aggr)] params: ListVersionsParams,
+  ) -> Result<String, ServerError> {
+    let version_history = self.require_version_history()?;
+    serde_json::to_string(&version_history.list(&params.file_path))
+      .map_err(|e| ServerError(e.into()))
+  }
+
+  #[tool(
+    description = "Load the content of a file as it was in one of its versions from list_versions. Needs --version-history"
+  )]
+  async fn load_version(
+    &self,
+    #[tool(aggr)] params: LoadVersionParams,
+  ) -> Result<String, ServerError> {
+    let version_history = self.require_version_history()?;
+    self.require_privacy_confirmation(params.confirm, "load_version", || {
+      format!("an earlier version of {} may be loaded", params.file_path)
+    })?;
+    let content = version_history
+      .load(&params.file_path, &params.version)
+      .map_err(ServerError)?;
+    Ok(String::from_utf8_lossy(&content).into_owned())
   }
 
   #[tool(description = "Load a file by its path")]
   async fn load_file(&self, #[tool(aggr)] params: LoadFileParams) -> Result<String, ServerError> {
+    self.require_privacy_confirmation(params.confirm, "load_file", || {
+      format!("{} may be loaded", params.file_path)
+    })?;
     self
       .file_loader
       .load_file(&params.file_path)
+      .await
       .map_err(|e| ServerError(anyhow::anyhow!("Failed to load file: {}", e)))
-      .map(|file| file.content)
+      .map(|file| match (params.start_line, params.end_line) {
+        (None, None) => file.content,
+        (start_line, end_line) => select_lines(&file.content, start_line, end_line),
+      })
+  }
+
+  #[tool(
+    description = "Get the heading structure of a Markdown, Org or reStructuredText file with the line range of each section, to load only the relevant section with load_file"
+  )]
+  async fn get_outline(
+    &self,
+    #[tool(aggr)] params: GetOutlineParams,
+  ) -> Result<String, ServerError> {
+    /* Headings are file content too */
+    self.require_privacy_confirmation(params.confirm, "get_outline", || {
+      format!("the headings of {} may be shown", params.file_path)
+    })?;
+    let file = self
+      .file_loader
+      .load_file(&params.file_path)
+      .await
+      .map_err(|e| ServerError(anyhow::anyhow!("Failed to load file: {}", e)))?;
+    let headings = outline(&params.file_path, &file.content).ok_or_else(|| {
+      ServerError(anyhow::anyhow!(
+        "Outlines are only available for {} files",
+        OUTLINE_EXTENSIONS.join(", ")
+      ))
+    })?;
+    serde_json::to_string(&headings).map_err(|e| ServerError(e.into()))
   }
 }
 
-#[tool(tool_box)]
+/// Results returned by a tool answering with a JSON list, optionally followed by warnings.
+fn count_hits(result: &CallToolResult) -> Option<usize> {
+  let text = &result.content.first()?.as_text()?.text;
+  let list = text.lines().next()?;
+  serde_json::from_str::<Vec<serde_json::Value>>(list)
+    .ok()
+    .map(|hits| hits.len())
+}
+
 impl ServerHandler for SearchServer {
+  async fn call_tool(
+    &self,
+    request: CallToolRequestParam,
+    context: RequestContext<RoleServer>,
+  ) -> Result<CallToolResult, rmcp::Error> {
+    let Some(audit_log) = self.audit_log.clone() else {
+      return self.call_limited_tool(request, context).await;
+    };
+    let mut entry = AuditEntry::new(
+      &request.name,
+      serde_json::Value::Object(request.arguments.clone().unwrap_or_default()),
+    );
+    let client = &context.peer.peer_info().client_info;
+    entry.client = Some(format!("{} {}", client.name, client.version));
+    let result = self.call_limited_tool(request, context).await;
+    match &result {
+      Ok(result) => {
+        entry.is_error = result.is_error.unwrap_or(false);
+        entry.hits = count_hits(result).or(entry.is_error.then_some(0));
+      }
+      Err(_) => entry.is_error = true,
+    }
+    /* Failing to audit a call must not fail the call itself */
+    if let Err(e) = audit_log.record(&entry) {
+      tracing::warn!("Failed to write to the audit log: {}", e);
+    }
+    result
+  }
+
+  /// Completes path arguments such as the `file_path` of load_file and the `path` filter of
+  /// search_index from the indexed paths. Other arguments have no completions.
+  async fn complete(
+    &self,
+    request: CompleteRequestParam,
+    _context: RequestContext<RoleServer>,
+  ) -> Result<CompleteResult, rmcp::Error> {
+    let completions = if PATH_ARGUMENTS.contains(&request.argument.name.as_str()) {
+      let internal_error = |e: anyhow::Error| rmcp::Error::internal_error(e.to_string(), None);
+      let roots = self.index.roots().map_err(internal_error)?;
+      let file_paths = self
+        .index
+        .indexed_paths(&SearchScope::default())
+        .map_err(internal_error)?;
+      complete_path(
+        &file_paths,
+        &roots,
+        &request.argument.value,
+        MAX_COMPLETIONS,
+      )
+    } else {
+      Default::default()
+    };
+    Ok(CompleteResult {
+      completion: CompletionInfo {
+        has_more: Some(completions.total > completions.values.len()),
+        total: Some(completions.total as u32),
+        values: completions.values,
+      },
+    })
+  }
+
+  async fn list_tools(
+    &self,
+    _request: PaginatedRequestParam,
+    _context: RequestContext<RoleServer>,
+  ) -> Result<ListToolsResult, rmcp::Error> {
+    Ok(ListToolsResult {
+      next_cursor: None,
+      tools: Self::tool_box().list(),
+    })
+  }
+
+  /// Only capabilities the server implements are advertised: it has no prompts or resources.
+  /// rmcp answers the initialize request with this info without looking at the client's protocol
+  /// version, so the version stays the one its model implements.
   fn get_info(&self) -> ServerInfo {
     ServerInfo {
       protocol_version: ProtocolVersion::V_2024_11_05,
-      capabilities: ServerCapabilities::builder()
-        .enable_prompts()
-        .enable_resources()
-        .enable_tools()
-        .build(),
+      capabilities: ServerCapabilities::builder().enable_tools().build(),
       server_info: Implementation::from_build_env(),
       instructions: Some(
         "This is a search server that can search for strings in files.".to_string(),
@@ -90,3 +1873,365 @@ impl ServerHandler for SearchServer {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    search::{
+      file::File, index_backend::IndexBackend, index_operation::IndexOperation,
+      text_index::TextIndex,
+    },
+    testing::{
+      MockFileFilter, MockFileLoader, TestClient, in_memory_index, relative_to_current_dir,
+    },
+  };
+  use parking_lot::Mutex;
+
+  fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().canonicalize().unwrap().join(format!(
+      "search-server-{}-test-{}",
+      name,
+      std::process::id()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn test_server(root: &str, files: &[(PathBuf, &str)]) -> SearchServer {
+    let files = files
+      .iter()
+      .map(|(path, content)| File::new(path.to_string_lossy().to_string(), content.to_string()))
+      .collect();
+    SearchServer::new(
+      in_memory_index(root, files).unwrap(),
+      Arc::new(MockFileLoader::new(Vec::new(), String::new())),
+      Arc::new(SkippedFiles::new()),
+      Arc::new(EventLog::default()),
+    )
+  }
+
+  /// Searches until `keyword` finds what `expected` asks for, as changes are indexed in the
+  /// background.
+  async fn search_until(client: &TestClient, keyword: &str, expected: impl Fn(&str) -> bool) {
+    let mut results = String::new();
+    for _ in 0..100 {
+      /* Searches without results fail with "No results found" */
+      results = client
+        .call_tool("search_index", serde_json::json!({ "keyword": keyword }))
+        .await
+        .unwrap_or_else(|e| e.to_string());
+      if expected(&results) {
+        return;
+      }
+      tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    panic!("Unexpected results for {}: {}", keyword, results);
+  }
+
+  #[tokio::test]
+  async fn file_tools_should_confirm_trash_update_index_and_undo() {
+    let dir = test_dir("file-tools");
+    let root = dir.join("root");
+    std::fs::create_dir_all(&root).unwrap();
+    let (a, b, c, outside) = (
+      root.join("a.txt"),
+      root.join("b.txt"),
+      root.join("c.txt"),
+      dir.join("outside.txt"),
+    );
+    let mut text_index = TextIndex::new().unwrap();
+    for (path, content) in [(&a, "apple"), (&b, "banana"), (&outside, "apple")] {
+      std::fs::write(path, content).unwrap();
+      text_index
+        .add_doc(&File::new(
+          path.to_string_lossy().to_string(),
+          content.to_string(),
+        ))
+        .unwrap();
+    }
+    text_index.commit().unwrap();
+    let text_index = Arc::new(Mutex::new(text_index));
+    let file_loader = Arc::new(MockFileLoader::new(
+      Vec::new(),
+      "restored content".to_string(),
+    ));
+    let index_operation = Arc::new(
+      IndexOperation::new(
+        text_index.clone(),
+        Arc::new(MockFileFilter::default()),
+        file_loader.clone(),
+      )
+      .unwrap(),
+    );
+    let index = Arc::new(ShardedIndex::new());
+    let root_str = root.to_string_lossy().to_string();
+    index.add_shard(&root_str, text_index).unwrap();
+    index
+      .set_reindex_handler(
+        &root_str,
+        Box::new(move |op| index_operation.enqueue_prioritized(op)),
+      )
+      .unwrap();
+    let server = SearchServer::new(
+      index,
+      file_loader,
+      Arc::new(SkippedFiles::new()),
+      Arc::new(EventLog::default()),
+    )
+    .with_file_writer(Some(Arc::new(FileWriter::new(
+      std::slice::from_ref(&root),
+      dir.join("trash"),
+    ))));
+    let client = TestClient::connect(server).await.unwrap();
+    let path_of = |path: &PathBuf| path.to_string_lossy().to_string();
+
+    let refused = client
+      .call_tool(
+        "delete_file",
+        serde_json::json!({ "file_path": path_of(&outside), "confirm": true }),
+      )
+      .await;
+    assert!(
+      refused
+        .unwrap_err()
+        .to_string()
+        .contains("not inside a watched directory")
+    );
+    assert!(outside.exists());
+
+    let unconfirmed = client
+      .call_tool(
+        "delete_file",
+        serde_json::json!({ "file_path": path_of(&a) }),
+      )
+      .await;
+    assert!(
+      unconfirmed
+        .unwrap_err()
+        .to_string()
+        .contains("Ask the user")
+    );
+    assert!(a.exists());
+    client
+      .call_tool(
+        "delete_file",
+        serde_json::json!({ "file_path": path_of(&a), "confirm": true }),
+      )
+      .await
+      .unwrap();
+    assert!(!a.exists());
+    assert_eq!(std::fs::read_dir(dir.join("trash")).unwrap().count(), 1);
+    search_until(&client, "apple", |results| !results.contains(&path_of(&a))).await;
+
+    let move_arguments = serde_json::json!({ "from_path": path_of(&b), "to_path": path_of(&c) });
+    assert!(
+      client
+        .call_tool("move_file", move_arguments.clone())
+        .await
+        .is_err()
+    );
+    assert!(b.exists());
+    let mut confirmed_move = move_arguments;
+    confirmed_move["confirm"] = serde_json::json!(true);
+    client.call_tool("move_file", confirmed_move).await.unwrap();
+    assert!(!b.exists() && c.exists());
+    search_until(&client, "banana", |results| !results.contains(&path_of(&b))).await;
+    search_until(&client, "restored", |results| {
+      results.contains(&path_of(&c))
+    })
+    .await;
+
+    client
+      .call_tool("undo_last_change", serde_json::json!({}))
+      .await
+      .unwrap();
+    assert!(b.exists() && !c.exists());
+    client
+      .call_tool("undo_last_change", serde_json::json!({}))
+      .await
+      .unwrap();
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), "apple");
+    search_until(&client, "restored", |results| {
+      results.contains(&path_of(&a))
+        && results.contains(&path_of(&b))
+        && !results.contains(&path_of(&c))
+    })
+    .await;
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn privacy_mode_should_hide_content_until_confirmed() {
+    let dir = test_dir("privacy");
+    let files = [(
+      PathBuf::from("/notes/plan.md"),
+      "tomato secret recipe TODO secret task",
+    )];
+    let file_loader = Arc::new(MockFileLoader::new(
+      Vec::new(),
+      "# Secret heading\nTODO secret task".to_string(),
+    ));
+    let server = |privacy_mode| {
+      let files = files
+        .iter()
+        .map(|(path, content)| File::new(path.to_string_lossy().to_string(), content.to_string()))
+        .collect();
+      SearchServer::new(
+        in_memory_index("/notes", files).unwrap(),
+        file_loader.clone(),
+        Arc::new(SkippedFiles::new()),
+        Arc::new(EventLog::default()),
+      )
+      .with_privacy_mode(privacy_mode)
+      .with_export_dir(Some(dir.clone()))
+    };
+
+    /* Without privacy mode the same calls show the content */
+    let client = TestClient::connect(server(false)).await.unwrap();
+    let results = client
+      .call_tool("search_index", serde_json::json!({ "keyword": "tomato" }))
+      .await
+      .unwrap();
+    assert!(results.contains("recipe"));
+
+    let client = TestClient::connect(server(true)).await.unwrap();
+    for (tool, arguments) in [
+      ("search_index", serde_json::json!({ "keyword": "tomato" })),
+      ("multi_search", serde_json::json!({ "queries": ["tomato"] })),
+      (
+        "export_results",
+        serde_json::json!({ "keyword": "tomato", "path": "export.json" }),
+      ),
+      ("todo_report", serde_json::json!({})),
+    ] {
+      let result = client.call_tool(tool, arguments).await.unwrap();
+      /* export_results only answers with the export file */
+      assert!(
+        tool == "export_results" || result.contains("plan.md"),
+        "{}: {}",
+        tool,
+        result
+      );
+      assert!(!result.contains("secret"), "{}: {}", tool, result);
+    }
+    let exported = std::fs::read_to_string(dir.join("export.json")).unwrap();
+    assert!(exported.contains("/notes/plan.md"));
+    assert!(!exported.contains("secret"));
+
+    for tool in ["load_file", "get_outline"] {
+      let refused = client
+        .call_tool(tool, serde_json::json!({ "file_path": "/notes/plan.md" }))
+        .await
+        .unwrap_err()
+        .to_string();
+      assert!(refused.contains("Ask the user"), "{}: {}", tool, refused);
+      assert!(!refused.contains("Secret"), "{}: {}", tool, refused);
+      let confirmed = client
+        .call_tool(
+          tool,
+          serde_json::json!({ "file_path": "/notes/plan.md", "confirm": true }),
+        )
+        .await
+        .unwrap();
+      assert!(
+        confirmed.contains("Secret heading"),
+        "{}: {}",
+        tool,
+        confirmed
+      );
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn multi_search_should_answer_each_query_and_reject_empty_queries() {
+    let files = [
+      (PathBuf::from("/notes/a.txt"), "apple pie"),
+      (PathBuf::from("/notes/b.txt"), "banana bread"),
+    ];
+    let client = TestClient::connect(test_server("/notes", &files))
+      .await
+      .unwrap();
+
+    let searches: serde_json::Value = serde_json::from_str(
+      &client
+        .call_tool(
+          "multi_search",
+          serde_json::json!({ "queries": ["apple", "banana", "cherry"] }),
+        )
+        .await
+        .unwrap(),
+    )
+    .unwrap();
+    let found = |index: usize| {
+      assert_eq!(
+        searches[index]["query"],
+        ["apple", "banana", "cherry"][index]
+      );
+      searches[index]["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result.to_string())
+        .collect::<Vec<_>>()
+    };
+    assert!(matches!(found(0).as_slice(), [hit] if hit.contains("/notes/a.txt")));
+    assert!(matches!(found(1).as_slice(), [hit] if hit.contains("/notes/b.txt")));
+    assert!(found(2).is_empty());
+
+    for queries in [Vec::new(), vec!["apple"; MULTI_SEARCH_QUERY_LIMIT + 1]] {
+      let rejected = client
+        .call_tool("multi_search", serde_json::json!({ "queries": queries }))
+        .await
+        .unwrap_err()
+        .to_string();
+      assert!(rejected.contains("Give between 1 and"), "{}", rejected);
+    }
+  }
+
+  #[tokio::test]
+  async fn tree_should_count_files_under_relative_root() {
+    let dir = test_dir("tree");
+    std::fs::create_dir_all(dir.join("docs")).unwrap();
+    let files = [
+      (dir.join("a.txt"), "alpha"),
+      (dir.join("docs/b.txt"), "beta"),
+    ];
+    for (path, content) in &files {
+      std::fs::write(path, content).unwrap();
+    }
+    let root = relative_to_current_dir(&dir).to_string_lossy().to_string();
+    let client = TestClient::connect(test_server(&root, &files))
+      .await
+      .unwrap();
+
+    let trees: serde_json::Value = serde_json::from_str(
+      &client
+        .call_tool("tree", serde_json::json!({}))
+        .await
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(trees[0]["path"], dir.to_string_lossy().as_ref());
+    assert_eq!(trees[0]["files"], 2);
+    assert_eq!(trees[0]["size"], 9);
+    assert_eq!(trees[0]["directories"][0]["files"], 1);
+
+    let trees: serde_json::Value = serde_json::from_str(
+      &client
+        .call_tool(
+          "tree",
+          serde_json::json!({ "path": format!("{}/docs", root) }),
+        )
+        .await
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(trees[0]["files"], 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
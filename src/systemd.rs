@@ -0,0 +1,70 @@
+use anyhow::Result;
+use std::{ffi::OsStr, time::Duration};
+
+/// Sends a state such as `READY=1` to systemd, doing nothing unless started by systemd with
+/// `Type=notify`.
+pub fn notify(state: &str) -> Result<()> {
+  match std::env::var_os("NOTIFY_SOCKET") {
+    Some(socket_path) => send(&socket_path, state),
+    None => Ok(()),
+  }
+}
+
+#[cfg(target_os = "linux")]
+fn send(socket_path: &OsStr, state: &str) -> Result<()> {
+  use std::os::{
+    linux::net::SocketAddrExt,
+    unix::{
+      ffi::OsStrExt,
+      net::{SocketAddr, UnixDatagram},
+    },
+  };
+  /* A leading @ names a socket in the abstract namespace */
+  let address = match socket_path.as_bytes().strip_prefix(b"@") {
+    Some(name) => SocketAddr::from_abstract_name(name)?,
+    None => SocketAddr::from_pathname(socket_path)?,
+  };
+  UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &address)?;
+  Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send(_socket_path: &OsStr, _state: &str) -> Result<()> {
+  Ok(())
+}
+
+/// How often to send `WATCHDOG=1`: half of `WatchdogSec=` as systemd recommends, or `None` when
+/// the watchdog is off or meant for another process.
+pub fn watchdog_interval() -> Option<Duration> {
+  if let Ok(pid) = std::env::var("WATCHDOG_PID")
+    && pid.parse::<u32>().ok() != Some(std::process::id())
+  {
+    return None;
+  }
+  let timeout = std::env::var("WATCHDOG_USEC")
+    .ok()?
+    .parse::<u64>()
+    .ok()
+    .filter(|micros| *micros > 0)
+    .map(Duration::from_micros)?;
+  Some(timeout / 2)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+  use super::*;
+  use std::os::unix::net::UnixDatagram;
+
+  #[test]
+  fn send_should_deliver_state_to_notify_socket() {
+    let socket_path = std::env::temp_dir().join(format!("notify-test-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&socket_path);
+    let receiver = UnixDatagram::bind(&socket_path).unwrap();
+
+    send(socket_path.as_os_str(), "READY=1").unwrap();
+    let mut buffer = [0; 16];
+    let length = receiver.recv(&mut buffer).unwrap();
+    std::fs::remove_file(&socket_path).unwrap();
+    assert_eq!(&buffer[..length], b"READY=1");
+  }
+}
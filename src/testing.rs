@@ -0,0 +1,296 @@
+//! Mocks and an in-process MCP client for tests of code built on this library, enabled with the
+//! `test-util` feature.
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use rmcp::{
+  RoleClient, RoleServer, ServiceExt,
+  model::{CallToolRequestParam, CallToolResult},
+  service::RunningService,
+};
+use std::{
+  collections::HashMap,
+  path::{Path, PathBuf},
+  sync::Arc,
+};
+
+use crate::{
+  search::{
+    file::{File, FileFilter, FileLoader, FileOperation, FileOperationHandler, FileWatcher},
+    sharded_index::ShardedIndex,
+    text_index::TextIndex,
+  },
+  servers::search::SearchServer,
+};
+
+/// Bytes buffered in each direction between the test client and the server.
+const TRANSPORT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Accepts the paths ending with one of its suffixes, `.txt` by default.
+pub struct MockFileFilter {
+  suffixes: Vec<String>,
+}
+
+impl MockFileFilter {
+  pub fn new(suffixes: &[&str]) -> Self {
+    MockFileFilter {
+      suffixes: suffixes.iter().map(|suffix| suffix.to_string()).collect(),
+    }
+  }
+}
+
+impl Default for MockFileFilter {
+  fn default() -> Self {
+    Self::new(&[".txt"])
+  }
+}
+
+impl FileFilter for MockFileFilter {
+  fn is_target(&self, path: &str) -> bool {
+    self.suffixes.iter().any(|suffix| path.ends_with(suffix))
+  }
+}
+
+/// Lists `files` for every directory, and loads every path with `loaded_file_content`, so that
+/// tests can tell documents indexed from a change apart from those of the initial listing.
+pub struct MockFileLoader {
+  files: Vec<File>,
+  loaded_file_content: String,
+}
+
+impl MockFileLoader {
+  pub fn new(files: Vec<File>, loaded_file_content: String) -> Self {
+    MockFileLoader {
+      files,
+      loaded_file_content,
+    }
+  }
+}
+
+#[async_trait::async_trait]
+impl FileLoader for MockFileLoader {
+  fn load_directory<'a>(
+    &'a self,
+    _path: &str,
+    file_filter: &'a dyn FileFilter,
+  ) -> Box<dyn Iterator<Item = Result<File>> + 'a> {
+    Box::new(
+      self
+        .files
+        .iter()
+        .filter(move |file| file_filter.is_target(&file.path))
+        .cloned()
+        .map(Ok),
+    )
+  }
+
+  fn load_file_blocking(&self, path: &str) -> Result<File> {
+    Ok(File::new(
+      path.to_string(),
+      self.loaded_file_content.clone(),
+    ))
+  }
+}
+
+/// Watches nothing: tests hand it the changes with `emit`. Clones share their directories, so a
+/// clone kept by the test reaches the watcher handed to the code under test.
+#[derive(Clone, Default)]
+pub struct MockFileWatcher {
+  handlers: Arc<Mutex<HashMap<String, Box<FileOperationHandler>>>>,
+}
+
+impl MockFileWatcher {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Passes `operation` to the handler of every watched directory holding one of its paths, and
+  /// returns whether any did.
+  pub fn emit(&self, operation: &FileOperation) -> Result<bool> {
+    let handlers = self.handlers.lock();
+    let mut handled = false;
+    for (directory, handler) in handlers.iter() {
+      if operation
+        .paths()
+        .iter()
+        .any(|path| Path::new(path).starts_with(directory))
+      {
+        handler(operation)?;
+        handled = true;
+      }
+    }
+    Ok(handled)
+  }
+}
+
+impl FileWatcher for MockFileWatcher {
+  fn watch_directory(&mut self, path: &str, handler: Box<FileOperationHandler>) -> Result<()> {
+    self.handlers.lock().insert(path.to_string(), handler);
+    Ok(())
+  }
+
+  fn stop_watching(&mut self) -> Result<()> {
+    self.handlers.lock().clear();
+    Ok(())
+  }
+}
+
+/// An index in memory holding `files` as the only shard, under `root`.
+pub fn in_memory_index(root: &str, files: Vec<File>) -> Result<Arc<ShardedIndex>> {
+  let mut text_index = TextIndex::new()?;
+  text_index.add_docs(Box::new(files.into_iter()))?;
+  let index = Arc::new(ShardedIndex::new());
+  index.add_shard(root, Arc::new(Mutex::new(text_index)))?;
+  Ok(index)
+}
+
+/// `path`, an absolute path, as a path relative to the current directory, like a watch dir given
+/// on the command line.
+pub fn relative_to_current_dir(path: &Path) -> PathBuf {
+  let current_dir = std::env::current_dir()
+    .and_then(|dir| dir.canonicalize())
+    .unwrap_or_default();
+  current_dir
+    .components()
+    .skip(1)
+    .map(|_| Path::new(".."))
+    .collect::<PathBuf>()
+    .join(path.strip_prefix("/").unwrap_or(path))
+}
+
+/// MCP client connected to a server running in the same process, to call its tools as a client
+/// application would.
+pub struct TestClient {
+  client: RunningService<RoleClient, ()>,
+  _server: RunningService<RoleServer, SearchServer>,
+}
+
+impl TestClient {
+  pub async fn connect(server: SearchServer) -> Result<Self> {
+    let (server_stream, client_stream) = tokio::io::duplex(TRANSPORT_BUFFER_SIZE);
+    /* The server only returns once the client completed the handshake */
+    let server = tokio::spawn(server.serve(tokio::io::split(server_stream)));
+    let client = ().serve(tokio::io::split(client_stream)).await?;
+    let server = server.await??;
+    Ok(TestClient {
+      client,
+      _server: server,
+    })
+  }
+
+  pub async fn list_tools(&self) -> Result<Vec<String>> {
+    Ok(
+      self
+        .client
+        .list_all_tools()
+        .await?
+        .into_iter()
+        .map(|tool| tool.name.to_string())
+        .collect(),
+    )
+  }
+
+  /// Calls a tool with `arguments`, a JSON object, as it comes back, errors included.
+  pub async fn call_tool_result(
+    &self,
+    name: &str,
+    arguments: serde_json::Value,
+  ) -> Result<CallToolResult> {
+    let serde_json::Value::Object(arguments) = arguments else {
+      anyhow::bail!("Tool arguments must be a JSON object, got {}", arguments);
+    };
+    Ok(
+      self
+        .client
+        .call_tool(CallToolRequestParam {
+          name: name.to_string().into(),
+          arguments: Some(arguments),
+        })
+        .await?,
+    )
+  }
+
+  /// Calls a tool with `arguments` and returns its text, failing when the tool reports an error.
+  pub async fn call_tool(&self, name: &str, arguments: serde_json::Value) -> Result<String> {
+    let result = self.call_tool_result(name, arguments).await?;
+    let text = result
+      .content
+      .iter()
+      .filter_map(|content| content.as_text())
+      .map(|content| content.text.as_str())
+      .collect::<Vec<_>>()
+      .join("\n");
+    if result.is_error == Some(true) {
+      anyhow::bail!("{} failed: {}", name, text);
+    }
+    Ok(text)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::search::{event_log::EventLog, skipped_files::SkippedFiles};
+
+  #[tokio::test]
+  async fn test_client_should_call_tools_of_an_in_process_server() {
+    let index = in_memory_index(
+      "/notes",
+      vec![File::new(
+        "/notes/garden.txt".to_string(),
+        "Plant tomatoes in May".to_string(),
+      )],
+    )
+    .unwrap();
+    let server = SearchServer::new(
+      index,
+      Arc::new(MockFileLoader::new(Vec::new(), String::new())),
+      Arc::new(SkippedFiles::new()),
+      Arc::new(EventLog::default()),
+    );
+    let client = TestClient::connect(server).await.unwrap();
+
+    assert!(
+      client
+        .list_tools()
+        .await
+        .unwrap()
+        .contains(&"search_index".to_string())
+    );
+    let results = client
+      .call_tool("search_index", serde_json::json!({ "keyword": "tomatoes" }))
+      .await
+      .unwrap();
+    assert!(results.contains("/notes/garden.txt"));
+    assert!(
+      client
+        .call_tool("search_index", serde_json::json!({}))
+        .await
+        .is_err()
+    );
+
+    let mut watcher = MockFileWatcher::new();
+    let emitted = Arc::new(Mutex::new(Vec::new()));
+    let recorded = emitted.clone();
+    watcher
+      .clone()
+      .watch_directory(
+        "/notes",
+        Box::new(move |operation| {
+          recorded.lock().push(format!("{:?}", operation));
+          Ok(())
+        }),
+      )
+      .unwrap();
+    let created = FileOperation::FileCreated("/notes/new.txt".to_string());
+    assert!(watcher.emit(&created).unwrap());
+    assert!(
+      !watcher
+        .emit(&FileOperation::FileCreated("/other/new.txt".to_string()))
+        .unwrap()
+    );
+    watcher.stop_watching().unwrap();
+    assert!(!watcher.emit(&created).unwrap());
+    assert_eq!(emitted.lock().len(), 1);
+  }
+}